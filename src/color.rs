@@ -0,0 +1,325 @@
+use super::*;
+
+/// The chromaticity of D50, which the DNG transforms map the white of the camera to
+pub(crate) const D50_XY: [f32; 2] = [0.3457, 0.3585];
+
+/// The chromaticity of D65, the white of the output matrices of `data`
+pub(crate) const D65_XY: [f32; 2] = [0.3127, 0.3290];
+
+/// The cone response of the Bradford chromatic adaptation
+const BRADFORD: [f32; 9] = [
+    0.8951, 0.2664, -0.1614, -0.7502, 1.7135, 0.0367, 0.0389, -0.0685, 1.0296,
+];
+
+const IDENTITY: [f32; 9] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+
+/// The color calibration of a DNG file, which maps the camera colors to XYZ like the chapter
+/// "Mapping Camera Color Space to CIE XYZ Space" of the DNG specification. The matrices of the
+/// two calibration illuminants are interpolated by the color temperature of the white.
+pub(crate) struct DngColor {
+    /// The color temperatures of the illuminants, from the lower to the higher one when both
+    /// are known, 0 when it is unknown
+    temperatures: [f32; 2],
+    color_matrices: [[f32; 9]; 2],
+    forward_matrices: Option<[[f32; 9]; 2]>,
+    camera_calibrations: [[f32; 9]; 2],
+    analog_balance: [f32; 3],
+}
+
+impl DngColor {
+    /// The calibration of the DNG files with the ColorMatrix1 of 3 color planes
+    pub(crate) fn from_info(info: &quickexif::ParsedInfo) -> Option<DngColor> {
+        let matrix = |name: &str| -> Option<[f32; 9]> {
+            let mut matrix = [0f32; 9];
+            for (i, item) in matrix.iter_mut().enumerate() {
+                *item = info.f64(format!("{}_{}", name, i).as_str()).ok()? as f32;
+            }
+            matrix.iter().all(|x| x.is_finite()).then_some(matrix)
+        };
+        let temperature = |name: &str| info.u16(name).map_or(0.0, illuminant_temperature);
+
+        let color_matrix_1 = matrix("cm1")?;
+        let mut color = match matrix("cm2") {
+            Some(color_matrix_2) => DngColor {
+                temperatures: [
+                    temperature("calibration_illuminant_1"),
+                    temperature("calibration_illuminant_2"),
+                ],
+                color_matrices: [color_matrix_1, color_matrix_2],
+                forward_matrices: matrix("fm1").zip(matrix("fm2")).map(|(a, b)| [a, b]),
+                camera_calibrations: [
+                    matrix("cc1").unwrap_or(IDENTITY),
+                    matrix("cc2").unwrap_or(IDENTITY),
+                ],
+                analog_balance: [1.0; 3],
+            },
+            None => DngColor {
+                temperatures: [temperature("calibration_illuminant_1"); 2],
+                color_matrices: [color_matrix_1; 2],
+                forward_matrices: matrix("fm1").map(|x| [x; 2]),
+                camera_calibrations: [matrix("cc1").unwrap_or(IDENTITY); 2],
+                analog_balance: [1.0; 3],
+            },
+        };
+        if let (Ok(r), Ok(g), Ok(b)) = (info.f64("ab_0"), info.f64("ab_1"), info.f64("ab_2")) {
+            color.analog_balance = [r as f32, g as f32, b as f32];
+        }
+        if color.temperatures[1] > 0.0 && color.temperatures[0] > color.temperatures[1] {
+            color.temperatures.swap(0, 1);
+            color.color_matrices.swap(0, 1);
+            color.camera_calibrations.swap(0, 1);
+            if let Some(forward_matrices) = &mut color.forward_matrices {
+                forward_matrices.swap(0, 1);
+            }
+        }
+        Some(color)
+    }
+
+    /// The matrix from the white balanced camera colors to XYZ for the white of `neutral`,
+    /// the camera color of the white. Its rows are normalized like the matrices of
+    /// `data::CAM_XYZ_MAP`, the white maps to (1, 1, 1).
+    pub(crate) fn cam_matrix(&self, neutral: [f32; 3]) -> [f32; 9] {
+        let white_xy = self.white_xy(neutral);
+        let weight = self.weight(xy_to_temperature(white_xy));
+        let analog_calibration = utility::matrix3_mul(
+            &diagonal(self.analog_balance),
+            &interpolate(&self.camera_calibrations, weight),
+        );
+
+        let camera_to_xyz = match &self.forward_matrices {
+            // the forward matrix maps the white balanced colors to D50
+            Some(forward_matrices) => {
+                let inverse = utility::matrix3_inverse(&analog_calibration);
+                let reference_neutral = matrix3_apply(&inverse, neutral);
+                let white_balance = diagonal(reference_neutral.map(|x| 1.0 / x));
+                utility::matrix3_mul(
+                    &interpolate(forward_matrices, weight),
+                    &utility::matrix3_mul(&white_balance, &inverse),
+                )
+            }
+            // the inverse of the color matrix maps the white to its XYZ, then it is adapted
+            None => {
+                let xyz_to_camera = utility::matrix3_mul(
+                    &analog_calibration,
+                    &interpolate(&self.color_matrices, weight),
+                );
+                utility::matrix3_mul(
+                    &bradford_adaptation(white_xy, D50_XY),
+                    &utility::matrix3_inverse(&xyz_to_camera),
+                )
+            }
+        };
+
+        let matrix = utility::matrix3_mul(&camera_to_xyz, &diagonal(neutral));
+        let mut normalized = matrix;
+        for (row, normalized) in matrix.chunks_exact(3).zip(normalized.chunks_exact_mut(3)) {
+            let sum: f32 = row.iter().sum();
+            normalized.iter_mut().for_each(|x| *x /= sum);
+        }
+        normalized
+    }
+
+    /// The weight of the matrices of the lower temperature, by the inverse temperatures
+    fn weight(&self, temperature: f32) -> f32 {
+        let [low, high] = self.temperatures;
+        // the matrix of the second illuminant is used without the temperatures, like the
+        // simple path
+        if low <= 0.0 || low >= high {
+            return 0.0;
+        }
+        ((1.0 / temperature - 1.0 / high) / (1.0 / low - 1.0 / high)).clamp(0.0, 1.0)
+    }
+
+    /// The chromaticity of the white, which is found by iterating as its color matrix depends
+    /// on its temperature
+    fn white_xy(&self, neutral: [f32; 3]) -> [f32; 2] {
+        let mut xy = D50_XY;
+        for _ in 0..30 {
+            let weight = self.weight(xy_to_temperature(xy));
+            let xyz_to_camera = utility::matrix3_mul(
+                &utility::matrix3_mul(
+                    &diagonal(self.analog_balance),
+                    &interpolate(&self.camera_calibrations, weight),
+                ),
+                &interpolate(&self.color_matrices, weight),
+            );
+            let next = xyz_to_xy(matrix3_apply(
+                &utility::matrix3_inverse(&xyz_to_camera),
+                neutral,
+            ));
+            if !next.iter().all(|x| x.is_finite()) {
+                break;
+            }
+            let converged = (next[0] - xy[0]).abs() + (next[1] - xy[1]).abs() < 1e-7;
+            xy = next;
+            if converged {
+                break;
+            }
+        }
+        xy
+    }
+}
+
+/// The matrix from the white balanced camera colors to XYZ which undoes the multipliers of the
+/// white balance and adapts the white `neutral` to the one of the output by the Bradford
+/// transform instead. The rows of the matrices of `data` are normalized to the white, which is
+/// taken as D65 for the transform, and the white maps to (1, 1, 1) like by `cam_matrix`.
+pub(crate) fn bradford_cam_matrix(cam_matrix: &[f32; 9], neutral: [f32; 3]) -> [f32; 9] {
+    let d65 = xy_to_xyz(D65_XY);
+    let white = matrix3_apply(cam_matrix, neutral);
+    let white_xy = xyz_to_xy([0, 1, 2].map(|i| white[i] * d65[i]));
+    let adaptation = utility::matrix3_mul(
+        &diagonal(d65.map(|x| 1.0 / x)),
+        &utility::matrix3_mul(&bradford_adaptation(white_xy, D65_XY), &diagonal(d65)),
+    );
+    let matrix = utility::matrix3_mul(
+        &adaptation,
+        &utility::matrix3_mul(cam_matrix, &diagonal(neutral)),
+    );
+    matrix.map(|x| x / white[1])
+}
+
+/// The multipliers of red, green and blue relative to green which render the white of the
+/// chromaticity neutral, by the ColorMatrix from XYZ to the camera colors of the sensor. The
+/// camera colors of the white are its neutral like the AsShotNeutral of DNG.
+pub(crate) fn xy_to_multipliers(color_matrix: &[f32; 9], xy: [f32; 2]) -> [f32; 3] {
+    let neutral = matrix3_apply(color_matrix, xy_to_xyz(xy));
+    neutral.map(|x| neutral[1] / x)
+}
+
+/// The chromaticity of the white which the multipliers of the white balance render neutral by
+/// the ColorMatrix, the inverse of `xy_to_multipliers`
+pub(crate) fn multipliers_to_xy(color_matrix: &[f32; 9], multipliers: [f32; 3]) -> [f32; 2] {
+    let neutral = multipliers.map(|x| 1.0 / x);
+    xyz_to_xy(matrix3_apply(&utility::matrix3_inverse(color_matrix), neutral))
+}
+
+/// The temperatures where the Planckian locus is approximated
+pub(crate) const TEMPERATURE_RANGE: (f32, f32) = (1000.0, 15000.0);
+/// The tint of the white 1/3000 away from the Planckian locus in the CIE 1960 UCS, like the
+/// one of the DNG SDK
+const TINT_SCALE: f64 = 3000.0;
+
+/// The chromaticity of the white of the color temperature in Kelvin, shifted from the
+/// Planckian locus toward green by the positive tint, which renders magenta
+pub(crate) fn temperature_to_xy(temperature: f32, tint: f32) -> [f32; 2] {
+    let temperature = temperature.clamp(TEMPERATURE_RANGE.0, TEMPERATURE_RANGE.1) as f64;
+    let [u, v] = planckian_uv(temperature);
+    let [du, dv] = planckian_normal(temperature);
+    let offset = tint as f64 / TINT_SCALE;
+    let (u, v) = (u + du * offset, v + dv * offset);
+    let denominator = 2.0 * u - 8.0 * v + 4.0;
+    [3.0 * u / denominator, 2.0 * v / denominator].map(|x| x as f32)
+}
+
+/// The color temperature in Kelvin and the tint of the white of the chromaticity, the
+/// inverse of `temperature_to_xy`. The temperature is where the distance from the locus is
+/// perpendicular to it, bisected in mired.
+pub(crate) fn xy_to_temperature_tint([x, y]: [f32; 2]) -> (f32, f32) {
+    let (x, y) = (x as f64, y as f64);
+    let denominator = -2.0 * x + 12.0 * y + 3.0;
+    let [u, v] = [4.0 * x / denominator, 6.0 * y / denominator];
+    let offset = |temperature: f64| {
+        let [locus_u, locus_v] = planckian_uv(temperature);
+        [u - locus_u, v - locus_v]
+    };
+
+    let mut bounds = [
+        1e6 / TEMPERATURE_RANGE.1 as f64,
+        1e6 / TEMPERATURE_RANGE.0 as f64,
+    ];
+    let mut temperature = 0.0;
+    for _ in 0..48 {
+        let mired = (bounds[0] + bounds[1]) / 2.0;
+        temperature = 1e6 / mired;
+        // the distance along the tangent toward the higher temperatures
+        let [du, dv] = offset(temperature);
+        let [normal_u, normal_v] = planckian_normal(temperature);
+        let is_beyond = du * normal_v - dv * normal_u < 0.0;
+        bounds[is_beyond as usize] = mired;
+    }
+    let [du, dv] = offset(temperature);
+    let [normal_u, normal_v] = planckian_normal(temperature);
+    let tint = (du * normal_u + dv * normal_v) * TINT_SCALE;
+    (temperature as f32, tint as f32)
+}
+
+/// The CIE 1960 UCS chromaticity of the Planckian locus by the approximation of Krystek
+fn planckian_uv(t: f64) -> [f64; 2] {
+    let u = (0.860117757 + 1.54118254e-4 * t + 1.28641212e-7 * t * t)
+        / (1.0 + 8.42420235e-4 * t + 7.08145163e-7 * t * t);
+    let v = (0.317398726 + 4.22806245e-5 * t + 4.20481691e-8 * t * t)
+        / (1.0 - 2.89741816e-5 * t + 1.61456053e-7 * t * t);
+    [u, v]
+}
+
+/// The unit normal of the Planckian locus at the temperature toward green, the tangent toward
+/// the higher temperatures turned clockwise as u falls along the locus
+fn planckian_normal(t: f64) -> [f64; 2] {
+    let ([u0, v0], [u1, v1]) = (planckian_uv(t - 1.0), planckian_uv(t + 1.0));
+    let (du, dv) = (u1 - u0, v1 - v0);
+    let length = du.hypot(dv);
+    [dv / length, -du / length]
+}
+
+/// The color temperatures of the LightSource values of the CalibrationIlluminant tags, like
+/// the DNG SDK, 0 when it is unknown
+fn illuminant_temperature(illuminant: u16) -> f32 {
+    match illuminant {
+        3 | 17 => 2850.0,
+        24 => 3200.0,
+        23 => 5000.0,
+        1 | 4 | 9 | 18 | 20 => 5500.0,
+        10 | 19 | 21 => 6500.0,
+        11 | 22 => 7500.0,
+        12 => 6400.0,
+        13 => 5050.0,
+        2 | 14 => 4150.0,
+        15 => 3525.0,
+        16 => 2925.0,
+        _ => 0.0,
+    }
+}
+
+/// The correlated color temperature of McCamy's approximation
+fn xy_to_temperature([x, y]: [f32; 2]) -> f32 {
+    let n = (x - 0.3320) / (0.1858 - y);
+    (449.0 * n * n * n + 3525.0 * n * n + 6823.3 * n + 5520.33).clamp(2000.0, 50000.0)
+}
+
+fn xyz_to_xy([x, y, z]: [f32; 3]) -> [f32; 2] {
+    let sum = x + y + z;
+    [x / sum, y / sum]
+}
+
+pub(crate) fn xy_to_xyz([x, y]: [f32; 2]) -> [f32; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+/// The Bradford transform from the white of `from` to the one of `to`
+pub(crate) fn bradford_adaptation(from: [f32; 2], to: [f32; 2]) -> [f32; 9] {
+    let from = matrix3_apply(&BRADFORD, xy_to_xyz(from));
+    let to = matrix3_apply(&BRADFORD, xy_to_xyz(to));
+    let scale = diagonal([0, 1, 2].map(|i| to[i] / from[i]));
+    utility::matrix3_mul(
+        &utility::matrix3_inverse(&BRADFORD),
+        &utility::matrix3_mul(&scale, &BRADFORD),
+    )
+}
+
+fn interpolate(matrices: &[[f32; 9]; 2], weight: f32) -> [f32; 9] {
+    let [a, b] = matrices;
+    [0, 1, 2, 3, 4, 5, 6, 7, 8].map(|i| a[i] * weight + b[i] * (1.0 - weight))
+}
+
+pub(crate) fn diagonal([a, b, c]: [f32; 3]) -> [f32; 9] {
+    [a, 0.0, 0.0, 0.0, b, 0.0, 0.0, 0.0, c]
+}
+
+pub(crate) fn matrix3_apply(m: &[f32; 9], [a, b, c]: [f32; 3]) -> [f32; 3] {
+    [
+        m[0] * a + m[1] * b + m[2] * c,
+        m[3] * a + m[4] * b + m[5] * c,
+        m[6] * a + m[7] * b + m[8] * c,
+    ]
+}
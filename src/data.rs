@@ -1,6 +1,12 @@
 //! Contains predefined color spaces from XYZ to target, predefined gamma parameters and color spaces from CAM to XYZ for different cameras.
 
 // http://www.brucelindbloom.com/index.html?Eqn_RGB_to_XYZ.html
+//
+// The camera matrices are normalized like dcraw, they bring the white of the white balanced
+// camera to the XYZ of (1, 1, 1) instead of the one of D50 or D65. So the rows of the matrices
+// from XYZ to the color spaces below are normalized to sum up to 1 as well, which keeps the
+// white neutral whichever white point the color space is defined for, D65 for Adobe RGB,
+// Display P3, Rec. 2020 and sRGB, D50 for ProPhoto.
 pub static XYZ2ADOBE_RGB: [f32; 9] = [
     1.8037626,
     -0.49918914,
@@ -25,10 +31,56 @@ pub static XYZ2SRGB: [f32; 9] = [
     1.1632653,
 ];
 
+pub static XYZ2PROPHOTO: [f32; 9] = [
+    1.2951427,
+    -0.24595997,
+    -0.04918266,
+    -0.553396,
+    1.5325291,
+    0.02086681,
+    0.0,
+    0.0,
+    1.0,
+];
+
+pub static XYZ2DISPLAY_P3: [f32; 9] = [
+    2.150674,
+    -0.80333066,
+    -0.3473434,
+    -0.86694103,
+    1.8422496,
+    0.024691373,
+    0.039109148,
+    -0.083107024,
+    1.0439979,
+];
+
+pub static XYZ2REC2020: [f32; 9] = [
+    1.5498639,
+    -0.32111436,
+    -0.22874962,
+    -0.6904602,
+    1.6741294,
+    0.016330848,
+    0.01923711,
+    -0.04664328,
+    1.0274062,
+];
+
 pub static XYZ2RAW: [f32; 9] = [1.0, 0., 0., 0., 1.0, 0., 0., 0., 1.0];
+/// The channel mixer which keeps every channel as it is
+pub static CHANNEL_MIXER_IDENTITY: [f32; 9] = [1.0, 0., 0., 0., 1.0, 0., 0., 0., 1.0];
 
 pub static GAMMA_LINEAR: f32 = 1.0;
 pub static GAMMA_SRGB: f32 = 0.45;
+/// The gamma of 2.2 of Adobe RGB, which is 563/256
+pub static GAMMA_ADOBE_RGB: f32 = 256. / 563.;
+/// The gamma of 1.8 of ProPhoto, the linear toe of ROMM RGB is left out
+pub static GAMMA_PROPHOTO: f32 = 1. / 1.8;
+/// Display P3 shares the curve of sRGB, `TransferFunction::Srgb` is exact
+pub static GAMMA_DISPLAY_P3: f32 = 0.45;
+/// The power of Rec. 2020, `TransferFunction::Rec2020` adds its linear toe
+pub static GAMMA_REC2020: f32 = 0.45;
 
 
 pub static CAM_XYZ_MAP: phf::Map<&'static str, [f32; 9]> = phf::phf_map! {
@@ -151,6 +203,10 @@ pub static CAM_XYZ_MAP: phf::Map<&'static str, [f32; 9]> = phf::phf_map! {
     "CanonPowerShotSX60HS" => [0.86764276, 0.16695194, -0.034594692, 0.34200603, 0.8806422, -0.22264822, 0.05067761, -0.14826426, 1.0975866],
     "CanonPowerShotSX70HS" => [0.92972994, 0.17323913, -0.10296906, 0.3795861, 0.82379305, -0.20337915, 0.05414782, -0.12295352, 1.0688057],
 
+    // epson
+    "R-D1" => [0.63566387, 0.3289599, 0.03537622, 0.22185494, 0.8246087, -0.046463605, 0.0775426, -0.12727351, 1.0497309],
+    "R-D1s" => [0.63566387, 0.3289599, 0.03537622, 0.22185494, 0.8246087, -0.046463605, 0.0775426, -0.12727351, 1.0497309],
+    "R-D1x" => [0.63566387, 0.3289599, 0.03537622, 0.22185494, 0.8246087, -0.046463605, 0.0775426, -0.12727351, 1.0497309],
     // fujifilm
     "FinePix E550" => [0.6549494, 0.30752385, 0.037526738, 0.29702693, 0.90066314, -0.19769005, 0.06391598, -0.12374492, 1.0598289],
     "FinePix E900" => [0.6340089, 0.3031351, 0.062856026, 0.24279515, 0.9760505, -0.21884564, 0.08570383, -0.13714989, 1.0514461],
@@ -233,6 +289,17 @@ pub static CAM_XYZ_MAP: phf::Map<&'static str, [f32; 9]> = phf::phf_map! {
     "XQ1" => [0.6934473, 0.28372124, 0.022831475, 0.31258285, 1.3174419, -0.6300248, 0.0900715, -0.08220879, 0.9921373],
     "XQ2" => [0.6934473, 0.28372124, 0.022831475, 0.31258285, 1.3174419, -0.6300248, 0.0900715, -0.08220879, 0.9921373],
 
+    // minolta
+    "DiMAGE5" => [0.6337755, 0.27255395, 0.093670584, 0.2902425, 0.9720828, -0.26232526, 0.07053861, -0.13209854, 1.0615599],
+    "DiMAGE7" => [0.6311041, 0.27468836, 0.09420752, 0.27979517, 1.0341105, -0.3139057, 0.07546666, -0.13075724, 1.0552906],
+    "DiMAGE7Hi" => [0.62962645, 0.26953077, 0.10084275, 0.31021163, 1.0067, -0.3169117, 0.06812724, -0.14438744, 1.0762602],
+    "DiMAGEA1" => [0.62388897, 0.3058125, 0.07029852, 0.24640842, 1.0186597, -0.2650681, 0.103588514, -0.061907507, 0.958319],
+    "DiMAGEA2" => [0.63696486, 0.32482064, 0.03821448, 0.2412359, 0.8341569, -0.07539276, 0.07910662, -0.14947267, 1.070366],
+    "DiMAGEA200" => [0.6333797, 0.32294512, 0.04367516, 0.24027202, 0.87117696, -0.11144898, 0.079860225, -0.14900354, 1.0691433],
+    "DYNAX5D" => [0.6430564, 0.31906277, 0.03788085, 0.25453267, 0.82295686, -0.077489525, 0.073010616, -0.116463035, 1.0434524],
+    "MAXXUM5D" => [0.6430564, 0.31906277, 0.03788085, 0.25453267, 0.82295686, -0.077489525, 0.073010616, -0.116463035, 1.0434524],
+    "DYNAX7D" => [0.63773817, 0.31919238, 0.04306947, 0.24456145, 0.8348318, -0.07939324, 0.07472165, -0.12558898, 1.0508673],
+    "MAXXUM7D" => [0.63773817, 0.31919238, 0.04306947, 0.24456145, 0.8348318, -0.07939324, 0.07472165, -0.12558898, 1.0508673],
     // nikon
     "NIKON1AW1" => [0.79506737, 0.24729358, -0.04236093, 0.2037986, 1.2922511, -0.49604967, 0.07301701, -0.26366532, 1.1906483],
     "NIKON1J1" => [0.72608846, 0.26981458, 0.0040969625, 0.26744434, 0.98320407, -0.25064838, 0.046175923, -0.22738712, 1.1812112],
@@ -616,4 +683,90 @@ pub static CAM_XYZ_MAP: phf::Map<&'static str, [f32; 9]> = phf::phf_map! {
     "XZ-1" => [0.9040267, 0.11734312, -0.021369824, 0.32360524, 0.9272336, -0.2508388, 0.006896814, -0.328173, 1.3212762],
     "XZ-10" => [0.77757245, 0.19413547, 0.02829204, 0.31539285, 0.95791024, -0.27330312, 0.046629336, -0.16652574, 1.1198964],
     "XZ-2" => [0.77757245, 0.19413547, 0.02829204, 0.31539285, 0.95791024, -0.27330312, 0.046629336, -0.16652574, 1.1198964],
+
+    // hasselblad
+    "X1D" => [0.69093704, 0.27376842, 0.035294537, 0.19024545, 1.0783463, -0.26859173, -0.14075705, -0.5464826, 1.6872396],
+    // kodak
+    "DCSPro14N" => [0.681343, 0.31553015, 0.0031268066, 1.2773849, -2.6980214, 2.4206364, 0.114752345, -0.16193216, 1.0471798],
+    "DCSPro14nx" => [0.681343, 0.31553015, 0.0031268066, 1.2773849, -2.6980214, 2.4206364, 0.114752345, -0.16193216, 1.0471798],
+    "DCSProSLR/n" => [0.681343, 0.31553015, 0.0031268066, 1.2773849, -2.6980214, 2.4206364, 0.114752345, -0.16193216, 1.0471798],
+    "KODAKP712ZOOMDIGITALCAMERA" => [0.6716791, 0.25779057, 0.07053033, 0.2965443, 0.91733485, -0.21387915, 0.01512459, -0.26675197, 1.2516274],
+    "KODAKP880ZOOMDIGITALCAMERA" => [0.6445251, 0.3085246, 0.04695026, 0.3012715, 0.8814155, -0.18268701, 0.066390686, -0.13223179, 1.0658411],
+    // leica
+    "LEICAM(Typ240)" => [0.7628465, 0.22884427, 0.008309197, 0.321148, 1.2805336, -0.6016816, 0.08649211, -0.019440426, 0.9329483],
+    "LEICAQ(Typ116)" => [0.67050064, 0.24610242, 0.08339696, 0.37219706, 1.1393427, -0.51153976, 0.103559926, -0.020451525, 0.9168916],
+    "LEICAQ2" => [0.6830033, 0.28044155, 0.036555134, 0.3378321, 0.7969841, -0.13481618, 0.0928089, -0.010412677, 0.9176038],
+    // pentax
+    "PENTAXK-1" => [0.7274924, 0.2490335, 0.023474125, 0.29226905, 0.87963486, -0.17190391, 0.006150461, -0.24859115, 1.2424407],
+    "PENTAXK-1MarkII" => [0.7274924, 0.2490335, 0.023474125, 0.29226905, 0.87963486, -0.17190391, 0.006150461, -0.24859115, 1.2424407],
+    "PENTAXK-3" => [0.6770658, 0.26174092, 0.061193265, 0.25451967, 1.0141038, -0.2686234, 0.051800396, -0.22505784, 1.1732575],
+    "PENTAXK-5" => [0.7217147, 0.25879472, 0.019490594, 0.28031087, 0.9127367, -0.19304758, 0.013837338, -0.29289672, 1.2790594],
+    "PENTAXK-70" => [0.7419464, 0.2443809, 0.013672683, 0.2985142, 0.87125957, -0.16977377, 0.0221996, -0.22307046, 1.2008709],
 };
+
+// the white points of daylight (D65), a cloudy sky (D75) and tungsten light (A) in XYZ
+const WHITE_POINTS: [[f32; 3]; 3] = [
+    [0.95047, 1.0, 1.08883],
+    [0.94972, 1.0, 1.22638],
+    [1.0985, 1.0, 0.35585],
+];
+
+fn preset_white_point(white_balance: crate::WhiteBalance) -> Option<[f32; 3]> {
+    match white_balance {
+        crate::WhiteBalance::Daylight => Some(WHITE_POINTS[0]),
+        crate::WhiteBalance::Cloudy => Some(WHITE_POINTS[1]),
+        crate::WhiteBalance::Tungsten => Some(WHITE_POINTS[2]),
+        _ => None,
+    }
+}
+
+/// The multipliers of red, green and blue relative to green of the preset of the white balance,
+/// estimated from the `color_matrix` from XYZ to the camera colors, the ColorMatrix of DNG
+/// files, which keeps the native white balance of the sensor: the camera colors of the white
+/// point of the light are rendered neutral. None when the white balance is not a preset or
+/// there is no ColorMatrix, see `white_balance_preset_from_cam_matrix` for the other files.
+pub fn white_balance_preset(
+    color_matrix: Option<&[f32; 9]>,
+    white_balance: crate::WhiteBalance,
+) -> Option<[f32; 3]> {
+    let white_point = preset_white_point(white_balance)?;
+    let neutral = crate::color::matrix3_apply(color_matrix?, white_point);
+    Some(neutral.map(|x| neutral[1] / x))
+}
+
+/// The multipliers of red, green and blue relative to green of the preset of the white balance
+/// for the files without a ColorMatrix, by the `cam_matrix` of `CAM_XYZ_MAP`. The matrices are
+/// normalized to the white balanced camera, which leaves out the native white balance of the
+/// sensor, so the white balance `as_shot` stands for daylight and is turned to the light of
+/// the preset in the white balanced camera colors, where daylight is (1, 1, 1). The daylight
+/// preset is therefore the white balance as shot. None when the white balance is not a preset.
+pub fn white_balance_preset_from_cam_matrix(
+    cam_matrix: &[f32; 9],
+    as_shot: [f32; 3],
+    white_balance: crate::WhiteBalance,
+) -> Option<[f32; 3]> {
+    let white_point = preset_white_point(white_balance)?;
+    let daylight = WHITE_POINTS[0];
+    let white_point = [0, 1, 2].map(|c| white_point[c] / daylight[c]);
+    let light = crate::color::matrix3_apply(
+        &crate::utility::matrix3_inverse(cam_matrix),
+        white_point,
+    );
+    Some([0, 1, 2].map(|c| as_shot[c] / as_shot[1] * light[1] / light[c]))
+}
+
+/// The multipliers of red, green and blue relative to green which render the white of the
+/// color temperature in Kelvin neutral, by the ColorMatrix like the estimated presets. The
+/// positive tint shifts the white toward green and the temperature is clamped to the range of
+/// the approximated Planckian locus, from 1000 K to 15000 K.
+pub fn temperature_white_balance(color_matrix: &[f32; 9], kelvin: f32, tint: f32) -> [f32; 3] {
+    crate::color::xy_to_multipliers(color_matrix, crate::color::temperature_to_xy(kelvin, tint))
+}
+
+/// The color temperature in Kelvin and the tint of the white which the multipliers relative to
+/// green render neutral by the ColorMatrix, the inverse of `temperature_white_balance`, e.g.
+/// to show the white balance as shot
+pub fn white_balance_temperature(color_matrix: &[f32; 9], multipliers: [f32; 3]) -> (f32, f32) {
+    let xy = crate::color::multipliers_to_xy(color_matrix, multipliers);
+    crate::color::xy_to_temperature_tint(xy)
+}
@@ -2,16 +2,41 @@ use super::*;
 use std::{fs::File, io::Read};
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CFAPattern {
     RGGB,
     GRBG,
     GBRG,
     BGGR,
-    XTrans0, // RBGBRG
-    XTrans1, // GGRGGB
+    XTrans0,    // RBGBRG
+    XTrans1,    // GGRGGB
+    None,       // interleaved RGB which needs no demosaicing, e.g. Canon sRAW
+    Monochrome, // a single channel from a sensor without CFA, e.g. Leica Monochrom
 }
 
+impl CFAPattern {
+    /// The pattern of the image cropped at `x` and `y`, an odd x swaps the columns of a 2x2
+    /// pattern and an odd y swaps its rows. The X-Trans patterns are kept as they are, their
+    /// crops are expected to start on multiples of 6.
+    pub fn shifted(self, x: u32, y: u32) -> CFAPattern {
+        let pattern = match (x % 2, self) {
+            (1, CFAPattern::RGGB) => CFAPattern::GRBG,
+            (1, CFAPattern::GRBG) => CFAPattern::RGGB,
+            (1, CFAPattern::GBRG) => CFAPattern::BGGR,
+            (1, CFAPattern::BGGR) => CFAPattern::GBRG,
+            (_, pattern) => pattern,
+        };
+        match (y % 2, pattern) {
+            (1, CFAPattern::RGGB) => CFAPattern::GBRG,
+            (1, CFAPattern::GBRG) => CFAPattern::RGGB,
+            (1, CFAPattern::GRBG) => CFAPattern::BGGR,
+            (1, CFAPattern::BGGR) => CFAPattern::GRBG,
+            (_, pattern) => pattern,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Crop {
     pub x: u32,
     pub y: u32,
@@ -19,6 +44,21 @@ pub struct Crop {
     pub height: u32,
 }
 
+impl Crop {
+    /// The crop of the half size image of SuperPixel, the rounding keeps it inside the crop of
+    /// the full size image
+    pub fn halved(&self) -> Crop {
+        let (x, y) = (self.x.div_ceil(2), self.y.div_ceil(2));
+        let (right, bottom) = ((self.x + self.width) / 2, (self.y + self.height) / 2);
+        Crop {
+            x,
+            y,
+            width: right.saturating_sub(x),
+            height: bottom.saturating_sub(y),
+        }
+    }
+}
+
 pub struct DecodedImage {
     pub cfa_pattern: CFAPattern,
     pub width: usize,
@@ -27,10 +67,55 @@ pub struct DecodedImage {
     pub orientation: Orientation,
     pub image: Vec<u16>,
     pub white_balance: [i32; 3],
+    /// The value where the samples of `image` clip, above the black level
+    pub white_level: u16,
+    /// The black levels of the positions of the 2x2 CFA block in the scale of `image`, which
+    /// are subtracted from it unless it is decoded by `decode_buffer_keeping_black_level`.
+    /// Zero for the decoders which measure or subtract the black level while decoding.
+    pub black_level: [u16; 4],
+    /// The exposure in EV the image is rendered with on top of the exposure compensation, the
+    /// BaselineExposure and the BaselineExposureOffset of DNG files
+    pub baseline_exposure: f32,
+    /// The tone curve of the file as the lookup table of the linear values, e.g. the
+    /// ProfileToneCurve of ProRAW files, which the rendering uses instead of the transfer
+    /// function unless the options choose their own
+    pub tone_curve: Option<Vec<u16>>,
     pub cam_matrix: [f32; 9],
+    /// The ColorMatrix of the file from XYZ to the camera colors, which keeps the native white
+    /// balance of the sensor unlike `cam_matrix`, none for the models whose matrix is built in
+    pub color_matrix: Option<[f32; 9]>,
+    /// The image is read by the generic decoder of plain TIFF raws, its camera matrix is the
+    /// identity and its white balance neutral, so the colors are only approximate
+    pub is_generic_fallback: bool,
     pub parsed_info: quickexif::ParsedInfo,
 }
 
+impl DecodedImage {
+    /// The color temperature in Kelvin and the tint of the white balance as shot, see
+    /// `data::white_balance_temperature`. None without the ColorMatrix.
+    pub fn as_shot_temperature(&self) -> Option<(f32, f32)> {
+        let multipliers = self.white_balance.map(|x| x as f32 / self.white_balance[1] as f32);
+        let color_matrix = self.color_matrix.as_ref()?;
+        Some(data::white_balance_temperature(color_matrix, multipliers))
+    }
+    /// Pads the samples of fewer bits with zero bits up to the 14 bits of most sensors, e.g.
+    /// the 12 bit samples of uncompressed ARW files, along with the black level. The white
+    /// level is padded with one bits to the full range. The samples of 14 bits and more are
+    /// kept.
+    pub fn pad_to_14bit(&mut self) {
+        let bits = u16::BITS - self.white_level.leading_zeros();
+        if bits == 0 || bits >= 14 {
+            return;
+        }
+        let shift = 14 - bits;
+        let pad = |x: u16| ((x as u32) << shift).min(u16::MAX as u32) as u16;
+        self.image.iter_mut().for_each(|x| *x = pad(*x));
+        self.black_level = self.black_level.map(pad);
+        self.white_level = pad(self.white_level) | ((1 << shift) - 1);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Orientation {
     Horizontal = 0,
     Rotate90 = 90,
@@ -45,14 +130,20 @@ pub(super) fn get_buffer_from_file(path: &str) -> Result<Vec<u8>, RawFileReading
         .metadata()
         .map_err(|_| RawFileReadingError::FileMetadataReadingError(path.to_owned()))?
         .len() as usize;
-    let mut buffer = vec![0u8; len];
+    // the padding of `prepare_buffer` fits in the allocation, so big files are not copied
+    let mut buffer = Vec::with_capacity(len + BUFFER_PADDING);
+    buffer.resize(len, 0);
     f.read(&mut buffer)
         .map_err(|_| RawFileReadingError::FileContentReadingError(path.to_owned()))?;
 
     Ok(buffer)
 }
+/// The zeros after the data of a file, which is for BitPumpMSB fix
+pub(super) const BUFFER_PADDING: usize = 16;
+
 fn prepare_buffer(mut buffer: Vec<u8>) -> Vec<u8> {
-    buffer.extend([0u8; 16]); // + 16 is for BitPumpMSB fix
+    buffer.reserve_exact(BUFFER_PADDING);
+    buffer.extend([0u8; BUFFER_PADDING]);
 
     fuji_buffer_fix(buffer)
 }
@@ -71,7 +162,7 @@ fn fuji_buffer_slice_fix(buffer: &[u8]) -> &[u8] {
     }
 }
 
-fn largest_jpeg_slice(buffer: &[u8]) -> Option<&[u8]> {
+pub(super) fn largest_jpeg_slice(buffer: &[u8]) -> Option<&[u8]> {
     let mut start = 0usize;
     let mut best: Option<(usize, usize)> = None;
     while let Some(rel_soi) = buffer[start..].windows(3).position(|w| w == [0xff, 0xd8, 0xff]) {
@@ -91,8 +182,14 @@ fn largest_jpeg_slice(buffer: &[u8]) -> Option<&[u8]> {
 }
 
 fn try_cr3_thumbnail(buffer: &[u8]) -> Option<(&[u8], Orientation)> {
-    let is_cr3 = buffer.get(4..12).map(|b| b == b"ftypcrx ").unwrap_or(false);
-    if !is_cr3 {
+    if !maker::cr3::is_cr3(buffer) {
+        return None;
+    }
+    let jpeg = largest_jpeg_slice(buffer)?;
+    Some((jpeg, Orientation::Horizontal))
+}
+fn try_iiq_thumbnail(buffer: &[u8]) -> Option<(&[u8], Orientation)> {
+    if !maker::phase_one::is_iiq(buffer) {
         return None;
     }
     let jpeg = largest_jpeg_slice(buffer)?;
@@ -138,6 +235,7 @@ fn parse_basic_info_with_fallback<'a>(
     buffer: &'a [u8],
 ) -> Result<(quickexif::ParsedInfo, &'a [u8]), RawFileReadingError> {
     let buffer = fuji_buffer_slice_fix(buffer);
+    let buffer = maker::minolta::ttw_slice(buffer).unwrap_or(buffer);
     let rule = &utility::BASIC_INFO_RULE;
     match quickexif::parse(buffer, rule) {
         Ok(info) => Ok((info, buffer)),
@@ -161,14 +259,88 @@ pub fn decode_file(path: &str) -> Result<DecodedImage, RawFileReadingError> {
 /// Gets `RawImage` from a buffer
 #[inline(always)]
 pub fn decode_buffer(buffer: Vec<u8>) -> Result<DecodedImage, RawFileReadingError> {
+    decode_buffer_with_opcodes(buffer, true)
+}
+
+/// Gets `RawImage` from a buffer, the DNG opcode lists are left out when `apply_opcodes` is false
+pub fn decode_buffer_with_opcodes(
+    buffer: Vec<u8>,
+    apply_opcodes: bool,
+) -> Result<DecodedImage, RawFileReadingError> {
     let buffer = prepare_buffer(buffer);
 
-    let rule = &utility::BASIC_INFO_RULE;
-    let decoder_select_info = quickexif::parse(&buffer, rule)?;
+    let decoder_select_info = parse_decoder_select_info(&buffer)?;
+    let decoded_image = maker::selector::select_and_decode(
+        buffer.as_slice(),
+        decoder_select_info,
+        0,
+        apply_opcodes,
+        false,
+    )?;
 
-    let decoded_image = maker::selector::select_and_decode(buffer.as_slice(), decoder_select_info)?;
+    Ok(decoded_image)
+}
+
+/// Gets `RawImage` from a buffer with the black level left in the samples, so the noise below
+/// it is not clamped to zero, e.g. to measure dark frames. The opcode lists are left out as
+/// they expect the black level to be subtracted.
+pub fn decode_buffer_keeping_black_level(
+    buffer: Vec<u8>,
+) -> Result<DecodedImage, RawFileReadingError> {
+    let buffer = prepare_buffer(buffer);
+
+    let decoder_select_info = parse_decoder_select_info(&buffer)?;
+    let decoded_image =
+        maker::selector::select_and_decode(buffer.as_slice(), decoder_select_info, 0, false, true)?;
+
+    Ok(decoded_image)
+}
+
+/// Gets every raw frame from a buffer, e.g. the combined image and the A sub-pixel image
+/// of a Canon Dual Pixel RAW file, or the four frames of a Pentax Pixel Shift Resolution file
+pub fn decode_buffer_multi(buffer: Vec<u8>) -> Result<Vec<DecodedImage>, RawFileReadingError> {
+    let buffer = prepare_buffer(buffer);
+
+    let mut decoded_images = vec![];
+    loop {
+        let decoder_select_info = parse_decoder_select_info(&buffer)?;
+        let frame = decoded_images.len();
+        match maker::selector::select_and_decode(
+            buffer.as_slice(),
+            decoder_select_info,
+            frame,
+            true,
+            false,
+        ) {
+            Ok(decoded_image) => decoded_images.push(decoded_image),
+            Err(RawFileReadingError::DecodingError(maker::DecodingError::FrameNotFound(_)))
+                if frame > 0 =>
+            {
+                break
+            }
+            Err(e) => return Err(e),
+        }
+    }
 
-    Ok(decoded_image)
+    Ok(decoded_images)
+}
+
+fn parse_decoder_select_info(buffer: &[u8]) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    let rule = &utility::BASIC_INFO_RULE;
+    let crw_info = maker::crw::info_tiff(buffer);
+    let iiq_info = maker::phase_one::info_tiff(buffer);
+    let info_buffer = if maker::cr3::is_cr3(buffer) {
+        maker::cr3::cmt1_slice(buffer).unwrap_or(buffer)
+    } else if let Some(crw_info) = &crw_info {
+        crw_info.as_slice()
+    } else if let Some(iiq_info) = &iiq_info {
+        iiq_info.as_slice()
+    } else if let Some(ttw) = maker::minolta::ttw_slice(buffer) {
+        ttw
+    } else {
+        buffer
+    };
+    Ok(quickexif::parse(info_buffer, rule)?)
 }
 
 pub(super) fn get_exif_info(buffer: &[u8]) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
@@ -181,6 +353,12 @@ pub fn get_thumbnail(buffer: &[u8]) -> Result<(&[u8], Orientation), RawFileReadi
     if let Some(result) = try_cr3_thumbnail(buffer) {
         return Ok(result);
     }
+    if let Some(result) = maker::crw::thumbnail(buffer) {
+        return Ok(result);
+    }
+    if let Some(result) = try_iiq_thumbnail(buffer) {
+        return Ok(result);
+    }
 
     match parse_basic_info_with_fallback(buffer) {
         Ok((decoder_select_info, buffer)) => {
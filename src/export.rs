@@ -7,6 +7,31 @@ pub struct Options<'a> {
     gamma: f32,
     color_space: &'a [f32; 9],
     no_demosaicing: bool,
+    apply_opcodes: bool,
+    pads_14bit: bool,
+    demosaicing_method: DemosaicingMethod,
+    white_balance: WhiteBalance,
+    white_balance_method: WhiteBalanceMethod,
+    exposure_ev: f32,
+    baseline_exposure: bool,
+    auto_brightness: Option<f32>,
+    highlight_mode: HighlightMode,
+    white_level: WhiteLevel,
+    tone_mapping: ToneMapping,
+    transfer_function: Option<TransferFunction>,
+    channel_gammas: Option<[[f32; 2]; 3]>,
+    tone_curve: Option<(Vec<u16>, bool)>,
+    lut3d: Option<Lut3d>,
+    brightness: f32,
+    contrast: f32,
+    saturation: f32,
+    vibrance: f32,
+    gamut_mapping: GamutMapping,
+    channel_mixer: [f32; 9],
+    negative_inversion: Option<NegativeInversion>,
+    monochrome: Option<Monochrome>,
+    sharpen: Option<Sharpen>,
+    simple_color: bool,
 }
 impl<'a> Options<'a> {
     pub fn new(gamma: f32, color_space: &'a [f32; 9], no_demosaicing: bool) -> Self {
@@ -14,8 +39,471 @@ impl<'a> Options<'a> {
             gamma,
             color_space,
             no_demosaicing,
+            apply_opcodes: true,
+            pads_14bit: false,
+            demosaicing_method: DemosaicingMethod::Linear,
+            white_balance: WhiteBalance::AsShot,
+            white_balance_method: WhiteBalanceMethod::CameraSpace,
+            exposure_ev: 0.0,
+            baseline_exposure: true,
+            auto_brightness: None,
+            highlight_mode: HighlightMode::Clip,
+            white_level: WhiteLevel::AsShot,
+            tone_mapping: ToneMapping::None,
+            transfer_function: None,
+            channel_gammas: None,
+            tone_curve: None,
+            lut3d: None,
+            brightness: 1.0,
+            contrast: 0.0,
+            saturation: 1.0,
+            vibrance: 0.0,
+            gamut_mapping: GamutMapping::Clip,
+            channel_mixer: data::CHANNEL_MIXER_IDENTITY,
+            negative_inversion: None,
+            monochrome: None,
+            sharpen: None,
+            simple_color: false,
         }
     }
+    /// Applies the DNG opcode lists and the rendering hints of Apple ProRAW files, turn it off
+    /// to keep the data untouched, on by default
+    pub fn with_apply_opcodes(mut self, apply_opcodes: bool) -> Self {
+        self.apply_opcodes = apply_opcodes;
+        self
+    }
+    /// Pads the decoded samples of fewer bits up to 14 bits before they are scaled to the
+    /// white level, see `DecodedImage::pad_to_14bit`. The custom white level is in the scale of
+    /// the padded samples. Off by default.
+    pub fn with_pad_14bit(mut self, pads_14bit: bool) -> Self {
+        self.pads_14bit = pads_14bit;
+        self
+    }
+    /// The demosaicing method of the bayer images, Linear by default
+    pub fn with_demosaicing_method(mut self, demosaicing_method: DemosaicingMethod) -> Self {
+        self.demosaicing_method = demosaicing_method;
+        self
+    }
+    /// The white balance of the rendered images, as shot by default
+    pub fn with_white_balance(mut self, white_balance: WhiteBalance) -> Self {
+        self.white_balance = white_balance;
+        self
+    }
+    /// Where the white balance of the rendered images is applied, in the camera space by
+    /// default
+    pub fn with_white_balance_method(mut self, method: WhiteBalanceMethod) -> Self {
+        self.white_balance_method = method;
+        self
+    }
+    /// The exposure compensation of the rendered images in EV, which is applied after the white
+    /// balance and the color matrix and clips at the white point, 0 by default
+    pub fn with_exposure_ev(mut self, exposure_ev: f32) -> Self {
+        self.exposure_ev = exposure_ev;
+        self
+    }
+    /// Applies the BaselineExposure of DNG files on top of the exposure compensation, on by
+    /// default
+    pub fn with_baseline_exposure(mut self, baseline_exposure: bool) -> Self {
+        self.baseline_exposure = baseline_exposure;
+        self
+    }
+    /// Brightens the rendered images until the `percentile` of their luminance is white, 99 like
+    /// dcraw, off by default
+    pub fn with_auto_brightness(mut self, percentile: f32) -> Self {
+        self.auto_brightness = Some(percentile);
+        self
+    }
+    /// How the clipped highlights of the rendered images look, clipped to neutral by default
+    pub fn with_highlight_mode(mut self, highlight_mode: HighlightMode) -> Self {
+        self.highlight_mode = highlight_mode;
+        self
+    }
+    /// The white level of the rendered images, which are normalized to it and clip at it, the
+    /// one of the file by default
+    pub fn with_white_level(mut self, white_level: WhiteLevel) -> Self {
+        self.white_level = white_level;
+        self
+    }
+    /// How the linear values of the rendered images are compressed before the gamma, after the
+    /// exposure compensation and the highlight reconstruction, clipped by default
+    pub fn with_tone_mapping(mut self, tone_mapping: ToneMapping) -> Self {
+        self.tone_mapping = tone_mapping;
+        self
+    }
+    /// The transfer function of the rendered images instead of the gamma, which is a pure
+    /// power curve
+    pub fn with_transfer_function(mut self, transfer_function: TransferFunction) -> Self {
+        self.transfer_function = Some(transfer_function);
+        self
+    }
+    /// The 1D lookup table the values of the rendered images are mapped by after the color
+    /// matrix, interpolated linearly when it has fewer than 65536 entries. The gamma is
+    /// applied before it unless it `replaces_gamma`, then it maps the linear values.
+    pub fn with_tone_curve(mut self, tone_curve: Vec<u16>, replaces_gamma: bool) -> Self {
+        self.tone_curve = Some((tone_curve, replaces_gamma));
+        self
+    }
+    /// The 3D LUT the colors of the rendered images are looked up in as the last step
+    pub fn with_lut3d(mut self, lut3d: Lut3d) -> Self {
+        self.lut3d = Some(lut3d);
+        self
+    }
+    /// The gain of the linear values of the rendered images, which adds up with the exposure
+    /// compensation, 1 by default
+    pub fn with_brightness(mut self, brightness: f32) -> Self {
+        self.brightness = brightness;
+        self
+    }
+    /// The contrast of the rendered images around middle gray after the brightness, where the
+    /// slope of their S-curve is `1 + contrast`, 0 by default
+    pub fn with_contrast(mut self, contrast: f32) -> Self {
+        self.contrast = contrast;
+        self
+    }
+    /// The saturation of the rendered images after the contrast, which keeps their Rec. 709
+    /// luma, 1 by default
+    pub fn with_saturation(mut self, saturation: f32) -> Self {
+        self.saturation = saturation;
+        self
+    }
+    /// The saturation of the less saturated colors of the rendered images along with the
+    /// saturation, which spares the hues of skin, 0 by default
+    pub fn with_vibrance(mut self, vibrance: f32) -> Self {
+        self.vibrance = vibrance;
+        self
+    }
+    /// How the colors of the rendered images outside of the gamut of the output are mapped
+    /// after the color matrix in linear light, before the transfer function, clipped by default
+    pub fn with_gamut_mapping(mut self, gamut_mapping: GamutMapping) -> Self {
+        self.gamut_mapping = gamut_mapping;
+        self
+    }
+    /// The matrix the red, green and blue of the rendered images are mixed by in linear light
+    /// after the color matrix, e.g. to swap red and blue, the identity by default
+    pub fn with_channel_mixer(mut self, channel_mixer: [f32; 9]) -> Self {
+        self.channel_mixer = channel_mixer;
+        self
+    }
+    /// Inverts the rendered images of negatives into positives before the white balance, which
+    /// is left out, off by default
+    pub fn with_negative_inversion(mut self, negative_inversion: NegativeInversion) -> Self {
+        self.negative_inversion = Some(negative_inversion);
+        self
+    }
+    /// Renders the images in black and white by the weights of the channels in linear light
+    /// after the color matrix, without the saturation and the vibrance, in color by default
+    pub fn with_monochrome(mut self, monochrome: Monochrome) -> Self {
+        self.monochrome = Some(monochrome);
+        self
+    }
+    /// Sharpens the rendered images by an unsharp mask on their luma after the transfer
+    /// function, off by default
+    pub fn with_sharpen(mut self, sharpen: Sharpen) -> Self {
+        self.sharpen = Some(sharpen);
+        self
+    }
+    /// Converts the colors of the DNG files by their single ColorMatrix, instead of the
+    /// matrices of both illuminants interpolated for the white balance, off by default
+    pub fn with_simple_color(mut self, simple_color: bool) -> Self {
+        self.simple_color = simple_color;
+        self
+    }
+    /// The gammas of the red, green and blue channels after the color matrix instead of the
+    /// gamma, as the exponents and the slopes of their linear toes. The transfer function is
+    /// used instead of them when it is set.
+    pub fn with_channel_gammas(mut self, channel_gammas: [[f32; 2]; 3]) -> Self {
+        self.channel_gammas = Some(channel_gammas);
+        self
+    }
+}
+
+/// The options of rendering the images as the `Output`, its type, crop and rotation are left
+/// to the caller
+impl<'a> From<&'a Output> for Options<'a> {
+    fn from(output: &'a Output) -> Self {
+        let gammas = output.gamma.channels();
+        Options {
+            gamma: gammas[1][0],
+            color_space: &output.color_space,
+            no_demosaicing: output.demosaicing_method == DemosaicingMethod::None,
+            apply_opcodes: output.apply_opcodes,
+            pads_14bit: output.pads_14bit,
+            demosaicing_method: output.demosaicing_method,
+            white_balance: output.white_balance,
+            white_balance_method: output.white_balance_method,
+            exposure_ev: output.exposure_ev,
+            baseline_exposure: output.baseline_exposure,
+            auto_brightness: output.auto_brightness,
+            highlight_mode: output.highlight_mode,
+            white_level: output.white_level,
+            tone_mapping: output.tone_mapping,
+            transfer_function: output.transfer_function,
+            channel_gammas: Some(gammas),
+            tone_curve: output.tone_curve.clone(),
+            lut3d: output.lut3d.clone(),
+            brightness: output.brightness,
+            contrast: output.contrast,
+            saturation: output.saturation,
+            vibrance: output.vibrance,
+            gamut_mapping: output.gamut_mapping,
+            channel_mixer: output.channel_mixer,
+            negative_inversion: output.negative_inversion,
+            monochrome: output.monochrome,
+            sharpen: output.sharpen,
+            simple_color: output.simple_color,
+        }
+    }
+}
+
+/// Decodes the buffer by the options, with or without the opcodes and the padding
+fn decode_with_options(
+    buffer: Vec<u8>,
+    options: &Options,
+) -> Result<decode::DecodedImage, RawFileReadingError> {
+    let mut decoded_image = decode::decode_buffer_with_opcodes(buffer, options.apply_opcodes)?;
+    if options.pads_14bit {
+        decoded_image.pad_to_14bit();
+    }
+    Ok(decoded_image)
+}
+
+/// The lookup table of the tone mapping and the transfer function for the linear values
+/// multiplied by `2^exposure_ev`, the gamma of the options is a pure power curve
+fn gen_transfer_lut(options: &Options, exposure_ev: f32) -> [u16; 65536] {
+    let transfer_function = output_transfer_function(options);
+    let curve = transfer_curve(transfer_function);
+    let encode = |l| encode_transfer(l, transfer_function, &curve);
+    gen_tone_mapped_transfer_lut(encode, exposure_ev, options.tone_mapping)
+}
+
+/// The transfer function of the rendered images, the linear one when the tone curve replaces it.
+/// The gamma of green is shared by the channels until their own gammas are applied.
+fn output_transfer_function(options: &Options) -> TransferFunction {
+    match options.transfer_function {
+        _ if matches!(options.tone_curve, Some((_, true))) => TransferFunction::Linear,
+        None => TransferFunction::PowerCurve(
+            options
+                .channel_gammas
+                .map_or([options.gamma, 0.0], |gammas| gammas[1]),
+        ),
+        Some(transfer_function) => transfer_function,
+    }
+}
+
+/// The lookup tables from the curve shared by the channels to their own gammas, none when the
+/// gammas are not used or the channels share one
+fn gen_channel_gamma_luts(options: &Options) -> Result<Option<[Vec<u16>; 3]>, RawFileReadingError> {
+    if options.transfer_function.is_some() || matches!(options.tone_curve, Some((_, true))) {
+        return Ok(None);
+    }
+    let gammas = options.channel_gammas.unwrap_or([[options.gamma, 0.0]; 3]);
+    let invalid = gammas
+        .into_iter()
+        .find(|&[exponent, _]| !(exponent.is_finite() && exponent > 0.0));
+    if let Some(gamma) = invalid {
+        return Err(RawFileReadingError::InvalidGamma(gamma));
+    }
+    // the channels of the monochrome rendering share the gamma of green to stay gray
+    if options.monochrome.is_some() || gammas.iter().all(|&gamma| gamma == gammas[1]) {
+        return Ok(None);
+    }
+    let shared = gen_power_curve(gammas[1][0], gammas[1][1]);
+    Ok(Some(gammas.map(|[exponent, toe_slope]| {
+        gen_curve_conversion_lut(&shared, &gen_power_curve(exponent, toe_slope))
+    })))
+}
+
+/// The contrast curve of the options, none when it keeps the values
+fn contrast_lut(options: &Options) -> Option<Vec<u16>> {
+    if options.contrast == 0.0 {
+        return None;
+    }
+    // middle gray is the encoded value of 18% gray
+    let transfer_function = output_transfer_function(options);
+    let middle_gray = encode_transfer(0.18, transfer_function, &transfer_curve(transfer_function));
+    Some(gen_contrast_lut(options.contrast, middle_gray))
+}
+
+/// The brightness of the options in EV, it is a gain of the linear values like the exposure
+/// compensation. The gains of 0 and below render black.
+fn brightness_ev(options: &Options) -> f32 {
+    options.brightness.max(f32::MIN_POSITIVE).log2()
+}
+
+/// The linear values of the encoded ones for the vibrance, decoded by the power curve of the
+/// transfer function, none when the vibrance is not used
+fn gen_linear_lut(options: &Options) -> Vec<f32> {
+    if options.vibrance == 0.0 {
+        return vec![];
+    }
+    let curve = transfer_curve(output_transfer_function(options));
+    (0..=u16::MAX)
+        .map(|i| decode_curve(i as f32 / 65535., &curve))
+        .collect()
+}
+
+/// The multipliers of the white balance in `1 << BIT_SHIFT`, none when it is skipped. The
+/// custom multipliers and the presets are normalized to green like the ones of the camera, the
+/// presets are estimated from the ColorMatrix or else the `cam_matrix` of the model. The
+/// automatic white balance is estimated before, it is the one as shot when nothing was left to
+/// estimate from.
+fn white_balance_multipliers(
+    white_balance: WhiteBalance,
+    as_shot: [i32; 3],
+    color_matrix: Option<&[f32; 9]>,
+    cam_matrix: Option<&[f32; 9]>,
+) -> Result<Option<[i32; 3]>, RawFileReadingError> {
+    let multipliers = match white_balance {
+        WhiteBalance::AsShot | WhiteBalance::Auto => {
+            let shift = BIT_SHIFT - utility::log2(as_shot[1]);
+            return Ok(Some(as_shot.mul(1 << shift)));
+        }
+        WhiteBalance::None => return Ok(None),
+        WhiteBalance::Custom(multipliers) => multipliers,
+        WhiteBalance::Daylight | WhiteBalance::Cloudy | WhiteBalance::Tungsten => {
+            let preset = |cam_matrix| {
+                let as_shot = as_shot.map(|x| x as f32);
+                data::white_balance_preset_from_cam_matrix(cam_matrix, as_shot, white_balance)
+            };
+            data::white_balance_preset(color_matrix, white_balance)
+                .or_else(|| cam_matrix.and_then(preset))
+                .ok_or(RawFileReadingError::WhiteBalanceNeedsColorMatrix(white_balance))?
+        }
+        WhiteBalance::Temperature { kelvin, tint } => color_matrix
+            .map(|color_matrix| data::temperature_white_balance(color_matrix, kelvin, tint))
+            .ok_or(RawFileReadingError::WhiteBalanceNeedsColorMatrix(white_balance))?,
+    };
+    if !multipliers.iter().all(|x| x.is_finite() && *x > 0.0) {
+        return Err(RawFileReadingError::InvalidWhiteBalance(multipliers));
+    }
+    let scale = (1 << BIT_SHIFT) as f32 / multipliers[1];
+    Ok(Some(multipliers.map(|x| (x * scale).round() as i32)))
+}
+
+/// The X-Trans images are only demosaiced linearly or by the methods which are only for them.
+/// The images are demosaiced before any crop, at the origin of their pattern.
+fn check_demosaicing_method(
+    options: &Options,
+    cfa_pattern: CFAPattern,
+) -> Result<(), RawFileReadingError> {
+    let is_xtrans = matches!(cfa_pattern, CFAPattern::XTrans0 | CFAPattern::XTrans1);
+    let is_bayer = matches!(
+        cfa_pattern,
+        CFAPattern::RGGB | CFAPattern::GRBG | CFAPattern::GBRG | CFAPattern::BGGR
+    );
+    let is_supported = match options.demosaicing_method {
+        DemosaicingMethod::SuperPixel
+        | DemosaicingMethod::AHD
+        | DemosaicingMethod::VNG
+        | DemosaicingMethod::DCB { .. }
+        | DemosaicingMethod::RCD
+        | DemosaicingMethod::LMMSE
+        | DemosaicingMethod::PPG => !is_xtrans,
+        #[cfg(feature = "amaze")]
+        DemosaicingMethod::AMaZE => !is_xtrans,
+        DemosaicingMethod::Markesteijn { .. } | DemosaicingMethod::XTransFast => !is_bayer,
+        _ => true,
+    };
+    if is_supported || options.no_demosaicing {
+        Ok(())
+    } else {
+        Err(RawFileReadingError::DemosaicingNotSupported(
+            options.demosaicing_method,
+            cfa_pattern,
+        ))
+    }
+}
+
+/// SuperPixel bins the 2x2 cells of a bayer image into the interleaved RGB pixels of an image
+/// of half the width and the height
+fn super_pixel(
+    image: Vec<u16>,
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+    options: &Options,
+) -> (Vec<u16>, usize, usize, CFAPattern) {
+    if options.demosaicing_method != DemosaicingMethod::SuperPixel || options.no_demosaicing {
+        return (image, width, height, cfa_pattern);
+    }
+    match pass::super_pixel(&image, width, height, cfa_pattern) {
+        Some((image, width, height)) => (image, width, height, CFAPattern::None),
+        None => (image, width, height, cfa_pattern),
+    }
+}
+
+/// Demosaics the `image` by the method of the `options` and goes on with the passes of `tail`,
+/// the pixels are collected at last
+macro_rules! demosaic {
+    ($image:ident, $width:ident, $height:ident, $cfa_pattern:ident, $options:ident, $($tail:tt)*) => {{
+        let iter = $image.iter().copied();
+        pass::iters_to_vec! (
+            iter
+                ..enumerate()
+                [($options.no_demosaicing, $options.demosaicing_method, $cfa_pattern)] {
+                    (_, _, CFAPattern::None) => .rgb(&$image),
+                    (_, _, CFAPattern::Monochrome) => .none(),
+                    (true, _, _) => .none(),
+                    (false, DemosaicingMethod::Passthrough, _) => .none(),
+                    (false, DemosaicingMethod::AHD, _) => .ahd(&$image, $width, $height, $cfa_pattern),
+                    (false, DemosaicingMethod::RCD, _) => .rcd(&$image, $width, $height, $cfa_pattern),
+                    (false, DemosaicingMethod::LMMSE, _) => .lmmse(&$image, $width, $height, $cfa_pattern),
+                    (false, DemosaicingMethod::PPG, _) => .ppg(&$image, $width, $height, $cfa_pattern),
+                    #[cfg(feature = "amaze")]
+                    (false, DemosaicingMethod::AMaZE, _) => .amaze(&$image, $width, $height, $cfa_pattern),
+                    (false, DemosaicingMethod::DCB { iterations, refine }, _) => .dcb(&$image, $width, $height, $cfa_pattern, iterations, refine),
+                    (false, DemosaicingMethod::Markesteijn { passes }, _) => .markesteijn(&$image, $width, $height, $cfa_pattern, passes, (0, 0)),
+                    (false, DemosaicingMethod::XTransFast, _) => .xtrans_fast(&$image, $width, $height, $cfa_pattern),
+                    (false, DemosaicingMethod::VNG, CFAPattern::RGGB) => .vng_rggb(&$image, $width, $height),
+                    (false, DemosaicingMethod::VNG, CFAPattern::GRBG) => .vng_grbg(&$image, $width, $height),
+                    (false, DemosaicingMethod::VNG, CFAPattern::GBRG) => .vng_gbrg(&$image, $width, $height),
+                    (false, DemosaicingMethod::VNG, CFAPattern::BGGR) => .vng_bggr(&$image, $width, $height),
+                    (false, DemosaicingMethod::LinearMHC, CFAPattern::RGGB) => .mhc_rggb(&$image, $width, $height),
+                    (false, DemosaicingMethod::LinearMHC, CFAPattern::GRBG) => .mhc_grbg(&$image, $width, $height),
+                    (false, DemosaicingMethod::LinearMHC, CFAPattern::GBRG) => .mhc_gbrg(&$image, $width, $height),
+                    (false, DemosaicingMethod::LinearMHC, CFAPattern::BGGR) => .mhc_bggr(&$image, $width, $height),
+                    (false, DemosaicingMethod::LinearDirected, CFAPattern::RGGB) => .directed_rggb(&$image, $width, $height),
+                    (false, DemosaicingMethod::LinearDirected, CFAPattern::GRBG) => .directed_grbg(&$image, $width, $height),
+                    (false, DemosaicingMethod::LinearDirected, CFAPattern::GBRG) => .directed_gbrg(&$image, $width, $height),
+                    (false, DemosaicingMethod::LinearDirected, CFAPattern::BGGR) => .directed_bggr(&$image, $width, $height),
+                    (false, _, CFAPattern::RGGB) => .linear_rggb(&$image, $width, $height),
+                    (false, _, CFAPattern::GRBG) => .linear_grbg(&$image, $width, $height),
+                    (false, _, CFAPattern::GBRG) => .linear_gbrg(&$image, $width, $height),
+                    (false, _, CFAPattern::BGGR) => .linear_bggr(&$image, $width, $height),
+                    (false, _, CFAPattern::XTrans0) => .linear_xtrans0(&$image, $width, $height),
+                    (false, _, CFAPattern::XTrans1) => .linear_xtrans1(&$image, $width, $height)
+                }
+                $($tail)*
+        )
+    }};
+}
+
+pub fn load_cfa_image_from_file(
+    path: &str,
+    options: Options,
+) -> Result<(Vec<u16>, usize, usize, CFAPattern), RawFileReadingError> {
+    let buffer = decode::get_buffer_from_file(path)?;
+    load_cfa_image_from_buffer(buffer, options)
+}
+
+/// The samples of the sensor as a single channel of `width * height` with their CFA pattern,
+/// neither demosaiced, white balanced nor color converted. The images which are RGB already
+/// keep their interleaved samples and come with `CFAPattern::None`.
+pub fn load_cfa_image_from_buffer(
+    buffer: Vec<u8>,
+    options: Options,
+) -> Result<(Vec<u16>, usize, usize, CFAPattern), RawFileReadingError> {
+    let decoded_image = decode_with_options(buffer, &options)?;
+    let image = decoded_image.image;
+    let width = decoded_image.width;
+    let height = decoded_image.height;
+
+    let cfa_pattern = if image.len() == width * height * 3 {
+        CFAPattern::None
+    } else {
+        decoded_image.cfa_pattern
+    };
+    Ok((image, width, height, cfa_pattern))
 }
 
 pub fn load_image_from_file(
@@ -39,27 +527,19 @@ pub fn load_origin_image_from_buffer(
     buffer: Vec<u8>,
     options: Options,
 ) -> Result<(Vec<u16>, usize, usize), RawFileReadingError> {
-    let decoded_image = decode::decode_buffer(buffer)?;
+    let decoded_image = decode_with_options(buffer, &options)?;
 
     let image = decoded_image.image;
     let width = decoded_image.width;
     let height = decoded_image.height;
 
-    let iter = image.iter().copied();
-    let data = pass::iters_to_vec! (
-        iter
-            ..enumerate()
-            [(options.no_demosaicing, decoded_image.cfa_pattern)] {
-                (true, _) => .none(),
-                (false, CFAPattern::RGGB) => .linear_rggb(&image, width, height),
-                (false, CFAPattern::GRBG) => .linear_grbg(&image, width, height),
-                (false, CFAPattern::GBRG) => .linear_gbrg(&image, width, height),
-                (false, CFAPattern::BGGR) => .linear_bggr(&image, width, height),
-                (false, CFAPattern::XTrans0) => .linear_xtrans0(&image, width, height),
-                (false, CFAPattern::XTrans1) => .linear_xtrans1(&image, width, height)
-            }
-            .u16rgb_to_u16rgba()
-            ..flatten()
+    let cfa_pattern = decoded_image.cfa_pattern;
+    check_demosaicing_method(&options, cfa_pattern)?;
+    let (image, width, height, cfa_pattern) =
+        super_pixel(image, width, height, cfa_pattern, &options);
+    let data = demosaic!(image, width, height, cfa_pattern, options,
+        .u16rgb_to_u16rgba()
+        ..flatten()
     );
 
     Ok((data, width, height))
@@ -69,44 +549,509 @@ pub fn load_image_from_buffer(
     buffer: Vec<u8>,
     options: Options,
 ) -> Result<(Vec<u16>, usize, usize), RawFileReadingError> {
-    let decoded_image = decode::decode_buffer(buffer)?;
+    let (data, width, height, _) = load_image_with_info_from_buffer(buffer, options)?;
+    Ok((data, width, height))
+}
 
-    let color_matrix = utility::matrix3_mul(options.color_space, &decoded_image.cam_matrix);
-    let color_matrix = color_matrix.mul(1 << BIT_SHIFT);
+/// What was chosen for the rendering of an image, to be logged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderInfo {
+    /// The multipliers of the white balance which were applied, relative to green, they are
+    /// all 1 when the white balance is skipped
+    pub white_balance: [f32; 3],
+    /// The exposure in EV added by the automatic brightness, on top of the one of the options
+    pub auto_brightness_ev: f32,
+    /// The white level the samples were normalized to and clipped at
+    pub white_level: u16,
+    /// The numbers the rendering was resolved to
+    pub parameters: RenderParameters,
+}
+
+/// The numbers the rendering of an image is resolved to from the options and the file, e.g.
+/// to reproduce it elsewhere or to write them into a sidecar
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderParameters {
+    /// The multipliers of the white balance relative to green, they are all 1 when the white
+    /// balance is skipped
+    pub white_balance: [f32; 3],
+    /// The matrix from the white balanced colors of the camera to the color space of the
+    /// output, in rows
+    pub color_matrix: [f32; 9],
+    /// The black levels of the positions of the 2x2 CFA block, which were subtracted from the
+    /// samples by the decoder
+    pub black_level: [u16; 4],
+    /// The white level the samples are normalized to and clipped at
+    pub white_level: u16,
+    /// The crop of the file in the rendered image, which is halved by SuperPixel
+    pub crop: Option<Crop>,
+    pub orientation: Orientation,
+}
 
-    let white_balance = decoded_image
-        .white_balance
-        .mul(1 << (BIT_SHIFT - utility::log2(decoded_image.white_balance[1])));
+/// The images which are decoded to RGB by the decoder are not rendered any further
+fn is_rendered(decoded_image: &decode::DecodedImage) -> bool {
+    let len = decoded_image.width * decoded_image.height * 3;
+    decoded_image.image.len() == len && !matches!(decoded_image.cfa_pattern, CFAPattern::None)
+}
 
-    let gamma_lut = gen_gamma_lut(options.gamma);
+/// Monochrome images and the sensor values passed through stay gray, they are neither white
+/// balanced nor color converted
+fn is_monochrome(decoded_image: &decode::DecodedImage, options: &Options) -> bool {
+    matches!(decoded_image.cfa_pattern, CFAPattern::Monochrome)
+        || (options.demosaicing_method == DemosaicingMethod::Passthrough
+            && !matches!(decoded_image.cfa_pattern, CFAPattern::None))
+}
 
-    let image = decoded_image.image;
+/// The white level of the options the samples are normalized to
+fn resolve_white_level(decoded_image: &decode::DecodedImage, options: &Options) -> u16 {
+    match options.white_level {
+        WhiteLevel::AsShot => decoded_image.white_level,
+        WhiteLevel::Auto => {
+            pass::detect_white_level(&decoded_image.image).unwrap_or(decoded_image.white_level)
+        }
+        WhiteLevel::Custom(white_level) => white_level,
+    }
+    .max(1)
+}
+
+/// The multipliers of the white balance in `1 << BIT_SHIFT`, which are all 1 when it is
+/// skipped, and the color matrix of the rendering. The automatic white balance is measured on
+/// the `demosaiced` image.
+fn resolve_color(
+    decoded_image: &decode::DecodedImage,
+    options: &Options,
+    demosaiced: Option<&[[u16; 3]]>,
+) -> Result<([i32; 3], [f32; 9]), RawFileReadingError> {
+    let is_monochrome = is_monochrome(decoded_image, options);
+    let white_balance = match demosaiced {
+        _ if is_monochrome => WhiteBalance::None,
+        Some(demosaiced) if options.white_balance == WhiteBalance::Auto => {
+            pass::gray_world(demosaiced).map_or(WhiteBalance::AsShot, WhiteBalance::Custom)
+        }
+        _ => options.white_balance,
+    };
+    let white_balance = white_balance_multipliers(
+        white_balance,
+        decoded_image.white_balance,
+        decoded_image.color_matrix.as_ref(),
+        // the generic raws have no matrix of their model
+        (!decoded_image.is_generic_fallback).then_some(&decoded_image.cam_matrix),
+    )?;
+    // the inversion of negatives balances the channels on its own
+    let skips_white_balance = white_balance.is_none() || options.negative_inversion.is_some();
+    let white_balance = match white_balance {
+        Some(white_balance) if !skips_white_balance => white_balance,
+        _ => [1 << BIT_SHIFT; 3],
+    };
+
+    // the DNG color pipeline maps the camera colors for the white of the white balance
+    let dng_color = match options.simple_color {
+        false => color::DngColor::from_info(&decoded_image.parsed_info),
+        true => None,
+    };
+    let neutral = white_balance.map(|x| (1 << BIT_SHIFT) as f32 / x as f32);
+    let color_matrix = match dng_color {
+        _ if is_monochrome => data::XYZ2RAW,
+        Some(dng_color) => {
+            utility::matrix3_mul(options.color_space, &dng_color.cam_matrix(neutral))
+        }
+        None if options.white_balance_method == WhiteBalanceMethod::XyzBradford => {
+            let cam_matrix = color::bradford_cam_matrix(&decoded_image.cam_matrix, neutral);
+            utility::matrix3_mul(options.color_space, &cam_matrix)
+        }
+        None => utility::matrix3_mul(options.color_space, &decoded_image.cam_matrix),
+    };
+    // the mixer is not a colorimetric transform, it mixes the channels of the color space
+    let color_matrix = match options.channel_mixer == data::CHANNEL_MIXER_IDENTITY {
+        true => color_matrix,
+        false => utility::matrix3_mul(&check_channel_mixer(options)?, &color_matrix),
+    };
+    let color_matrix = match options.monochrome {
+        Some(monochrome) => utility::matrix3_mul(&monochrome_matrix(monochrome)?, &color_matrix),
+        None => color_matrix,
+    };
+    Ok((white_balance, color_matrix))
+}
+
+// the coefficients of the channel mixer are from -4 to 4
+const MAX_CHANNEL_MIXER: f32 = 4.0;
+
+/// The channel mixer of the options, whose coefficients need to be finite and in the range
+fn check_channel_mixer(options: &Options) -> Result<[f32; 9], RawFileReadingError> {
+    let channel_mixer = options.channel_mixer;
+    match channel_mixer.iter().all(|x| x.abs() <= MAX_CHANNEL_MIXER) {
+        true => Ok(channel_mixer),
+        false => Err(RawFileReadingError::InvalidChannelMixer(channel_mixer)),
+    }
+}
+
+/// The sharpening of the options, which needs a positive radius and a finite amount
+fn check_sharpen(options: &Options) -> Result<Option<Sharpen>, RawFileReadingError> {
+    match options.sharpen {
+        Some(sharpen)
+            if !(sharpen.radius.is_finite() && sharpen.radius > 0.0)
+                || !sharpen.amount.is_finite() =>
+        {
+            Err(RawFileReadingError::InvalidSharpen(sharpen))
+        }
+        sharpen => Ok(sharpen),
+    }
+}
+
+/// The matrix which turns every channel into the mix of the normalized weights
+fn monochrome_matrix(monochrome: Monochrome) -> Result<[f32; 9], RawFileReadingError> {
+    let weights = monochrome.weights;
+    let sum: f32 = weights.iter().sum();
+    if !(sum.is_finite() && sum > 0.0) {
+        return Err(RawFileReadingError::InvalidMonochromeWeights(weights));
+    }
+    let [r, g, b] = weights.map(|x| x / sum);
+    Ok([r, g, b, r, g, b, r, g, b])
+}
+
+/// The parameters of the rendering, the crop is halved when the image was binned by SuperPixel
+fn gen_render_parameters(
+    decoded_image: &decode::DecodedImage,
+    white_balance: [i32; 3],
+    color_matrix: [f32; 9],
+    white_level: u16,
+    is_binned: bool,
+) -> RenderParameters {
+    RenderParameters {
+        white_balance: white_balance.map(|x| x as f32 / (1 << BIT_SHIFT) as f32),
+        color_matrix,
+        black_level: decoded_image.black_level,
+        white_level,
+        crop: rendered_crop(decoded_image, is_binned),
+        orientation: decoded_image.orientation,
+    }
+}
+
+/// The crop of the file in the rendered image, which is halved when it was binned by SuperPixel
+fn rendered_crop(decoded_image: &decode::DecodedImage, is_binned: bool) -> Option<Crop> {
+    match (&decoded_image.crop, is_binned) {
+        (Some(crop), true) => Some(crop.halved()),
+        (crop, _) => *crop,
+    }
+}
+
+// the film base is sampled from the pixels up to 2 away from the point
+const FILM_BASE_RADIUS: usize = 2;
+
+/// The film base of the negative inversion in the samples of the demosaiced image of `width`
+fn film_base(
+    negative_inversion: NegativeInversion,
+    pixels: &[[u16; 3]],
+    (width, height): (usize, usize),
+    crop: Option<Crop>,
+    orientation: Orientation,
+    white_level: u16,
+) -> Result<[f32; 3], RawFileReadingError> {
+    let base = match negative_inversion {
+        NegativeInversion::Auto { sample: None } => pass::estimate_film_base(pixels, white_level),
+        NegativeInversion::Auto { sample: Some(point) } => {
+            unorient_point(point, (width, height), crop, orientation).and_then(|point| {
+                pass::patch_mean(pixels, width, point, FILM_BASE_RADIUS, white_level)
+            })
+        }
+        NegativeInversion::Manual { base_rgb } => Some(base_rgb.map(|x| x * white_level as f32)),
+    };
+    base.filter(|base| base.iter().all(|x| x.is_finite() && *x > 0.0))
+        .ok_or(RawFileReadingError::InvalidFilmBase)
+}
+
+/// The point of the image as it is shown, cropped by the crop and rotated by the orientation,
+/// in the image of `width` and `height` before them. None when it is outside of the crop.
+fn unorient_point(
+    (x, y): (usize, usize),
+    (width, height): (usize, usize),
+    crop: Option<Crop>,
+    orientation: Orientation,
+) -> Option<(usize, usize)> {
+    let (left, top, width, height) = match crop {
+        Some(crop) => (crop.x as usize, crop.y as usize, crop.width as usize, crop.height as usize),
+        None => (0, 0, width, height),
+    };
+    let (x, y) = match orientation {
+        Orientation::Horizontal => (x, y),
+        Orientation::Rotate90 => (y, height.checked_sub(x + 1)?),
+        Orientation::Rotate180 => (width.checked_sub(x + 1)?, height.checked_sub(y + 1)?),
+        Orientation::Rotate270 => (width.checked_sub(y + 1)?, x),
+    };
+    (x < width && y < height).then_some((left + x, top + y))
+}
+
+pub fn render_parameters_from_file(
+    path: &str,
+    options: Options,
+) -> Result<RenderParameters, RawFileReadingError> {
+    let buffer = decode::get_buffer_from_file(path)?;
+    render_parameters_from_buffer(buffer, options)
+}
+
+/// The parameters `load_image_with_info_from_buffer` renders the image by, without rendering
+/// it. The image is only demosaiced to measure the automatic white balance.
+pub fn render_parameters_from_buffer(
+    buffer: Vec<u8>,
+    options: Options,
+) -> Result<RenderParameters, RawFileReadingError> {
+    let mut decoded_image = decode_with_options(buffer, &options)?;
+    if is_rendered(&decoded_image) {
+        let white_level = decoded_image.white_level;
+        let white_balance = [1 << BIT_SHIFT; 3];
+        let parameters =
+            gen_render_parameters(&decoded_image, white_balance, data::XYZ2RAW, white_level, false);
+        return Ok(parameters);
+    }
+
+    let white_level = resolve_white_level(&decoded_image, &options);
+    let cfa_pattern = decoded_image.cfa_pattern;
+    check_demosaicing_method(&options, cfa_pattern)?;
+    let image = std::mem::take(&mut decoded_image.image);
+    let (width, height) = (decoded_image.width, decoded_image.height);
+    let (image, width, height, cfa_pattern) =
+        super_pixel(image, width, height, cfa_pattern, &options);
+    let is_binned = cfa_pattern != decoded_image.cfa_pattern;
+
+    let is_auto_white_balance = options.white_balance == WhiteBalance::Auto
+        && !is_monochrome(&decoded_image, &options);
+    let demosaiced = match is_auto_white_balance {
+        true => Some(demosaic!(image, width, height, cfa_pattern, options,)),
+        false => None,
+    };
+    let (white_balance, color_matrix) =
+        resolve_color(&decoded_image, &options, demosaiced.as_deref())?;
+    Ok(gen_render_parameters(
+        &decoded_image,
+        white_balance,
+        color_matrix,
+        white_level,
+        is_binned,
+    ))
+}
+
+pub fn pick_white_balance_from_file(
+    path: &str,
+    options: Options,
+    point: (usize, usize),
+    radius: usize,
+) -> Result<[f32; 3], RawFileReadingError> {
+    let buffer = decode::get_buffer_from_file(path)?;
+    pick_white_balance_from_buffer(buffer, options, point, radius)
+}
+
+/// The multipliers of `WhiteBalance::Custom` which render the patch around the point neutral,
+/// e.g. where a neutral area is clicked. The point is in the image as it is shown, cropped by
+/// the crop of the file and rotated by its orientation, and the patch of the pixels up to
+/// `radius` away is measured on the demosaiced image before the white balance without the
+/// clipped pixels. The images which are not white balanced are left neutral.
+pub fn pick_white_balance_from_buffer(
+    buffer: Vec<u8>,
+    options: Options,
+    (x, y): (usize, usize),
+    radius: usize,
+) -> Result<[f32; 3], RawFileReadingError> {
+    let mut decoded_image = decode_with_options(buffer, &options)?;
+    if is_rendered(&decoded_image) || is_monochrome(&decoded_image, &options) {
+        return Ok([1.0; 3]);
+    }
+
+    let white_level = resolve_white_level(&decoded_image, &options);
+    let cfa_pattern = decoded_image.cfa_pattern;
+    check_demosaicing_method(&options, cfa_pattern)?;
+    let image = std::mem::take(&mut decoded_image.image);
+    let (width, height) = (decoded_image.width, decoded_image.height);
+    let (image, width, height, cfa_pattern) =
+        super_pixel(image, width, height, cfa_pattern, &options);
+    let crop = rendered_crop(&decoded_image, cfa_pattern != decoded_image.cfa_pattern);
+
+    let demosaiced = demosaic!(image, width, height, cfa_pattern, options,);
+    unorient_point((x, y), (width, height), crop, decoded_image.orientation)
+        .and_then(|point| pass::patch_white_balance(&demosaiced, width, point, radius, white_level))
+        .ok_or(RawFileReadingError::InvalidWhiteBalancePoint(x, y))
+}
+
+pub fn load_image_with_info_from_file(
+    path: &str,
+    options: Options,
+) -> Result<(Vec<u16>, usize, usize, RenderInfo), RawFileReadingError> {
+    let buffer = decode::get_buffer_from_file(path)?;
+    load_image_with_info_from_buffer(buffer, options)
+}
+
+/// Renders the image like `load_image_from_buffer` and comes with the info of the rendering
+pub fn load_image_with_info_from_buffer(
+    buffer: Vec<u8>,
+    options: Options,
+) -> Result<(Vec<u16>, usize, usize, RenderInfo), RawFileReadingError> {
+    let mut decoded_image = decode_with_options(buffer, &options)?;
+    // the tone curve of the file replaces the transfer function, like the opcodes it is left
+    // out for the untouched data
+    let uses_file_curve = options.apply_opcodes
+        && options.tone_curve.is_none()
+        && options.transfer_function.is_none();
+    let options = match &decoded_image.tone_curve {
+        Some(tone_curve) if uses_file_curve => options.with_tone_curve(tone_curve.clone(), true),
+        _ => options,
+    };
+
+    let is_monochrome = is_monochrome(&decoded_image, &options);
     let width = decoded_image.width;
     let height = decoded_image.height;
 
-    if image.len() == width * height * 3 {
-        return Ok((image, width, height));
-    }
-
-    let iter = image.iter().copied();
-    let data = pass::iters_to_vec! (
-        iter
-            ..enumerate()
-            [(options.no_demosaicing, decoded_image.cfa_pattern)] {
-                (true, _) => .none(),
-                (false, CFAPattern::RGGB) => .linear_rggb(&image, width, height),
-                (false, CFAPattern::GRBG) => .linear_grbg(&image, width, height),
-                (false, CFAPattern::GBRG) => .linear_gbrg(&image, width, height),
-                (false, CFAPattern::BGGR) => .linear_bggr(&image, width, height),
-                (false, CFAPattern::XTrans0) => .linear_xtrans0(&image, width, height),
-                (false, CFAPattern::XTrans1) => .linear_xtrans1(&image, width, height)
-            }
-            .gamma_correct(&gamma_lut)
+    if is_rendered(&decoded_image) {
+        let white_level = decoded_image.white_level;
+        let white_balance = [1 << BIT_SHIFT; 3];
+        let parameters =
+            gen_render_parameters(&decoded_image, white_balance, data::XYZ2RAW, white_level, false);
+        let info = RenderInfo {
+            white_balance: [1.0; 3],
+            auto_brightness_ev: 0.0,
+            white_level,
+            parameters,
+        };
+        return Ok((decoded_image.image, width, height, info));
+    }
+
+    // the samples are normalized so the white level renders like the one of the file
+    let white_level = resolve_white_level(&decoded_image, &options);
+
+    let cfa_pattern = decoded_image.cfa_pattern;
+    check_demosaicing_method(&options, cfa_pattern)?;
+    let image = std::mem::take(&mut decoded_image.image);
+    let (image, width, height, cfa_pattern) =
+        super_pixel(image, width, height, cfa_pattern, &options);
+    let is_binned = cfa_pattern != decoded_image.cfa_pattern;
+
+    // the automatic white balance and brightness are measured on the demosaiced image and the
+    // highlights are reconstructed on it, which is kept for the passes after them
+    let is_auto_white_balance = options.white_balance == WhiteBalance::Auto && !is_monochrome;
+    let is_reconstructing = options.highlight_mode == HighlightMode::Reconstruct && !is_monochrome;
+    let is_inverting = options.negative_inversion.is_some();
+    let mut demosaiced = if is_auto_white_balance
+        || is_reconstructing
+        || is_inverting
+        || options.auto_brightness.is_some()
+    {
+        Some(demosaic!(image, width, height, cfa_pattern, options,))
+    } else {
+        None
+    };
+    if let (Some(negative_inversion), Some(demosaiced)) =
+        (options.negative_inversion, &mut demosaiced)
+    {
+        let crop = rendered_crop(&decoded_image, is_binned);
+        let base = film_base(
+            negative_inversion,
+            demosaiced,
+            (width, height),
+            crop,
+            decoded_image.orientation,
+            white_level,
+        )?;
+        pass::invert_negative(demosaiced, base, white_level);
+    }
+    let (white_balance, color_matrix) =
+        resolve_color(&decoded_image, &options, demosaiced.as_deref())?;
+    let parameters =
+        gen_render_parameters(&decoded_image, white_balance, color_matrix, white_level, is_binned);
+    let color_matrix = color_matrix.mul(1 << BIT_SHIFT);
+
+    if is_reconstructing {
+        if let Some(demosaiced) = &mut demosaiced {
+            pass::reconstruct_highlights(demosaiced, width, height, white_level, &white_balance);
+        }
+    }
+
+    // the gains of the white balance normalize the samples too, in linear light before the
+    // color matrix
+    let gains = white_balance
+        .map(|x| (x as i64 * decoded_image.white_level as i64 / white_level as i64) as i32);
+    let applies_gains = gains != [1 << BIT_SHIFT; 3];
+
+    let auto_brightness_ev = match (&demosaiced, options.auto_brightness) {
+        (Some(demosaiced), Some(percentile)) => {
+            pass::auto_brightness_ev(demosaiced, &gains, percentile)
+        }
+        _ => 0.0,
+    };
+    let baseline_ev = match options.baseline_exposure {
+        true => decoded_image.baseline_exposure,
+        false => 0.0,
+    };
+    let exposure_ev =
+        options.exposure_ev + baseline_ev + auto_brightness_ev + brightness_ev(&options);
+    let gamma_lut = gen_transfer_lut(&options, exposure_ev);
+    let tone_lut = match &options.tone_curve {
+        Some((tone_curve, _)) => gen_tone_curve_lut(tone_curve)
+            .ok_or(RawFileReadingError::InvalidToneCurve(tone_curve.len()))?,
+        None => vec![],
+    };
+    let has_tone_curve = options.tone_curve.is_some();
+    let channel_gamma_luts = gen_channel_gamma_luts(&options)?;
+    let has_channel_gammas = channel_gamma_luts.is_some();
+    let channel_gamma_luts = channel_gamma_luts.unwrap_or_default();
+    let contrast_lut = contrast_lut(&options);
+    let has_contrast = contrast_lut.is_some();
+    let contrast_lut = contrast_lut.unwrap_or_default();
+    let saturation = options.saturation;
+    let gamut_mapping = options.gamut_mapping;
+    let vibrance = options.vibrance;
+    let linear_lut = gen_linear_lut(&options);
+    let has_saturation = (saturation != 1.0 || vibrance != 0.0) && options.monochrome.is_none();
+    let lut3d = options.lut3d.as_ref();
+    let sharpen = check_sharpen(&options)?;
+    let highlight_limit = match options.highlight_mode {
+        HighlightMode::Clip => gains
+            .iter()
+            .map(|&x| (white_level as i64 * x as i64) >> BIT_SHIFT)
+            .fold(u16::MAX as i64, i64::min) as i32,
+        HighlightMode::Unclip | HighlightMode::Reconstruct => u16::MAX as i32,
+    };
+
+    let mut data = match demosaiced {
+        Some(demosaiced) => {
+            let iter = demosaiced.into_iter();
+            pass::iters_to_vec! (
+                iter
+                    .u16rgb_to_i32rgb()
+                    [.white_balance_clip_fix(&gains, &highlight_limit) applies_gains]
+                    .color_convert_gamut(&color_matrix, gamut_mapping)
+                    .gamma_correct(&gamma_lut)
+                    [.channel_curve_correct(&channel_gamma_luts) has_channel_gammas]
+                    [.tone_curve_correct(&contrast_lut) has_contrast]
+                    [.saturate(saturation, vibrance, &linear_lut) has_saturation]
+                    [.tone_curve_correct(&tone_lut) has_tone_curve]
+                    [.lut3d_correct(lut3d) lut3d.is_some()]
+                    ..flatten()
+            )
+        }
+        None => demosaic!(image, width, height, cfa_pattern, options,
             .u16rgb_to_i32rgb()
-            .white_balance_fix(&white_balance)
-            .color_convert(&color_matrix)
+            [.white_balance_clip_fix(&gains, &highlight_limit) applies_gains]
+            .color_convert_gamut(&color_matrix, gamut_mapping)
+            .gamma_correct(&gamma_lut)
+            [.channel_curve_correct(&channel_gamma_luts) has_channel_gammas]
+            [.tone_curve_correct(&contrast_lut) has_contrast]
+            [.saturate(saturation, vibrance, &linear_lut) has_saturation]
+            [.tone_curve_correct(&tone_lut) has_tone_curve]
+            [.lut3d_correct(lut3d) lut3d.is_some()]
             ..flatten()
-    );
+        ),
+    };
+    if let Some(Sharpen {
+        amount,
+        radius,
+        threshold,
+    }) = sharpen
+    {
+        pass::unsharp_mask(&mut data, width, height, amount, radius, threshold);
+    }
 
-    Ok((data, width, height))
+    let info = RenderInfo {
+        white_balance: white_balance.map(|x| x as f32 / (1 << BIT_SHIFT) as f32),
+        auto_brightness_ev,
+        white_level,
+        parameters,
+    };
+    Ok((data, width, height, info))
 }
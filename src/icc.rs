@@ -0,0 +1,196 @@
+use super::*;
+use color::{bradford_adaptation, diagonal, matrix3_apply, xy_to_xyz, D50_XY, D65_XY};
+
+/// The identifier of the APP2 segments which carry the ICC profiles of JPEG images
+const JPEG_ICC_MARKER: &[u8] = b"ICC_PROFILE\0";
+/// The most profile data of an APP2 segment, after its length, the identifier and the numbers
+const JPEG_ICC_CHUNK: usize = 65535 - 2 - 14;
+/// The number of samples of the TRC curves which are not pure power curves
+const CURVE_SIZE: usize = 1024;
+/// The illuminant of the profile connection space, D50 by the ICC specification
+const PCS_ILLUMINANT: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+/// The ICC profile of the exported images, which tells the viewers their color space.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IccProfile {
+    /// A matrix/TRC profile of the color space and the transfer function of the output
+    Generated,
+    /// A profile built elsewhere, which is embedded as it is
+    Provided(Vec<u8>),
+}
+
+/// Generates a version 2 matrix/TRC profile of a display for the images in `color_space`,
+/// the matrices of `data` or custom ones, encoded by `transfer_function`. The rows of the
+/// matrices are normalized, so the white is taken as D65, or D50 for ProPhoto, and the
+/// primaries come from the matrix.
+pub fn gen_icc_profile(color_space: &[f32; 9], transfer_function: TransferFunction) -> Vec<u8> {
+    let white_xy = if color_space == &data::XYZ2PROPHOTO {
+        D50_XY
+    } else {
+        D65_XY
+    };
+    let white = xy_to_xyz(white_xy);
+    // the primaries are scaled so the white of the RGB values is the white of the space
+    let rgb_to_xyz = utility::matrix3_mul(
+        &utility::matrix3_inverse(color_space),
+        &diagonal(matrix3_apply(color_space, white)),
+    );
+    // the colorants are adapted to the profile connection space
+    let colorants = utility::matrix3_mul(&bradford_adaptation(white_xy, D50_XY), &rgb_to_xyz);
+    let colorant = |i: usize| xyz_tag([colorants[i], colorants[i + 3], colorants[i + 6]]);
+    let curve = curve_tag(transfer_function);
+    let description = format!("quickraw {}", name(color_space));
+
+    let tags = [
+        (b"desc", desc_tag(&description)),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(white)),
+        (b"rXYZ", colorant(0)),
+        (b"gXYZ", colorant(1)),
+        (b"bXYZ", colorant(2)),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    let mut profile = vec![0u8; 128];
+    profile[8..12].copy_from_slice(&0x0210_0000u32.to_be_bytes());
+    profile[12..16].copy_from_slice(b"mntr");
+    profile[16..20].copy_from_slice(b"RGB ");
+    profile[20..24].copy_from_slice(b"XYZ ");
+    for (i, value) in [2000u16, 1, 1, 0, 0, 0].iter().enumerate() {
+        profile[24 + i * 2..26 + i * 2].copy_from_slice(&value.to_be_bytes());
+    }
+    profile[36..40].copy_from_slice(b"acsp");
+    for (i, value) in PCS_ILLUMINANT.iter().enumerate() {
+        profile[68 + i * 4..72 + i * 4].copy_from_slice(&s15_fixed16(*value));
+    }
+
+    // the tag table is followed by the data of the tags, which start on 4 bytes
+    profile.extend((tags.len() as u32).to_be_bytes());
+    let mut offset = profile.len() + tags.len() * 12;
+    let mut data = vec![];
+    for (signature, tag) in tags.iter() {
+        profile.extend(*signature);
+        profile.extend((offset as u32).to_be_bytes());
+        profile.extend((tag.len() as u32).to_be_bytes());
+        let padding = (4 - tag.len() % 4) % 4;
+        data.extend(tag);
+        data.extend(vec![0u8; padding]);
+        offset += tag.len() + padding;
+    }
+    profile.extend(data);
+    let size = profile.len() as u32;
+    profile[0..4].copy_from_slice(&size.to_be_bytes());
+    profile
+}
+
+/// Embeds `profile` in a JPEG image as APP2 segments, which follow its APP0 and APP1
+/// segments of JFIF and Exif. None when it is not a JPEG or the profile needs more than 255
+/// segments.
+pub fn embed_icc_profile_in_jpeg(jpeg: &[u8], profile: &[u8]) -> Option<Vec<u8>> {
+    if !jpeg.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let count = u8::try_from(profile.chunks(JPEG_ICC_CHUNK).len()).ok()?;
+
+    let mut position = 2;
+    while let Some(&[0xff, 0xe0 | 0xe1, high, low, ..]) = jpeg.get(position..) {
+        position += 2 + u16::from_be_bytes([high, low]) as usize;
+    }
+    let position = position.min(jpeg.len());
+
+    let mut result = jpeg[..position].to_vec();
+    for (i, chunk) in profile.chunks(JPEG_ICC_CHUNK).enumerate() {
+        let len = 2 + JPEG_ICC_MARKER.len() + 2 + chunk.len();
+        result.extend([0xff, 0xe2]);
+        result.extend((len as u16).to_be_bytes());
+        result.extend(JPEG_ICC_MARKER);
+        result.extend([i as u8 + 1, count]);
+        result.extend(chunk);
+    }
+    result.extend(&jpeg[position..]);
+    Some(result)
+}
+
+/// The name of the matrices of `data`
+fn name(color_space: &[f32; 9]) -> &'static str {
+    [
+        (&data::XYZ2SRGB, "sRGB"),
+        (&data::XYZ2ADOBE_RGB, "Adobe RGB"),
+        (&data::XYZ2PROPHOTO, "ProPhoto RGB"),
+        (&data::XYZ2DISPLAY_P3, "Display P3"),
+        (&data::XYZ2REC2020, "Rec. 2020"),
+    ]
+    .iter()
+    .find(|(matrix, _)| *matrix == color_space)
+    .map_or("RGB", |(_, name)| name)
+}
+
+/// The curve from the encoded values to the linear ones, the inverse of the transfer function
+fn curve_tag(transfer_function: TransferFunction) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    let curve = pass::transfer_curve(transfer_function);
+    match transfer_function {
+        TransferFunction::Linear => tag.extend(0u32.to_be_bytes()),
+        // a pure power curve is stored as its gamma
+        TransferFunction::PowerCurve([exponent, toe_slope]) if toe_slope == 0.0 => {
+            tag.extend(1u32.to_be_bytes());
+            tag.extend(((256.0 / exponent).round() as u16).to_be_bytes());
+        }
+        _ => {
+            let encode = |l| pass::encode_transfer(l, transfer_function, &curve);
+            tag.extend((CURVE_SIZE as u32).to_be_bytes());
+            for i in 0..CURVE_SIZE {
+                let linear = decode(encode, i as f32 / (CURVE_SIZE - 1) as f32);
+                tag.extend(((linear * u16::MAX as f32).round() as u16).to_be_bytes());
+            }
+        }
+    }
+    tag
+}
+
+/// The linear value which `encode` encodes to `encoded` by bisection, the curve rises from 0
+/// to 1
+fn decode(encode: impl Fn(f32) -> f32, encoded: f32) -> f32 {
+    let (mut low, mut high) = (0f32, 1f32);
+    for _ in 0..24 {
+        let middle = (low + high) / 2.0;
+        if encode(middle) < encoded {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// The textDescriptionType of version 2, with the ASCII description only
+fn desc_tag(description: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend((description.len() as u32 + 1).to_be_bytes());
+    tag.extend(description.as_bytes());
+    tag.push(0);
+    // the empty Unicode and ScriptCode descriptions
+    tag.extend([0u8; 8 + 3 + 67]);
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+fn xyz_tag(xyz: [f32; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for value in xyz {
+        tag.extend(s15_fixed16(value));
+    }
+    tag
+}
+
+fn s15_fixed16(value: f32) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
@@ -24,32 +24,28 @@
 //! ```
 //! #### Export image
 //! ```no_run
-//! use quickraw::{data, DemosaicingMethod, Input, Output, Export, OutputType};
+//! use quickraw::{data, export, DemosaicingMethod, Output, OutputType, WhiteBalance};
 //! 
 //! let demosaicing_method = DemosaicingMethod::Linear;
 //! let color_space = data::XYZ2SRGB;
-//! let gamma = data::GAMMA_SRGB;
+//! let gamma = [data::GAMMA_SRGB, 0.0];
 //! let output_type = OutputType::Raw16;
 //! let auto_crop = false;
 //! let auto_rotate = false;
 //! 
-//! let export_job = Export::new(
-//!     Input::ByFile("sample.ARW"),
-//!     Output::new(
-//!         demosaicing_method,
-//!         color_space,
-//!         gamma,
-//!         output_type,
-//!         auto_crop,
-//!         auto_rotate,
-//!     ),
-//! ).unwrap();
+//! let output = Output::new(
+//!     demosaicing_method,
+//!     color_space,
+//!     gamma,
+//!     output_type,
+//!     auto_crop,
+//!     auto_rotate,
+//! )
+//! .with_white_balance(WhiteBalance::Daylight);
 //! 
-//! let (image, width, height) = export_job.export_16bit_image();
-//! 
-//! // or you can also export an image with quality(only works when the output type is JPEG).
-//! // notice that this function is available on feature `image` only.
-//! export_job.export_image(92).unwrap();
+//! // the output renders the image by its options
+//! let (image, width, height) =
+//!     export::load_image_from_file("sample.ARW", export::Options::from(&output)).unwrap();
 //! ```
 
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
@@ -67,10 +63,23 @@ mod utility;
 mod pass;
 mod maker;
 mod decode;
+mod color;
+mod lut3d;
+pub use lut3d::Lut3d;
+mod icc;
+pub use icc::{embed_icc_profile_in_jpeg, gen_icc_profile, IccProfile};
 pub use decode::decode_file;
 pub use decode::decode_buffer;
+pub use decode::decode_buffer_with_opcodes;
+pub use decode::decode_buffer_keeping_black_level;
+pub use decode::decode_buffer_multi;
 pub use decode::get_thumbnail;
 pub use decode::Orientation;
+pub use decode::CFAPattern;
+pub use decode::Crop;
+pub use maker::registry::register_decoder;
+pub use maker::DecodingError;
+pub use maker::RawDecoder;
 
 #[cfg(feature = "wasm-bindgen")]
 mod lib_wasm;
@@ -82,11 +91,233 @@ pub mod export;
 const BIT_SHIFT: u32 = 13u32;
 
 /// All the demosaicing method currently supported.
-#[derive(Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DemosaicingMethod {
     None,
+    /// Every 2x2 cell of a bayer sensor becomes a pixel, the image has half the width and height
     SuperPixel,
+    /// No interpolation, every sensor value becomes a gray pixel which is neither white balanced
+    /// nor color converted
+    Passthrough,
+    Linear,
+    /// Malvar-He-Cutler, the linear interpolation corrected by the gradients on bayer sensors
+    LinearMHC,
+    /// The linear interpolation with green along the smaller gradient on bayer sensors
+    LinearDirected,
+    /// Adaptive Homogeneity-Directed, sharper edges without zippering on bayer sensors
+    AHD,
+    /// Variable Number of Gradients, smooth gradients and fine details on bayer sensors
+    VNG,
+    /// Jacek Gozdz's DCB, few false colors in fine detail on bayer sensors, green is corrected
+    /// `iterations` times and `refine` adds the refinement by the ratios of the colors
+    DCB { iterations: u8, refine: bool },
+    /// Ratio Corrected Demosaicing, sharp high frequency diagonal detail on bayer sensors
+    RCD,
+    /// Linear minimum mean square error, less chroma noise on high ISO files of bayer sensors
+    LMMSE,
+    /// Patterned Pixel Grouping, a fast interpolation by the gradients which is sharper than
+    /// linear on bayer sensors, green is refined at last
+    PPG,
+    /// Aliasing Minimization and Zipper Elimination, the finest detail with few artifacts on
+    /// bayer sensors, only with the `amaze` feature
+    #[cfg(feature = "amaze")]
+    AMaZE,
+    /// Frank Markesteijn's interpolation, sharp detail with few false colors on X-Trans sensors,
+    /// more than 1 of the `passes` refines green and compares both interpolations
+    Markesteijn { passes: u8 },
+    /// A fast interpolation for the previews of X-Trans sensors, the colors follow green by the
+    /// 3x3 blocks of the pattern
+    XTransFast,
+}
+
+/// The white balance applied to the demosaiced image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WhiteBalance {
+    /// The white balance of the camera when the image was shot
+    AsShot,
+    /// The multipliers of red, green and blue relative to green like the ones of the camera,
+    /// the values beyond the white level clip the same way
+    Custom([f32; 3]),
+    /// Estimated from the demosaiced image by the gray world, leaving out the pixels near black
+    /// and the ones which may be clipped
+    Auto,
+    /// The preset for daylight, estimated from the ColorMatrix of the file or else the matrix
+    /// of the model, see `data::white_balance_preset`
+    Daylight,
+    /// The preset of the camera model for a cloudy sky
+    Cloudy,
+    /// The preset of the camera model for tungsten light
+    Tungsten,
+    /// The white of the correlated color temperature in Kelvin, shifted by the tint from the
+    /// Planckian locus toward green when it is positive, which renders magenta like in the
+    /// raw editors. It is converted to the multipliers by the ColorMatrix of the file, see
+    /// `data::temperature_white_balance`.
+    Temperature { kelvin: f32, tint: f32 },
+    /// No white balance, the values of the sensor are kept
+    None,
+}
+
+/// Where the white balance is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhiteBalanceMethod {
+    /// The channels of the camera are multiplied, which skews the hues under light far from
+    /// daylight, e.g. tungsten
+    CameraSpace,
+    /// The white is adapted to the one of the output in XYZ by the Bradford transform, on top
+    /// of the camera matrix. The DNG color pipeline adapts its colors this way already.
+    XyzBradford,
+}
+
+/// How the highlights are rendered where the sensor clips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HighlightMode {
+    /// All the channels clip at the lowest level where one of them clips after the white
+    /// balance, the clipped highlights are neutral instead of magenta
+    Clip,
+    /// The values of the sensor are kept, the channels clip at different levels after the
+    /// white balance
+    Unclip,
+    /// The clipped channels are rebuilt by the hue of the unclipped neighbors
+    Reconstruct,
+}
+
+/// The level where the sensor saturates, in the 16 bit scale of the decoded samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhiteLevel {
+    /// The white level of the file, or the maximum of its bit depth without one
+    AsShot,
+    /// Found by the plateau where the saturated samples pile up in the histogram, the one of
+    /// the file when nothing saturates
+    Auto,
+    /// Overrides the white level of the file, e.g. for the bodies saturating below it
+    Custom(u16),
+}
+
+/// How the linear values are encoded in the output.
+#[derive(Debug, Clone, Copy)]
+pub enum TransferFunction {
+    /// The power curve of the exponent with a linear toe of the slope, which is a pure power
+    /// curve when the slope is 0, e.g. `[0.45, 4.5]` for BT.709
+    PowerCurve([f32; 2]),
+    /// The curve of sRGB, a power of 1/2.4 with a linear toe of 12.92
+    Srgb,
+    /// The curve of BT.709, a power of 0.45 with a linear toe of 4.5
+    Rec709,
+    /// The curve of BT.2020, which is the one of BT.709 with the constants precise enough for
+    /// 10 and 12 bits
+    Rec2020,
+    /// The linear values are kept
     Linear,
+    /// A flat log curve like the one of Cineon for grading, `slope * log10(l + black_offset)`
+    /// raised so the white level encodes to 1. The black encodes to
+    /// `1 - slope * log10(1 / black_offset + 1)`.
+    Log { black_offset: f32, slope: f32 },
+    /// The log curve of `black_offset` 0.08 and `slope` 0.8, which puts the black at about 0.1
+    /// and 18% gray at about 0.5
+    FlatLog,
+    /// The perceptual quantizer of SMPTE ST 2084 for HDR, where the white level is shown at
+    /// `peak_nits` of the 10000 nits of the curve
+    Pq { peak_nits: f32 },
+    /// The hybrid log-gamma of BT.2100 for HDR, of the scene light where the white level is 1
+    Hlg,
+    /// A curve of the caller from the linear values between 0 and 1 to the encoded ones, it
+    /// is sampled into the lookup table of the rendering
+    Custom(fn(f32) -> f32),
+}
+
+/// The gamma of the output, the exponent of the power curve and the slope of its linear toe.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gamma {
+    /// The same curve for all the channels
+    Single([f32; 2]),
+    /// The curves of the red, green and blue channels after the color matrix, e.g. to
+    /// neutralize the orange mask of the negatives
+    PerChannel([[f32; 2]; 3]),
+}
+impl Gamma {
+    /// The curves of the red, green and blue channels
+    pub fn channels(&self) -> [[f32; 2]; 3] {
+        match *self {
+            Gamma::Single(gamma) => [gamma; 3],
+            Gamma::PerChannel(gammas) => gammas,
+        }
+    }
+}
+impl From<[f32; 2]> for Gamma {
+    fn from(gamma: [f32; 2]) -> Self {
+        Gamma::Single(gamma)
+    }
+}
+impl From<[[f32; 2]; 3]> for Gamma {
+    fn from(gammas: [[f32; 2]; 3]) -> Self {
+        Gamma::PerChannel(gammas)
+    }
+}
+
+/// How the linear values are compressed into the range of the output, before the transfer
+/// function and after the exposure compensation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapping {
+    /// The values clip at the white level
+    None,
+    /// A toe of the slope `1 + contrast` in the log of the values around middle gray, and a
+    /// shoulder like the one of Reinhard which rolls off the highlights up to `shoulder` times
+    /// the white level, e.g. 4 for 2 EV
+    Filmic { contrast: f32, shoulder: f32 },
+    /// The fit of the reference rendering of ACES by Krzysztof Narkowicz, which is white at
+    /// about 16 times the white level
+    Aces,
+}
+
+/// How the negatives of films are inverted into positives, in linear light before the white
+/// balance, which is left out. Every channel is scaled from the film base to its densest parts
+/// on its own, which takes out the orange mask of color negatives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NegativeInversion {
+    /// The film base is the mean of the brightest pixels, or of the ones around the point of
+    /// the image as it is shown when there is a `sample`
+    Auto { sample: Option<(usize, usize)> },
+    /// The film base in the colors of the camera, where 1 is the white level
+    Manual { base_rgb: [f32; 3] },
+}
+
+/// The black-and-white rendering after the color matrix, which mixes the red, green and blue in
+/// linear light by the `weights` like the color filters of black-and-white films, e.g.
+/// `[0.6, 0.3, 0.1]` for a red filter. The weights are normalized to keep the exposure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Monochrome {
+    pub weights: [f32; 3],
+}
+
+/// The unsharp mask on the luma after the transfer function. The difference of the luma to its
+/// gaussian blur of `radius` in pixels is added by `amount`, e.g. 0.5 for a light sharpening,
+/// where it reaches the `threshold` from 0 to 1 so the smooth areas are left alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sharpen {
+    pub amount: f32,
+    pub radius: f32,
+    pub threshold: f32,
+}
+
+/// How the colors outside of the gamut of the output are brought into it after the color
+/// matrix, in linear light before the transfer function, e.g. the saturated flowers and LEDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamutMapping {
+    /// Every channel clips on its own, which shifts the hues and flattens them to the primaries
+    Clip,
+    /// The chroma is scaled toward the neutral axis until the color fits, which keeps its hue
+    /// and luma
+    DesaturateToFit,
+    /// Like `DesaturateToFit`, the chroma beyond 80% of the way to the border of the gamut is
+    /// compressed softly so the saturated colors keep their gradations
+    Perceptual,
+}
+impl GamutMapping {
+    /// Maps the color, where 1 is white, into the range from 0 to 1
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        pass::map_gamut(rgb, *self)
+    }
 }
 
 /// Decides if the output should be 8bit or 16bit.
@@ -104,33 +335,249 @@ pub enum Input<'a> {
     ByBuffer(Vec<u8>),
 }
 
-/// Contains options for image rendering.
+/// Contains options for image rendering, the images are rendered by them through
+/// `export::Options::from`.
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct Output {
     demosaicing_method: DemosaicingMethod,
+    white_balance: WhiteBalance,
+    white_balance_method: WhiteBalanceMethod,
+    exposure_ev: f32,
+    baseline_exposure: bool,
+    auto_brightness: Option<f32>,
+    highlight_mode: HighlightMode,
+    white_level: WhiteLevel,
+    tone_mapping: ToneMapping,
+    transfer_function: Option<TransferFunction>,
+    tone_curve: Option<(Vec<u16>, bool)>,
+    lut3d: Option<Lut3d>,
+    brightness: f32,
+    contrast: f32,
+    saturation: f32,
+    vibrance: f32,
+    gamut_mapping: GamutMapping,
+    channel_mixer: [f32; 9],
+    negative_inversion: Option<NegativeInversion>,
+    monochrome: Option<Monochrome>,
+    sharpen: Option<Sharpen>,
+    simple_color: bool,
+    icc: Option<IccProfile>,
     color_space: [f32; 9],
-    gamma: [f32; 2],
+    gamma: Gamma,
     output_type: OutputType,
     auto_crop: bool,
     auto_rotate: bool,
+    apply_opcodes: bool,
+    pads_14bit: bool,
 }
 impl Output {
     pub fn new(
         demosaicing_method: DemosaicingMethod,
         color_space: [f32; 9],
-        gamma: [f32; 2],
+        gamma: impl Into<Gamma>,
         output_type: OutputType,
         auto_crop: bool,
         auto_rotate: bool,
     ) -> Output {
         Output {
             demosaicing_method,
+            white_balance: WhiteBalance::AsShot,
+            white_balance_method: WhiteBalanceMethod::CameraSpace,
+            exposure_ev: 0.0,
+            baseline_exposure: true,
+            auto_brightness: None,
+            highlight_mode: HighlightMode::Clip,
+            white_level: WhiteLevel::AsShot,
+            tone_mapping: ToneMapping::None,
+            transfer_function: None,
+            tone_curve: None,
+            lut3d: None,
+            brightness: 1.0,
+            contrast: 0.0,
+            saturation: 1.0,
+            vibrance: 0.0,
+            gamut_mapping: GamutMapping::Clip,
+            channel_mixer: data::CHANNEL_MIXER_IDENTITY,
+            negative_inversion: None,
+            monochrome: None,
+            sharpen: None,
+            simple_color: false,
+            icc: None,
             color_space,
-            gamma,
+            gamma: gamma.into(),
             output_type,
             auto_crop,
             auto_rotate,
+            apply_opcodes: true,
+            pads_14bit: false,
+        }
+    }
+    /// Applies the DNG opcode lists and the rendering hints of Apple ProRAW files, turn it off
+    /// to keep the data untouched, on by default
+    pub fn with_apply_opcodes(mut self, apply_opcodes: bool) -> Output {
+        self.apply_opcodes = apply_opcodes;
+        self
+    }
+    /// Pads the decoded samples of fewer bits up to 14 bits before they are scaled to the
+    /// white level, e.g. the 12 bit samples of uncompressed ARW files, off by default
+    pub fn with_pad_14bit(mut self, pads_14bit: bool) -> Output {
+        self.pads_14bit = pads_14bit;
+        self
+    }
+    /// The white balance of the output, as shot by default
+    pub fn with_white_balance(mut self, white_balance: WhiteBalance) -> Output {
+        self.white_balance = white_balance;
+        self
+    }
+    /// Where the white balance is applied, in the camera space by default
+    pub fn with_white_balance_method(mut self, method: WhiteBalanceMethod) -> Output {
+        self.white_balance_method = method;
+        self
+    }
+    /// The exposure compensation in EV, the linear values are multiplied by `2^exposure_ev`
+    /// after the white balance and the color matrix, before the gamma curve, and clip at the
+    /// white point, 0 by default
+    pub fn with_exposure_ev(mut self, exposure_ev: f32) -> Output {
+        self.exposure_ev = exposure_ev;
+        self
+    }
+    /// Applies the BaselineExposure and the BaselineExposureOffset of DNG files, which add up
+    /// with the exposure compensation, turn it off to render them as their raw data, on by
+    /// default
+    pub fn with_baseline_exposure(mut self, baseline_exposure: bool) -> Output {
+        self.baseline_exposure = baseline_exposure;
+        self
+    }
+    /// Brightens the output until the `percentile` of its luminance is white, 99 like dcraw,
+    /// by at most 3 EV on top of the exposure compensation, off by default
+    pub fn with_auto_brightness(mut self, percentile: f32) -> Output {
+        self.auto_brightness = Some(percentile);
+        self
+    }
+    /// How the clipped highlights are rendered, clipped to neutral by default
+    pub fn with_highlight_mode(mut self, highlight_mode: HighlightMode) -> Output {
+        self.highlight_mode = highlight_mode;
+        self
+    }
+    /// Where the samples are white, it normalizes them and the highlights clip at it, the one
+    /// of the file by default
+    pub fn with_white_level(mut self, white_level: WhiteLevel) -> Output {
+        self.white_level = white_level;
+        self
+    }
+    /// How the linear values are compressed into the output before the gamma, clipped by
+    /// default
+    pub fn with_tone_mapping(mut self, tone_mapping: ToneMapping) -> Output {
+        self.tone_mapping = tone_mapping;
+        self
+    }
+    /// The transfer function of the output, which is used instead of the gamma when it is set
+    pub fn with_transfer_function(mut self, transfer_function: TransferFunction) -> Output {
+        self.transfer_function = Some(transfer_function);
+        self
+    }
+    /// Maps the values after the color matrix by the 1D lookup table of `tone_curve`, e.g. the
+    /// curve of a camera style. The curves of fewer than 65536 entries are interpolated
+    /// linearly. It maps the encoded values, or the linear ones when it `replaces_gamma`.
+    pub fn with_tone_curve(mut self, tone_curve: Vec<u16>, replaces_gamma: bool) -> Output {
+        self.tone_curve = Some((tone_curve, replaces_gamma));
+        self
+    }
+    /// The 3D LUT the colors are looked up in as the last step, after the transfer function
+    pub fn with_lut3d(mut self, lut3d: Lut3d) -> Output {
+        self.lut3d = Some(lut3d);
+        self
+    }
+    /// The gain of the linear values after the color matrix, which adds up with the exposure
+    /// compensation before the gamma curve, 1 by default
+    pub fn with_brightness(mut self, brightness: f32) -> Output {
+        self.brightness = brightness;
+        self
+    }
+    /// The contrast around middle gray after the brightness, the slope of the S-curve there is
+    /// `1 + contrast`, 0 by default
+    pub fn with_contrast(mut self, contrast: f32) -> Output {
+        self.contrast = contrast;
+        self
+    }
+    /// The saturation after the contrast, which keeps the Rec. 709 luma, 0 for gray and 1 by
+    /// default
+    pub fn with_saturation(mut self, saturation: f32) -> Output {
+        self.saturation = saturation;
+        self
+    }
+    /// Boosts the saturation by how unsaturated the colors are along with the saturation, up to
+    /// the border of the range and less around the hues of skin, 0 by default
+    pub fn with_vibrance(mut self, vibrance: f32) -> Output {
+        self.vibrance = vibrance;
+        self
+    }
+    /// How the colors outside of the gamut of the output are mapped after the color matrix in
+    /// linear light, before the transfer function, clipped by default
+    pub fn with_gamut_mapping(mut self, gamut_mapping: GamutMapping) -> Output {
+        self.gamut_mapping = gamut_mapping;
+        self
+    }
+    /// Mixes the red, green and blue by the matrix after the color matrix, whose rows are the
+    /// weights of the channels in the linear colors of the color space, e.g. swaps red and blue
+    /// for infrared conversions. It is not a colorimetric transform and goes along with any
+    /// color space, its coefficients are from -4 to 4, the identity by default.
+    pub fn with_channel_mixer(mut self, channel_mixer: [f32; 9]) -> Output {
+        self.channel_mixer = channel_mixer;
+        self
+    }
+    /// Inverts the negative of a film into a positive before the tone curve, which is best kept
+    /// in 16 bits, off by default
+    pub fn with_negative_inversion(mut self, negative_inversion: NegativeInversion) -> Output {
+        self.negative_inversion = Some(negative_inversion);
+        self
+    }
+    /// Renders the output in black and white with the same red, green and blue, which are mixed
+    /// in linear light before the gamma curve, the saturation and the vibrance are left out, in
+    /// color by default
+    pub fn with_monochrome(mut self, monochrome: Monochrome) -> Output {
+        self.monochrome = Some(monochrome);
+        self
+    }
+    /// Sharpens the output by an unsharp mask on its luma as the last step, for 8 and 16 bit
+    /// outputs alike, off by default
+    pub fn with_sharpen(mut self, sharpen: Sharpen) -> Output {
+        self.sharpen = Some(sharpen);
+        self
+    }
+    /// The 3D LUT of a `.cube` file, which is parsed here so a malformed one fails before any
+    /// rendering
+    pub fn with_lut3d_file(self, path: &str) -> Result<Output, RawFileReadingError> {
+        Ok(self.with_lut3d(Lut3d::from_cube_file(path)?))
+    }
+    /// Converts the colors of DNG files by their single ColorMatrix like the other files,
+    /// instead of the DNG color pipeline which interpolates the matrices of both illuminants
+    /// by the temperature of the white and uses the ForwardMatrix, off by default
+    pub fn with_simple_color(mut self, simple_color: bool) -> Output {
+        self.simple_color = simple_color;
+        self
+    }
+    /// The ICC profile embedded in the exported images, none by default
+    pub fn with_icc(mut self, icc: IccProfile) -> Output {
+        self.icc = Some(icc);
+        self
+    }
+    /// The bytes of the ICC profile of the output, which `embed_icc_profile_in_jpeg` embeds in
+    /// the encoded images. The generated one follows the color space and the transfer function.
+    pub fn icc_profile(&self) -> Option<Vec<u8>> {
+        match &self.icc {
+            Some(IccProfile::Generated) => {
+                let transfer_function = match self.transfer_function {
+                    _ if matches!(self.tone_curve, Some((_, true))) => TransferFunction::Linear,
+                    // the profile takes the curve of green, which is shared by the channels
+                    None => TransferFunction::PowerCurve(self.gamma.channels()[1]),
+                    Some(transfer_function) => transfer_function,
+                };
+                Some(gen_icc_profile(&self.color_space, transfer_function))
+            }
+            Some(IccProfile::Provided(profile)) => Some(profile.clone()),
+            None => None,
         }
     }
 }
@@ -158,6 +605,28 @@ pub enum RawFileReadingError {
     MakerIsNotSupportedYet(String),
     #[error("This raw file model: '{0}' is not supported yet.")]
     ModelIsNotSupportedYet(String),
+    #[error("The demosaicing method '{0:?}' does not support the CFA pattern '{1:?}'.")]
+    DemosaicingNotSupported(DemosaicingMethod, CFAPattern),
+    #[error("The white balance multipliers '{0:?}' are not all positive.")]
+    InvalidWhiteBalance([f32; 3]),
+    #[error("The tone curve of {0} entries is too short, it needs at least 2.")]
+    InvalidToneCurve(usize),
+    #[error("The cube LUT is malformed, {0}.")]
+    InvalidCubeLut(String),
+    #[error("The gamma '{0:?}' does not have a positive exponent.")]
+    InvalidGamma([f32; 2]),
+    #[error("The white balance cannot be picked at ({0}, {1}), it is outside of the image or clipped.")]
+    InvalidWhiteBalancePoint(usize, usize),
+    #[error("The film base of the negative is black, clipped or outside of the image.")]
+    InvalidFilmBase,
+    #[error("The weights '{0:?}' of the monochrome rendering do not add up to a positive sum.")]
+    InvalidMonochromeWeights([f32; 3]),
+    #[error("The sharpening '{0:?}' does not have a positive radius and a finite amount.")]
+    InvalidSharpen(Sharpen),
+    #[error("The channel mixer '{0:?}' has coefficients which are not finite or beyond 4.")]
+    InvalidChannelMixer([f32; 9]),
+    #[error("The white balance '{0:?}' needs the ColorMatrix of the file or the matrix of the model.")]
+    WhiteBalanceNeedsColorMatrix(WhiteBalance),
 }
 
 pub struct Export;
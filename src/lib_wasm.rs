@@ -60,13 +60,21 @@ macro_rules! gen_image_loader {
             let width = decoded_image.width;
             let height = decoded_image.height;
 
-            let color_matrix = utility::matrix3_mul(&data::XYZ2SRGB, &decoded_image.cam_matrix);
-            let white_balance = {
-                let [r, g, b] = decoded_image.white_balance;
-                [r as f32 / g as f32, 1f32, b as f32 / g as f32]
-            };
-
-            if image.len() == width * height * 3 {
+            // monochrome images stay gray, they are neither white balanced nor color converted
+            let (color_matrix, white_balance) =
+                if matches!(decoded_image.cfa_pattern, CFAPattern::Monochrome) {
+                    (data::XYZ2RAW, [1f32; 3])
+                } else {
+                    let [r, g, b] = decoded_image.white_balance;
+                    (
+                        utility::matrix3_mul(&data::XYZ2SRGB, &decoded_image.cam_matrix),
+                        [r as f32 / g as f32, 1f32, b as f32 / g as f32],
+                    )
+                };
+
+            if image.len() == width * height * 3
+                && !matches!(decoded_image.cfa_pattern, CFAPattern::None)
+            {
                 return Ok(Image {
                     data: image,
                     orientation,
@@ -87,7 +95,9 @@ macro_rules! gen_image_loader {
                         CFAPattern::GBRG => .$gbrg(&image, width, height),
                         CFAPattern::BGGR => .$bggr(&image, width, height),
                         CFAPattern::XTrans0 => .linear_xtrans0(&image, width, height),
-                        CFAPattern::XTrans1 => .linear_xtrans1(&image, width, height)
+                        CFAPattern::XTrans1 => .linear_xtrans1(&image, width, height),
+                        CFAPattern::None => .rgb(&image),
+                        CFAPattern::Monochrome => .none()
                     }
                     ..flatten()
             );
@@ -191,27 +201,41 @@ fn quick_image_load(
     let decoded_image = decode::decode_buffer(input)?;
 
     let width = decoded_image.width;
-    let image = decoded_image
-        .image
-        .chunks(width * 4)
-        .enumerate()
-        .map(|(row_index, row)| {
-            let left_bound = row_index % 4 * width;
-            row[left_bound..left_bound + width]
-                .chunks(4)
-                .enumerate()
-                .map(|(column_index, values)| values[column_index % 4])
-                .collect::<Vec<_>>()
-        })
-        .flatten()
-        .collect::<Vec<_>>();
+    let image = match decoded_image.cfa_pattern {
+        // keeps the top left pixel of every 4x4 block
+        CFAPattern::None => decoded_image
+            .image
+            .chunks_exact(width * 3 * 4)
+            .flat_map(|rows| rows[..width * 3].chunks_exact(3 * 4).flat_map(|x| &x[..3]))
+            .copied()
+            .collect::<Vec<_>>(),
+        _ => decoded_image
+            .image
+            .chunks(width * 4)
+            .enumerate()
+            .map(|(row_index, row)| {
+                let left_bound = row_index % 4 * width;
+                row[left_bound..left_bound + width]
+                    .chunks(4)
+                    .enumerate()
+                    .map(|(column_index, values)| values[column_index % 4])
+                    .collect::<Vec<_>>()
+            })
+            .flatten()
+            .collect::<Vec<_>>(),
+    };
 
     let orientation = decoded_image.orientation;
     let width = decoded_image.width / 4;
     let height = decoded_image.height / 4;
 
     let gamma_lut = gen_gamma_lut(0.45);
-    let color_matrix = utility::matrix3_mul(&data::XYZ2SRGB, &decoded_image.cam_matrix);
+    let is_monochrome = matches!(decoded_image.cfa_pattern, CFAPattern::Monochrome);
+    let color_matrix = if is_monochrome {
+        data::XYZ2RAW
+    } else {
+        utility::matrix3_mul(&data::XYZ2SRGB, &decoded_image.cam_matrix)
+    };
     let color_matrix = color_matrix.mul(1 << BIT_SHIFT);
     let white_balance = decoded_image
         .white_balance
@@ -227,10 +251,12 @@ fn quick_image_load(
                 CFAPattern::GBRG => .linear_gbrg(&image, width, height),
                 CFAPattern::BGGR => .linear_bggr(&image, width, height),
                 CFAPattern::XTrans0 => .linear_xtrans0(&image, width, height),
-                CFAPattern::XTrans1 => .linear_xtrans1(&image, width, height)
+                CFAPattern::XTrans1 => .linear_xtrans1(&image, width, height),
+                CFAPattern::None => .rgb(&image),
+                CFAPattern::Monochrome => .none()
             }
             .u16rgb_to_i32rgb()
-            .white_balance_fix(&white_balance)
+            [.white_balance_fix(&white_balance) !is_monochrome]
             .color_convert(&color_matrix)
             .gamma_correct(&gamma_lut)
             .u16rgb_to_u8rgb()
@@ -0,0 +1,144 @@
+use super::*;
+use std::fs;
+
+/// A 3D lookup table of colors, e.g. a film emulation, which is read from the `.cube` files of
+/// Adobe and Resolve. The RGB values from 0 to 1 are looked up by trilinear interpolation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    title: Option<String>,
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// The colors with red changing the fastest and blue the slowest, like the cube files
+    table: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    /// Parses the text of a `.cube` file, the 1D tables of the format are not supported
+    pub fn from_cube_str(cube: &str) -> Result<Lut3d, RawFileReadingError> {
+        let error = |line: usize, message: &str| {
+            RawFileReadingError::InvalidCubeLut(format!("line {}: {}", line + 1, message))
+        };
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = vec![];
+
+        for (i, line) in cube.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            match keyword {
+                // the colors follow the keywords
+                _ if !table.is_empty() || keyword.parse::<f32>().is_ok() => {
+                    let size =
+                        size.ok_or_else(|| error(i, "the colors come before LUT_3D_SIZE"))?;
+                    if table.len() == size * size * size {
+                        return Err(error(i, "there are more colors than LUT_3D_SIZE"));
+                    }
+                    table.push(three_numbers(line).ok_or_else(|| error(i, "expects 3 numbers"))?);
+                }
+                "TITLE" => title = Some(rest.trim_matches('"').to_owned()),
+                "LUT_3D_SIZE" => match rest.parse::<usize>() {
+                    Ok(x) if (2..=256).contains(&x) => size = Some(x),
+                    _ => return Err(error(i, "LUT_3D_SIZE expects a size from 2 to 256")),
+                },
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let domain =
+                        three_numbers(rest).ok_or_else(|| error(i, "expects 3 numbers"))?;
+                    match keyword {
+                        "DOMAIN_MIN" => domain_min = domain,
+                        _ => domain_max = domain,
+                    }
+                }
+                "LUT_1D_SIZE" => return Err(error(i, "the 1D tables are not supported")),
+                // the keywords of the applications, e.g. LUT_3D_INPUT_RANGE of Resolve
+                _ if keyword.chars().all(|x| x.is_ascii_uppercase() || x == '_') => {}
+                _ => return Err(error(i, &format!("unknown keyword '{}'", keyword))),
+            }
+        }
+
+        let size = size.ok_or_else(|| {
+            RawFileReadingError::InvalidCubeLut("LUT_3D_SIZE is missing".to_owned())
+        })?;
+        if table.len() != size * size * size {
+            return Err(RawFileReadingError::InvalidCubeLut(format!(
+                "{} colors for the size of {}, which needs {}",
+                table.len(),
+                size,
+                size * size * size
+            )));
+        }
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err(RawFileReadingError::InvalidCubeLut(
+                "DOMAIN_MAX is not above DOMAIN_MIN".to_owned(),
+            ));
+        }
+        Ok(Lut3d {
+            title,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Reads and parses a `.cube` file
+    pub fn from_cube_file(path: &str) -> Result<Lut3d, RawFileReadingError> {
+        let cube = fs::read_to_string(path)
+            .map_err(|_| RawFileReadingError::FileContentReadingError(path.to_owned()))?;
+        Lut3d::from_cube_str(&cube)
+    }
+
+    /// The TITLE of the cube file
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// The number of colors along every axis of the cube
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Looks up the color by trilinear interpolation, the values outside of the domain of the
+    /// cube take the colors of its border
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let last = (self.size - 1) as f32;
+        let position = [0, 1, 2].map(|c| {
+            let x = (rgb[c] - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c]);
+            (x * last).clamp(0.0, last)
+        });
+        let index = position.map(|x| (x as usize).min(self.size - 2));
+        let t = [0, 1, 2].map(|c| position[c] - index[c] as f32);
+        let color = |r: usize, g: usize, b: usize| {
+            self.table[(index[0] + r)
+                + (index[1] + g) * self.size
+                + (index[2] + b) * self.size * self.size]
+        };
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t);
+
+        let along_red = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .map(|(g, b)| lerp(color(0, g, b), color(1, g, b), t[0]));
+        let along_green = [
+            lerp(along_red[0], along_red[1], t[1]),
+            lerp(along_red[2], along_red[3], t[1]),
+        ];
+        lerp(along_green[0], along_green[1], t[2])
+    }
+}
+
+/// The 3 finite numbers separated by whitespace
+fn three_numbers(text: &str) -> Option<[f32; 3]> {
+    let values = text
+        .split_whitespace()
+        .map(|x| x.parse::<f32>().ok().filter(|x| x.is_finite()))
+        .collect::<Option<Vec<_>>>()?;
+    match values.as_slice() {
+        &[r, g, b] => Some([r, g, b]),
+        _ => None,
+    }
+}
@@ -2,7 +2,7 @@ use super::*;
 use once_cell::sync::Lazy;
 
 use super::utility::GetNumFromBytes;
-use super::{decode_utility::ljpeg::LjpegDecompressor, utility::*};
+use super::{decode_utility::ljpeg::LjpegDecompressor, opcode::*, utility::*};
 
 pub(super) struct General {
     info: quickexif::ParsedInfo,
@@ -51,126 +51,314 @@ pub(super) static THUMBNAIL_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     })
 });
 
-pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
-    let template_rule = quickexif::describe_rule!(template {
-        0x0100 / width
-        0x0101 / height
-        0x0102 : u16 / bps
-        0x0103 : u16 / compression
-        0x828e? / cfa_pattern
-        0xc61d / wl(white_level_len)
-
-        if white_level_len == 1
-        {
-            0xc61d : u16 / white_level
-        }
-        else
-        {
-            0xc61d {
-                u16 + 0 / white_level
+// the tags of the raw IFD, which is found by its NewSubfileType
+macro_rules! raw_ifd_template {
+    () => {
+        quickexif::describe_rule!(template {
+            0x0100 / width
+            0x0101 / height
+            0x0102 : u16 / bps
+            0x0103 : u16 / compression
+            0x0106? : u16 / photometric
+            0x0115? : u16 / samples_per_pixel
+            0x828d? / cfa_repeat_dim
+            0x828e? / cfa_pattern(cfa_pattern_len)
+            if cfa_pattern ? {
+                if cfa_pattern_len == 36 {
+                    0x828e { // the first row of a 6x6 pattern
+                        u32 + 0 / cfa_pattern_row
+                        u16 + 2 / cfa_pattern_row_tail
+                    }
+                }
             }
-        }
-        0xc61a / bl(black_level_len)
-        if black_level_len == 1 {
-            0xc61a : u16 / black_level
-        } else {
-            0xc61a {
-                r64 + 0 / black_level
+            0x0153? : u16 / sample_format
+            0x013d? : u16 / predictor
+            0xc618? / linearization_table(linearization_table_len)
+            0xc740? / opcode_list_1(opcode_list_1_len)
+            0xc741? / opcode_list_2(opcode_list_2_len)
+            0xc74e? / opcode_list_3(opcode_list_3_len)
+            0xc68d? { // ActiveArea is expected as LONG values
+                u32 + 0 / active_area_top
+                u32 + 1 / active_area_left
+                u32 + 2 / active_area_bottom
+                u32 + 3 / active_area_right
             }
-        }
-        0x0111? / strip(strip_offsets_count)
-        if strip ?
-        {
-            0x0117 / strip_len
-        }
-        else
-        {
-            0x0144 / tile_offsets(tile_offsets_count)
-            0x0145 / tile_byte_counts
-            0x0142 / tile_width
-            0x0143 / tile_len
-        }
-        if is_adobe_dng_converted ? {
-            0xc61f? {
-                r64 + 0 / crop_x
-                r64 + 1 / crop_y
+            0xc61d? / wl(white_level_len)
+            if wl ? {
+                if white_level_len == 1
+                {
+                    0xc61d : u16 / white_level
+                }
+                else
+                {
+                    0xc61d {
+                        u16 + 0 / white_level
+                    }
+                }
             }
-            0xc620? {
-                r64 + 0 / crop_width
-                r64 + 1 / crop_height
+            0xc61a? / bl(black_level_len)
+            if bl ? {
+                if black_level_len == 1 {
+                    0xc61a : u16 / black_level
+                } else {
+                    0xc61a {
+                        r64 + 0 / black_level
+                    }
+                    if black_level_len > 2 {
+                        0xc61a {
+                            r64 + 1 / black_level_1
+                            r64 + 2 / black_level_2
+                        }
+                    }
+                    if black_level_len > 3 {
+                        0xc61a {
+                            r64 + 3 / black_level_3
+                        }
+                    }
+                }
             }
-        } else {
-            0xc61f? / crop_origin
-            0xc620? / crop_size
-        }
-    });
+            0x0111? / strip(strip_offsets_count)
+            if strip ?
+            {
+                0x0117 / strip_len
+                0x0116? / rows_per_strip
+            }
+            else
+            {
+                0x0144 / tile_offsets(tile_offsets_count)
+                0x0145 / tile_byte_counts
+                0x0142 / tile_width
+                0x0143 / tile_len
+            }
+            if is_adobe_dng_converted ? {
+                0xc61f? {
+                    r64 + 0 / crop_x
+                    r64 + 1 / crop_y
+                }
+                0xc620? {
+                    r64 + 0 / crop_width
+                    r64 + 1 / crop_height
+                }
+            } else {
+                0xc61f? / crop_origin
+                0xc620? / crop_size
+            }
+        })
+    };
+}
 
+pub(super) static RAW_IFD_RULES: Lazy<RawIfdRules> =
+    Lazy::new(|| raw_ifd_rules!(raw_ifd_template!()));
+
+pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     quickexif::describe_rule!(tiff {
         0x0112: u16 / orientation
-        0x00fe / sub_file_type
         0xc628 {
             r64 + 0 / white_balance_r
             r64 + 1 / white_balance_g
             r64 + 2 / white_balance_b
         }
         0xc717? / is_adobe_dng_converted
-        if sub_file_type == 0
-        {
-            load(template_rule)
+        0xc62a? {
+            r64 + 0 / baseline_exposure
         }
-        else
-        {
-            0x014a / sub_ifd(sub_ifd_count)
-            if sub_ifd_count == 1
-            {
-                0x014a {
-                    0x00fe / sub_file_type1
-                    if sub_file_type1 == 0
-                    {
-                        load(template_rule)
-                    }
-                }
-            }
-            else
-            {
-                0x014a {
-                    offset address {
-                        0x00fe / sub_file_type2
-                        if sub_file_type2 == 0
-                        {
-                            load(template_rule)
-                        }
-                    }
-                }
-            }
+        0xc7a5? {
+            r64 + 0 / baseline_exposure_offset
+        }
+        0xc6fc? / profile_tone_curve(profile_tone_curve_len)
+        // the color calibration of the two illuminants for the DNG color pipeline
+        0xc65a? : u16 / calibration_illuminant_1
+        0xc65b? : u16 / calibration_illuminant_2
+        0xc621? {
+            r64 + 0 / cm1_0
+            r64 + 1 / cm1_1
+            r64 + 2 / cm1_2
+            r64 + 3 / cm1_3
+            r64 + 4 / cm1_4
+            r64 + 5 / cm1_5
+            r64 + 6 / cm1_6
+            r64 + 7 / cm1_7
+            r64 + 8 / cm1_8
+        }
+        0xc622? {
+            r64 + 0 / cm2_0
+            r64 + 1 / cm2_1
+            r64 + 2 / cm2_2
+            r64 + 3 / cm2_3
+            r64 + 4 / cm2_4
+            r64 + 5 / cm2_5
+            r64 + 6 / cm2_6
+            r64 + 7 / cm2_7
+            r64 + 8 / cm2_8
+        }
+        0xc714? {
+            r64 + 0 / fm1_0
+            r64 + 1 / fm1_1
+            r64 + 2 / fm1_2
+            r64 + 3 / fm1_3
+            r64 + 4 / fm1_4
+            r64 + 5 / fm1_5
+            r64 + 6 / fm1_6
+            r64 + 7 / fm1_7
+            r64 + 8 / fm1_8
+        }
+        0xc715? {
+            r64 + 0 / fm2_0
+            r64 + 1 / fm2_1
+            r64 + 2 / fm2_2
+            r64 + 3 / fm2_3
+            r64 + 4 / fm2_4
+            r64 + 5 / fm2_5
+            r64 + 6 / fm2_6
+            r64 + 7 / fm2_7
+            r64 + 8 / fm2_8
+        }
+        0xc623? {
+            r64 + 0 / cc1_0
+            r64 + 1 / cc1_1
+            r64 + 2 / cc1_2
+            r64 + 3 / cc1_3
+            r64 + 4 / cc1_4
+            r64 + 5 / cc1_5
+            r64 + 6 / cc1_6
+            r64 + 7 / cc1_7
+            r64 + 8 / cc1_8
+        }
+        0xc624? {
+            r64 + 0 / cc2_0
+            r64 + 1 / cc2_1
+            r64 + 2 / cc2_2
+            r64 + 3 / cc2_3
+            r64 + 4 / cc2_4
+            r64 + 5 / cc2_5
+            r64 + 6 / cc2_6
+            r64 + 7 / cc2_7
+            r64 + 8 / cc2_8
+        }
+        0xc627? {
+            r64 + 0 / ab_0
+            r64 + 1 / ab_1
+            r64 + 2 / ab_2
         }
     })
 });
 
 impl General {
+    /// The WhiteLevel defaults to the maximum of the bit depth
+    fn white_level(&self) -> Result<u16, quickexif::parsed_info::Error> {
+        match self.info.u16("white_level") {
+            Ok(white_level) => Ok(white_level),
+            Err(_) => Ok(((1u32 << self.info.u16("bps")?.min(16)) - 1) as u16),
+        }
+    }
     fn get_white_level_scale(&self) -> Result<u16, quickexif::parsed_info::Error> {
-        let white_level = self.info.u16("white_level")?;
-        Ok(u16::MAX / white_level)
+        Ok(u16::MAX / self.white_level()?.max(1))
     }
-}
-
-impl RawDecoder for General {
-    fn new(info: quickexif::ParsedInfo) -> Self {
-        General { info }
+    /// Floating point samples (SampleFormat 3) are kept as 16, 24 or 32 bit floats
+    fn is_float(&self) -> bool {
+        matches!(self.info.u16("sample_format"), Ok(3))
     }
-    fn get_info(&self) -> &quickexif::ParsedInfo {
-        &self.info
+    /// LinearRaw (34892) data is already demosaiced and holds three samples per pixel
+    fn is_linear_raw(&self) -> bool {
+        match self.info.u16("samples_per_pixel") {
+            Ok(samples_per_pixel) => samples_per_pixel > 1,
+            Err(_) => matches!(self.info.u16("photometric"), Ok(34892)),
+        }
     }
-    fn into_info(self) -> quickexif::ParsedInfo {
+    /// Monochrome sensors give a single sample per pixel as LinearRaw or BlackIsZero (1)
+    /// data without CFAPattern
+    fn is_monochrome(&self) -> bool {
+        !self.is_linear_raw()
+            && (matches!(self.info.u16("photometric"), Ok(1 | 34892))
+                || self.info.u8a4("cfa_pattern").is_err())
+    }
+    /// The LinearizationTable maps the stored samples to linear values before the black level
+    fn linearization_table(&self, buffer: &[u8]) -> Result<Option<Vec<u16>>, DecodingError> {
+        let (offset, count) = match (
+            self.info.usize("linearization_table"),
+            self.info.usize("linearization_table_len"),
+        ) {
+            (Ok(offset), Ok(count)) if count > 2 => (offset, count),
+            _ => return Ok(None),
+        };
+        let is_le = self.info.is_le;
+        let table = buffer.get(offset..offset + count * 2).ok_or_else(|| {
+            DecodingError::InvalidRawData("DNG linearization table is out of range".to_owned())
+        })?;
+        Ok(Some(to_16bit_iter(table, is_le).collect()))
+    }
+    /// Apple ProRAW files are named by their UniqueCameraModel, e.g. "Apple iPhone 14 Pro"
+    fn is_pro_raw(&self) -> bool {
         self.info
+            .str("make_model")
+            .map(|x| x.starts_with("Apple"))
+            .unwrap_or(false)
     }
-    fn get_white_balance(&self) -> Result<[i32; 3], DecodingError> {
-        let r = 512.0 / self.info.f64("white_balance_r")?;
-        let g = 512.0 / self.info.f64("white_balance_g")?;
-        let b = 512.0 / self.info.f64("white_balance_b")?;
-        Ok([r as i32, g as i32, b as i32])
+    fn opcode_list(&self, buffer: &[u8], name: &str) -> Result<Vec<Opcode>, DecodingError> {
+        let offset = match self.info.usize(name) {
+            Ok(offset) => offset,
+            Err(_) => return Ok(vec![]),
+        };
+        let len = self.info.usize(format!("{}_len", name).as_str())?;
+        let data = buffer.get(offset..offset + len).ok_or_else(|| {
+            DecodingError::InvalidRawData("DNG opcode list is out of range".to_owned())
+        })?;
+        parse_opcode_list(data)
     }
-    fn get_crop(&self) -> Option<Crop> {
+    /// The lookup table of the ProfileToneCurve for the linear values, none without the curve
+    fn tone_lut(&self, buffer: &[u8]) -> Result<Option<Vec<u16>>, DecodingError> {
+        let curve: Vec<(f64, f64)> = match (
+            self.info.usize("profile_tone_curve"),
+            self.info.usize("profile_tone_curve_len"),
+        ) {
+            (Ok(offset), Ok(count)) if count >= 4 => {
+                let is_le = self.info.is_le;
+                let data = buffer.get(offset..offset + count * 4).ok_or_else(|| {
+                    DecodingError::InvalidRawData("DNG tone curve is out of range".to_owned())
+                })?;
+                data.chunks_exact(8)
+                    .map(|x| {
+                        let input = f32::from_bits(x.u32(is_le, 0)) as f64;
+                        let output = f32::from_bits(x.u32(is_le, 4)) as f64;
+                        (input, output)
+                    })
+                    .collect()
+            }
+            _ => return Ok(None),
+        };
+
+        let lut = (0..=u16::MAX)
+            .map(|x| {
+                let x = x as f64 / u16::MAX as f64;
+                let y = match curve.iter().position(|&(input, _)| input >= x) {
+                    Some(0) => curve[0].1,
+                    Some(i) => {
+                        let (x0, y0) = curve[i - 1];
+                        let (x1, y1) = curve[i];
+                        if x1 > x0 {
+                            y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+                        } else {
+                            y1
+                        }
+                    }
+                    None => curve.last().map_or(x, |&(_, y)| y),
+                };
+                (y.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16
+            })
+            .collect();
+        Ok(Some(lut))
+    }
+    /// The crop of the aspect ratio set in the camera, which Ricoh only keeps in the maker
+    /// notes
+    fn maker_note_crop(&self) -> Option<Crop> {
+        Some(Crop {
+            x: self.info.u32("maker_note_crop_left").ok()?,
+            y: self.info.u32("maker_note_crop_top").ok()?,
+            width: self.info.u32("maker_note_crop_width").ok()?,
+            height: self.info.u32("maker_note_crop_height").ok()?,
+        })
+    }
+    fn default_crop(&self) -> Option<Crop> {
         if let (Ok(crop_origin), Ok(crop_size)) =
             (self.info.u8a4("crop_origin"), self.info.u8a4("crop_size"))
         {
@@ -197,9 +385,238 @@ impl RawDecoder for General {
             })
         }
     }
+    /// The ActiveArea as top, left, bottom and right, the pixels out of it are masked
+    fn active_area(&self) -> Option<[usize; 4]> {
+        Some([
+            self.info.usize("active_area_top").ok()?,
+            self.info.usize("active_area_left").ok()?,
+            self.info.usize("active_area_bottom").ok()?,
+            self.info.usize("active_area_right").ok()?,
+        ])
+    }
+    /// The BlackLevel of every position of the 2x2 CFA block or of every sample of LinearRaw
+    /// data, the rational levels written by e.g. DJI are rounded
+    fn black_levels(&self) -> Vec<u16> {
+        let level = |name: &str| self.info.f64(name).map(|x| x.round() as u16);
+        let first = level("black_level")
+            .or_else(|_| self.info.u16("black_level"))
+            .unwrap_or(0);
+        let mut levels = vec![first];
+        for name in ["black_level_1", "black_level_2", "black_level_3"] {
+            match level(name) {
+                Ok(x) => levels.push(x),
+                Err(_) => break,
+            }
+        }
+        levels
+    }
+    /// The size of the image with the offset and the size of every tile, strips are
+    /// handled as tiles as wide as the image
+    fn layout(&self, buffer: &[u8]) -> Result<Layout, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let samples_per_pixel = if self.is_linear_raw() { 3 } else { 1 };
+        let (offsets, byte_counts, count, tile_width, tile_height) = match self.info.usize("strip")
+        {
+            Ok(offsets) => (
+                offsets,
+                self.info.usize("strip_len")?,
+                self.info.usize("strip_offsets_count")?,
+                width,
+                self.info.usize("rows_per_strip").unwrap_or(height),
+            ),
+            Err(_) => (
+                self.info.usize("tile_offsets")?,
+                self.info.usize("tile_byte_counts")?,
+                self.info.usize("tile_offsets_count")?,
+                self.info.usize("tile_width")?,
+                self.info.usize("tile_len")?,
+            ),
+        };
+        if tile_width == 0 || tile_height == 0 {
+            return Err(DecodingError::InvalidRawData(
+                "DNG tiles are empty".to_owned(),
+            ));
+        }
+
+        let tiles = if count == 1 {
+            vec![(offsets, byte_counts)]
+        } else {
+            let is_le = self.info.is_le;
+            let table = |addr: usize| {
+                buffer
+                    .get(addr..addr + count * 4)
+                    .map(|x| x.chunks_exact(4).map(move |x| x.u32(is_le, 0) as usize))
+                    .ok_or_else(|| {
+                        DecodingError::InvalidRawData("DNG tiles are out of range".to_owned())
+                    })
+            };
+            table(offsets)?.zip(table(byte_counts)?).collect()
+        };
+        Ok(Layout {
+            width,
+            height,
+            samples_per_pixel,
+            tiles,
+            tile_width,
+            tile_height,
+        })
+    }
+}
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    /// The WhiteLevel less the highest black level, scaled like the samples
+    fn get_white_level(&self) -> u16 {
+        if self.is_float() {
+            return u16::MAX;
+        }
+        let black_level = self.black_levels().into_iter().max().unwrap_or(0);
+        match (self.white_level(), self.get_white_level_scale()) {
+            (Ok(white_level), Ok(scale)) => {
+                scale.saturating_mul(white_level.saturating_sub(black_level))
+            }
+            _ => u16::MAX,
+        }
+    }
+    /// The BaselineExposure with its BaselineExposureOffset
+    fn get_baseline_exposure(&self) -> f32 {
+        let baseline_exposure = self.info.f64("baseline_exposure").unwrap_or(0.0);
+        let offset = self.info.f64("baseline_exposure_offset").unwrap_or(0.0);
+        (baseline_exposure + offset) as f32
+    }
+    /// The levels of every position of the 2x2 CFA block, LinearRaw data is left out as its
+    /// levels are per sample and subtracted while decoding
+    fn get_black_level(&self) -> [u16; 4] {
+        if self.is_float() || self.is_linear_raw() {
+            return [0; 4];
+        }
+        let scale = self.get_white_level_scale().unwrap_or(1);
+        let black_levels = self.black_levels();
+        [0, 1, 2, 3].map(|i| match black_levels.len() {
+            4 => scale.saturating_mul(black_levels[i]),
+            _ => scale.saturating_mul(black_levels[0]),
+        })
+    }
+    /// The white balance of the maker notes is preferred over the AsShotNeutral
+    fn get_white_balance(&self, _buffer: &[u8]) -> Result<[i32; 3], DecodingError> {
+        if let (Ok(r), Ok(g), Ok(b)) = (
+            self.info.i32("maker_note_white_balance_r"),
+            self.info.i32("maker_note_white_balance_g"),
+            self.info.i32("maker_note_white_balance_b"),
+        ) {
+            return Ok([r, g, b]);
+        }
+        let r = 512.0 / self.info.f64("white_balance_r")?;
+        let g = 512.0 / self.info.f64("white_balance_g")?;
+        let b = 512.0 / self.info.f64("white_balance_b")?;
+        Ok([r as i32, g as i32, b as i32])
+    }
+    fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        if self.is_linear_raw() {
+            Ok(CFAPattern::None)
+        } else if self.is_monochrome() {
+            Ok(CFAPattern::Monochrome)
+        } else {
+            // the CFAPattern starts at the origin of the ActiveArea
+            let (top, left) = self
+                .active_area()
+                .map_or((0, 0), |[top, left, ..]| (top, left));
+            Ok(cfa_pattern_from_info(&self.info)?.shifted(left as u32, top as u32))
+        }
+    }
+    /// The DefaultCrop is relative to the ActiveArea, which is the crop when there is no
+    /// DefaultCrop. The crop of the maker notes comes first.
+    fn get_crop(&self) -> Option<Crop> {
+        if let Some(crop) = self.maker_note_crop() {
+            return Some(crop);
+        }
+        let active_area = self.active_area();
+        let (top, left) = active_area.map_or((0, 0), |[top, left, ..]| (top, left));
+        match (self.default_crop(), active_area) {
+            (Some(crop), _) => Some(Crop {
+                x: crop.x + left as u32,
+                y: crop.y + top as u32,
+                ..crop
+            }),
+            (None, Some([top, left, bottom, right])) => Some(Crop {
+                x: left as u32,
+                y: top as u32,
+                width: right.saturating_sub(left) as u32,
+                height: bottom.saturating_sub(top) as u32,
+            }),
+            (None, None) => None,
+        }
+    }
+    /// Applies OpcodeList1 and OpcodeList2, then the radial vignette and the lens distortion
+    /// corrections of OpcodeList3, which are applied to every CFA color on its own before
+    /// demosaicing
+    fn apply_opcodes(&self, buffer: &[u8], mut image: Vec<u16>) -> Result<Vec<u16>, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let samples_per_pixel = image.len() / (width * height).max(1);
+        // the opcodes work on the ActiveArea
+        let [top, left, bottom, right] = self.active_area().unwrap_or([0, 0, height, width]);
+        let (bottom, right) = (bottom.min(height), right.min(width));
+        let area = [top.min(bottom), left.min(right), bottom, right];
+
+        let cfa_pattern = self.get_cfa_pattern().unwrap_or(CFAPattern::None);
+        let cfa_colors = match cfa_pattern {
+            CFAPattern::RGGB => Some([0, 1, 1, 2]),
+            CFAPattern::GRBG => Some([1, 0, 2, 1]),
+            CFAPattern::GBRG => Some([1, 2, 0, 1]),
+            CFAPattern::BGGR => Some([2, 1, 1, 0]),
+            _ => None,
+        };
+        // the colors of X-Trans are not on a grid of their own
+        let is_x_trans = matches!(cfa_pattern, CFAPattern::XTrans0 | CFAPattern::XTrans1);
+
+        let apply = |opcodes: &[Opcode], image: &mut [u16]| {
+            apply_opcode_list(opcodes, image, width, samples_per_pixel, area, cfa_colors)
+        };
+        for name in ["opcode_list_1", "opcode_list_2"] {
+            apply(&self.opcode_list(buffer, name)?, &mut image);
+        }
+        let opcodes = self
+            .opcode_list(buffer, "opcode_list_3")?
+            .into_iter()
+            .filter(|x| match x {
+                Opcode::FixVignetteRadial(_) => true,
+                Opcode::WarpRectilinear(_) => !is_x_trans,
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        apply(&opcodes, &mut image);
+        Ok(image)
+    }
+    /// The ProfileToneCurve of ProRAW files, which brings their rendering close to their
+    /// previews
+    fn get_tone_curve(&self, buffer: &[u8]) -> Result<Option<Vec<u16>>, DecodingError> {
+        match self.is_pro_raw() {
+            true => self.tone_lut(buffer),
+            false => Ok(None),
+        }
+    }
+    /// The files keeping their CFA data in IFD0 without a preview IFD, e.g. GoPro GPR, fall
+    /// back to the largest JPEG of the file
     fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
-        let offset = self.info.usize("thumbnail")?;
-        let len = self.info.usize("thumbnail_len")?;
+        let (offset, len) = match (
+            self.info.usize("thumbnail"),
+            self.info.usize("thumbnail_len"),
+        ) {
+            (Ok(offset), Ok(len)) => (offset, len),
+            (Err(e), _) | (_, Err(e)) => {
+                return crate::decode::largest_jpeg_slice(buffer).ok_or_else(|| e.into());
+            }
+        };
 
         Ok(&buffer[offset..offset + len])
     }
@@ -209,64 +626,75 @@ impl RawDecoder for General {
         let compression = self.info.u16("compression")?;
         let bps = self.info.u16("bps")?;
         let white_level_scale = self.get_white_level_scale()?;
-        let black_level = self.info.u16("black_level")?;
+        // the levels of the CFA and monochrome images are subtracted after the preprocessing
+        let black_levels = match self.is_linear_raw() {
+            true => self.black_levels(),
+            false => vec![0],
+        };
+        let black_level = |i: usize| match black_levels.len() {
+            3 => black_levels[i % 3],
+            _ => black_levels[0],
+        };
 
         macro_rules! to_image {
             ($iter:expr) => {
                 $iter
-                    .map(|x| white_level_scale.saturating_mul(x.saturating_sub(black_level)))
+                    .enumerate()
+                    .map(|(i, x)| {
+                        white_level_scale.saturating_mul(x.saturating_sub(black_level(i)))
+                    })
                     .collect()
             };
         }
 
-        let image: Vec<u16> = match compression {
-            1 => { // uncompressed dng
-                let offset_addr = self.info.usize("strip")?;
-                let offset_count = self.info.usize("strip_offsets_count")?;
-                let len_addr = self.info.usize("strip_len")?;
-
-                let buf = if (offset_count) > 1 {
-                    let strip_addr = buffer.u32(self.info.is_le, offset_addr) as usize;
-                    let tile_count = buffer.u32(self.info.is_le, len_addr) as usize;
-                    &buffer[strip_addr..strip_addr + tile_count * offset_count]
-                } else {
-                    &buffer[offset_addr..offset_addr + len_addr]
-                };
-
-                match bps {
-                    12 => to_image!(to_12bit_iter_packed(buf, self.info.is_le)),
-                    14 => to_image!(to_14bit_iter_packed(buf, self.info.is_le)),
-                    _ => to_image!(to_16bit_iter(buf, self.info.is_le)),
+        let layout = self.layout(buffer)?;
+        if self.is_float() {
+            // the samples are already in the range of 0 to 1
+            return match compression {
+                8 => {
+                    let predictor = self.info.u16("predictor").unwrap_or(1);
+                    load_deflate_float(buffer, &layout, bps as usize, predictor, self.info.is_le)
                 }
+                _ => Err(DecodingError::CompressionNotSupported(format!(
+                    "floating point DNG {}",
+                    compression
+                ))),
+            };
+        }
+        let image: Vec<u16> = match compression {
+            // uncompressed dng
+            1 => load_uncompressed(buffer, &layout, bps as u32, self.info.is_le)?,
+            7 => load_compressed(buffer, &layout)?,
+            #[cfg(feature = "image")]
+            34892 => load_lossy(buffer, &layout)?,
+            #[cfg(not(feature = "image"))]
+            34892 => {
+                return Err(DecodingError::CompressionNotSupported(
+                    "DNG lossy JPEG, which needs the `lossy_dng` feature".to_owned(),
+                ))
             }
-            7 => {
-                // only support Apple ProRaw now
-                let byte_counts_addr = self.info.usize("tile_byte_counts")?;
-                let tile_offsets_count = self.info.usize("tile_offsets_count")?;
-                let tile_offsets_addr = self.info.usize("tile_offsets")?;
-                let tile_width = self.info.usize("tile_width")?;
-                let tile_len = self.info.usize("tile_len")?;
-
-                let offsets_iter =
-                    buffer[tile_offsets_addr..tile_offsets_addr + 4 * tile_offsets_count].chunks(4);
-                let counts_iter =
-                    buffer[byte_counts_addr..byte_counts_addr + 4 * tile_offsets_count].chunks(4);
-
-                let tiles = offsets_iter
-                    .zip(counts_iter)
-                    .map(|(offset_bytes, count_bytes)| {
-                        let offset_addr = offset_bytes.u32(self.info.is_le, 0) as usize;
-                        let count_addr = count_bytes.u32(self.info.is_le, 0) as usize;
-                        (offset_addr, count_addr)
-                    })
-                    .collect::<Vec<_>>();
-
-                load_compressed(buffer, width, height, tiles, tile_width, tile_len)?
+            #[cfg(feature = "jxl")]
+            52546 => load_jxl(buffer, &layout, bps as u32)?,
+            #[cfg(not(feature = "jxl"))]
+            52546 => {
+                return Err(DecodingError::CompressionNotSupported(
+                    "DNG JPEG XL, which needs the `jxl` feature".to_owned(),
+                ))
             }
             _ => {
-                unimplemented!()
+                return Err(DecodingError::CompressionNotSupported(format!(
+                    "DNG {}",
+                    compression
+                )))
             }
         };
+        let image: Vec<u16> = match self.linearization_table(buffer)? {
+            Some(table) => {
+                let last = table.len() - 1;
+                to_image!(image.into_iter().map(|x| table[(x as usize).min(last)]))
+            }
+            None => to_image!(image.into_iter()),
+        };
 
         if image.len() != width * height && image.len() != width * height * 3 {
             Err(DecodingError::InvalidDecodedImageSize(
@@ -279,44 +707,261 @@ impl RawDecoder for General {
     }
 }
 
-fn load_compressed(
+/// The size of the image with the offset and the size of every strip or tile
+pub(super) struct Layout {
+    pub(super) width: usize,
+    pub(super) height: usize,
+    pub(super) samples_per_pixel: usize,
+    pub(super) tiles: Vec<(usize, usize)>,
+    pub(super) tile_width: usize,
+    pub(super) tile_height: usize,
+}
+
+/// Decodes every tile and copies it to its place, the tiles on the right and the bottom
+/// edges can be larger than the rest of the image.
+///
+/// `decode_tile` gives the samples of a tile with the number of samples in a line of it.
+fn load_tiles(
     buffer: &[u8],
-    width: usize,
-    height: usize,
-    tiles: Vec<(usize, usize)>,
-    tile_width: usize,
-    tile_height: usize,
+    layout: &Layout,
+    mut decode_tile: impl FnMut(&[u8]) -> Result<(Vec<u16>, usize), DecodingError>,
 ) -> Result<Vec<u16>, DecodingError> {
-    let mut out = vec![0u16; width * height * 3];
-
-    let tile_count_per_row = width / tile_width;
-
-    for (tile_index, (addr, size)) in tiles.into_iter().enumerate() {
-        let col = tile_index % tile_count_per_row * tile_width * 3;
-        let row = tile_index / tile_count_per_row * tile_height;
-
-        let mut tile_out = vec![0u16; tile_width * tile_height * 3];
+    let line_len = layout.width * layout.samples_per_pixel;
+    let tile_line_len = layout.tile_width * layout.samples_per_pixel;
+    let mut out = vec![0u16; line_len * layout.height];
 
-        let src = &buffer[addr..addr + size];
-        let decompressor = LjpegDecompressor::new(src)?;
+    let tile_count_per_row = layout.width.div_ceil(layout.tile_width);
+    for (tile_index, &(addr, size)) in layout.tiles.iter().enumerate() {
+        let col = tile_index % tile_count_per_row * tile_line_len;
+        let row = tile_index / tile_count_per_row * layout.tile_height;
+        if row >= layout.height {
+            break;
+        }
 
-        decompressor.decode(
-            &mut tile_out,
-            0,
-            tile_width * 3,
-            tile_width * 3,
-            tile_height,
-        )?;
+        let src = buffer
+            .get(addr..addr + size)
+            .ok_or_else(|| DecodingError::InvalidRawData("DNG tile is out of range".to_owned()))?;
+        let (tile_out, tile_out_line_len) = decode_tile(src)?;
+        if tile_out_line_len == 0 {
+            continue;
+        }
 
+        let copy_len = tile_line_len.min(tile_out_line_len).min(line_len - col);
         tile_out
-            .chunks(tile_width * 3)
+            .chunks_exact(tile_out_line_len)
+            .take(layout.tile_height.min(layout.height - row))
             .enumerate()
             .for_each(|(offset_row, data)| {
-                let start = col + (row + offset_row) * width * 3;
-                let end = start + tile_width * 3;
-                (&mut out[start..end]).copy_from_slice(data);
+                let start = col + (row + offset_row) * line_len;
+                out[start..start + copy_len].copy_from_slice(&data[..copy_len]);
             });
     }
 
     Ok(out)
 }
+
+/// Reads the uncompressed strips or tiles, every line of them starts at a byte boundary.
+///
+/// Samples of 8 and 16 bits are stored as they are, the other sizes are packed from the
+/// most significant bit no matter the byte order of the file.
+pub(super) fn load_uncompressed(
+    buffer: &[u8],
+    layout: &Layout,
+    bps: u32,
+    is_le: bool,
+) -> Result<Vec<u16>, DecodingError> {
+    if !(8..=16).contains(&bps) {
+        return Err(DecodingError::InvalidRawData(format!(
+            "DNG with {} bits per sample",
+            bps
+        )));
+    }
+    let tile_line_len = layout.tile_width * layout.samples_per_pixel;
+    let tile_line_bytes = (tile_line_len * bps as usize).div_ceil(8);
+
+    load_tiles(buffer, layout, |src| {
+        let mut tile_out = vec![0u16; src.len() / tile_line_bytes * tile_line_len];
+        tile_out
+            .chunks_exact_mut(tile_line_len)
+            .zip(src.chunks_exact(tile_line_bytes))
+            .for_each(|(out, src)| unpack_line(src, out, bps, is_le));
+        Ok((tile_out, tile_line_len))
+    })
+}
+
+fn unpack_line(src: &[u8], out: &mut [u16], bps: u32, is_le: bool) {
+    match bps {
+        8 => out.iter_mut().zip(src).for_each(|(x, &b)| *x = b as u16),
+        16 => out
+            .iter_mut()
+            .zip(to_16bit_iter(src, is_le))
+            .for_each(|(x, v)| *x = v),
+        _ => {
+            let mut bytes = src.iter();
+            let (mut bits, mut nbits) = (0u32, 0u32);
+            for x in out.iter_mut() {
+                while nbits < bps {
+                    bits = (bits << 8) | *bytes.next().unwrap_or(&0) as u32;
+                    nbits += 8;
+                }
+                nbits -= bps;
+                *x = (bits >> nbits) as u16 & ((1u32 << bps) - 1) as u16;
+                bits &= (1 << nbits) - 1;
+            }
+        }
+    }
+}
+
+/// Decodes the lossless JPEG tiles
+fn load_compressed(buffer: &[u8], layout: &Layout) -> Result<Vec<u16>, DecodingError> {
+    load_tiles(buffer, layout, |src| {
+        let decompressor = LjpegDecompressor::new(src)?;
+        let jpeg_width = decompressor.width();
+        let jpeg_height = decompressor.height();
+
+        let mut tile_out = vec![0u16; jpeg_width * jpeg_height];
+        decompressor.decode(&mut tile_out, 0, jpeg_width, jpeg_width, jpeg_height)?;
+        Ok((tile_out, jpeg_width))
+    })
+}
+
+/// Decodes the 8 bit baseline JPEG tiles of lossy DNG
+#[cfg(feature = "image")]
+fn load_lossy(buffer: &[u8], layout: &Layout) -> Result<Vec<u16>, DecodingError> {
+    use image::codecs::jpeg::JpegDecoder;
+    use image::ImageDecoder;
+
+    let jpeg_error = |e: image::ImageError| DecodingError::InvalidRawData(e.to_string());
+    load_tiles(buffer, layout, |src| {
+        let decoder = JpegDecoder::new(std::io::Cursor::new(src)).map_err(jpeg_error)?;
+        let jpeg_width =
+            decoder.dimensions().0 as usize * decoder.color_type().channel_count() as usize;
+        let mut tile_out = vec![0u8; decoder.total_bytes() as usize];
+        decoder.read_image(&mut tile_out).map_err(jpeg_error)?;
+        Ok((tile_out.into_iter().map(|x| x as u16).collect(), jpeg_width))
+    })
+}
+
+/// Decodes the JPEG XL tiles of DNG 1.7, the samples are brought back to the bit depth of the file
+#[cfg(feature = "jxl")]
+fn load_jxl(buffer: &[u8], layout: &Layout, bps: u32) -> Result<Vec<u16>, DecodingError> {
+    let max = ((1u32 << bps) - 1) as f32;
+    load_tiles(buffer, layout, |src| {
+        let image = jxl_oxide::JxlImage::builder()
+            .read(std::io::Cursor::new(src))
+            .map_err(|e| DecodingError::InvalidRawData(e.to_string()))?;
+        let render = image
+            .render_frame(0)
+            .map_err(|e| DecodingError::InvalidRawData(e.to_string()))?;
+        let mut stream = render.stream();
+        let line_len = stream.width() as usize * stream.channels() as usize;
+        let mut tile_out = vec![0f32; line_len * stream.height() as usize];
+        stream.write_to_buffer(&mut tile_out);
+        let tile_out = tile_out
+            .into_iter()
+            .map(|x| (x.clamp(0.0, 1.0) * max).round() as u16)
+            .collect();
+        Ok((tile_out, line_len))
+    })
+}
+
+/// Inflates the floating point tiles and maps the range of 0 to 1 to the whole range of `u16`.
+///
+/// The floating point predictors (3, 34894 and 34895) keep the bytes of every line split
+/// into planes from the most significant one and store the differences of the bytes.
+fn load_deflate_float(
+    buffer: &[u8],
+    layout: &Layout,
+    bps: usize,
+    predictor: u16,
+    is_le: bool,
+) -> Result<Vec<u16>, DecodingError> {
+    let bytes_per_sample = match bps {
+        16 | 24 | 32 => bps / 8,
+        _ => {
+            return Err(DecodingError::InvalidRawData(format!(
+                "floating point DNG with {} bits per sample",
+                bps
+            )))
+        }
+    };
+    let factor = match predictor {
+        1 | 3 => 1,
+        34894 => 2,
+        34895 => 4,
+        _ => {
+            return Err(DecodingError::CompressionNotSupported(format!(
+                "DNG predictor {}",
+                predictor
+            )))
+        }
+    };
+    let tile_line_len = layout.tile_width * layout.samples_per_pixel;
+    let tile_line_bytes = tile_line_len * bytes_per_sample;
+
+    load_tiles(buffer, layout, |src| {
+        let mut data = miniz_oxide::inflate::decompress_to_vec_zlib(src).map_err(|e| {
+            DecodingError::InvalidRawData(format!("DNG deflate data is invalid: {:?}", e))
+        })?;
+        let mut tile_out = Vec::with_capacity(data.len() / bytes_per_sample);
+        for line in data.chunks_exact_mut(tile_line_bytes) {
+            if predictor != 1 {
+                let stride = factor * layout.samples_per_pixel;
+                for i in stride..line.len() {
+                    line[i] = line[i].wrapping_add(line[i - stride]);
+                }
+            }
+            let sample_bits = |col: usize| -> u32 {
+                (0..bytes_per_sample).fold(0, |bits, b| {
+                    let byte = if predictor == 1 {
+                        let at = col * bytes_per_sample;
+                        line[if is_le {
+                            at + bytes_per_sample - 1 - b
+                        } else {
+                            at + b
+                        }]
+                    } else {
+                        line[col + b * tile_line_len]
+                    };
+                    (bits << 8) | byte as u32
+                })
+            };
+            tile_out.extend((0..tile_line_len).map(|col| {
+                let value = float_from_bits(sample_bits(col), bps);
+                (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+            }));
+        }
+        Ok((tile_out, tile_line_len))
+    })
+}
+
+/// Converts the 16 bit half floats and the 24 bit floats of DNG to `f32`
+fn float_from_bits(bits: u32, bps: usize) -> f32 {
+    let (exp_bits, mantissa_bits): (u32, u32) = match bps {
+        16 => (5, 10),
+        24 => (7, 16),
+        _ => return f32::from_bits(bits),
+    };
+    let sign = bits >> (bps - 1);
+    let exp = (bits >> mantissa_bits) & ((1 << exp_bits) - 1);
+    let mantissa = bits & ((1 << mantissa_bits) - 1);
+    let bias: i32 = (1 << (exp_bits - 1)) - 1;
+
+    let value = if exp == 0 {
+        // subnormal
+        mantissa as f32 * 2f32.powi(1 - bias - mantissa_bits as i32)
+    } else if exp == (1 << exp_bits) - 1 {
+        if mantissa == 0 {
+            f32::INFINITY
+        } else {
+            f32::NAN
+        }
+    } else {
+        (1.0 + mantissa as f32 / (1u32 << mantissa_bits) as f32) * 2f32.powi(exp as i32 - bias)
+    };
+    if sign == 1 {
+        -value
+    } else {
+        value
+    }
+}
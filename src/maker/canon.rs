@@ -1,6 +1,9 @@
 use super::*;
 use once_cell::sync::Lazy;
 
+use super::decode_utility::ljpeg::LjpegDecompressor;
+use super::utility::GetNumFromBytes;
+
 pub(super) struct General {
     info: quickexif::ParsedInfo,
 }
@@ -15,7 +18,7 @@ pub(super) static THUMBNAIL_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     })
 });
 
-pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+pub(super) static EXIF_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     quickexif::describe_rule!(tiff {
         0x0112 / orientation
         0x8769 {
@@ -29,6 +32,199 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     })
 });
 
+// the Canon ColorData block is shared by CR2 makernotes and the CMT3 box of CR3 files
+macro_rules! white_balance_rule {
+    () => {
+        quickexif::describe_rule!(template {
+            // the offset of WB_RGGBLevelsAsShot depends on the ColorData version
+            if color_data_len > 5000 {
+                0x4001 {
+                    u16 + 71 / white_balance_r
+                    u16 + 72 / white_balance_g
+                    u16 + 74 / white_balance_b
+                }
+            } else {
+                if color_data_len > 3700 {
+                    0x4001 {
+                        u16 + 105 / white_balance_r
+                        u16 + 106 / white_balance_g
+                        u16 + 108 / white_balance_b
+                    }
+                } else {
+                    if color_data_len > 2000 {
+                        0x4001 {
+                            u16 + 85 / white_balance_r
+                            u16 + 86 / white_balance_g
+                            u16 + 88 / white_balance_b
+                        }
+                    } else {
+                        if color_data_len > 1800 {
+                            0x4001 {
+                                u16 + 71 / white_balance_r
+                                u16 + 72 / white_balance_g
+                                u16 + 74 / white_balance_b
+                            }
+                        } else {
+                            if color_data_len > 660 {
+                                0x4001 {
+                                    u16 + 63 / white_balance_r
+                                    u16 + 64 / white_balance_g
+                                    u16 + 66 / white_balance_b
+                                }
+                            } else {
+                                if color_data_len == 653 {
+                                    0x4001 {
+                                        u16 + 24 / white_balance_r
+                                        u16 + 25 / white_balance_g
+                                        u16 + 27 / white_balance_b
+                                    }
+                                } else {
+                                    0x4001 {
+                                        u16 + 25 / white_balance_r
+                                        u16 + 26 / white_balance_g
+                                        u16 + 28 / white_balance_b
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    };
+}
+
+pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    let white_balance_rule = white_balance_rule!();
+
+    quickexif::describe_rule!(tiff {
+        0x0112 / orientation
+        0x8769 {
+            0x927c {
+                0x0001? {
+                    u16 + 46 / sraw_quality
+                }
+                0x0010? / model_id
+                0x00e0? {
+                    u16 + 5 / sensor_left_border
+                    u16 + 6 / sensor_top_border
+                    u16 + 7 / sensor_right_border
+                    u16 + 8 / sensor_bottom_border
+                }
+                0x4001 / color_data(color_data_len)
+                load(white_balance_rule)
+            }
+        }
+        next {
+            next {
+                next {
+                    0x0111 / strip(strip_count)
+                    0x0117 / strip_len
+                    0xc640? {
+                        u16 + 0 / cr2_slice_count
+                        u16 + 1 / cr2_slice_width
+                        u16 + 2 / cr2_slice_last_width
+                    }
+                }
+            }
+        }
+    })
+});
+
+/// The CMT3 box of a CR3 file is a TIFF holding the Canon makernote as its first IFD
+pub(super) static CMT3_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    let white_balance_rule = white_balance_rule!();
+
+    quickexif::describe_rule!(tiff {
+        0x00e0? {
+            u16 + 5 / sensor_left_border
+            u16 + 6 / sensor_top_border
+            u16 + 7 / sensor_right_border
+            u16 + 8 / sensor_bottom_border
+        }
+        0x4001 / color_data(color_data_len)
+        load(white_balance_rule)
+    })
+});
+
+/// Canon model ids of the cameras whose sRAW chroma needs the old hue correction
+const SRAW_OLD_HUE_MODELS: [u32; 5] = [0x80000218, 0x80000250, 0x80000261, 0x80000281, 0x80000287];
+
+impl General {
+    /// sRAW and mRAW files store subsampled YCbCr instead of the bayer mosaic
+    fn is_sraw(&self) -> bool {
+        self.info
+            .u16("sraw_quality")
+            .map(|x| x > 0)
+            .unwrap_or(false)
+    }
+    /// Dual Pixel RAW files keep a second LJPEG strip with the A sub-pixel image
+    fn get_raw_data<'a>(&self, buffer: &'a [u8], frame: usize) -> Result<&'a [u8], DecodingError> {
+        let count = self.info.usize("strip_count").unwrap_or(1);
+        if frame >= count {
+            return Err(DecodingError::FrameNotFound(frame));
+        }
+
+        let offset = self.info.usize("strip")?;
+        let len = self.info.usize("strip_len")?;
+        let (offset, len) = if count > 1 {
+            // the fields hold the addresses of the offset and length arrays
+            let is_le = self.info.is_le;
+            (
+                buffer.u32(is_le, offset + frame * 4) as usize,
+                buffer.u32(is_le, len + frame * 4) as usize,
+            )
+        } else {
+            (offset, len)
+        };
+        buffer
+            .get(offset..offset + len)
+            .ok_or_else(|| DecodingError::InvalidRawData("CR2 strip is out of range".to_owned()))
+    }
+    /// Decodes sRAW and mRAW data to interleaved RGB, follows `canon_sraw_load_raw` of dcraw
+    fn decode_sraw(&self, buffer: &[u8], frame: usize) -> Result<Vec<u16>, DecodingError> {
+        let decompressor = LjpegDecompressor::new(self.get_raw_data(buffer, frame)?)?;
+        let layout = SrawLayout::new(&decompressor, self.get_slices())?;
+
+        let mut mcus = vec![0u16; layout.line_len * decompressor.height()];
+        decompressor.decode_mcus(&mut mcus, layout.line_len)?;
+        let mut ycc = layout.place(&mcus);
+        layout.interpolate_chroma(&mut ycc);
+
+        let model_id = self.info.u32("model_id").ok();
+        let luma_count = (layout.super_h * layout.super_v) as i32;
+        let hue = match model_id {
+            Some(id) if id >= 0x80000281 => (luma_count - 1) << 1,
+            _ => luma_count << 2,
+        };
+
+        // the camera has already applied the as shot white balance to sRAW data, it is
+        // divided out here so that it isn't applied twice later on
+        let [wb_r, wb_g, wb_b] = self.get_white_balance(buffer)?;
+        let wb_fix = [wb_r, wb_g, wb_b].map(|x| (wb_g << 10) / x.max(1));
+        let max = (1 << decompressor.precision()) - 1;
+        let scale = 1 << 16usize.saturating_sub(decompressor.precision());
+
+        Ok(ycc
+            .into_iter()
+            .flat_map(|pixel| {
+                let rgb = sraw_to_rgb(pixel, model_id, hue);
+                [0, 1, 2].map(|c| (((rgb[c] * wb_fix[c]) >> 10).clamp(0, max) * scale) as u16)
+            })
+            .collect())
+    }
+    fn get_slices(&self) -> Option<[usize; 3]> {
+        let count = self.info.usize("cr2_slice_count").ok()?;
+        let width = self.info.usize("cr2_slice_width").ok()?;
+        let last_width = self.info.usize("cr2_slice_last_width").ok()?;
+        if width == 0 || last_width == 0 {
+            None
+        } else {
+            Some([count, width, last_width])
+        }
+    }
+}
+
 impl RawDecoder for General {
     fn new(info: quickexif::ParsedInfo) -> Self {
         General { info }
@@ -40,13 +236,71 @@ impl RawDecoder for General {
         self.info
     }
     fn get_crop(&self) -> Option<Crop> {
-        None
+        if self.is_sraw() {
+            // the sensor borders are given in full resolution pixels
+            return None;
+        }
+        sensor_crop(&self.info)
     }
     fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
-        Ok(CFAPattern::RGGB)
+        if self.is_sraw() {
+            return Ok(CFAPattern::None);
+        }
+        Ok(cfa_pattern_from_info(&self.info).unwrap_or(CFAPattern::RGGB))
+    }
+    fn get_image_size(&self, buffer: &[u8]) -> Result<(usize, usize), DecodingError> {
+        let decompressor = LjpegDecompressor::new(self.get_raw_data(buffer, 0)?)?;
+        if self.is_sraw() {
+            let layout = SrawLayout::new(&decompressor, self.get_slices())?;
+            return Ok((layout.width, layout.height));
+        }
+        let sample_count = decompressor.width() * decompressor.height();
+        let width = match self.get_slices() {
+            Some([count, width, last_width]) => count * width + last_width,
+            None => decompressor.width(),
+        };
+        Ok((width, sample_count / width))
+    }
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        self.decode_frame_with_preprocess(buffer, 0)
     }
-    fn decode_with_preprocess(&self, _buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
-        unimplemented!("Canon raw decoding is not implemented yet.")
+    fn get_frame_count(&self, _buffer: &[u8]) -> usize {
+        self.info.usize("strip_count").unwrap_or(1)
+    }
+    fn decode_frame_with_preprocess(
+        &self,
+        buffer: &[u8],
+        frame: usize,
+    ) -> Result<Vec<u16>, DecodingError> {
+        if self.is_sraw() {
+            return self.decode_sraw(buffer, frame);
+        }
+
+        let src = self.get_raw_data(buffer, frame)?;
+        let (width, height) = self.get_image_size(buffer)?;
+
+        let decompressor = LjpegDecompressor::new(src)?;
+        let jpeg_width = decompressor.width();
+        let jpeg_height = decompressor.height();
+        let bps_scale = match decompressor.precision() {
+            12 => 16,
+            14 => 4,
+            _ => 1,
+        };
+
+        let mut ljpeg_out = vec![0u16; jpeg_width * jpeg_height];
+        decompressor.decode(&mut ljpeg_out, 0, jpeg_width, jpeg_width, jpeg_height)?;
+
+        let image = match self.get_slices() {
+            Some(slices) => reassemble_slices(&ljpeg_out, slices, width, height)?,
+            None => ljpeg_out,
+        };
+
+        let black_level = masked_area_black_level(&self.info, &image, width);
+        Ok(image
+            .iter()
+            .map(|x| bps_scale.saturating_mul(x.saturating_sub(black_level)))
+            .collect())
     }
     fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
         // Prefer the Exif-provided preview when it looks like a displayable JPEG (APP0/APP1).
@@ -65,6 +319,48 @@ impl RawDecoder for General {
     }
 }
 
+/// Measures the black level from the optical black area on the left side of the sensor
+pub(super) fn masked_area_black_level(
+    info: &quickexif::ParsedInfo,
+    image: &[u16],
+    width: usize,
+) -> u16 {
+    let left = info.usize("sensor_left_border").unwrap_or(0);
+    let top = info.usize("sensor_top_border").unwrap_or(0);
+    let bottom = info.usize("sensor_bottom_border").unwrap_or(0);
+    if left <= 4 || bottom <= top || left >= width {
+        return 0;
+    }
+
+    let (sum, count) = image
+        .chunks_exact(width)
+        .skip(top)
+        .take(bottom - top)
+        .flat_map(|row| row[2..left - 2].iter())
+        .fold((0u64, 0u64), |(sum, count), &v| (sum + v as u64, count + 1));
+
+    if count == 0 {
+        0
+    } else {
+        (sum / count) as u16
+    }
+}
+
+pub(super) fn sensor_crop(info: &quickexif::ParsedInfo) -> Option<Crop> {
+    let x = info.u32("sensor_left_border").ok()?;
+    let y = info.u32("sensor_top_border").ok()?;
+    let right = info.u32("sensor_right_border").ok()?;
+    let bottom = info.u32("sensor_bottom_border").ok()?;
+
+    // the borders of broken files may be reversed
+    Some(Crop {
+        x,
+        y,
+        width: right.checked_sub(x)? + 1,
+        height: bottom.checked_sub(y)? + 1,
+    })
+}
+
 fn find_largest_jpeg_slice<'a>(buffer: &'a [u8]) -> Option<&'a [u8]> {
     let mut start = 0usize;
     let mut best: Option<(usize, usize)> = None;
@@ -142,3 +438,177 @@ fn find_display_jpeg_slice<'a>(buffer: &'a [u8]) -> Option<&'a [u8]> {
             None
         })
 }
+
+/// Where the MCUs of a sRAW stream go in the image
+struct SrawLayout {
+    super_h: usize,
+    super_v: usize,
+    line_len: usize,
+    slice_widths: Vec<usize>,
+    width: usize,
+    height: usize,
+}
+
+impl SrawLayout {
+    fn new(
+        decompressor: &LjpegDecompressor,
+        slices: Option<[usize; 3]>,
+    ) -> Result<SrawLayout, DecodingError> {
+        let super_h = decompressor.super_h();
+        let super_v = decompressor.super_v();
+        if decompressor.components() != 3 || super_h != 2 || !(1..=2).contains(&super_v) {
+            return Err(DecodingError::InvalidRawData(format!(
+                "sRAW with {} components and {}x{} subsampling is not supported",
+                decompressor.components(),
+                super_h,
+                super_v
+            )));
+        }
+
+        // every MCU holds the luma of super_h x super_v pixels followed by Cb and Cr
+        let mcu_len = super_h * super_v + 2;
+        let jpeg_width = decompressor.width() / decompressor.components();
+        let line_len = jpeg_width / super_h * mcu_len;
+
+        // the slice widths count the samples of the MCUs rather than pixels
+        let slice_widths: Vec<usize> = match slices {
+            Some([count, width, last_width]) => std::iter::repeat(width)
+                .take(count)
+                .chain([last_width])
+                .map(|x| x * super_h / mcu_len)
+                .collect(),
+            None => vec![jpeg_width],
+        };
+        let width: usize = slice_widths.iter().sum();
+        if width == 0 {
+            return Err(DecodingError::InvalidRawData("sRAW width is 0".to_owned()));
+        }
+        let pixel_count = line_len * decompressor.height() / mcu_len * super_h * super_v;
+
+        Ok(SrawLayout {
+            super_h,
+            super_v,
+            line_len,
+            slice_widths,
+            width,
+            height: pixel_count / width,
+        })
+    }
+
+    /// Puts the luma of every MCU to its pixels, the chroma only goes to the top left one
+    fn place(&self, mcus: &[u16]) -> Vec<[i32; 3]> {
+        let mut ycc = vec![[0i32; 3]; self.width * self.height];
+        let mut mcus = mcus.chunks_exact(self.super_h * self.super_v + 2);
+
+        let mut x = 0;
+        for &slice_width in &self.slice_widths {
+            for row in (0..self.height).step_by(self.super_v) {
+                for col in (x..x + slice_width).step_by(self.super_h) {
+                    let mcu = match mcus.next() {
+                        Some(mcu) => mcu,
+                        None => return ycc,
+                    };
+                    let (luma, chroma) = mcu.split_at(mcu.len() - 2);
+                    for (i, &y) in luma.iter().enumerate() {
+                        let (row, col) = (row + i / self.super_h, col + i % self.super_h);
+                        if row < self.height && col < self.width {
+                            ycc[row * self.width + col][0] = y as i32;
+                        }
+                    }
+                    let pixel = &mut ycc[row * self.width + col];
+                    pixel[1] = chroma[0] as i32 - 16384;
+                    pixel[2] = chroma[1] as i32 - 16384;
+                }
+            }
+            x += slice_width;
+        }
+
+        ycc
+    }
+
+    fn interpolate_chroma(&self, ycc: &mut [[i32; 3]]) {
+        let w = self.width;
+        let h = self.height;
+        for row in 0..h {
+            if self.super_v == 2 && row % 2 == 1 {
+                for i in (row * w..(row + 1) * w).step_by(2) {
+                    for c in 1..3 {
+                        ycc[i][c] = if row == h - 1 {
+                            ycc[i - w][c]
+                        } else {
+                            (ycc[i - w][c] + ycc[i + w][c] + 1) >> 1
+                        };
+                    }
+                }
+            }
+            for col in (1..w).step_by(2) {
+                let i = row * w + col;
+                for c in 1..3 {
+                    ycc[i][c] = if col == w - 1 {
+                        ycc[i - 1][c]
+                    } else {
+                        (ycc[i - 1][c] + ycc[i + 1][c] + 1) >> 1
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn sraw_to_rgb([y, cb, cr]: [i32; 3], model_id: Option<u32>, hue: i32) -> [i32; 3] {
+    match model_id {
+        Some(id) if SRAW_OLD_HUE_MODELS.contains(&id) => {
+            let cb = (cb << 2) + hue;
+            let cr = (cr << 2) + hue;
+            [
+                y + ((50 * cb + 22929 * cr) >> 14),
+                y + ((-5640 * cb - 11751 * cr) >> 14),
+                y + ((29040 * cb - 101 * cr) >> 14),
+            ]
+        }
+        _ => {
+            let y = match model_id {
+                Some(id) if id < 0x80000218 => y - 512,
+                _ => y,
+            };
+            [y + cr, y + ((-778 * cb - (cr << 11)) >> 12), y + cb]
+        }
+    }
+}
+
+/// CR2 stores the sensor as vertical slices, each slice is encoded from top to bottom
+/// before the next one starts in the LJPEG stream.
+fn reassemble_slices(
+    src: &[u16],
+    [count, slice_width, last_width]: [usize; 3],
+    width: usize,
+    height: usize,
+) -> Result<Vec<u16>, DecodingError> {
+    let mut out = vec![0u16; width * height];
+    let mut src = src;
+
+    for slice in 0..=count {
+        let current_width = if slice < count { slice_width } else { last_width };
+        let slice_len = current_width * height;
+        if src.len() < slice_len {
+            return Err(DecodingError::InvalidRawData(format!(
+                "CR2 slice {} needs {} samples but only {} are left",
+                slice,
+                slice_len,
+                src.len()
+            )));
+        }
+
+        let x = slice * slice_width;
+        src[..slice_len]
+            .chunks_exact(current_width)
+            .enumerate()
+            .for_each(|(row, data)| {
+                let start = row * width + x;
+                out[start..start + current_width].copy_from_slice(data);
+            });
+        src = &src[slice_len..];
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,269 @@
+use super::*;
+use crate::RawFileReadingError;
+use once_cell::sync::Lazy;
+
+use super::decode_utility::crx::{self, CrxHeader};
+use super::utility::GetNumFromBytes;
+
+pub(super) struct General {
+    info: quickexif::ParsedInfo,
+}
+
+// Canon's uuid box inside `moov` holding the CMT1-CMT4 metadata boxes
+const CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+static ORIENTATION_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 / orientation
+    })
+});
+
+// the values read from the CMP1 box, see `parse_info`
+static LAYOUT_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0001 / width
+        0x0002 / height
+        0x0003 / cfa_layout
+    })
+});
+
+#[derive(Default)]
+struct Track {
+    cmp1: Option<usize>,
+    offsets: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Container {
+    cmt1: Option<(usize, usize)>,
+    cmt3: Option<(usize, usize)>,
+    tracks: Vec<Track>,
+}
+
+impl Container {
+    fn new(buffer: &[u8]) -> Container {
+        let mut container = Container::default();
+        container.walk(buffer, 0, buffer.len());
+        container
+    }
+    fn walk(&mut self, buffer: &[u8], mut pos: usize, end: usize) {
+        while pos + 8 <= end {
+            let mut size = buffer.u32be(pos) as usize;
+            let name = &buffer[pos + 4..pos + 8];
+            let mut header_len = 8;
+            if size == 1 {
+                if pos + 16 > end {
+                    return;
+                }
+                size =
+                    ((buffer.u32be(pos + 8) as u64) << 32 | buffer.u32be(pos + 12) as u64) as usize;
+                header_len = 16;
+            } else if size == 0 {
+                size = end - pos;
+            }
+            if size < header_len || pos + size > end {
+                return;
+            }
+
+            let start = pos + header_len;
+            let box_end = pos + size;
+            match name {
+                b"moov" | b"mdia" | b"minf" | b"stbl" => self.walk(buffer, start, box_end),
+                b"trak" => {
+                    self.tracks.push(Track::default());
+                    self.walk(buffer, start, box_end);
+                }
+                b"uuid" if buffer.get(start..start + 16) == Some(&CANON_UUID[..]) => {
+                    self.walk(buffer, start + 16, box_end)
+                }
+                // sample description: version, flags and the entry count come first
+                b"stsd" => self.walk(buffer, start + 8, box_end),
+                // the CRAW sample entry has 82 bytes of fields before its child boxes
+                b"CRAW" => self.walk(buffer, start + 82, box_end),
+                b"CMT1" => self.cmt1 = Some((start, box_end)),
+                b"CMT3" => self.cmt3 = Some((start, box_end)),
+                b"CMP1" => {
+                    if let Some(track) = self.tracks.last_mut() {
+                        track.cmp1 = Some(start);
+                    }
+                }
+                b"stsz" if start + 12 <= box_end => {
+                    if let Some(track) = self.tracks.last_mut() {
+                        let sample_size = buffer.u32be(start + 4) as usize;
+                        let count = buffer.u32be(start + 8) as usize;
+                        track.sizes = match sample_size {
+                            0 => (start + 12..box_end)
+                                .step_by(4)
+                                .take(count)
+                                .filter(|&pos| pos + 4 <= box_end)
+                                .map(|pos| buffer.u32be(pos) as usize)
+                                .collect(),
+                            _ => vec![sample_size; count],
+                        };
+                    }
+                }
+                b"co64" if start + 8 <= box_end => {
+                    if let Some(track) = self.tracks.last_mut() {
+                        track.offsets = (start + 8..box_end)
+                            .step_by(8)
+                            .filter(|&pos| pos + 8 <= box_end)
+                            .map(|pos| {
+                                ((buffer.u32be(pos) as u64) << 32 | buffer.u32be(pos + 4) as u64)
+                                    as usize
+                            })
+                            .collect();
+                    }
+                }
+                b"stco" if start + 8 <= box_end => {
+                    if let Some(track) = self.tracks.last_mut() {
+                        track.offsets = (start + 8..box_end)
+                            .step_by(4)
+                            .filter(|&pos| pos + 4 <= box_end)
+                            .map(|pos| buffer.u32be(pos) as usize)
+                            .collect();
+                    }
+                }
+                _ => {}
+            }
+
+            pos = box_end;
+        }
+    }
+    fn slice<'a>(buffer: &'a [u8], range: Option<(usize, usize)>) -> Option<&'a [u8]> {
+        range.map(|(start, end)| &buffer[start..end])
+    }
+    /// The CMP1 offset, data offset and data size of every bayer CRX image.
+    ///
+    /// The first one is the full size raw image, Dual Pixel RAW files keep the A sub-pixel
+    /// image as another sample of the track. Every sample is stored in its own chunk.
+    fn raw_frames(&self, buffer: &[u8]) -> Vec<(usize, usize, usize)> {
+        self.tracks
+            .iter()
+            .filter_map(|track| {
+                let cmp1 = track.cmp1?;
+                CrxHeader::is_bayer(&buffer[cmp1..]).then_some((cmp1, track))
+            })
+            .flat_map(|(cmp1, track)| {
+                track
+                    .offsets
+                    .iter()
+                    .zip(track.sizes.iter())
+                    .map(move |(&offset, &size)| (cmp1, offset, size))
+            })
+            .collect()
+    }
+}
+
+pub(in super::super) fn is_cr3(buffer: &[u8]) -> bool {
+    buffer.get(4..12).map(|b| b == b"ftypcrx ").unwrap_or(false)
+}
+
+/// The CMT1 box is a TIFF with the IFD0 of the image, which has make and model
+pub(in super::super) fn cmt1_slice(buffer: &[u8]) -> Option<&[u8]> {
+    Container::slice(buffer, Container::new(buffer).cmt1)
+}
+
+/// Collects the decoding information from the CMT1 and CMT3 boxes and the raw track.
+///
+/// The track layout is not stored in TIFF tags, so it is written into a small TIFF
+/// and parsed the same way as the other fields.
+pub(super) fn parse_info(
+    buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    let container = Container::new(buffer);
+    let invalid =
+        |msg: &str| -> RawFileReadingError { DecodingError::InvalidRawData(msg.to_owned()).into() };
+
+    let (cmp1, _, _) = *container
+        .raw_frames(buffer)
+        .first()
+        .ok_or_else(|| invalid("CR3 raw track is not found"))?;
+    let header = CrxHeader::new(&buffer[cmp1..]).map_err(DecodingError::from)?;
+
+    let info = match Container::slice(buffer, container.cmt1) {
+        Some(cmt1) => quickexif::parse_with_prev_info(cmt1, &ORIENTATION_RULE, basic_info)?,
+        None => basic_info,
+    };
+    let cmt3 =
+        Container::slice(buffer, container.cmt3).ok_or_else(|| invalid("CMT3 box is not found"))?;
+    let info = quickexif::parse_with_prev_info(cmt3, &canon::CMT3_RULE, info)?;
+
+    let layout = utility::TiffBuilder::new()
+        .long(0x0001, header.width as u32)
+        .long(0x0002, header.height as u32)
+        .long(0x0003, header.cfa_layout as u32)
+        .build();
+    Ok(quickexif::parse_with_prev_info(
+        &layout,
+        &LAYOUT_RULE,
+        info,
+    )?)
+}
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        canon::sensor_crop(&self.info)
+    }
+    fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        let result = match self.info.u32("cfa_layout")? {
+            1 => CFAPattern::GRBG,
+            2 => CFAPattern::GBRG,
+            3 => CFAPattern::BGGR,
+            _ => CFAPattern::RGGB,
+        };
+        Ok(result)
+    }
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        self.decode_frame_with_preprocess(buffer, 0)
+    }
+    fn get_frame_count(&self, buffer: &[u8]) -> usize {
+        Container::new(buffer).raw_frames(buffer).len()
+    }
+    fn decode_frame_with_preprocess(
+        &self,
+        buffer: &[u8],
+        frame: usize,
+    ) -> Result<Vec<u16>, DecodingError> {
+        let (cmp1, offset, size) = *Container::new(buffer)
+            .raw_frames(buffer)
+            .get(frame)
+            .ok_or(DecodingError::FrameNotFound(frame))?;
+        if offset + size > buffer.len() {
+            return Err(DecodingError::InvalidRawData(
+                "CR3 raw track is out of range".to_owned(),
+            ));
+        }
+
+        let header = CrxHeader::new(&buffer[cmp1..])?;
+        let image = crx::decode(&header, &buffer[offset..offset + size])?;
+
+        let bps_scale: u16 = match header.bits {
+            12 => 16,
+            14 => 4,
+            _ => 1,
+        };
+        let black_level = canon::masked_area_black_level(&self.info, &image, header.width);
+        Ok(image
+            .iter()
+            .map(|x| bps_scale.saturating_mul(x.saturating_sub(black_level)))
+            .collect())
+    }
+    fn get_thumbnail<'a>(&self, _buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        Err(DecodingError::RawInfoError(
+            quickexif::parsed_info::Error::FieldNotFound("thumbnail".into()),
+        ))
+    }
+}
@@ -0,0 +1,448 @@
+use super::*;
+use crate::RawFileReadingError;
+use once_cell::sync::Lazy;
+
+use super::decode_utility::bit_pump::{BitPump, BitPumpJPEG};
+use super::decode_utility::huffman::HuffTable;
+use super::utility::GetNumFromBytes;
+
+pub(super) struct General {
+    info: quickexif::ParsedInfo,
+}
+
+// the values read from the CIFF records, see `parse_info`
+static LAYOUT_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0001 / width
+        0x0002 / height
+        0x0003 / decoder_table
+        0x0004 / heap_start
+        0x0005 / sensor_left_border
+        0x0006 / sensor_top_border
+        0x0007 / sensor_right_border
+        0x0008 / sensor_bottom_border
+        0x0009 / white_balance_r
+        0x000a / white_balance_g
+        0x000b / white_balance_b
+        0x0112 / orientation
+    })
+});
+
+const MAKE_MODEL: u16 = 0x080a;
+const COLOR_INFO: u16 = 0x102c;
+const WHITE_BALANCE_TABLE: u16 = 0x10a9;
+const SENSOR_INFO: u16 = 0x1031;
+const IMAGE_INFO: u16 = 0x1810;
+const DECODER_TABLE: u16 = 0x1835;
+const JPEG_IMAGE: u16 = 0x2007;
+
+struct Record {
+    tag: u16,
+    offset: usize,
+    len: usize,
+}
+
+/// The records of a CIFF file, the heaps are flattened as they don't share tags
+struct Ciff<'a> {
+    buffer: &'a [u8],
+    is_le: bool,
+    heap_start: usize,
+    records: Vec<Record>,
+}
+
+impl<'a> Ciff<'a> {
+    fn new(buffer: &'a [u8]) -> Option<Ciff<'a>> {
+        if !is_crw(buffer) {
+            return None;
+        }
+
+        let is_le = buffer[0] == 0x49;
+        let heap_start = buffer.u32(is_le, 2) as usize;
+        let heap_len = buffer.len().checked_sub(heap_start)?;
+        let mut ciff = Ciff {
+            buffer,
+            is_le,
+            heap_start,
+            records: vec![],
+        };
+        ciff.walk(heap_start, heap_len, 0);
+        Some(ciff)
+    }
+    fn walk(&mut self, start: usize, len: usize, depth: usize) {
+        let end = start + len;
+        if depth > 8 || len < 4 || end > self.buffer.len() {
+            return;
+        }
+
+        let dir = start + self.buffer.u32(self.is_le, end - 4) as usize;
+        if dir + 2 > end {
+            return;
+        }
+        let count = self.buffer.u16(self.is_le, dir) as usize;
+        for entry in (dir + 2..).step_by(10).take(count) {
+            if entry + 10 > end {
+                break;
+            }
+            let tag = self.buffer.u16(self.is_le, entry);
+            let size = self.buffer.u32(self.is_le, entry + 2) as usize;
+            let offset = start + self.buffer.u32(self.is_le, entry + 6) as usize;
+
+            if tag & 0xc000 == 0x4000 {
+                // small values are stored in the directory entry itself
+                self.records.push(Record {
+                    tag,
+                    offset: entry + 2,
+                    len: 8,
+                });
+            } else if offset + size <= end {
+                if tag >> 8 == 0x28 || tag >> 8 == 0x30 {
+                    self.walk(offset, size, depth + 1);
+                }
+                self.records.push(Record {
+                    tag,
+                    offset,
+                    len: size,
+                });
+            }
+        }
+    }
+    fn get(&self, tag: u16) -> Option<&'a [u8]> {
+        self.records
+            .iter()
+            .find(|r| r.tag == tag)
+            .map(|r| &self.buffer[r.offset..r.offset + r.len])
+    }
+    fn u16(&self, tag: u16, index: usize) -> Option<u16> {
+        let data = self.get(tag)?;
+        (data.len() >= index * 2 + 2).then(|| data.u16(self.is_le, index * 2))
+    }
+    fn u32(&self, tag: u16, index: usize) -> Option<u32> {
+        let data = self.get(tag)?;
+        (data.len() >= index * 4 + 4).then(|| data.u32(self.is_le, index * 4))
+    }
+    fn make_model(&self) -> Option<(&'a str, &'a str)> {
+        let mut strings = self
+            .get(MAKE_MODEL)?
+            .split(|&b| b == 0)
+            .map(|s| std::str::from_utf8(s).unwrap_or(""));
+        Some((strings.next()?, strings.next()?))
+    }
+    fn white_balance(&self) -> [u16; 3] {
+        // only the "as shot" entries are read, which are the first ones of each table
+        if self.get(WHITE_BALANCE_TABLE).is_some() {
+            // D60, 10D, 300D, the levels are stored as RGGB
+            if let (Some(r), Some(g), Some(b)) = (
+                self.u16(WHITE_BALANCE_TABLE, 1),
+                self.u16(WHITE_BALANCE_TABLE, 2),
+                self.u16(WHITE_BALANCE_TABLE, 4),
+            ) {
+                return [r, g, b];
+            }
+        }
+        match self.u16(COLOR_INFO, 0) {
+            // Pro90, G1
+            Some(version) if version > 512 => {
+                if let (Some(r), Some(g), Some(b)) = (
+                    self.u16(COLOR_INFO, 62),
+                    self.u16(COLOR_INFO, 63),
+                    self.u16(COLOR_INFO, 60),
+                ) {
+                    return [r, g, b];
+                }
+            }
+            // G2, S30, S40
+            Some(_) => {
+                if let (Some(r), Some(g), Some(b)) = (
+                    self.u16(COLOR_INFO, 51),
+                    self.u16(COLOR_INFO, 50),
+                    self.u16(COLOR_INFO, 52),
+                ) {
+                    return [r, g, b];
+                }
+            }
+            None => {}
+        }
+        [1024, 1024, 1024]
+    }
+    fn orientation(&self) -> u16 {
+        match self.u32(IMAGE_INFO, 3).map(|r| r as i32) {
+            Some(90) | Some(-270) => 6,
+            Some(180) | Some(-180) => 3,
+            Some(270) | Some(-90) => 8,
+            _ => 1,
+        }
+    }
+    fn info_tiff(&self) -> Option<Vec<u8>> {
+        let (make, model) = self.make_model()?;
+        let [r, g, b] = self.white_balance();
+
+        Some(
+            utility::TiffBuilder::new()
+                .ascii(0x010f, make)
+                .ascii(0x0110, model)
+                .short(0x0112, self.orientation())
+                .long(0x0001, self.u16(SENSOR_INFO, 1).unwrap_or(0) as u32)
+                .long(0x0002, self.u16(SENSOR_INFO, 2).unwrap_or(0) as u32)
+                .long(0x0003, self.u32(DECODER_TABLE, 0).unwrap_or(0))
+                .long(0x0004, self.heap_start as u32)
+                .long(0x0005, self.u16(SENSOR_INFO, 5).unwrap_or(0) as u32)
+                .long(0x0006, self.u16(SENSOR_INFO, 6).unwrap_or(0) as u32)
+                .long(0x0007, self.u16(SENSOR_INFO, 7).unwrap_or(0) as u32)
+                .long(0x0008, self.u16(SENSOR_INFO, 8).unwrap_or(0) as u32)
+                .long(0x0009, r as u32)
+                .long(0x000a, g as u32)
+                .long(0x000b, b as u32)
+                .build(),
+        )
+    }
+}
+
+pub(in super::super) fn is_crw(buffer: &[u8]) -> bool {
+    buffer.len() > 14
+        && (buffer.starts_with(b"II") || buffer.starts_with(b"MM"))
+        && &buffer[6..14] == b"HEAPCCDR"
+}
+
+/// A TIFF holding make and model of a CRW file, for selecting the decoder
+pub(in super::super) fn info_tiff(buffer: &[u8]) -> Option<Vec<u8>> {
+    Ciff::new(buffer)?.info_tiff()
+}
+
+pub(in super::super) fn thumbnail(buffer: &[u8]) -> Option<(&[u8], Orientation)> {
+    let ciff = Ciff::new(buffer)?;
+    let jpeg = ciff.get(JPEG_IMAGE)?;
+    let orientation = match ciff.orientation() {
+        3 => Orientation::Rotate180,
+        6 => Orientation::Rotate90,
+        8 => Orientation::Rotate270,
+        _ => Orientation::Horizontal,
+    };
+    Some((jpeg, orientation))
+}
+
+pub(super) fn parse_info(
+    buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    let layout = info_tiff(buffer).ok_or_else(|| {
+        DecodingError::InvalidRawData("CIFF make and model are not found".to_owned())
+    })?;
+    Ok(quickexif::parse_with_prev_info(
+        &layout,
+        &LAYOUT_RULE,
+        basic_info,
+    )?)
+}
+
+static FIRST_TREE: [[u8; 29]; 3] = [
+    [
+        0, 1, 4, 2, 3, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x04, 0x03, 0x05, 0x06, 0x02, 0x07, 0x01,
+        0x08, 0x09, 0x00, 0x0a, 0x0b, 0xff,
+    ],
+    [
+        0, 2, 2, 3, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0x03, 0x02, 0x04, 0x01, 0x05, 0x00, 0x06,
+        0x07, 0x09, 0x08, 0x0a, 0x0b, 0xff,
+    ],
+    [
+        0, 0, 6, 3, 1, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x06, 0x05, 0x07, 0x04, 0x08, 0x03, 0x09,
+        0x02, 0x00, 0x0a, 0x01, 0x0b, 0xff,
+    ],
+];
+
+static SECOND_TREE: [[u8; 180]; 3] = [
+    [
+        0, 2, 2, 2, 1, 4, 2, 1, 2, 5, 1, 1, 0, 0, 0, 139, 0x03, 0x04, 0x02, 0x05, 0x01, 0x06, 0x07,
+        0x08, 0x12, 0x13, 0x11, 0x14, 0x09, 0x15, 0x22, 0x00, 0x21, 0x16, 0x0a, 0xf0, 0x23, 0x17,
+        0x24, 0x31, 0x32, 0x18, 0x19, 0x33, 0x25, 0x41, 0x34, 0x42, 0x35, 0x51, 0x36, 0x37, 0x38,
+        0x29, 0x79, 0x26, 0x1a, 0x39, 0x56, 0x57, 0x28, 0x27, 0x52, 0x55, 0x58, 0x43, 0x76, 0x59,
+        0x77, 0x54, 0x61, 0xf9, 0x71, 0x78, 0x75, 0x96, 0x97, 0x49, 0xb7, 0x53, 0xd7, 0x74, 0xb6,
+        0x98, 0x47, 0x48, 0x95, 0x69, 0x99, 0x91, 0xfa, 0xb8, 0x68, 0xb5, 0xb9, 0xd6, 0xf7, 0xd8,
+        0x67, 0x46, 0x45, 0x94, 0x89, 0xf8, 0x81, 0xd5, 0xf6, 0xb4, 0x88, 0xb1, 0x2a, 0x44, 0x72,
+        0xd9, 0x87, 0x66, 0xd4, 0xf5, 0x3a, 0xa7, 0x73, 0xa9, 0xa8, 0x86, 0x62, 0xc7, 0x65, 0xc8,
+        0xc9, 0xa1, 0xf4, 0xd1, 0xe9, 0x5a, 0x92, 0x85, 0xa6, 0xe7, 0x93, 0xe8, 0xc1, 0xc6, 0x7a,
+        0x64, 0xe1, 0x4a, 0x6a, 0xe6, 0xb3, 0xf1, 0xd3, 0xa5, 0x8a, 0xb2, 0x9a, 0xba, 0x84, 0xa4,
+        0x63, 0xe5, 0xc5, 0xf3, 0xd2, 0xc4, 0x82, 0xaa, 0xda, 0xe4, 0xf2, 0xca, 0x83, 0xa3, 0xa2,
+        0xc3, 0xea, 0xc2, 0xe2, 0xe3, 0xff, 0xff,
+    ],
+    [
+        0, 2, 2, 1, 4, 1, 4, 1, 3, 3, 1, 0, 0, 0, 0, 140, 0x02, 0x03, 0x01, 0x04, 0x05, 0x12, 0x11,
+        0x06, 0x13, 0x07, 0x15, 0x14, 0x08, 0x22, 0x00, 0x21, 0x09, 0x16, 0x23, 0x0a, 0xf0, 0x24,
+        0x31, 0x32, 0x17, 0x18, 0x19, 0x33, 0x25, 0x41, 0x34, 0x42, 0x35, 0x51, 0x36, 0x37, 0x38,
+        0x29, 0x79, 0x26, 0x1a, 0x39, 0x56, 0x57, 0x28, 0x27, 0x52, 0x55, 0x58, 0x43, 0x76, 0x59,
+        0x77, 0x54, 0x61, 0xf9, 0x71, 0x78, 0x75, 0x96, 0x97, 0x49, 0xb7, 0x53, 0xd7, 0x74, 0xb6,
+        0x98, 0x47, 0x48, 0x95, 0x69, 0x99, 0x91, 0xfa, 0xb8, 0x68, 0xb5, 0xb9, 0xd6, 0xf7, 0xd8,
+        0x67, 0x46, 0x45, 0x94, 0x89, 0xf8, 0x81, 0xd5, 0xf6, 0xb4, 0x88, 0xb1, 0x2a, 0x44, 0x72,
+        0xd9, 0x87, 0x66, 0xd4, 0xf5, 0x3a, 0xa7, 0x73, 0xa9, 0xa8, 0x86, 0x62, 0xc7, 0x65, 0xc8,
+        0xc9, 0xa1, 0xf4, 0xd1, 0xe9, 0x5a, 0x92, 0x85, 0xa6, 0xe7, 0x93, 0xe8, 0xc1, 0xc6, 0x7a,
+        0x64, 0xe1, 0x4a, 0x6a, 0xe6, 0xb3, 0xf1, 0xd3, 0xa5, 0x8a, 0xb2, 0x9a, 0xba, 0x84, 0xa4,
+        0x63, 0xe5, 0xc5, 0xf3, 0xd2, 0xc4, 0x82, 0xaa, 0xda, 0xe4, 0xf2, 0xca, 0x83, 0xa3, 0xa2,
+        0xc3, 0xea, 0xc2, 0xe2, 0xe3, 0xff, 0xff,
+    ],
+    [
+        0, 0, 6, 2, 1, 3, 3, 2, 5, 1, 2, 2, 8, 10, 0, 117, 0x04, 0x05, 0x03, 0x06, 0x02, 0x07,
+        0x01, 0x08, 0x09, 0x12, 0x13, 0x14, 0x11, 0x15, 0x0a, 0x16, 0x17, 0xf0, 0x00, 0x22, 0x21,
+        0x18, 0x23, 0x19, 0x24, 0x32, 0x31, 0x25, 0x33, 0x38, 0x37, 0x34, 0x35, 0x36, 0x39, 0x79,
+        0x57, 0x58, 0x59, 0x28, 0x56, 0x78, 0x27, 0x41, 0x29, 0x77, 0x26, 0x42, 0x76, 0x99, 0x1a,
+        0x55, 0x98, 0x97, 0xf9, 0x48, 0x54, 0x96, 0x89, 0x47, 0xb7, 0x49, 0xfa, 0x75, 0x68, 0xb6,
+        0x67, 0x69, 0xb9, 0xb8, 0xd8, 0x52, 0xd7, 0x88, 0xb5, 0x74, 0x51, 0x46, 0xd9, 0xf8, 0x3a,
+        0xd6, 0x87, 0x45, 0x7a, 0x95, 0xd5, 0xf6, 0x86, 0xb4, 0xa9, 0x94, 0x53, 0x2a, 0xa8, 0x43,
+        0xf5, 0xf7, 0xd4, 0x66, 0xa7, 0x5a, 0x44, 0x8a, 0xc9, 0xe8, 0xc8, 0xe7, 0x9a, 0x6a, 0x73,
+        0x4a, 0x61, 0xc7, 0xf4, 0xc6, 0x65, 0xe9, 0x72, 0xe6, 0x71, 0x91, 0x93, 0xa6, 0xda, 0x92,
+        0x85, 0x62, 0xf3, 0xc5, 0xb2, 0xa4, 0x84, 0xba, 0x64, 0xa5, 0xb3, 0xd2, 0x81, 0xe5, 0xd3,
+        0xaa, 0xc4, 0xca, 0xf2, 0xb1, 0xe4, 0xd1, 0x83, 0x63, 0xea, 0xc3, 0xe2, 0x82, 0xf1, 0xa3,
+        0xc2, 0xa1, 0xc1, 0xe3, 0xa2, 0xe1, 0xff, 0xff,
+    ],
+];
+
+fn huff_table(source: &[u8]) -> HuffTable {
+    let mut table = HuffTable::empty();
+    for (bits, &count) in table.bits[1..].iter_mut().zip(&source[..16]) {
+        *bits = count as u32;
+    }
+    for (value, &symbol) in table.huffval.iter_mut().zip(&source[16..]) {
+        *value = symbol as u32;
+    }
+    // the symbols are run/length pairs rather than JPEG differences
+    table.disable_cache = true;
+    table.initialize();
+    table
+}
+
+impl General {
+    /// The 2 low bits of each sample are stored in front of the compressed data
+    /// in newer files, which is detected by a `0xff` not followed by a stuffed zero
+    fn has_low_bits(&self, buffer: &[u8], data_start: usize) -> bool {
+        let end = buffer.len().min(0x4000);
+        let mut result = true;
+        for pair in buffer.get(data_start..end).unwrap_or(&[]).windows(2) {
+            if pair[0] == 0xff {
+                if pair[1] != 0 {
+                    return true;
+                }
+                result = false;
+            }
+        }
+        result
+    }
+}
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        canon::sensor_crop(&self.info).filter(|crop| crop.width > 1 && crop.height > 1)
+    }
+    fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        Ok(cfa_pattern_from_info(&self.info).unwrap_or(CFAPattern::RGGB))
+    }
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let table = self.info.usize("decoder_table")?.min(2);
+        let heap_start = self.info.usize("heap_start")?;
+        if width == 0 || height == 0 {
+            return Err(DecodingError::InvalidRawData(
+                "CRW sensor size is not found".to_owned(),
+            ));
+        }
+
+        let low_bits = self.has_low_bits(buffer, heap_start + 514);
+        let data_start = heap_start + 514 + if low_bits { width * height / 4 } else { 0 };
+        let src = buffer.get(data_start..).ok_or_else(|| {
+            DecodingError::InvalidRawData("CRW raw data is out of range".to_owned())
+        })?;
+
+        let first = huff_table(&FIRST_TREE[table]);
+        let second = huff_table(&SECOND_TREE[table]);
+        let mut pump = BitPumpJPEG::new(src);
+
+        let mut image = vec![0u16; width * height];
+        let mut carry = 0i32;
+        let mut base = [512i32; 2];
+        let mut pixel_count = 0usize;
+
+        for row in (0..height).step_by(8) {
+            // the image is coded in blocks of 64 samples, 8 rows at a time
+            let blocks = (8.min(height - row) * width) >> 6;
+            for block in 0..blocks {
+                let mut diffs = [0i32; 64];
+                let mut i = 0;
+                while i < 64 {
+                    let tree = if i > 0 { &second } else { &first };
+                    let (_, leaf, _) = tree.huff_len(&mut pump);
+                    if leaf == 0 && i > 0 {
+                        break;
+                    }
+                    if leaf != 0xff {
+                        i += (leaf >> 4) as usize;
+                        let len = (leaf & 15) as u32;
+                        if len > 0 {
+                            let mut diff = pump.get_bits(len) as i32;
+                            if diff & (1 << (len - 1)) == 0 {
+                                diff -= (1 << len) - 1;
+                            }
+                            if i < 64 {
+                                diffs[i] = diff;
+                            }
+                        }
+                    }
+                    i += 1;
+                }
+
+                diffs[0] += carry;
+                carry = diffs[0];
+
+                let start = row * width + (block << 6);
+                for (i, diff) in diffs.iter().enumerate() {
+                    if pixel_count % width == 0 {
+                        base = [512, 512];
+                    }
+                    pixel_count += 1;
+                    base[i & 1] += diff;
+                    image[start + i] = base[i & 1].clamp(0, 0x3ff) as u16;
+                }
+            }
+
+            if low_bits {
+                let low_start = heap_start + row * width / 4;
+                let rows = 8.min(height - row);
+                let pixels = &mut image[row * width..(row + rows) * width];
+                let low = buffer
+                    .get(low_start..low_start + pixels.len() / 4)
+                    .ok_or_else(|| {
+                        DecodingError::InvalidRawData("CRW low bits are out of range".to_owned())
+                    })?;
+                for (chunk, &c) in pixels.chunks_mut(4).zip(low) {
+                    for (r, pixel) in chunk.iter_mut().enumerate() {
+                        let mut value = (*pixel << 2) + ((c as u16 >> (r * 2)) & 3);
+                        if width == 2672 && value < 512 {
+                            value += 2;
+                        }
+                        *pixel = value;
+                    }
+                }
+            }
+        }
+
+        let bps_scale = if low_bits { 16 } else { 64 };
+        let black_level = canon::masked_area_black_level(&self.info, &image, width);
+        Ok(image
+            .iter()
+            .map(|x| bps_scale.saturating_mul(x.saturating_sub(black_level)))
+            .collect())
+    }
+    fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        thumbnail(buffer).map(|(jpeg, _)| jpeg).ok_or_else(|| {
+            DecodingError::RawInfoError(quickexif::parsed_info::Error::FieldNotFound(
+                "thumbnail".into(),
+            ))
+        })
+    }
+}
@@ -0,0 +1,672 @@
+use super::super::utility::GetNumFromBytes;
+use super::bit_pump::{BitPump, BitPumpMSBPadded};
+use super::DecodingError;
+
+// run length parameters of the adaptive Golomb-Rice coder
+const J: [u32; 32] = [
+    0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 9, 10, 11, 12, 13,
+    14, 15,
+];
+const JS: [usize; 32] = [
+    1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 8, 8, 8, 8, 0x10, 0x10, 0x20, 0x20, 0x40, 0x40, 0x80, 0x80,
+    0x100, 0x200, 0x400, 0x800, 0x1000, 0x2000, 0x4000, 0x8000,
+];
+
+fn crx_error<T>(msg: &str) -> Result<T, DecodingError> {
+    Err(DecodingError::CrxError(msg.to_owned()))
+}
+
+/// The image header stored in the `CMP1` box of a CR3 track
+pub(in super::super) struct CrxHeader {
+    pub(in super::super) width: usize,
+    pub(in super::super) height: usize,
+    pub(in super::super) bits: u32,
+    pub(in super::super) cfa_layout: u8,
+    tile_width: usize,
+    tile_height: usize,
+    planes: usize,
+    enc_type: u8,
+    levels: u8,
+    mdat_header_size: usize,
+}
+
+impl CrxHeader {
+    pub(in super::super) fn new(cmp1: &[u8]) -> Result<CrxHeader, DecodingError> {
+        if cmp1.len() < 32 {
+            return crx_error("CMP1 header is too short");
+        }
+
+        let version = cmp1.u16be(4);
+        let header = CrxHeader {
+            width: cmp1.u32be(8) as usize,
+            height: cmp1.u32be(12) as usize,
+            tile_width: cmp1.u32be(16) as usize,
+            tile_height: cmp1.u32be(20) as usize,
+            bits: cmp1[24] as u32,
+            planes: (cmp1[25] >> 4) as usize,
+            cfa_layout: cmp1[25] & 0xf,
+            enc_type: cmp1[26] >> 4,
+            levels: cmp1[26] & 0xf,
+            mdat_header_size: cmp1.u32be(28) as usize,
+        };
+
+        if (version != 0x100 && version != 0x200) || header.mdat_header_size == 0 {
+            return crx_error("unknown CMP1 version");
+        }
+        if header.planes != 4
+            || header.width & 1 != 0
+            || header.height & 1 != 0
+            || header.tile_width & 1 != 0
+            || header.tile_height & 1 != 0
+            || header.tile_width == 0
+            || header.tile_height == 0
+            || header.cfa_layout > 3
+            || header.bits <= 8
+            || header.bits > 14
+        {
+            return crx_error("only 4 plane bayer images are supported");
+        }
+        if header.tile_width > header.width || header.tile_height > header.height {
+            return crx_error("the tile is larger than the image");
+        }
+
+        Ok(header)
+    }
+    #[inline(always)]
+    pub(in super::super) fn is_bayer(cmp1: &[u8]) -> bool {
+        CrxHeader::new(cmp1).is_ok()
+    }
+}
+
+struct Component {
+    data_offset: usize,
+    data_size: usize,
+    supports_partial: bool,
+    rounded_bits_mask: i32,
+}
+
+struct Tile {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+}
+
+fn read_tiles(
+    header: &CrxHeader,
+    mdat_header: &[u8],
+    [tile_cols, tile_rows]: [usize; 2],
+    [plane_width, plane_height]: [usize; 2],
+) -> Result<Vec<Tile>, DecodingError> {
+    let tile_width = header.tile_width / 2;
+    let tile_height = header.tile_height / 2;
+    let tile_count = tile_cols * tile_rows;
+
+    let mut tiles = Vec::with_capacity(tile_count);
+    let mut pos = 0usize;
+    let mut tile_offset = 0usize;
+
+    for tile_index in 0..tile_count {
+        if pos + 12 > mdat_header.len() {
+            return crx_error("tile header is out of range");
+        }
+        let sign = mdat_header.u16be(pos);
+        let size = mdat_header.u16be(pos + 2) as usize;
+        if !((sign == 0xff01 && size == 8) || (sign == 0xff11 && (size == 8 || size == 16))) {
+            return crx_error("invalid tile header");
+        }
+        if mdat_header.u16be(pos + 8) as usize != tile_index {
+            return crx_error("unexpected tile index");
+        }
+        let tile_size = mdat_header.u32be(pos + 4) as usize;
+        // lossy encodings store quantization data in front of the planes
+        let (qp_data_size, extra_size) = if size == 16 {
+            (
+                mdat_header.u32be(pos + 12) as usize,
+                mdat_header.u16be(pos + 16) as usize,
+            )
+        } else {
+            (0, 0)
+        };
+        pos += size + 4;
+
+        let mut components = Vec::with_capacity(header.planes);
+        let mut component_offset = 0usize;
+        for plane in 0..header.planes {
+            if pos + 12 > mdat_header.len() {
+                return crx_error("plane header is out of range");
+            }
+            let sign = mdat_header.u16be(pos);
+            let size = mdat_header.u16be(pos + 2);
+            let flags = mdat_header[pos + 8];
+            if (sign != 0xff02 && sign != 0xff12) || size != 8 || (flags >> 4) as usize != plane {
+                return crx_error("invalid plane header");
+            }
+            let component_size = mdat_header.u32be(pos + 4) as usize;
+            let rounded_bits = (flags >> 1) & 3;
+            let supports_partial = flags & 8 != 0;
+            pos += 12;
+
+            let rounded_bits_mask = if rounded_bits > 0 {
+                if header.levels > 0 || !supports_partial {
+                    return crx_error("invalid rounded bits");
+                }
+                1 << (rounded_bits - 1)
+            } else {
+                0
+            };
+
+            // without wavelet levels there is exactly one subband per plane
+            if pos + 12 > mdat_header.len() {
+                return crx_error("subband header is out of range");
+            }
+            let sign = mdat_header.u16be(pos);
+            let size = mdat_header.u16be(pos + 2) as usize;
+            if !((sign == 0xff03 && size == 8) || (sign == 0xff13 && size == 16)) {
+                return crx_error("invalid subband header");
+            }
+            let subband_size = mdat_header.u32be(pos + 4) as usize;
+            let padding = if sign == 0xff03 {
+                (mdat_header.u32be(pos + 8) & 0x7ffff) as usize
+            } else {
+                mdat_header.u16be(pos + 16) as usize
+            };
+            pos += size + 4;
+
+            components.push(Component {
+                data_offset: tile_offset + qp_data_size + extra_size + component_offset,
+                data_size: subband_size.saturating_sub(padding),
+                supports_partial,
+                rounded_bits_mask,
+            });
+            component_offset += component_size;
+        }
+
+        let col = tile_index % tile_cols;
+        let row = tile_index / tile_cols;
+        tiles.push(Tile {
+            width: if col == tile_cols - 1 && plane_width % tile_width != 0 {
+                plane_width % tile_width
+            } else {
+                tile_width
+            },
+            height: if row == tile_rows - 1 && plane_height % tile_height != 0 {
+                plane_height % tile_height
+            } else {
+                tile_height
+            },
+            components,
+        });
+        tile_offset += tile_size;
+    }
+
+    Ok(tiles)
+}
+
+struct BandParam<'a> {
+    // the data of the subband alone, the bits past its end read as zeros
+    pump: BitPumpMSBPadded<'a>,
+    width: usize,
+    height: usize,
+    cur_line: usize,
+    k_param: u32,
+    s_param: usize,
+    rounded_bits_mask: i32,
+    rounded_bits: u32,
+    // two lines with one extra sample on each side, the previous and the current one
+    line_buf: Vec<i32>,
+    l0: usize,
+    l1: usize,
+}
+
+#[inline(always)]
+fn to_signed(bit_code: u32) -> i32 {
+    -((bit_code & 1) as i32) ^ ((bit_code >> 1) as i32)
+}
+
+#[inline(always)]
+fn predict_k_param(prev_k: u32, bit_code: u32, max: u32) -> u32 {
+    let k = prev_k as i32 - ((bit_code < (1 << prev_k >> 1)) as i32)
+        + ((bit_code >> prev_k > 2) as i32)
+        + ((bit_code >> prev_k > 5) as i32);
+    (k as u32).min(max)
+}
+
+#[inline(always)]
+fn median_index(a: i32, b: i32, c: i32, delta: i32) -> usize {
+    ((((a < b) ^ (delta < 0)) as usize) << 1) + (((b < c) ^ (delta < 0)) as usize)
+}
+
+impl<'a> BandParam<'a> {
+    fn new(src: &'a [u8], width: usize, height: usize, rounded_bits_mask: i32) -> BandParam<'a> {
+        BandParam {
+            pump: BitPumpMSBPadded::new(src),
+            width,
+            height,
+            cur_line: 0,
+            k_param: 0,
+            s_param: 0,
+            rounded_bits_mask,
+            rounded_bits: 0,
+            line_buf: vec![0i32; 2 * (width + 2)],
+            l0: 0,
+            l1: 0,
+        }
+    }
+    #[inline(always)]
+    fn get_zeros(&mut self) -> u32 {
+        let mut result = 0;
+        loop {
+            let bits = self.pump.peek_bits(32);
+            // the zeros past the end never stop, the overrun fails the line
+            if bits == 0 && self.pump.is_overrun() {
+                return result;
+            } else if bits == 0 {
+                self.pump.consume_bits(32);
+                result += 32;
+            } else {
+                let zeros = bits.leading_zeros();
+                self.pump.consume_bits(zeros + 1);
+                return result + zeros;
+            }
+        }
+    }
+    #[inline(always)]
+    fn read_code(&mut self) -> u32 {
+        let bit_code = self.get_zeros();
+        if bit_code >= 41 {
+            self.pump.get_bits(21)
+        } else if self.k_param > 0 {
+            self.pump.get_bits(self.k_param) | (bit_code << self.k_param)
+        } else {
+            bit_code
+        }
+    }
+    fn read_run_length(&mut self, length: usize) -> Result<usize, DecodingError> {
+        let mut count = 1;
+        while self.pump.get_bits(1) == 1 {
+            count += JS[self.s_param];
+            if count > length {
+                count = length;
+                break;
+            }
+            if self.s_param < 31 {
+                self.s_param += 1;
+            }
+            if count == length {
+                break;
+            }
+        }
+
+        if count < length {
+            if J[self.s_param] > 0 {
+                count += self.pump.get_bits(J[self.s_param]) as usize;
+            }
+            if self.s_param > 0 {
+                self.s_param -= 1;
+            }
+            if count > length {
+                return crx_error("run length is out of range");
+            }
+        }
+        Ok(count)
+    }
+    fn copy_run(&mut self, count: usize) {
+        for _ in 0..count {
+            self.line_buf[self.l1 + 1] = self.line_buf[self.l1];
+            self.l1 += 1;
+        }
+    }
+    fn add_code(&mut self, rounded: bool) {
+        let bit_code = self.read_code();
+        let code = to_signed(bit_code);
+        self.line_buf[self.l1 + 1] += if rounded {
+            self.rounded_bits_mask * 2 * code + (code >> 31)
+        } else {
+            code
+        };
+        self.k_param = predict_k_param(self.k_param, bit_code, 15);
+        self.l1 += 1;
+    }
+    fn decode_top_line(&mut self, rounded: bool) -> Result<(), DecodingError> {
+        self.line_buf[self.l1] = 0;
+
+        let mut length = self.width as isize;
+        while length > 1 {
+            let value = self.line_buf[self.l1];
+            let is_flat = if rounded {
+                value.abs() <= self.rounded_bits_mask
+            } else {
+                value == 0
+            };
+            if !is_flat {
+                self.line_buf[self.l1 + 1] = value;
+            } else {
+                if self.pump.get_bits(1) == 1 {
+                    let count = self.read_run_length(length as usize)?;
+                    length -= count as isize;
+                    self.copy_run(count);
+                    if length <= 0 {
+                        break;
+                    }
+                }
+                self.line_buf[self.l1 + 1] = 0;
+            }
+            self.add_code(rounded);
+            length -= 1;
+        }
+
+        if length == 1 {
+            self.line_buf[self.l1 + 1] = self.line_buf[self.l1];
+            self.add_code(rounded);
+        }
+
+        self.line_buf[self.l1 + 1] = self.line_buf[self.l1] + 1;
+        Ok(())
+    }
+    fn decode_symbol(&mut self, do_median_prediction: bool, not_eol: bool) {
+        let (l0, l1) = (self.l0, self.l1);
+        let buf = &self.line_buf;
+        let predicted = if do_median_prediction {
+            let delta = buf[l0 + 1] - buf[l0];
+            let symbols = [delta + buf[l1], delta + buf[l1], buf[l1], buf[l0 + 1]];
+            symbols[median_index(buf[l0], buf[l1], buf[l0 + 1], delta)]
+        } else {
+            buf[l0 + 1]
+        };
+
+        let mut bit_code = self.read_code();
+        self.line_buf[l1 + 1] = predicted + to_signed(bit_code);
+
+        // use one symbol ahead to estimate the next k parameter
+        if not_eol {
+            let next_delta = (self.line_buf[l0 + 2] - self.line_buf[l0 + 1]) << 1;
+            bit_code = (bit_code + next_delta.unsigned_abs()) >> 1;
+            self.l0 += 1;
+        }
+
+        self.k_param = predict_k_param(self.k_param, bit_code, 15);
+        self.l1 += 1;
+    }
+    fn decode_line(&mut self) -> Result<(), DecodingError> {
+        self.line_buf[self.l1] = self.line_buf[self.l0 + 1];
+
+        let mut length = self.width as isize;
+        while length > 1 {
+            let (l0, l1) = (self.l0, self.l1);
+            if self.line_buf[l1] != self.line_buf[l0 + 1]
+                || self.line_buf[l1] != self.line_buf[l0 + 2]
+            {
+                self.decode_symbol(true, true);
+            } else {
+                if self.pump.get_bits(1) == 1 {
+                    let count = self.read_run_length(length as usize)?;
+                    length -= count as isize;
+                    self.l0 += count;
+                    self.copy_run(count);
+                }
+                if length > 0 {
+                    self.decode_symbol(false, length > 1);
+                }
+            }
+            length -= 1;
+        }
+
+        if length == 1 {
+            self.decode_symbol(true, false);
+        }
+
+        self.line_buf[self.l1 + 1] = self.line_buf[self.l1] + 1;
+        Ok(())
+    }
+    fn decode_symbol_rounded(&mut self, do_sym: bool, do_code: bool) {
+        let (l0, l1) = (self.l0, self.l1);
+        let buf = &self.line_buf;
+        let sym = if do_sym {
+            let delta = buf[l0 + 1] - buf[l0];
+            let symbols = [delta + buf[l1], delta + buf[l1], buf[l1], buf[l0 + 1]];
+            symbols[median_index(buf[l0], buf[l1], buf[l0 + 1], delta)]
+        } else {
+            buf[l0 + 1]
+        };
+
+        let bit_code = self.read_code();
+        let code = to_signed(bit_code);
+        self.line_buf[l1 + 1] = self.rounded_bits_mask * 2 * code + (code >> 31) + sym;
+
+        self.k_param = if do_code {
+            let (prev, next) = (self.line_buf[l0 + 1], self.line_buf[l0 + 2]);
+            let code = if next > prev {
+                (next - prev + self.rounded_bits_mask - 1) >> self.rounded_bits
+            } else {
+                -((prev - next + self.rounded_bits_mask) >> self.rounded_bits)
+            };
+            predict_k_param(self.k_param, (bit_code + 2 * code.unsigned_abs()) >> 1, 15)
+        } else {
+            predict_k_param(self.k_param, bit_code, 15)
+        };
+        self.l1 += 1;
+    }
+    fn decode_line_rounded(&mut self) -> Result<(), DecodingError> {
+        let mask = self.rounded_bits_mask;
+        let mut value_reached = false;
+
+        self.line_buf[self.l0] = self.line_buf[self.l0 + 1];
+        self.line_buf[self.l1] = self.line_buf[self.l0 + 1];
+
+        let mut length = self.width as isize;
+        while length > 1 {
+            let (l0, l1) = (self.l0, self.l1);
+            if (self.line_buf[l0 + 2] - self.line_buf[l0 + 1]).abs() > mask {
+                self.decode_symbol_rounded(true, true);
+                self.l0 += 1;
+                value_reached = true;
+            } else if value_reached || (self.line_buf[l0] - self.line_buf[l1]).abs() > mask {
+                self.decode_symbol_rounded(true, true);
+                self.l0 += 1;
+                value_reached = false;
+            } else {
+                let count = if self.pump.get_bits(1) == 1 {
+                    self.read_run_length(length as usize)?
+                } else {
+                    0
+                };
+                length -= count as isize;
+                self.l0 += count;
+                self.copy_run(count);
+
+                if length > 1 {
+                    self.decode_symbol_rounded(false, true);
+                    self.l0 += 1;
+                    value_reached =
+                        (self.line_buf[self.l0 + 1] - self.line_buf[self.l0]).abs() > mask;
+                } else if length == 1 {
+                    self.decode_symbol_rounded(false, false);
+                }
+            }
+            length -= 1;
+        }
+
+        if length == 1 {
+            self.decode_symbol_rounded(true, false);
+        }
+
+        self.line_buf[self.l1 + 1] = self.line_buf[self.l1] + 1;
+        Ok(())
+    }
+    fn decode_next_line(&mut self) -> Result<&[i32], DecodingError> {
+        if self.cur_line >= self.height {
+            return crx_error("too many lines in subband");
+        }
+
+        let line_len = self.width + 2;
+        let rounded = self.rounded_bits_mask > 0;
+        if self.cur_line == 0 {
+            self.s_param = 0;
+            self.k_param = 0;
+            if rounded {
+                self.rounded_bits = 1;
+                if self.rounded_bits_mask & !1 != 0 {
+                    while self.rounded_bits_mask >> self.rounded_bits != 0 {
+                        self.rounded_bits += 1;
+                    }
+                }
+            }
+            self.l0 = 0;
+            self.l1 = line_len;
+        } else if self.cur_line & 1 == 1 {
+            self.l1 = 0;
+            self.l0 = line_len;
+        } else {
+            self.l0 = 0;
+            self.l1 = line_len;
+        }
+
+        let start = self.l1 + 1;
+        match (self.cur_line, rounded) {
+            (0, _) => self.decode_top_line(rounded)?,
+            (_, false) => self.decode_line()?,
+            (_, true) => self.decode_line_rounded()?,
+        }
+        if self.pump.is_overrun() {
+            return crx_error("subband data is out of range");
+        }
+        self.cur_line += 1;
+
+        Ok(&self.line_buf[start..start + self.width])
+    }
+}
+
+/// Decodes the non-wavelet (lossless RAW) flavour of the CRX codec into a bayer mosaic.
+///
+/// `data` starts at the beginning of the raw track sample, which holds the tile headers
+/// followed by the encoded planes.
+pub(in super::super) fn decode(header: &CrxHeader, data: &[u8]) -> Result<Vec<u16>, DecodingError> {
+    if header.levels != 0 {
+        return crx_error("wavelet compressed C-RAW images are not supported");
+    }
+    if header.enc_type != 0 && header.enc_type != 3 {
+        return crx_error("unsupported encoding type");
+    }
+    if data.len() < header.mdat_header_size {
+        return crx_error("raw track is shorter than its header");
+    }
+
+    let width = header.width;
+    let plane_width = header.width / 2;
+    let plane_height = header.height / 2;
+    let tile_cols = (plane_width + header.tile_width / 2 - 1) / (header.tile_width / 2);
+    let tile_rows = (plane_height + header.tile_height / 2 - 1) / (header.tile_height / 2);
+
+    let tiles = read_tiles(
+        header,
+        &data[..header.mdat_header_size],
+        [tile_cols, tile_rows],
+        [plane_width, plane_height],
+    )?;
+    let plane_data = &data[header.mdat_header_size..];
+
+    // each plane fills one position of the 2x2 bayer block
+    let plane_offsets = match header.cfa_layout {
+        0 => [0, 1, width, width + 1],
+        1 => [1, 0, width + 1, width],
+        2 => [width, width + 1, 0, 1],
+        _ => [width + 1, width, 1, 0],
+    };
+
+    let median = 1i32 << (header.bits - 1);
+    let max_value = (1i32 << header.bits) - 1;
+    let mut image = vec![0u16; header.width * header.height];
+    let mut planes = if header.enc_type == 3 {
+        vec![0i16; 4 * plane_width * plane_height]
+    } else {
+        vec![]
+    };
+
+    for plane in 0..4 {
+        let mut image_row = 0;
+        for tile_row in tiles.chunks_exact(tile_cols) {
+            let mut image_col = 0;
+            for tile in tile_row {
+                let component = &tile.components[plane];
+                if !component.supports_partial {
+                    return crx_error("planes without partial decoding support are not supported");
+                }
+
+                let src = component
+                    .data_offset
+                    .checked_add(component.data_size)
+                    .and_then(|end| plane_data.get(component.data_offset..end))
+                    .ok_or_else(|| DecodingError::CrxError("plane is out of range".to_owned()))?;
+                let mut band = if component.data_size > 0 {
+                    Some(BandParam::new(
+                        src,
+                        tile.width,
+                        tile.height,
+                        component.rounded_bits_mask,
+                    ))
+                } else {
+                    None
+                };
+                let empty_line = vec![0i32; tile.width];
+
+                for row in 0..tile.height {
+                    let line = match band.as_mut() {
+                        Some(band) => band.decode_next_line()?,
+                        None => &empty_line[..],
+                    };
+                    let y = image_row + row;
+                    if header.enc_type == 3 {
+                        let start =
+                            plane * plane_width * plane_height + y * plane_width + image_col;
+                        planes[start..start + tile.width]
+                            .iter_mut()
+                            .zip(line)
+                            .for_each(|(dst, &v)| *dst = v as i16);
+                    } else {
+                        let start = plane_offsets[plane] + y * 2 * width + 2 * image_col;
+                        line.iter().enumerate().for_each(|(i, &v)| {
+                            image[start + 2 * i] = (median + v).clamp(0, max_value) as u16;
+                        });
+                    }
+                }
+                image_col += tile.width;
+            }
+            image_row += tile_row[0].height;
+        }
+    }
+
+    if header.enc_type == 3 {
+        // encoding type 3 stores a luma plane and three color differences
+        let plane_size = plane_width * plane_height;
+        let median = median << 10;
+        for y in 0..plane_height {
+            let row = y * plane_width;
+            for x in 0..plane_width {
+                let p0 = (planes[row + x] as i32) << 10;
+                let p1 = planes[plane_size + row + x] as i32;
+                let p2 = planes[2 * plane_size + row + x] as i32;
+                let p3 = planes[3 * plane_size + row + x] as i32;
+
+                let gr = median + p0 - 168 * p1 - 585 * p3;
+                let gr = if gr < 0 {
+                    -(((gr.abs() + 512) >> 9) & !1)
+                } else {
+                    ((gr.abs() + 512) >> 9) & !1
+                };
+
+                let values = [
+                    (median + p0 + 1510 * p3 + 512) >> 10,
+                    (p2 + gr + 1) >> 1,
+                    (gr - p2 + 1) >> 1,
+                    (median + p0 + 1927 * p1 + 512) >> 10,
+                ];
+                let start = y * 2 * width + 2 * x;
+                for (offset, value) in plane_offsets.iter().zip(values) {
+                    image[start + offset] = value.clamp(0, max_value) as u16;
+                }
+            }
+        }
+    }
+
+    Ok(image)
+}
@@ -0,0 +1,523 @@
+use super::super::utility::GetNumFromBytes;
+use super::DecodingError;
+
+// the line buffers of a 6 lines group, the first lines of every color hold the last lines
+// of the previous group, which are used by the predictors
+const R0: usize = 0;
+const R1: usize = 1;
+const R2: usize = 2;
+const R3: usize = 3;
+const R4: usize = 4;
+const G0: usize = 5;
+const G1: usize = 6;
+const G2: usize = 7;
+const G3: usize = 8;
+const G4: usize = 9;
+const G5: usize = 10;
+const G6: usize = 11;
+const G7: usize = 12;
+const B0: usize = 13;
+const B1: usize = 14;
+const B2: usize = 15;
+const B3: usize = 16;
+const B4: usize = 17;
+const LINE_COUNT: usize = 18;
+
+// 0 is red, 1 is green and 2 is blue, compressed X-Trans data always starts with GGRGGB
+const XTRANS: [[u8; 6]; 6] = [
+    [1, 1, 0, 1, 1, 2],
+    [1, 1, 2, 1, 1, 0],
+    [2, 0, 1, 0, 2, 1],
+    [1, 1, 2, 1, 1, 0],
+    [1, 1, 0, 1, 1, 2],
+    [0, 2, 1, 2, 0, 1],
+];
+const BAYER: [[u8; 2]; 2] = [[0, 1], [1, 2]];
+
+const MIN_VALUE: i32 = 0x40;
+
+fn fuji_error<T>(msg: &str) -> Result<T, DecodingError> {
+    Err(DecodingError::FujiCompressedError(msg.to_owned()))
+}
+
+/// The 16 bytes header in front of the compressed raw data of a RAF
+pub(in super::super) struct FujiHeader {
+    pub(in super::super) is_lossless: bool,
+    pub(in super::super) is_xtrans: bool,
+    pub(in super::super) bits: u32,
+    pub(in super::super) width: usize,
+    pub(in super::super) height: usize,
+    block_size: usize,
+    blocks_in_row: usize,
+    total_lines: usize,
+}
+
+impl FujiHeader {
+    pub(in super::super) fn new(data: &[u8]) -> Result<FujiHeader, DecodingError> {
+        if !FujiHeader::is_compressed(data) {
+            return fuji_error("the raw data is not compressed");
+        }
+
+        let raw_type = data[3];
+        let rounded_width = data.u16be(7) as usize;
+        let header = FujiHeader {
+            is_lossless: data[2] == 1,
+            is_xtrans: raw_type == 16,
+            bits: data[4] as u32,
+            height: data.u16be(5) as usize,
+            width: data.u16be(9) as usize,
+            block_size: data.u16be(11) as usize,
+            blocks_in_row: data[13] as usize,
+            total_lines: data.u16be(14) as usize,
+        };
+
+        if data[2] > 1
+            || (raw_type != 0 && raw_type != 16)
+            || !matches!(header.bits, 12 | 14 | 16)
+            || header.height < 6
+            || header.height % 6 != 0
+            || header.total_lines != header.height / 6
+            || header.width < 0x300
+            || header.width % 24 != 0
+            || header.block_size != 0x300
+            || rounded_width % header.block_size != 0
+            || rounded_width < header.width
+            || rounded_width - header.width >= header.block_size
+            || header.blocks_in_row == 0
+            || header.blocks_in_row > 0x10
+            || header.blocks_in_row != rounded_width / header.block_size
+        {
+            return fuji_error("invalid compressed RAF header");
+        }
+
+        Ok(header)
+    }
+    /// Lossless and lossy compressed data share the header, the third byte tells them apart
+    #[inline(always)]
+    pub(in super::super) fn is_compressed(data: &[u8]) -> bool {
+        data.len() >= 16 && data.u16be(0) == 0x4953
+    }
+}
+
+struct Params {
+    q_table: Vec<i8>,
+    max_value: i32,
+    total_values: i32,
+    raw_bits: u32,
+    max_bits: u32,
+    line_width: usize,
+}
+
+impl Params {
+    fn new(header: &FujiHeader) -> Params {
+        let max_value = (1i32 << header.bits) - 1;
+        let q_table = (-max_value..=max_value)
+            .map(|v| match v {
+                v if v <= -0x114 => -4,
+                v if v <= -0x43 => -3,
+                v if v <= -0x12 => -2,
+                v if v < 0 => -1,
+                0 => 0,
+                v if v < 0x12 => 1,
+                v if v < 0x43 => 2,
+                v if v < 0x114 => 3,
+                _ => 4,
+            })
+            .collect();
+        let line_width = match header.is_xtrans {
+            true => header.block_size * 2 / 3,
+            false => header.block_size / 2,
+        };
+
+        Params {
+            q_table,
+            max_value,
+            total_values: max_value + 1,
+            raw_bits: header.bits,
+            max_bits: header.bits * 4,
+            line_width,
+        }
+    }
+    #[inline(always)]
+    fn q(&self, v: i32) -> i32 {
+        self.q_table[(self.max_value + v) as usize] as i32
+    }
+    #[inline(always)]
+    fn max_diff(&self) -> i32 {
+        match self.raw_bits {
+            12 => 64,
+            14 => 256,
+            _ => 1024,
+        }
+    }
+}
+
+/// A MSB first bit reader which gives zeros after the end of the data
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u64,
+    nbits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            pos: 0,
+            bits: 0,
+            nbits: 0,
+        }
+    }
+    #[inline(always)]
+    fn fill(&mut self) {
+        while self.nbits <= 56 {
+            let byte = self.data.get(self.pos).copied().unwrap_or(0);
+            self.bits |= (byte as u64) << (56 - self.nbits);
+            self.pos += 1;
+            self.nbits += 8;
+        }
+    }
+    #[inline(always)]
+    fn consume(&mut self, num: u32) {
+        self.bits = self.bits.checked_shl(num).unwrap_or(0);
+        self.nbits -= num;
+    }
+    #[inline(always)]
+    fn get_bits(&mut self, num: u32) -> i32 {
+        if num == 0 {
+            return 0;
+        }
+        self.fill();
+        let value = (self.bits >> (64 - num)) as i32;
+        self.consume(num);
+        value
+    }
+    /// Counts the zeros in front of the next one bit and skips that bit, gives up after `limit`
+    #[inline(always)]
+    fn get_zeros(&mut self, limit: u32) -> u32 {
+        let mut count = 0;
+        loop {
+            self.fill();
+            let zeros = self.bits.leading_zeros();
+            if zeros < self.nbits {
+                self.consume(zeros + 1);
+                return count + zeros;
+            }
+            count += self.nbits;
+            self.consume(self.nbits);
+            if count >= limit {
+                return count;
+            }
+        }
+    }
+}
+
+fn bit_diff(value1: i32, value2: i32) -> u32 {
+    let mut result = 0;
+    if value2 < value1 {
+        while result <= 14 {
+            result += 1;
+            if (value2 << result) >= value1 {
+                break;
+            }
+        }
+    }
+    result
+}
+
+// how the even samples of a line are restored, odd samples are always decoded
+#[derive(Clone, Copy)]
+enum Even {
+    Decoded,
+    Interpolated,
+    // decoded unless `pos & 3` is the given value
+    InterpolatedAt(usize),
+}
+
+impl Even {
+    #[inline(always)]
+    fn is_decoded(self, pos: usize) -> bool {
+        match self {
+            Even::Decoded => true,
+            Even::Interpolated => false,
+            Even::InterpolatedAt(x) => pos & 3 != x,
+        }
+    }
+}
+
+struct Block<'a> {
+    params: &'a Params,
+    reader: BitReader<'a>,
+    lines: Vec<u16>,
+    even: [[(i32, i32); 41]; 3],
+    odd: [[(i32, i32); 41]; 3],
+}
+
+impl<'a> Block<'a> {
+    fn new(params: &'a Params, data: &'a [u8]) -> Block<'a> {
+        let grads = [[(params.max_diff(), 1); 41]; 3];
+        Block {
+            params,
+            reader: BitReader::new(data),
+            lines: vec![0; LINE_COUNT * (params.line_width + 2)],
+            even: grads,
+            odd: grads,
+        }
+    }
+    #[inline(always)]
+    fn stride(&self) -> usize {
+        self.params.line_width + 2
+    }
+    #[inline(always)]
+    fn index(&self, line: usize, pos: usize) -> usize {
+        line * self.stride() + 1 + pos
+    }
+    #[inline(always)]
+    fn get(&self, index: usize) -> i32 {
+        self.lines[index] as i32
+    }
+    fn read_code(&mut self, is_even: bool, table: usize, gradient: usize) -> i32 {
+        let params = self.params;
+        let sample = self.reader.get_zeros(params.max_bits);
+        let grads = match is_even {
+            true => &mut self.even[table][gradient],
+            false => &mut self.odd[table][gradient],
+        };
+
+        let code = if sample < params.max_bits - params.raw_bits - 1 {
+            let bits = bit_diff(grads.0, grads.1);
+            self.reader.get_bits(bits) + ((sample as i32) << bits)
+        } else {
+            self.reader.get_bits(params.raw_bits) + 1
+        };
+        let code = match code & 1 {
+            1 => -1 - code / 2,
+            _ => code / 2,
+        };
+
+        grads.0 += code.abs();
+        if grads.1 == MIN_VALUE {
+            grads.0 >>= 1;
+            grads.1 >>= 1;
+        }
+        grads.1 += 1;
+        code
+    }
+    fn store(&mut self, index: usize, interp: i32, code: i32, grad: i32) {
+        let params = self.params;
+        let mut value = match grad < 0 {
+            true => interp - code,
+            false => interp + code,
+        };
+        if value < 0 {
+            value += params.total_values;
+        } else if value > params.max_value {
+            value -= params.total_values;
+        }
+        self.lines[index] = value.clamp(0, params.max_value) as u16;
+    }
+    fn even_interpolation(&self, index: usize) -> i32 {
+        let stride = self.stride();
+        let rb = self.get(index - stride);
+        let rc = self.get(index - stride - 1);
+        let rd = self.get(index - stride + 1);
+        let rf = self.get(index - 2 * stride);
+
+        let diff_rc_rb = (rc - rb).abs();
+        let diff_rf_rb = (rf - rb).abs();
+        let diff_rd_rb = (rd - rb).abs();
+        if diff_rc_rb > diff_rf_rb && diff_rc_rb > diff_rd_rb {
+            rf + rd + 2 * rb
+        } else if diff_rd_rb > diff_rc_rb && diff_rd_rb > diff_rf_rb {
+            rf + rc + 2 * rb
+        } else {
+            rd + rc + 2 * rb
+        }
+    }
+    fn interpolate_even(&mut self, line: usize, pos: usize) {
+        let index = self.index(line, pos);
+        self.lines[index] = (self.even_interpolation(index) >> 2) as u16;
+    }
+    fn decode_even(&mut self, line: usize, pos: usize, table: usize) {
+        let stride = self.stride();
+        let index = self.index(line, pos);
+        let rb = self.get(index - stride);
+        let rc = self.get(index - stride - 1);
+        let rf = self.get(index - 2 * stride);
+
+        let interp = self.even_interpolation(index);
+        let grad = self.params.q(rb - rf) * 9 + self.params.q(rc - rb);
+        let code = self.read_code(true, table, grad.unsigned_abs() as usize);
+        self.store(index, interp >> 2, code, grad);
+    }
+    fn decode_odd(&mut self, line: usize, pos: usize, table: usize) {
+        let stride = self.stride();
+        let index = self.index(line, pos);
+        let ra = self.get(index - 1);
+        let rb = self.get(index - stride);
+        let rc = self.get(index - stride - 1);
+        let rd = self.get(index - stride + 1);
+        let rg = self.get(index + 1);
+
+        let interp = if (rb > rc && rb > rd) || (rb < rc && rb < rd) {
+            (rg + ra + 2 * rb) >> 2
+        } else {
+            (ra + rg) >> 1
+        };
+        let grad = self.params.q(rb - rc) * 9 + self.params.q(rc - ra);
+        let code = self.read_code(false, table, grad.unsigned_abs() as usize);
+        self.store(index, interp, code, grad);
+    }
+    /// Restores two lines at the same time, the odd samples run a few samples behind the even ones
+    fn decode_lines(&mut self, lines: [(usize, Even); 2], table: usize) {
+        let line_width = self.params.line_width;
+        let mut even_pos = 0;
+        let mut odd_pos = 1;
+        while even_pos < line_width || odd_pos < line_width {
+            if even_pos < line_width {
+                for (line, even) in lines {
+                    match even.is_decoded(even_pos) {
+                        true => self.decode_even(line, even_pos, table),
+                        false => self.interpolate_even(line, even_pos),
+                    }
+                }
+                even_pos += 2;
+            }
+            if even_pos > 8 {
+                for (line, _) in lines {
+                    self.decode_odd(line, odd_pos, table);
+                }
+                odd_pos += 2;
+            }
+        }
+    }
+    /// The borders of every line are taken from the line above
+    fn extend(&mut self, start: usize, end: usize) {
+        let line_width = self.params.line_width;
+        for line in start..=end {
+            let prev = self.index(line - 1, 0);
+            let current = self.index(line, 0);
+            self.lines[current - 1] = self.lines[prev];
+            self.lines[current + line_width] = self.lines[prev + line_width - 1];
+        }
+    }
+    fn decode_group(&mut self, is_xtrans: bool) {
+        // X-Trans has fewer red and blue samples, the missing ones come from the lines above
+        let [r2, r3, r4, b2, b3, b4] = match is_xtrans {
+            true => [
+                Even::Interpolated,
+                Even::InterpolatedAt(0),
+                Even::InterpolatedAt(2),
+                Even::Interpolated,
+                Even::InterpolatedAt(2),
+                Even::InterpolatedAt(0),
+            ],
+            false => [Even::Decoded; 6],
+        };
+        let g = Even::Decoded;
+
+        self.decode_lines([(R2, r2), (G2, g)], 0);
+        self.extend(R2, R4);
+        self.extend(G2, G7);
+        self.decode_lines([(G3, g), (B2, b2)], 1);
+        self.extend(G2, G7);
+        self.extend(B2, B4);
+        self.decode_lines([(R3, r3), (G4, g)], 2);
+        self.extend(R2, R4);
+        self.extend(G2, G7);
+        self.decode_lines([(G5, g), (B3, b3)], 0);
+        self.extend(G2, G7);
+        self.extend(B2, B4);
+        self.decode_lines([(R4, r4), (G6, g)], 1);
+        self.extend(R2, R4);
+        self.extend(G2, G7);
+        self.decode_lines([(G7, g), (B4, b4)], 2);
+        self.extend(G2, G7);
+        self.extend(B2, B4);
+    }
+    /// Keeps the last two lines of every color for the next group and clears the others
+    fn next_group(&mut self) {
+        let stride = self.stride();
+        for (to, from) in [(R0, R3), (R1, R4), (G0, G6), (G1, G7), (B0, B3), (B1, B4)] {
+            self.lines
+                .copy_within(from * stride..(from + 1) * stride, to * stride);
+        }
+        for (start, end) in [(R2, R4), (G2, G7), (B2, B4)] {
+            self.lines[start * stride..(end + 1) * stride].fill(0);
+            self.extend(start, start);
+        }
+    }
+}
+
+fn decode_block(
+    header: &FujiHeader,
+    params: &Params,
+    data: &[u8],
+    block: usize,
+    image: &mut [u16],
+) {
+    let x = block * header.block_size;
+    let block_width = header.block_size.min(header.width - x);
+    let mut decoder = Block::new(params, data);
+
+    for group in 0..header.total_lines {
+        decoder.decode_group(header.is_xtrans);
+
+        for row in 0..6 {
+            let out = &mut image[(group * 6 + row) * header.width + x..][..block_width];
+            for (col, value) in out.iter_mut().enumerate() {
+                let (color, index) = match header.is_xtrans {
+                    true => (
+                        XTRANS[row][col % 6],
+                        (((col * 2 / 3) & !1) | ((col % 3) & 1)) + ((col % 3) >> 1),
+                    ),
+                    false => (BAYER[row & 1][col & 1], col >> 1),
+                };
+                let line = match color {
+                    0 => R2 + (row >> 1),
+                    2 => B2 + (row >> 1),
+                    _ => G2 + row,
+                };
+                *value = decoder.lines[decoder.index(line, index)];
+            }
+        }
+
+        decoder.next_group();
+    }
+}
+
+/// Decodes the lossless compressed raw data of a RAF, follows `fuji_compressed.cpp` of LibRaw.
+///
+/// The image is split into vertical blocks of 768 columns, which are coded one after another.
+pub(in super::super) fn decode(
+    header: &FujiHeader,
+    data: &[u8],
+) -> Result<Vec<u16>, DecodingError> {
+    if !header.is_lossless {
+        return fuji_error("lossy compressed data is not supported");
+    }
+    let params = Params::new(header);
+
+    let table_len = header.blocks_in_row * 4;
+    let mut offset = 16 + table_len;
+    if table_len & 0xc != 0 {
+        offset += 0x10 - (table_len & 0xc);
+    }
+    if data.len() < offset {
+        return fuji_error("the block sizes are out of range");
+    }
+
+    let mut image = vec![0u16; header.width * header.height];
+    for block in 0..header.blocks_in_row {
+        let size = data.u32be(16 + block * 4) as usize;
+        let block_data = match data.get(offset..offset + size) {
+            Some(x) => x,
+            None => return fuji_error("the compressed block is out of range"),
+        };
+        decode_block(header, &params, block_data, block, &mut image);
+        offset += size;
+    }
+
+    Ok(image)
+}
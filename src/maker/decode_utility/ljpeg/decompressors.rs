@@ -277,6 +277,54 @@ pub fn decode_ljpeg_422(
     Ok(())
 }
 
+/// Decode a subsampled YCbCr stream (Canon sRAW and mRAW) to `out` in stream order.
+/// Every MCU is written as its `super_h * super_v` luma samples followed by Cb and Cr,
+/// `line_len` is the number of samples in one LJPEG line.
+pub fn decode_ljpeg_mcus(
+    ljpeg: &LjpegDecompressor,
+    out: &mut [u16],
+    line_len: usize,
+) -> Result<(), String> {
+    let luma_count = ljpeg.sof.components[0].super_h * ljpeg.sof.components[0].super_v;
+    let mcu_len = luma_count + 2;
+    if ljpeg.sof.cps != 3 || line_len == 0 || line_len % mcu_len != 0 {
+        return Err(format!(
+            "ljpeg: cannot decode lines of {} samples with {} components into MCUs of {}",
+            line_len, ljpeg.sof.cps, mcu_len
+        ));
+    }
+
+    let htables = [0, 1, 2].map(|c| &ljpeg.dhts[ljpeg.sof.components[c].dc_tbl_num]);
+    let mut pump = BitPumpJPEG::new(ljpeg.buffer);
+
+    let base_prediction = 1 << (ljpeg.sof.precision - ljpeg.point_transform - 1);
+    let mut pred = [base_prediction; 3];
+    for line_start in (0..out.len() - out.len() % line_len).step_by(line_len) {
+        if line_start > 0 {
+            // At start of line predictors start with the first MCU of the previous line
+            let prev = line_start - line_len;
+            pred = [
+                out[prev] as i32,
+                out[prev + luma_count] as i32,
+                out[prev + luma_count + 1] as i32,
+            ];
+        }
+        for mcu in out[line_start..line_start + line_len].chunks_exact_mut(mcu_len) {
+            // Every luma sample is predicted by the one decoded before it
+            for luma in &mut mcu[..luma_count] {
+                pred[0] += htables[0].huff_decode(&mut pump);
+                *luma = pred[0] as u16;
+            }
+            pred[1] += htables[1].huff_decode(&mut pump);
+            pred[2] += htables[2].huff_decode(&mut pump);
+            mcu[luma_count] = pred[1] as u16;
+            mcu[luma_count + 1] = pred[2] as u16;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn decode_hasselblad(
     ljpeg: &LjpegDecompressor,
     out: &mut [u16],
@@ -335,6 +335,17 @@ impl<'a> LjpegDecompressor<'a> {
         result.map_err(|err| DecodingError::LJpegError(err))
     }
 
+    /// Decodes the Hasselblad variant whatever the predictor of the stream says,
+    /// the 3FR files do not always mark it with predictor 8
+    pub fn decode_hasselblad(&self, out: &mut [u16], width: usize) -> Result<(), DecodingError> {
+        decode_hasselblad(self, out, width).map_err(|err| DecodingError::LJpegError(err))
+    }
+
+    /// Decodes a subsampled YCbCr stream without upsampling it, see `decode_ljpeg_mcus`
+    pub fn decode_mcus(&self, out: &mut [u16], line_len: usize) -> Result<(), DecodingError> {
+        decode_ljpeg_mcus(self, out, line_len).map_err(|err| DecodingError::LJpegError(err))
+    }
+
     // pub fn decode_leaf(&self, width: usize, height: usize) -> Result<PixU16, String> {
     //     let mut offsets = vec![0_usize; 1];
     //     let mut input = ByteStream::new(self.buffer, Endian::Big);
@@ -380,18 +391,21 @@ impl<'a> LjpegDecompressor<'a> {
     //     ))
     // }
 
-    // pub fn width(&self) -> usize {
-    //     self.sof.width * self.sof.cps
-    // }
-    // pub fn height(&self) -> usize {
-    //     self.sof.height
-    // }
-    // pub fn super_v(&self) -> usize {
-    //     self.sof.components[0].super_v
-    // }
-    // pub fn super_h(&self) -> usize {
-    //     self.sof.components[0].super_h
-    // }
+    pub fn width(&self) -> usize {
+        self.sof.width * self.sof.cps
+    }
+    pub fn height(&self) -> usize {
+        self.sof.height
+    }
+    pub fn precision(&self) -> usize {
+        self.sof.precision
+    }
+    pub fn super_v(&self) -> usize {
+        self.sof.components[0].super_v
+    }
+    pub fn super_h(&self) -> usize {
+        self.sof.components[0].super_h
+    }
     pub fn components(&self) -> usize {
         self.sof.components.len()
     }
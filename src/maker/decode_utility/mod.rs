@@ -5,6 +5,8 @@ pub(in super::super) mod bit_pump;
 pub(in super::super) mod byte_stream;
 pub(in super::super) mod lookup_table;
 pub(in super::super) mod ljpeg;
+pub(in super::super) mod crx;
+pub(in super::super) mod fuji;
 
 #[derive(Error, Debug)]
 pub enum DecodingError {
@@ -14,4 +16,8 @@ pub enum DecodingError {
     LJpegErrorConstructor(String),
     #[error("LJpegDecompressing error: {0}")]
     LJpegError(String),
-}
\ No newline at end of file
+    #[error("CRX decompressing error: {0}")]
+    CrxError(String),
+    #[error("Fujifilm decompressing error: {0}")]
+    FujiCompressedError(String),
+}
\ No newline at end of file
@@ -0,0 +1,106 @@
+use super::*;
+use once_cell::sync::Lazy;
+
+use super::utility::*;
+
+pub(super) struct General {
+    info: quickexif::ParsedInfo,
+}
+
+pub(super) static THUMBNAIL_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 : u16 / orientation
+    })
+});
+
+pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    let template_rule = quickexif::describe_rule!(template {
+        0x0100 / width
+        0x0101 / height
+        0x0111 / strip
+    });
+
+    quickexif::describe_rule!(tiff {
+        0x0112 : u16 / orientation
+        0x828e? / cfa_pattern
+        if cfa_pattern ? {
+            load(template_rule)
+        } else {
+            0x014a {
+                load(template_rule)
+            }
+        }
+        0x8769 {
+            0x927c {
+                offset + 8 {
+                    0x0e80 / white_balance_levels
+                }
+            }
+        }
+    })
+});
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    /// The maker notes hold the R and B levels at byte 48 of tag 0xe80
+    fn get_white_balance(&self, buffer: &[u8]) -> Result<[i32; 3], DecodingError> {
+        let offset = self.info.usize("white_balance_levels")?;
+        let levels = buffer.get(offset + 48..offset + 52).ok_or_else(|| {
+            DecodingError::InvalidRawData("ERF white balance is out of range".to_owned())
+        })?;
+        let is_le = self.info.is_le;
+        let r = levels.u16(is_le, 0) as f64 * 508.0 * 1.078 / 0x10000 as f64;
+        let b = levels.u16(is_le, 2) as f64 * 382.0 * 1.173 / 0x10000 as f64;
+        Ok([(r * 512.0) as i32, 512, (b * 512.0) as i32])
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        None
+    }
+    fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        Ok(cfa_pattern_from_info(&self.info).unwrap_or(CFAPattern::RGGB))
+    }
+    /// The preview is taken as the largest JPEG of the file
+    fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        crate::decode::largest_jpeg_slice(buffer).ok_or_else(|| {
+            DecodingError::RawInfoError(quickexif::parsed_info::Error::FieldNotFound(
+                "thumbnail".into(),
+            ))
+        })
+    }
+    /// The 12 bit samples are packed big endian, with a control byte after every 10 of them
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let strip = self.info.usize("strip")?;
+        let line_len = width * 12 / 8 + (width + 2) / 10;
+
+        let src = buffer
+            .get(strip..)
+            .ok_or_else(|| DecodingError::InvalidRawData("ERF strip is out of range".to_owned()))?;
+        let image: Vec<u16> = src
+            .chunks(line_len)
+            .take(height)
+            .flat_map(|line| {
+                line.chunks(16)
+                    .flat_map(|group| to_12bit_iter_packed(&group[..group.len().min(15)], false))
+                    .take(width)
+            })
+            .collect();
+
+        if image.len() != width * height {
+            return Err(DecodingError::InvalidDecodedImageSize(
+                image.len(),
+                width * height,
+            ));
+        }
+        Ok(image.into_iter().map(|x| x << 4).collect())
+    }
+}
@@ -1,6 +1,10 @@
 use super::*;
+use crate::RawFileReadingError;
 use once_cell::sync::Lazy;
 
+use super::decode_utility::fuji::{self, FujiHeader};
+use super::utility::GetNumFromBytes;
+
 static FUJI_SENSOR_TABLE: phf::Map<&'static str, u8> = phf::phf_map! {
     "X-T1" => 0, // RBGBRG by default
 
@@ -11,6 +15,7 @@ static FUJI_SENSOR_TABLE: phf::Map<&'static str, u8> = phf::phf_map! {
     "X-Pro3" => 1,
     "X-Pro4" => 1,
     "X-E4" => 1,
+    "X-T5" => 1,
     "X100V" => 1,
 
     "GFX50R" => 100, // RGGB
@@ -18,8 +23,35 @@ static FUJI_SENSOR_TABLE: phf::Map<&'static str, u8> = phf::phf_map! {
     "GFX100" => 100,
     "GFX50SII" => 100,
     "GFX100S" => 100,
+    "GFX100IR" => 100,
 };
 
+// the X-Trans layouts of the RAF meta block, 0 is red, 1 is green and 2 is blue
+const XTRANS_LAYOUTS: [(u8, [[u8; 6]; 6]); 2] = [
+    (
+        0,
+        [
+            [0, 2, 1, 2, 0, 1],
+            [1, 1, 0, 1, 1, 2],
+            [1, 1, 2, 1, 1, 0],
+            [2, 0, 1, 0, 2, 1],
+            [1, 1, 2, 1, 1, 0],
+            [1, 1, 0, 1, 1, 2],
+        ],
+    ),
+    (
+        1,
+        [
+            [1, 1, 0, 1, 1, 2],
+            [1, 1, 2, 1, 1, 0],
+            [2, 0, 1, 0, 2, 1],
+            [1, 1, 2, 1, 1, 0],
+            [1, 1, 0, 1, 1, 2],
+            [0, 2, 1, 2, 0, 1],
+        ],
+    ),
+];
+
 pub(super) struct General {
     info: quickexif::ParsedInfo,
 }
@@ -61,6 +93,303 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     })
 });
 
+// the value read from the RAF meta block, see `parse_info`
+static LAYOUT_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0001 / cfa_layout
+    })
+});
+
+// the layout of the SuperCCD files read from the RAF meta block, see `SuperCcd`
+static SUPER_CCD_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0001 / super_ccd_layout
+        0x0002 / super_ccd_width
+        0x0003 / raw_width
+        0x0004 / raw_height
+        0x0005 / image_width
+        0x0006 / image_height
+        0x0007 / data_offset
+        0x0008 / s_pixels
+        0x0009 / width
+        0x000a / height
+        0x000b? / white_balance_r
+        0x000c? / white_balance_g
+        0x000d? / white_balance_b
+    })
+});
+
+/// Reads the records of the RAF meta block at `pos`, a big endian record count followed by
+/// the tag, size and data of every record. The end of the block is given with them.
+fn meta_records(buffer: &[u8], mut pos: usize) -> Option<(Vec<(u16, &[u8])>, usize)> {
+    let count = buffer.get(pos..pos + 4)?.u32be(0) as usize;
+    if count == 0 || count > 0x100 {
+        return None;
+    }
+
+    pos += 4;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        let head = buffer.get(pos..pos + 4)?;
+        let size = head.u16be(2) as usize;
+        records.push((head.u16be(0), buffer.get(pos + 4..pos + 4 + size)?));
+        pos += 4 + size;
+    }
+    Some((records, pos))
+}
+
+/// The meta block is placed after the preview JPEG and always starts with the 0x100 record
+/// of the raw image size, so the buffer is scanned for it
+fn meta_block(buffer: &[u8]) -> Option<(Vec<(u16, &[u8])>, usize)> {
+    buffer
+        .windows(4)
+        .enumerate()
+        .filter(|&(pos, data)| pos >= 4 && data == [0x01, 0x00, 0x00, 0x04])
+        .find_map(|(pos, _)| meta_records(buffer, pos - 4))
+}
+
+/// The sensors of the S3 Pro, S5 Pro and other SuperCCD bodies are rotated by 45 degrees, their
+/// rows are diagonals of the Bayer grid. The 0x130 record tells them from the other sensors.
+struct SuperCcd {
+    /// 1 when the raw rows hold the pixels of two diagonals
+    layout: usize,
+    /// the diagonal size of the sensor, which places the raw rows in the Bayer grid
+    width: usize,
+    raw_width: usize,
+    raw_height: usize,
+    image_width: usize,
+    image_height: usize,
+    data_offset: usize,
+    /// the rows of the low sensitivity S pixels follow every row of the R pixels
+    s_pixels: bool,
+    white_balance: Option<[u16; 3]>,
+}
+
+impl SuperCcd {
+    /// The 16 bit samples end the file, so they are found from its end. The S pixels double
+    /// their size, which would reach into the meta block otherwise.
+    fn new(buffer: &[u8]) -> Option<SuperCcd> {
+        let (records, meta_end) = meta_block(buffer)?;
+        let record = |tag: u16| records.iter().find(|x| x.0 == tag).map(|x| x.1);
+        let flags = record(0x130)?.get(..2)?;
+        if flags[1] & 8 != 0 {
+            return None;
+        }
+        let layout = (flags[0] >> 7) as usize;
+        let raw_size = record(0x100)?;
+        let image_size = record(0x121)?;
+        let (raw_height, raw_width) = (raw_size.u16be(0) as usize, raw_size.u16be(2) as usize);
+        let (image_height, mut image_width) =
+            (image_size.u16be(0) as usize, image_size.u16be(2) as usize);
+        if image_width == 4284 {
+            image_width += 3;
+        }
+        if image_width == 0 || image_height == 0 {
+            return None;
+        }
+
+        let data_end = buffer.len().saturating_sub(crate::decode::BUFFER_PADDING);
+        let data_len = raw_width * raw_height * 2;
+        let s_pixels = data_end >= meta_end + data_len * 2;
+        let data_offset = data_end.checked_sub(data_len * if s_pixels { 2 } else { 1 })?;
+        // the levels are stored as G, R, G and B
+        let white_balance = record(0x2ff0)
+            .filter(|x| x.len() >= 8)
+            .map(|x| [x.u16be(2), x.u16be(0), x.u16be(6)]);
+
+        Some(SuperCcd {
+            layout,
+            width: image_width >> (1 - layout),
+            raw_width,
+            raw_height,
+            image_width,
+            image_height,
+            data_offset,
+            s_pixels,
+            white_balance,
+        })
+    }
+    /// The size of the Bayer grid the diagonals are placed in
+    fn size(&self) -> (usize, usize) {
+        let width = (self.image_height >> self.layout) + self.width;
+        (width, width - 1)
+    }
+    fn info_tiff(&self) -> Vec<u8> {
+        let (width, height) = self.size();
+        let mut tiff = utility::TiffBuilder::new()
+            .long(0x0001, self.layout as u32)
+            .long(0x0002, self.width as u32)
+            .long(0x0003, self.raw_width as u32)
+            .long(0x0004, self.raw_height as u32)
+            .long(0x0005, self.image_width as u32)
+            .long(0x0006, self.image_height as u32)
+            .long(0x0007, self.data_offset as u32)
+            .long(0x0008, self.s_pixels as u32)
+            .long(0x0009, width as u32)
+            .long(0x000a, height as u32);
+        if let Some([r, g, b]) = self.white_balance.filter(|x| x[1] > 0) {
+            let white_balance = |x: u16| x as u32 * 512 / g as u32;
+            tiff = tiff
+                .long(0x000b, white_balance(r))
+                .long(0x000c, 512)
+                .long(0x000d, white_balance(b));
+        }
+        tiff.build()
+    }
+}
+
+/// The CFA layout of the sensor from the RAF meta block, with the codes of `FUJI_SENSOR_TABLE`
+///
+/// X-Trans sensors have the 0x131 record with their 6x6 layout, the others are bayer sensors.
+fn cfa_layout(buffer: &[u8]) -> Option<u8> {
+    let (records, _) = meta_block(buffer)?;
+    let xtrans = records
+        .iter()
+        .find(|(tag, data)| *tag == 0x131 && data.len() >= 36)
+        .map(|(_, data)| data);
+
+    let result = match xtrans {
+        None => 100,
+        Some(data) => {
+            // the layout is stored from the last color to the first one
+            let mut layout = [[0u8; 6]; 6];
+            for (i, &x) in data[..36].iter().rev().enumerate() {
+                layout[i / 6][i % 6] = x & 3;
+            }
+            XTRANS_LAYOUTS
+                .iter()
+                .find(|(_, x)| *x == layout)
+                .map(|&(code, _)| code)?
+        }
+    };
+    Some(result)
+}
+
+pub(super) fn parse_info(
+    buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    if let Some(super_ccd) = SuperCcd::new(buffer) {
+        return Ok(quickexif::parse_with_prev_info(
+            &super_ccd.info_tiff(),
+            &SUPER_CCD_RULE,
+            basic_info,
+        )?);
+    }
+    let info = quickexif::parse_with_prev_info(buffer, &IMAGE_RULE, basic_info)?;
+    match cfa_layout(buffer) {
+        Some(layout) => {
+            let layout = utility::TiffBuilder::new()
+                .long(0x0001, layout as u32)
+                .build();
+            Ok(quickexif::parse_with_prev_info(
+                &layout,
+                &LAYOUT_RULE,
+                info,
+            )?)
+        }
+        None => Ok(info),
+    }
+}
+
+impl General {
+    fn is_super_ccd(&self) -> bool {
+        self.info.usize("super_ccd_width").is_ok()
+    }
+    /// Places the raw rows on the diagonals of the Bayer grid, the corners out of the sensor
+    /// are left black. The 14 bit samples are big endian.
+    fn load_super_ccd(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let layout = self.info.usize("super_ccd_layout")?;
+        let fuji_width = self.info.usize("super_ccd_width")?;
+        let raw_width = self.info.usize("raw_width")?;
+        let raw_height = self.info.usize("raw_height")?;
+        let data_offset = self.info.usize("data_offset")?;
+        let s_pixels = self.info.u32("s_pixels")? == 1;
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        // the sensor is centered in the raw data on even rows and columns
+        let top = raw_height.saturating_sub(self.info.usize("image_height")?) >> 2 << 1;
+        let left = raw_width.saturating_sub(self.info.usize("image_width")?) >> 2 << 1;
+
+        let stride = raw_width * if s_pixels { 2 } else { 1 };
+        let data = buffer
+            .get(data_offset..data_offset + stride * raw_height * 2)
+            .ok_or_else(|| DecodingError::InvalidRawData("RAF data is out of range".to_owned()))?;
+        let sample = |row: usize, col: usize| data.u16be((row * stride + col) * 2) & 0x3fff;
+        let s_pixels = (cfg!(feature = "super_ccd_s_pixels") && s_pixels)
+            .then(|| SPixels::new(data, stride, raw_width, raw_height));
+
+        let mut image = vec![0u16; width * height];
+        let wide = (fuji_width << (1 - layout)).min(raw_width - left);
+        for row in 0..raw_height - top * 2 {
+            for col in 0..wide {
+                let (r, c) = if layout == 1 {
+                    (
+                        fuji_width as isize - 1 - col as isize + (row >> 1) as isize,
+                        col + ((row + 1) >> 1),
+                    )
+                } else {
+                    (
+                        (fuji_width - 1 + row) as isize - (col >> 1) as isize,
+                        row + ((col + 1) >> 1),
+                    )
+                };
+                if r < 0 || r as usize >= height || c >= width {
+                    continue;
+                }
+                let (raw_row, raw_col) = (row + top, col + left);
+                let value = sample(raw_row, raw_col);
+                image[r as usize * width + c] = match &s_pixels {
+                    Some(s_pixels) => s_pixels.merge(value, sample(raw_row, raw_col + raw_width)),
+                    None => value.saturating_mul(4),
+                };
+            }
+        }
+        Ok(image)
+    }
+}
+
+/// The low sensitivity S pixels take the place of the clipped R pixels when the
+/// `super_ccd_s_pixels` feature is on, their sensitivity is measured from the pixels which
+/// are not clipped. The range is compressed to keep the highlights.
+struct SPixels {
+    ratio: f32,
+    scale: f32,
+}
+
+impl SPixels {
+    const CLIPPED: u16 = 0x3000;
+
+    fn new(data: &[u8], stride: usize, raw_width: usize, raw_height: usize) -> SPixels {
+        let (mut r_sum, mut s_sum) = (0u64, 0u64);
+        for row in (0..raw_height).step_by(4) {
+            for col in (0..raw_width).step_by(4) {
+                let r = data.u16be((row * stride + col) * 2) & 0x3fff;
+                let s = data.u16be((row * stride + raw_width + col) * 2) & 0x3fff;
+                if (0x400..Self::CLIPPED).contains(&r) && s > 0 {
+                    r_sum += r as u64;
+                    s_sum += s as u64;
+                }
+            }
+        }
+        let ratio = match s_sum {
+            0 => 1.0,
+            _ => (r_sum as f32 / s_sum as f32).max(1.0),
+        };
+        SPixels {
+            ratio,
+            scale: u16::MAX as f32 / (0x3fff as f32 * ratio),
+        }
+    }
+    fn merge(&self, r: u16, s: u16) -> u16 {
+        let value = if r >= Self::CLIPPED {
+            (s as f32 * self.ratio).max(r as f32)
+        } else {
+            r as f32
+        };
+        (value * self.scale).round().min(u16::MAX as f32) as u16
+    }
+}
 
 impl RawDecoder for General {
     fn new(info: quickexif::ParsedInfo) -> Self {
@@ -76,12 +405,21 @@ impl RawDecoder for General {
         None
     }
     fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        if self.is_super_ccd() {
+            return match self.info.usize("super_ccd_width")? % 2 {
+                1 => Ok(CFAPattern::RGGB),
+                _ => Ok(CFAPattern::GBRG),
+            };
+        }
         let model = self
             .info
             .str("model")?
             .split_whitespace()
             .collect::<String>();
-        let pattern = FUJI_SENSOR_TABLE.get(model.as_str()).unwrap_or(&0);
+        let pattern = match self.info.u32("cfa_layout") {
+            Ok(layout) => layout as u8,
+            Err(_) => *FUJI_SENSOR_TABLE.get(model.as_str()).unwrap_or(&0),
+        };
         let result = match pattern {
             1 => CFAPattern::XTrans1,
             100 => CFAPattern::RGGB,
@@ -90,6 +428,9 @@ impl RawDecoder for General {
         Ok(result)
     }
     fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        if self.is_super_ccd() {
+            return self.load_super_ccd(buffer);
+        }
         let jpeg_header_offset = 12;
         let tiff_offset = self.info.usize("tiff_offset")?;
         let strip_offset = self.info.usize("strip")?;
@@ -97,13 +438,42 @@ impl RawDecoder for General {
         let width = self.info.usize("width")?;
         let height = self.info.usize("height")?;
         let black_level = self.info.u16("black_level")?;
-        let bps_scale = self.get_bps_scale()?;
 
         let data_offset = jpeg_header_offset + tiff_offset + strip_offset;
-        let buf = &buffer[data_offset..data_offset + strip_len];
-        let image: Vec<u16> = utility::to_14bit_iter(buf, self.info.is_le)
-            .map(|x| bps_scale.saturating_mul(x.saturating_sub(black_level)))
-            .collect();
+        let buf = buffer
+            .get(data_offset..data_offset + strip_len)
+            .ok_or_else(|| DecodingError::InvalidRawData("RAF strip is out of range".to_owned()))?;
+
+        let image: Vec<u16> = if FujiHeader::is_compressed(buf) {
+            let header = FujiHeader::new(buf)?;
+            if !header.is_lossless {
+                return Err(DecodingError::CompressionNotSupported(
+                    "Fujifilm lossy compressed RAF".to_owned(),
+                ));
+            }
+            let bps_scale: u16 = match header.bits {
+                12 => 16,
+                14 => 4,
+                _ => 1,
+            };
+            // the compressed width is a multiple of 24, which may be wider than the image
+            fuji::decode(&header, buf)?
+                .chunks_exact(header.width)
+                .flat_map(|row| row.iter().take(width))
+                .map(|x| bps_scale.saturating_mul(x.saturating_sub(black_level)))
+                .collect()
+        } else {
+            let bps_scale = self.get_bps_scale()?;
+            let sub_black_level = |x: u16| bps_scale.saturating_mul(x.saturating_sub(black_level));
+            match self.info.u16("bps")? {
+                16 => utility::to_16bit_iter(buf, self.info.is_le)
+                    .map(sub_black_level)
+                    .collect(),
+                _ => utility::to_14bit_iter(buf, self.info.is_le)
+                    .map(sub_black_level)
+                    .collect(),
+            }
+        };
 
         if image.len() != width * height {
             Err(DecodingError::InvalidDecodedImageSize(image.len(), width * height))
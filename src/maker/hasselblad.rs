@@ -0,0 +1,196 @@
+use super::*;
+use once_cell::sync::Lazy;
+
+use super::{decode_utility::ljpeg::LjpegDecompressor, utility::*};
+
+pub(super) struct General {
+    info: quickexif::ParsedInfo,
+}
+
+pub(super) static THUMBNAIL_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 : u16 / orientation
+        0x0111 / thumbnail
+        0x0117 / thumbnail_len
+    })
+});
+
+pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 : u16 / orientation
+        0xc628 {
+            r64 + 0 / white_balance_r
+            r64 + 1 / white_balance_g
+            r64 + 2 / white_balance_b
+        }
+    })
+});
+
+// the tags of the raw IFD, 3FR files keep it in the SubIFD of the preview and the FFF files
+// of tethered shooting and Phocus at other places, so it is found by its NewSubfileType
+macro_rules! raw_ifd_template {
+    () => {
+        quickexif::describe_rule!(template {
+            0x0100 / width
+            0x0101 / height
+            0x0102 : u16 / bps
+            0x0103 : u16 / compression
+            0x0111 / strip(strip_count)
+            0x0117 / strip_len
+            0x828e? / cfa_pattern
+            0xc61a? : u16 / black_level
+            0xc61f? / crop_origin
+            0xc620? / crop_size
+        })
+    };
+}
+
+pub(super) static RAW_IFD_RULES: Lazy<RawIfdRules> =
+    Lazy::new(|| raw_ifd_rules!(raw_ifd_template!()));
+
+/// The size of the images the X1D and X2D bodies render, their raw data has a few
+/// more rows and columns around it
+const OUTPUT_SIZES: [(&str, u32, u32); 4] = [
+    ("X1D", 8272, 6200),
+    ("X1D II 50C", 8272, 6200),
+    ("X1D-50c", 8272, 6200),
+    ("X2D 100C", 11656, 8742),
+];
+
+impl General {
+    /// The offset and the size of every strip, a single strip has them as the tag values
+    fn strips(&self, buffer: &[u8]) -> Result<Vec<(usize, usize)>, DecodingError> {
+        let offsets = self.info.usize("strip")?;
+        let byte_counts = self.info.usize("strip_len")?;
+        let count = self.info.usize("strip_count")?;
+        if count == 1 {
+            return Ok(vec![(offsets, byte_counts)]);
+        }
+
+        let is_le = self.info.is_le;
+        let table = |addr: usize| {
+            buffer
+                .get(addr..addr + count * 4)
+                .map(|x| x.chunks_exact(4).map(move |x| x.u32(is_le, 0) as usize))
+                .ok_or_else(|| {
+                    DecodingError::InvalidRawData("3FR strips are out of range".to_owned())
+                })
+        };
+        Ok(table(offsets)?.zip(table(byte_counts)?).collect())
+    }
+    /// The image is centered in the raw data of the bodies without a crop of their own
+    fn model_crop(&self) -> Option<Crop> {
+        let model = self.info.str("model").ok()?;
+        let (_, width, height) = OUTPUT_SIZES.iter().find(|(name, ..)| *name == model)?;
+        let raw_width = self.info.u32("width").ok()?;
+        let raw_height = self.info.u32("height").ok()?;
+        if raw_width < *width || raw_height < *height {
+            return None;
+        }
+
+        Some(Crop {
+            x: (raw_width - width) / 2,
+            y: (raw_height - height) / 2,
+            width: *width,
+            height: *height,
+        })
+    }
+}
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    /// 3FR files keep the white balance in the AsShotNeutral tag of the DNG spec
+    fn get_white_balance(&self, _buffer: &[u8]) -> Result<[i32; 3], DecodingError> {
+        let r = 512.0 / self.info.f64("white_balance_r")?;
+        let g = 512.0 / self.info.f64("white_balance_g")?;
+        let b = 512.0 / self.info.f64("white_balance_b")?;
+        Ok([r as i32, g as i32, b as i32])
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        if let (Ok(crop_origin), Ok(crop_size)) =
+            (self.info.u8a4("crop_origin"), self.info.u8a4("crop_size"))
+        {
+            let is_le = self.info.is_le;
+            Some(Crop {
+                x: crop_origin.as_slice().u16(is_le, 0) as u32,
+                y: crop_origin.as_slice().u16(is_le, 2) as u32,
+                width: crop_size.as_slice().u16(is_le, 0) as u32,
+                height: crop_size.as_slice().u16(is_le, 2) as u32,
+            })
+        } else {
+            self.model_crop()
+        }
+    }
+    /// The sensors are RGGB when the raw IFD has no CFAPattern
+    fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        Ok(cfa_pattern_from_info(&self.info).unwrap_or(CFAPattern::RGGB))
+    }
+    fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        let offset = self.info.usize("thumbnail")?;
+        let len = self.info.usize("thumbnail_len")?;
+        buffer
+            .get(offset..offset + len)
+            .ok_or_else(|| DecodingError::InvalidRawData("3FR preview is out of range".to_owned()))
+    }
+    fn get_black_level(&self) -> [u16; 4] {
+        let black_level = self.info.u16("black_level").unwrap_or(0);
+        let bps_scale = self.get_bps_scale().unwrap_or(1);
+        [bps_scale.saturating_mul(black_level); 4]
+    }
+    /// The images of the 100 MP bodies are large, so the samples are decoded straight
+    /// into the output and the scaling is applied in place
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let strips = self.strips(buffer)?;
+        let compression = self.info.u16("compression")?;
+        let bps_scale = self.get_bps_scale()?;
+
+        let strip_out_of_range =
+            || DecodingError::InvalidRawData("3FR strip is out of range".to_owned());
+        let mut image: Vec<u16> = match compression {
+            // the uncompressed FFF files of tethered shooting may have many strips
+            1 => {
+                let mut image = Vec::with_capacity(width * height);
+                for &(offset, len) in strips.iter() {
+                    let strip = buffer
+                        .get(offset..offset + len)
+                        .ok_or_else(strip_out_of_range)?;
+                    image.extend(to_16bit_iter(strip, self.info.is_le));
+                }
+                image.truncate(width * height);
+                image
+            }
+            _ => {
+                let buf = strips
+                    .first()
+                    .and_then(|&(offset, _)| buffer.get(offset..))
+                    .ok_or_else(strip_out_of_range)?;
+                let decompressor = LjpegDecompressor::new_full(buf, true, false)
+                    .map_err(decode_utility::DecodingError::LJpegErrorConstructor)?;
+                let mut image = vec![0u16; width * height];
+                decompressor.decode_hasselblad(&mut image, width)?;
+                image
+            }
+        };
+
+        if image.len() != width * height {
+            return Err(DecodingError::InvalidDecodedImageSize(
+                image.len(),
+                width * height,
+            ));
+        }
+        image
+            .iter_mut()
+            .for_each(|x| *x = bps_scale.saturating_mul(*x));
+        Ok(image)
+    }
+}
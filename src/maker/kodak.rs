@@ -0,0 +1,220 @@
+use super::*;
+use once_cell::sync::Lazy;
+
+use super::utility::*;
+
+pub(super) struct General {
+    info: quickexif::ParsedInfo,
+}
+
+pub(super) static THUMBNAIL_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 : u16 / orientation
+    })
+});
+
+/// The raw data is in the first IFD with a CFAPattern, which is the SubIFD of the files
+/// starting with a preview. The curve and the white balance are in the Kodak IFD.
+pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    let template_rule = quickexif::describe_rule!(template {
+        0x0100 / width
+        0x0101 / height
+        0x0103 : u16 / compression
+        0x0111 / strip
+    });
+
+    quickexif::describe_rule!(tiff {
+        0x0112 : u16 / orientation
+        0x828e? / cfa_pattern
+        if cfa_pattern ? {
+            load(template_rule)
+        } else {
+            0x014a {
+                0x828e / cfa_pattern
+                load(template_rule)
+            }
+        }
+        0x8290? {
+            0x03fd? / white_balance(white_balance_len)
+            0x090d? / linearization_table(linearization_table_len)
+        }
+    })
+});
+
+impl General {
+    /// The curve maps the decoded values to linear 12 bit samples, it is the identity
+    /// for the files without one
+    fn curve(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let (offset, count) = match (
+            self.info.usize("linearization_table"),
+            self.info.usize("linearization_table_len"),
+        ) {
+            (Ok(offset), Ok(count)) if count > 2 => (offset, count.min(0x10000)),
+            _ => return Ok((0..0x1000).collect()),
+        };
+        let table = buffer.get(offset..offset + count * 2).ok_or_else(|| {
+            DecodingError::InvalidRawData("Kodak linearization table is out of range".to_owned())
+        })?;
+        Ok(to_16bit_iter(table, self.info.is_le).collect())
+    }
+}
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    /// The white balance set in the software is a block of 72 bytes holding the R, G and B
+    /// levels at byte 40, the multipliers are their inverse
+    fn get_white_balance(&self, buffer: &[u8]) -> Result<[i32; 3], DecodingError> {
+        let offset = match (
+            self.info.usize("white_balance"),
+            self.info.usize("white_balance_len"),
+        ) {
+            (Ok(offset), Ok(72)) => offset,
+            _ => return Ok([512, 512, 512]),
+        };
+        let levels = buffer.get(offset + 40..offset + 46).ok_or_else(|| {
+            DecodingError::InvalidRawData("Kodak white balance is out of range".to_owned())
+        })?;
+        let is_le = self.info.is_le;
+        let [r, g, b] = [0, 2, 4].map(|i| levels.u16(is_le, i).max(1) as i32);
+        Ok([512 * g / r, 512, 512 * g / b])
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        None
+    }
+    /// Kodak files keep their JPEG previews in their own IFDs, the largest one is taken
+    fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        crate::decode::largest_jpeg_slice(buffer).ok_or_else(|| {
+            DecodingError::RawInfoError(quickexif::parsed_info::Error::FieldNotFound(
+                "thumbnail".into(),
+            ))
+        })
+    }
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let strip = self.info.usize("strip")?;
+        let compression = self.info.u16("compression")?;
+        let curve = self.curve(buffer)?;
+        let white_level = curve.last().copied().unwrap_or(1).max(1);
+        let scale = u16::MAX / white_level;
+
+        let src = buffer.get(strip..).ok_or_else(|| {
+            DecodingError::InvalidRawData("Kodak strip is out of range".to_owned())
+        })?;
+        let image = match compression {
+            65000 => load_raw(src, self.info.is_le, width, height, &curve),
+            _ => {
+                return Err(DecodingError::CompressionNotSupported(format!(
+                    "Kodak {}",
+                    compression
+                )))
+            }
+        };
+        Ok(image.into_iter().map(|x| scale.saturating_mul(x)).collect())
+    }
+}
+
+/// Reads the bytes of the raw data, the missing bytes of a truncated file are read as 0
+struct Reader<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> u8 {
+        let value = self.src.get(self.pos).copied().unwrap_or(0);
+        self.pos += 1;
+        value
+    }
+    fn u16(&mut self, is_le: bool) -> u16 {
+        let bytes = [self.u8(), self.u8()];
+        if is_le {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+}
+
+/// Decodes the Kodak 65000 compression, every row is cut in segments of 256 samples
+/// which are predicted from the previous sample of the same color
+fn load_raw(src: &[u8], is_le: bool, width: usize, height: usize, curve: &[u16]) -> Vec<u16> {
+    let mut reader = Reader { src, pos: 0 };
+    let mut image = vec![0u16; width * height];
+    let mut segment = [0i32; 256];
+    let last = curve.len() as i32 - 1;
+
+    for row in image.chunks_exact_mut(width) {
+        for out in row.chunks_mut(256) {
+            let is_diff = decode_segment(&mut reader, &mut segment, out.len(), is_le);
+            let mut pred = [0i32; 2];
+            for (i, out) in out.iter_mut().enumerate() {
+                let value = if is_diff {
+                    pred[i & 1] += segment[i];
+                    pred[i & 1]
+                } else {
+                    segment[i]
+                };
+                *out = curve[value.clamp(0, last) as usize];
+            }
+        }
+    }
+    image
+}
+
+/// A segment starts with the 4 bit lengths of its differences, which follow in words of
+/// 32 bits. The segments with lengths over 12 bits hold 8 samples of 12 bits in every 6
+/// shorts instead, in which case false is returned.
+fn decode_segment(reader: &mut Reader, out: &mut [i32; 256], len: usize, is_le: bool) -> bool {
+    let size = (len + 3) & !3;
+    let start = reader.pos;
+    let mut lens = [0u32; 256];
+    for i in (0..size).step_by(2) {
+        let c = reader.u8();
+        lens[i] = (c & 15) as u32;
+        lens[i + 1] = (c >> 4) as u32;
+        if lens[i] > 12 || lens[i + 1] > 12 {
+            reader.pos = start;
+            for i in (0..size).step_by(8) {
+                let raw = [0; 6].map(|_: u16| reader.u16(is_le));
+                out[i] = (raw[0] >> 12 << 8 | raw[2] >> 12 << 4 | raw[4] >> 12) as i32;
+                out[i + 1] = (raw[1] >> 12 << 8 | raw[3] >> 12 << 4 | raw[5] >> 12) as i32;
+                for (out, raw) in out[i + 2..i + 8].iter_mut().zip(raw) {
+                    *out = (raw & 0xfff) as i32;
+                }
+            }
+            return false;
+        }
+    }
+
+    let mut bitbuf = 0u64;
+    let mut bits = 0u32;
+    if size & 7 == 4 {
+        bitbuf = (reader.u8() as u64) << 8 | reader.u8() as u64;
+        bits = 16;
+    }
+    for (out, &len) in out.iter_mut().zip(lens.iter()).take(size) {
+        if bits < len {
+            for j in (0..32).step_by(8) {
+                bitbuf += (reader.u8() as u64) << (bits + (j ^ 8));
+            }
+            bits += 32;
+        }
+        let mut diff = (bitbuf & (0xffff >> (16 - len))) as i32;
+        bitbuf >>= len;
+        bits -= len;
+        if len > 0 && diff & (1 << (len - 1)) == 0 {
+            diff -= (1 << len) - 1;
+        }
+        *out = diff;
+    }
+    true
+}
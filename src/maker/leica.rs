@@ -0,0 +1,21 @@
+use once_cell::sync::Lazy;
+
+/// The maker notes of the M and Q bodies start with "LEICA", a version and the IFD
+pub(super) static MAKER_NOTE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x8769? {
+            0x927c? {
+                offset + 8 {
+                    0x0303? {
+                        str + 0 / lens_id
+                    }
+                }
+            }
+        }
+    })
+});
+
+/// Leica writes DNG files natively, they are decoded as the other DNG files
+pub(super) fn is_leica(make: &str) -> bool {
+    make.starts_with("Leica") || make.starts_with("LEICA")
+}
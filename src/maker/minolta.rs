@@ -0,0 +1,192 @@
+use super::*;
+use crate::RawFileReadingError;
+use once_cell::sync::Lazy;
+
+use super::utility::{self, GetNumFromBytes};
+
+pub(super) struct General {
+    info: quickexif::ParsedInfo,
+}
+
+/// The TIFF of the TTW block, whose offsets are relative to the block
+pub(super) static EXIF_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 / orientation
+        0x8769 {
+            0xa002 / width
+            0xa003 / height
+        }
+    })
+});
+
+pub(super) static THUMBNAIL_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 / orientation
+        0x8769 {
+            0x927c {
+                0x0088 / thumbnail
+                0x0089 / thumbnail_len
+            }
+        }
+    })
+});
+
+// the values read from the MRW blocks, see `parse_info`
+static LAYOUT_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0001 / width
+        0x0002 / height
+        0x0003 / raw_depth
+        0x0004 / data_offset
+        0x0005 / bayer_pattern
+        0x0009 / white_balance_r
+        0x000a / white_balance_g
+        0x000b / white_balance_b
+    })
+});
+
+const PRD: u32 = 0x00505244;
+const WBG: u32 = 0x00574247;
+const TTW: u32 = 0x00545457;
+
+/// The blocks of an MRW file, every block starts with its name and length and the
+/// raw data follows the last one
+struct Mrw<'a> {
+    buffer: &'a [u8],
+    data_offset: usize,
+    blocks: Vec<(u32, usize, usize)>,
+}
+
+impl<'a> Mrw<'a> {
+    fn new(buffer: &'a [u8]) -> Option<Mrw<'a>> {
+        if !is_mrw(buffer) {
+            return None;
+        }
+
+        let data_offset = buffer.u32be(4) as usize + 8;
+        let mut blocks = vec![];
+        let mut pos = 8;
+        while pos + 8 <= data_offset.min(buffer.len()) {
+            let name = buffer.u32be(pos);
+            let len = buffer.u32be(pos + 4) as usize;
+            blocks.push((name, pos + 8, len));
+            pos += len + 8;
+        }
+        Some(Mrw {
+            buffer,
+            data_offset,
+            blocks,
+        })
+    }
+    fn get(&self, name: u32) -> Option<&'a [u8]> {
+        let &(_, start, len) = self.blocks.iter().find(|&&(x, ..)| x == name)?;
+        self.buffer.get(start..start + len)
+    }
+    /// The white balance is given as the RGGB levels, the DiMAGE A200 starts with the
+    /// second green
+    fn white_balance(&self, model: &str) -> Option<[u16; 3]> {
+        let wbg = self.get(WBG)?;
+        let levels = wbg.get(4..12)?;
+        let level = |i: usize| levels.u16be(i * 2);
+        if model == "DiMAGE A200" {
+            Some([level(2), level(0), level(1)])
+        } else {
+            Some([level(0), level(1), level(3)])
+        }
+    }
+    fn info_tiff(&self, model: &str) -> Option<Vec<u8>> {
+        let prd = self.get(PRD)?.get(..24)?;
+        let [r, g, b] = self.white_balance(model).unwrap_or([1, 1, 1]);
+        let white_balance = |x: u16| x as u32 * 512 / g.max(1) as u32;
+        Some(
+            utility::TiffBuilder::new()
+                .long(0x0001, prd.u16be(10) as u32)
+                .long(0x0002, prd.u16be(8) as u32)
+                .short(0x0003, prd[16] as u16)
+                .long(0x0004, self.data_offset as u32)
+                .short(0x0005, prd.u16be(22))
+                .long(0x0009, white_balance(r))
+                .long(0x000a, white_balance(g))
+                .long(0x000b, white_balance(b))
+                .build(),
+        )
+    }
+}
+
+pub(in super::super) fn is_mrw(buffer: &[u8]) -> bool {
+    buffer.starts_with(b"\0MRM") && buffer.len() > 8
+}
+
+/// The TIFF of the TTW block, which holds make, model and the EXIF of the file
+pub(in super::super) fn ttw_slice(buffer: &[u8]) -> Option<&[u8]> {
+    Mrw::new(buffer)?.get(TTW)
+}
+
+pub(super) fn parse_info(
+    buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    let model = basic_info.str("model").unwrap_or_default().to_owned();
+    let layout = Mrw::new(buffer)
+        .and_then(|mrw| mrw.info_tiff(&model))
+        .ok_or_else(|| DecodingError::InvalidRawData("MRW PRD block is not found".to_owned()))?;
+    Ok(quickexif::parse_with_prev_info(
+        &layout,
+        &LAYOUT_RULE,
+        basic_info,
+    )?)
+}
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        None
+    }
+    fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        match self.info.u16("bayer_pattern")? {
+            0x0004 => Ok(CFAPattern::GBRG),
+            _ => Ok(CFAPattern::RGGB),
+        }
+    }
+    fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        let offset = self.info.usize("thumbnail")?;
+        let len = self.info.usize("thumbnail_len")?;
+        buffer
+            .get(offset..offset + len)
+            .ok_or_else(|| DecodingError::InvalidRawData("MRW preview is out of range".to_owned()))
+    }
+    /// The 12 bit samples are big endian, packed or padded to 16 bits by their raw depth
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let data_offset = self.info.usize("data_offset")?;
+
+        let src = buffer.get(data_offset..).ok_or_else(|| {
+            DecodingError::InvalidRawData("MRW raw data is out of range".to_owned())
+        })?;
+        let image: Vec<u16> = match self.info.u16("raw_depth")? {
+            12 => utility::to_12bit_iter_packed(src, false)
+                .take(width * height)
+                .collect(),
+            _ => utility::to_12bit_iter(src, false)
+                .take(width * height)
+                .collect(),
+        };
+
+        if image.len() != width * height {
+            return Err(DecodingError::InvalidDecodedImageSize(
+                image.len(),
+                width * height,
+            ));
+        }
+        Ok(image.into_iter().map(|x| x << 4).collect())
+    }
+}
@@ -1,25 +1,41 @@
 use crate::decode::{CFAPattern, Crop, Orientation};
 use thiserror::Error;
+use utility::{GetBytesFromInt, GetNumFromBytes};
 
+pub(super) mod cr3;
+pub(super) mod crw;
+pub(super) mod minolta;
+pub(super) mod phase_one;
+pub(super) mod registry;
 pub(super) mod selector;
+mod opcode;
+#[macro_use]
 mod utility;
 
 mod adobe;
 mod canon;
 mod decode_utility;
+mod epson;
 mod fujifilm;
+mod hasselblad;
+mod kodak;
+mod leica;
 mod nikon;
 mod olympus;
 mod panasonic;
+mod pentax;
+mod ricoh;
 mod sony;
+mod tiff;
 
-pub(super) trait RawDecoder {
+/// The decoder of a raw format, implement it to register a decoder with `register_decoder`
+pub trait RawDecoder {
     fn new(info: quickexif::ParsedInfo) -> Self
     where
         Self: Sized;
     fn get_info(&self) -> &quickexif::ParsedInfo;
     fn into_info(self) -> quickexif::ParsedInfo;
-    fn get_white_balance(&self) -> Result<[i32; 3], DecodingError> {
+    fn get_white_balance(&self, _buffer: &[u8]) -> Result<[i32; 3], DecodingError> {
         let info = self.get_info();
         Ok([
             info.i32("white_balance_r")?,
@@ -28,6 +44,10 @@ pub(super) trait RawDecoder {
         ])
     }
     fn get_crop(&self) -> Option<Crop>;
+    fn get_image_size(&self, _buffer: &[u8]) -> Result<(usize, usize), DecodingError> {
+        let info = self.get_info();
+        Ok((info.usize("width")?, info.usize("height")?))
+    }
     fn get_bps_scale(&self) -> Result<u16, DecodingError> {
         let bps = self.get_info().u16("bps")?;
         let result = match bps {
@@ -37,6 +57,18 @@ pub(super) trait RawDecoder {
         };
         Ok(result)
     }
+    /// The value of the clipped samples in the decoded image, after the black level and the
+    /// scaling of the preprocessing, the maximum of the bit depth by default
+    fn get_white_level(&self) -> u16 {
+        let bps = self.get_info().u16("bps").unwrap_or(16).clamp(1, 16);
+        let scale = self.get_bps_scale().unwrap_or(1) as u32;
+        (((1u32 << bps) - 1) * scale).min(u16::MAX as u32) as u16
+    }
+    /// The black levels of the positions of the 2x2 CFA block which are left in the samples
+    /// of the preprocessing, in their scale. They are subtracted after it, none by default.
+    fn get_black_level(&self) -> [u16; 4] {
+        [0; 4]
+    }
     fn get_orientation(&self) -> Orientation {
         match self.get_info().u16("orientation").ok() {
             None => Orientation::Horizontal,
@@ -49,22 +81,85 @@ pub(super) trait RawDecoder {
             },
         }
     }
+    /// The exposure in EV the file asks the image to be rendered with, none by default
+    fn get_baseline_exposure(&self) -> f32 {
+        0.0
+    }
     fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError>;
+    /// The number of raw frames in the file, Dual Pixel RAW files hold two of them
+    fn get_frame_count(&self, _buffer: &[u8]) -> usize {
+        1
+    }
+    fn decode_frame_with_preprocess(
+        &self,
+        buffer: &[u8],
+        frame: usize,
+    ) -> Result<Vec<u16>, DecodingError> {
+        match frame {
+            0 => self.decode_with_preprocess(buffer),
+            _ => Err(DecodingError::FrameNotFound(frame)),
+        }
+    }
+    /// The tone curve the file asks to be rendered with as the lookup table of the linear
+    /// values, which replaces the transfer function, none by default
+    fn get_tone_curve(&self, _buffer: &[u8]) -> Result<Option<Vec<u16>>, DecodingError> {
+        Ok(None)
+    }
+    /// Applies the corrections the file asks for after decoding, e.g. the DNG opcode lists
+    fn apply_opcodes(&self, _buffer: &[u8], image: Vec<u16>) -> Result<Vec<u16>, DecodingError> {
+        Ok(image)
+    }
     #[allow(dead_code)]
     fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError>;
     fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
-        let cfa_pattern = self.get_info().u8a4("cfa_pattern")?;
-        let result = match cfa_pattern {
-            [0, 1, 1, 2] => CFAPattern::RGGB,
-            [2, 1, 1, 0] => CFAPattern::BGGR,
-            [1, 0, 2, 1] => CFAPattern::GRBG,
-            [1, 2, 0, 1] => CFAPattern::GBRG,
-            _ => CFAPattern::RGGB,
-        };
-        Ok(result)
+        cfa_pattern_from_info(self.get_info())
+    }
+    /// The generic decoder of the makers without one of their own, whose colors are approximate
+    fn is_generic_fallback(&self) -> bool {
+        false
     }
 }
 
+/// Maps the CFAPattern tag to a `CFAPattern`, the CFARepeatPatternDim of 6x6 patterns tells
+/// the X-Trans ones, which are known by the first row of their pattern
+pub(super) fn cfa_pattern_from_info(
+    info: &quickexif::ParsedInfo,
+) -> Result<CFAPattern, DecodingError> {
+    let repeat_dim = info
+        .u8a4("cfa_repeat_dim")
+        .map(|x| [x.as_slice().u16(info.is_le, 0), x.as_slice().u16(info.is_le, 2)]);
+    match repeat_dim {
+        Ok([2, 2]) | Err(_) => {}
+        Ok([6, 6]) => {
+            let head = info.u8a4("cfa_pattern_row")?;
+            let tail = info.u16("cfa_pattern_row_tail")?.to_bytes(info.is_le);
+            return match [head[0], head[1], head[2], head[3], tail[0], tail[1]] {
+                [0, 2, 1, 2, 0, 1] => Ok(CFAPattern::XTrans0),
+                [1, 1, 0, 1, 1, 2] => Ok(CFAPattern::XTrans1),
+                _ => Err(DecodingError::InvalidRawData(
+                    "The X-Trans CFAPattern is not supported".to_owned(),
+                )),
+            };
+        }
+        Ok([rows, cols]) => {
+            return Err(DecodingError::InvalidRawData(format!(
+                "The {}x{} CFAPattern is not supported",
+                rows, cols
+            )))
+        }
+    }
+
+    let cfa_pattern = info.u8a4("cfa_pattern")?;
+    let result = match cfa_pattern {
+        [0, 1, 1, 2] => CFAPattern::RGGB,
+        [2, 1, 1, 0] => CFAPattern::BGGR,
+        [1, 0, 2, 1] => CFAPattern::GRBG,
+        [1, 2, 0, 1] => CFAPattern::GBRG,
+        _ => CFAPattern::RGGB,
+    };
+    Ok(result)
+}
+
 #[derive(Error, Debug)]
 pub enum DecodingError {
     #[error("Decoding error.")]
@@ -73,4 +168,10 @@ pub enum DecodingError {
     InvalidDecodedImageSize(usize, usize),
     #[error("JPEG error.")]
     LJPEGError(#[from] decode_utility::DecodingError),
+    #[error("The raw data is invalid: {0}")]
+    InvalidRawData(String),
+    #[error("The raw frame {0} is not found.")]
+    FrameNotFound(usize),
+    #[error("The compression '{0}' is not supported yet.")]
+    CompressionNotSupported(String),
 }
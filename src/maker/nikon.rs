@@ -28,12 +28,12 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     quickexif::describe_rule!(tiff {
         0x0112 : u16 / orientation
         0x8769 {
-            0xa302 {
+            0xa302? {
                 u32 + 1 / cfa_pattern
             }
             0x927c / maker_notes {
                 offset + 18 {
-                    0x000c {
+                    0x000c? {
                         offset + maker_notes {
                             offset + 10 {
                                 r64 + 0 / white_balance_r
@@ -59,26 +59,42 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
                             }
                         }
                     }
-                    0x008c / contrast_curve_offset(contrast_curve_len)
+                    0x008c? / contrast_curve_offset(contrast_curve_len)
                     0x0096? / linear_table_offset(linear_table_len)
-                }
-            }
-        }
-        0x014a {
-            offset + 4 {
-                offset address {
-                    0x0100 / width
-                    0x0101 / height
-                    0x0102 : u16 / bps
-                    0x0103 : u16 / compression
-                    0x0111 / strip
-                    0x0117 / strip_len
+                    0x0093? : u16 / nef_compression
+                    0x001d? {
+                        offset + maker_notes {
+                            offset + 10 {
+                                str + 0 / serial
+                            }
+                        }
+                    }
+                    0x0097? / color_balance_offset(color_balance_len)
+                    0x00a7? / shutter_count
+                    0x0014? / nrw_data_offset(nrw_data_len)
                 }
             }
         }
     })
 });
 
+// the tags of the raw IFD, which is a SubIFD found by its NewSubfileType
+macro_rules! raw_ifd_template {
+    () => {
+        quickexif::describe_rule!(template {
+            0x0100 / width
+            0x0101 / height
+            0x0102 : u16 / bps
+            0x0103 : u16 / compression
+            0x0111 / strip
+            0x0117 / strip_len
+        })
+    };
+}
+
+pub(super) static RAW_IFD_RULES: Lazy<RawIfdRules> =
+    Lazy::new(|| raw_ifd_rules!(raw_ifd_template!()));
+
 impl RawDecoder for General {
     fn new(info: quickexif::ParsedInfo) -> Self {
         General { info }
@@ -102,12 +118,29 @@ impl RawDecoder for General {
             height,
         })
     }
-    fn get_white_balance(&self) -> Result<[i32; 3], DecodingError> {
+    fn get_white_balance(&self, buffer: &[u8]) -> Result<[i32; 3], DecodingError> {
+        if let Some(white_balance) = self.color_balance(buffer) {
+            return Ok(white_balance);
+        }
+        if let Some(white_balance) = self.nrw_white_balance(buffer) {
+            return Ok(white_balance);
+        }
+
         let r = 512.0 * self.info.f64("white_balance_r")?;
         let g = 512.0 * self.info.f64("white_balance_g")?;
         let b = 512.0 * self.info.f64("white_balance_b")?;
         Ok([r as i32, g as i32, b as i32])
     }
+    /// The black level of the maker notes is given in 14 bits
+    fn get_black_level(&self) -> [u16; 4] {
+        let black_level = self.info.u16("black_level").unwrap_or(0);
+        let black_level = match self.info.u16("bps") {
+            Ok(12) => black_level / 4,
+            _ => black_level,
+        };
+        let bps_scale = self.get_bps_scale().unwrap_or(1);
+        [bps_scale.saturating_mul(black_level); 4]
+    }
     fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
         let offset = self.info.usize("thumbnail")?;
         let len = self.info.usize("thumbnail_len")?;
@@ -121,32 +154,19 @@ impl RawDecoder for General {
         let bps = self.info.u16("bps")?;
         let bps_scale = self.get_bps_scale()?;
         let compression = self.info.u16("compression")?;
-        let black_level = self.info.u16("black_level").unwrap_or(0);
-        let black_level = match bps {
-            12 => black_level / 4,
-            _ => black_level,
-        };
-        let maker_notes_addr = self.info.usize("maker_notes")? + 10;
-        let color_data = match self.info.usize("linear_table_offset") {
-            Ok(offset) => {
-                let offset = offset + maker_notes_addr;
-                let len = self.info.usize("linear_table_len")?;
-                &buffer[offset..offset + len]
-            }
-            Err(_) => {
-                let offset = self.info.usize("contrast_curve_offset")? + maker_notes_addr;
-                let len = self.info.usize("contrast_curve_len")?;
-                &buffer[offset..offset + len]
-            }
-        };
+
+        // NEFCompression 13 and 14 are the wavelet based High Efficiency and High Efficiency*
+        if let Ok(13 | 14) = self.info.u16("nef_compression") {
+            return Err(DecodingError::CompressionNotSupported(
+                "Nikon High Efficiency".to_owned(),
+            ));
+        }
 
         let buf = &buffer[strip_offset..];
 
         macro_rules! to_image {
             ($iter:expr) => {
-                $iter
-                    .map(|x| bps_scale.saturating_mul(x.saturating_sub(black_level)))
-                    .collect()
+                $iter.map(|x| bps_scale.saturating_mul(*x)).collect()
             };
         }
         let image: Vec<u16> = if width * height * 3 == strip_len {
@@ -155,17 +175,25 @@ impl RawDecoder for General {
             to_image!(load_raw_yuv2(buf, wb_r, wb_b, width, height).iter())
         } else {
             match compression {
-                1 => match bps {
-                    12 => to_image!(to_12bit_iter(buf, self.info.is_le)),
-                    14 => to_image!(to_14bit_iter(buf, self.info.is_le)),
-                    _ => to_image!(to_16bit_iter(buf, self.info.is_le)),
-                },
+                1 => {
+                    let strip = buf.get(..strip_len).ok_or_else(|| {
+                        DecodingError::InvalidRawData("NEF strip is out of range".to_owned())
+                    })?;
+                    // the packed samples of NRW files are big endian in either byte order
+                    let is_le = self.info.is_le && self.nrw_data(buffer).is_none();
+                    let image = load_raw_uncompressed(strip, is_le, bps, width, height);
+                    to_image!(image.iter())
+                }
                 0x8799 => {
-                    to_image!(
-                        load_raw(buf, color_data, self.info.is_le, bps, width, height)?.iter()
-                    )
+                    let meta = self.linearization_table(buffer)?;
+                    to_image!(load_raw(buf, meta, self.info.is_le, bps, width, height)?.iter())
+                }
+                _ => {
+                    return Err(DecodingError::CompressionNotSupported(format!(
+                        "NEF {}",
+                        compression
+                    )))
                 }
-                _ => unimplemented!(),
             }
         };
 
@@ -180,6 +208,151 @@ impl RawDecoder for General {
     }
 }
 
+impl General {
+    /// The huffman compressed NEF keeps the compression version, the initial predictors
+    /// and the linearization curve in maker note tag 0x96, old models use tag 0x8c instead.
+    fn linearization_table<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        let maker_notes_addr = self.info.usize("maker_notes")? + 10;
+        let (offset, len) = match self.info.usize("linear_table_offset") {
+            Ok(offset) => (offset, self.info.usize("linear_table_len")?),
+            Err(_) => (
+                self.info.usize("contrast_curve_offset")?,
+                self.info.usize("contrast_curve_len")?,
+            ),
+        };
+        let offset = offset + maker_notes_addr;
+        buffer.get(offset..offset + len).ok_or_else(|| {
+            DecodingError::InvalidRawData("NEF linearization table is out of range".to_owned())
+        })
+    }
+    /// The as shot white balance levels of maker note tag 0x97, the versions from 0200
+    /// are encrypted with the serial number and the shutter count of the camera.
+    fn color_balance(&self, buffer: &[u8]) -> Option<[i32; 3]> {
+        let offset = self.info.usize("color_balance_offset").ok()?;
+        let offset = offset + self.info.usize("maker_notes").ok()? + 10;
+        let len = self.info.usize("color_balance_len").ok()?;
+        let data = buffer.get(offset..offset + len)?;
+        let version: usize = std::str::from_utf8(data.get(..4)?).ok()?.parse().ok()?;
+        let is_le = self.info.is_le;
+
+        let levels = |at: usize| -> Option<[u16; 4]> {
+            let bytes = data.get(at..at + 8)?;
+            Some([0, 1, 2, 3].map(|i| bytes.u16(is_le, i * 2)))
+        };
+        // red, green and blue
+        let [r, g, b] = match version {
+            100 => levels(72).map(|[r, b, g, _]| [r, g, b])?,
+            102 => levels(10).map(|[r, g, _, b]| [r, g, b])?,
+            103 => levels(20).map(|[r, g, b, _]| [r, g, b])?,
+            200..=216 => {
+                let start = if version == 205 { 4 } else { 284 };
+                let mut encrypted = data.get(start..start + 324)?.to_vec();
+                let serial = self.info.str("serial").ok()?;
+                let shutter_count = self.info.u32("shutter_count").ok()?;
+                decrypt(&mut encrypted, serial, shutter_count);
+
+                let at = b"66666>666;6A;:;55"[version - 200] - b'0';
+                let bytes = encrypted.get((at & !1) as usize..)?;
+                let levels = [0, 1, 2, 3].map(|i| bytes.u16(is_le, i * 2));
+                match (at & 1, levels) {
+                    (0, [r, g, _, b]) => [r, g, b],
+                    (_, [g, r, b, _]) => [r, g, b],
+                }
+            }
+            _ => return None,
+        };
+
+        if r == 0 || g == 0 || b == 0 {
+            return None;
+        }
+        let scale = |x: u16| (512 * x as u32 / g as u32) as i32;
+        Some([scale(r), 512, scale(b)])
+    }
+    /// The NRW files of the Coolpix cameras have the maker note tag 0x14 starting with
+    /// the "NRW " signature and the version instead of the tags of NEF.
+    fn nrw_data<'a>(&self, buffer: &'a [u8]) -> Option<&'a [u8]> {
+        let offset = self.info.usize("nrw_data_offset").ok()?;
+        let offset = offset + self.info.usize("maker_notes").ok()? + 10;
+        let len = self.info.usize("nrw_data_len").ok()?;
+        buffer
+            .get(offset..offset + len)
+            .filter(|data| data.starts_with(b"NRW "))
+    }
+    /// The as shot levels of NRW, the green level is the sum of both greens
+    fn nrw_white_balance(&self, buffer: &[u8]) -> Option<[i32; 3]> {
+        let data = self.nrw_data(buffer)?;
+        let start = match data.get(4..8)? {
+            b"0100" => 1556,
+            _ => 56,
+        };
+        let levels = data.get(start..start + 16)?;
+        let level = |i: usize| levels.u32(self.info.is_le, i * 4) as u64;
+        let (r, g, b) = (level(0) * 4, level(1) + level(2), level(3) * 4);
+
+        if r == 0 || g == 0 || b == 0 {
+            return None;
+        }
+        let scale = |x: u64| (512 * x / g) as i32;
+        Some([scale(r), 512, scale(b)])
+    }
+}
+
+/// Decrypts the data in place with the keystream seeded by the serial number and the shutter count
+fn decrypt(data: &mut [u8], serial: &str, shutter_count: u32) {
+    let serial = serial.bytes().fold(0u32, |serial, c| {
+        let digit = if c.is_ascii_digit() { c - b'0' } else { c % 10 };
+        serial.wrapping_mul(10).wrapping_add(digit as u32)
+    });
+    let count = shutter_count.to_le_bytes().iter().fold(0u8, |x, c| x ^ c);
+
+    let ci = NIKON_XLAT[0][(serial & 0xff) as usize];
+    let mut cj = NIKON_XLAT[1][count as usize];
+    let mut ck = 0x60u8;
+    for x in data.iter_mut() {
+        cj = cj.wrapping_add(ci.wrapping_mul(ck));
+        ck = ck.wrapping_add(1);
+        *x ^= cj;
+    }
+}
+
+/// Uncompressed NEF samples are either kept in 16 bit words or packed tightly,
+/// some models also pad the end of every row.
+fn load_raw_uncompressed(
+    src: &[u8],
+    is_le: bool,
+    bps: u16,
+    width: usize,
+    height: usize,
+) -> Vec<u16> {
+    let mut out = Vec::with_capacity(width * height);
+    let row_len = src.len() / height.max(1);
+    if row_len == 0 {
+        return out;
+    }
+
+    macro_rules! unpack {
+        ($iter:ident) => {
+            src.chunks_exact(row_len)
+                .take(height)
+                .for_each(|row| out.extend($iter(row, is_le).take(width)))
+        };
+    }
+    if row_len >= width * 2 {
+        match bps {
+            12 => unpack!(to_12bit_iter),
+            14 => unpack!(to_14bit_iter),
+            _ => unpack!(to_16bit_iter),
+        }
+    } else {
+        match bps {
+            12 => unpack!(to_12bit_iter_packed),
+            14 => unpack!(to_14bit_iter_packed),
+            _ => unpack!(to_16bit_iter),
+        }
+    }
+    out
+}
+
 fn load_raw_yuv2(src: &[u8], wb_r: f64, wb_b: f64, width: usize, height: usize) -> Vec<u16> {
     let inv_wb_r = (1024.0 / wb_r) as i32;
     let inv_wb_b = (1024.0 / wb_b) as i32;
@@ -249,6 +422,11 @@ fn load_raw_yuv2(src: &[u8], wb_r: f64, wb_b: f64, width: usize, height: usize)
     out
 }
 
+/// Decodes the huffman compressed NEF, which is lossless when the first version byte
+/// of the linearization table is 0x46 and lossy otherwise.
+///
+/// Both expand the decoded values with the curve of the table, so the output has the
+/// same scale as the uncompressed files of the same bit depth.
 fn load_raw(
     src: &[u8],
     meta: &[u8],
@@ -276,9 +454,10 @@ fn load_raw(
     // Create the huffman table used to decode
     let mut htable = create_hufftable(huff_select);
 
-    // Setup the predictors
-    let mut pred_up1: [i32; 2] = [stream.get_u16() as i32, stream.get_u16() as i32];
-    let mut pred_up2: [i32; 2] = [stream.get_u16() as i32, stream.get_u16() as i32];
+    // Setup the predictors, the first two are the even rows' and the last two the odd rows'
+    let preds: [i32; 4] = [0; 4].map(|_| stream.get_u16() as i32);
+    let mut pred_up1: [i32; 2] = [preds[0], preds[2]];
+    let mut pred_up2: [i32; 2] = [preds[1], preds[3]];
 
     // Get the linearization curve
     let mut points = [0u16; 1 << 16];
@@ -299,13 +478,15 @@ fn load_raw(
                 / step) as u16;
         }
         split = meta.u16(is_le, 562) as usize;
-    } else if v0 != 70 && csize <= 0x4001 {
+    } else if v0 != 70 && csize > 0 && csize <= 0x4001 {
         for point in points.iter_mut().take(csize) {
             *point = stream.get_u16();
         }
         max = csize;
     }
     let curve = LookupTable::new(&points[0..max]);
+    // values out of the curve are clipped to its last point
+    let last = (max - 1) as u16;
 
     let mut pump = BitPumpMSB::new(src);
     let mut random = pump.peek_bits(24);
@@ -324,8 +505,10 @@ fn load_raw(
                 pred_left1 += htable.huff_decode(&mut pump);
                 pred_left2 += htable.huff_decode(&mut pump);
             }
-            out[row * width + col] = curve.dither(clampbits(pred_left1, bps), &mut random);
-            out[row * width + col + 1] = curve.dither(clampbits(pred_left2, bps), &mut random);
+            out[row * width + col] =
+                curve.dither(clampbits(pred_left1, bps).min(last), &mut random);
+            out[row * width + col + 1] =
+                curve.dither(clampbits(pred_left2, bps).min(last), &mut random);
         }
     }
     Ok(out)
@@ -394,3 +577,46 @@ const NIKON_TREE: [[[u8; 16]; 3]; 6] = [
         [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
     ],
 ];
+
+const NIKON_XLAT: [[u8; 256]; 2] = [
+    [
+        0xc1, 0xbf, 0x6d, 0x0d, 0x59, 0xc5, 0x13, 0x9d, 0x83, 0x61, 0x6b, 0x4f, 0xc7, 0x7f, 0x3d,
+        0x3d, 0x53, 0x59, 0xe3, 0xc7, 0xe9, 0x2f, 0x95, 0xa7, 0x95, 0x1f, 0xdf, 0x7f, 0x2b, 0x29,
+        0xc7, 0x0d, 0xdf, 0x07, 0xef, 0x71, 0x89, 0x3d, 0x13, 0x3d, 0x3b, 0x13, 0xfb, 0x0d, 0x89,
+        0xc1, 0x65, 0x1f, 0xb3, 0x0d, 0x6b, 0x29, 0xe3, 0xfb, 0xef, 0xa3, 0x6b, 0x47, 0x7f, 0x95,
+        0x35, 0xa7, 0x47, 0x4f, 0xc7, 0xf1, 0x59, 0x95, 0x35, 0x11, 0x29, 0x61, 0xf1, 0x3d, 0xb3,
+        0x2b, 0x0d, 0x43, 0x89, 0xc1, 0x9d, 0x9d, 0x89, 0x65, 0xf1, 0xe9, 0xdf, 0xbf, 0x3d, 0x7f,
+        0x53, 0x97, 0xe5, 0xe9, 0x95, 0x17, 0x1d, 0x3d, 0x8b, 0xfb, 0xc7, 0xe3, 0x67, 0xa7, 0x07,
+        0xf1, 0x71, 0xa7, 0x53, 0xb5, 0x29, 0x89, 0xe5, 0x2b, 0xa7, 0x17, 0x29, 0xe9, 0x4f, 0xc5,
+        0x65, 0x6d, 0x6b, 0xef, 0x0d, 0x89, 0x49, 0x2f, 0xb3, 0x43, 0x53, 0x65, 0x1d, 0x49, 0xa3,
+        0x13, 0x89, 0x59, 0xef, 0x6b, 0xef, 0x65, 0x1d, 0x0b, 0x59, 0x13, 0xe3, 0x4f, 0x9d, 0xb3,
+        0x29, 0x43, 0x2b, 0x07, 0x1d, 0x95, 0x59, 0x59, 0x47, 0xfb, 0xe5, 0xe9, 0x61, 0x47, 0x2f,
+        0x35, 0x7f, 0x17, 0x7f, 0xef, 0x7f, 0x95, 0x95, 0x71, 0xd3, 0xa3, 0x0b, 0x71, 0xa3, 0xad,
+        0x0b, 0x3b, 0xb5, 0xfb, 0xa3, 0xbf, 0x4f, 0x83, 0x1d, 0xad, 0xe9, 0x2f, 0x71, 0x65, 0xa3,
+        0xe5, 0x07, 0x35, 0x3d, 0x0d, 0xb5, 0xe9, 0xe5, 0x47, 0x3b, 0x9d, 0xef, 0x35, 0xa3, 0xbf,
+        0xb3, 0xdf, 0x53, 0xd3, 0x97, 0x53, 0x49, 0x71, 0x07, 0x35, 0x61, 0x71, 0x2f, 0x43, 0x2f,
+        0x11, 0xdf, 0x17, 0x97, 0xfb, 0x95, 0x3b, 0x7f, 0x6b, 0xd3, 0x25, 0xbf, 0xad, 0xc7, 0xc5,
+        0xc5, 0xb5, 0x8b, 0xef, 0x2f, 0xd3, 0x07, 0x6b, 0x25, 0x49, 0x95, 0x25, 0x49, 0x6d, 0x71,
+        0xc7,
+    ],
+    [
+        0xa7, 0xbc, 0xc9, 0xad, 0x91, 0xdf, 0x85, 0xe5, 0xd4, 0x78, 0xd5, 0x17, 0x46, 0x7c, 0x29,
+        0x4c, 0x4d, 0x03, 0xe9, 0x25, 0x68, 0x11, 0x86, 0xb3, 0xbd, 0xf7, 0x6f, 0x61, 0x22, 0xa2,
+        0x26, 0x34, 0x2a, 0xbe, 0x1e, 0x46, 0x14, 0x68, 0x9d, 0x44, 0x18, 0xc2, 0x40, 0xf4, 0x7e,
+        0x5f, 0x1b, 0xad, 0x0b, 0x94, 0xb6, 0x67, 0xb4, 0x0b, 0xe1, 0xea, 0x95, 0x9c, 0x66, 0xdc,
+        0xe7, 0x5d, 0x6c, 0x05, 0xda, 0xd5, 0xdf, 0x7a, 0xef, 0xf6, 0xdb, 0x1f, 0x82, 0x4c, 0xc0,
+        0x68, 0x47, 0xa1, 0xbd, 0xee, 0x39, 0x50, 0x56, 0x4a, 0xdd, 0xdf, 0xa5, 0xf8, 0xc6, 0xda,
+        0xca, 0x90, 0xca, 0x01, 0x42, 0x9d, 0x8b, 0x0c, 0x73, 0x43, 0x75, 0x05, 0x94, 0xde, 0x24,
+        0xb3, 0x80, 0x34, 0xe5, 0x2c, 0xdc, 0x9b, 0x3f, 0xca, 0x33, 0x45, 0xd0, 0xdb, 0x5f, 0xf5,
+        0x52, 0xc3, 0x21, 0xda, 0xe2, 0x22, 0x72, 0x6b, 0x3e, 0xd0, 0x5b, 0xa8, 0x87, 0x8c, 0x06,
+        0x5d, 0x0f, 0xdd, 0x09, 0x19, 0x93, 0xd0, 0xb9, 0xfc, 0x8b, 0x0f, 0x84, 0x60, 0x33, 0x1c,
+        0x9b, 0x45, 0xf1, 0xf0, 0xa3, 0x94, 0x3a, 0x12, 0x77, 0x33, 0x4d, 0x44, 0x78, 0x28, 0x3c,
+        0x9e, 0xfd, 0x65, 0x57, 0x16, 0x94, 0x6b, 0xfb, 0x59, 0xd0, 0xc8, 0x22, 0x36, 0xdb, 0xd2,
+        0x63, 0x98, 0x43, 0xa1, 0x04, 0x87, 0x86, 0xf7, 0xa6, 0x26, 0xbb, 0xd6, 0x59, 0x4d, 0xbf,
+        0x6a, 0x2e, 0xaa, 0x2b, 0xef, 0xe6, 0x78, 0xb6, 0x4e, 0xe0, 0x2f, 0xdc, 0x7c, 0xbe, 0x57,
+        0x19, 0x32, 0x7e, 0x2a, 0xd0, 0xb8, 0xba, 0x29, 0x00, 0x3c, 0x52, 0x7d, 0xa8, 0x49, 0x3b,
+        0x2d, 0xeb, 0x25, 0x49, 0xfa, 0xa3, 0xaa, 0x39, 0xa7, 0xc5, 0xa7, 0x50, 0x11, 0x36, 0xfb,
+        0xc6, 0x67, 0x4a, 0xf5, 0xa5, 0x12, 0x65, 0x7e, 0xb0, 0xdf, 0xaf, 0x4e, 0xb3, 0x61, 0x7f,
+        0x2f,
+    ],
+];
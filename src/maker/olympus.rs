@@ -38,6 +38,15 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
             }
             0x927c / maker_notes {
                 offset + 12 {
+                    0x2020? {
+                        offset + maker_notes {
+                            0x0804? {
+                                offset + maker_notes {
+                                    u32 + 0 / stacked_image
+                                }
+                            }
+                        }
+                    }
                     0x2040 {
                         offset + maker_notes {
                             0x0611 / bps
@@ -45,6 +54,14 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
                             0x0613 / crop_top
                             0x0614 / crop_width
                             0x0615 / crop_height
+                            0x1113? {
+                                offset + maker_notes {
+                                    u16 + 0 / aspect_left
+                                    u16 + 1 / aspect_top
+                                    u16 + 2 / aspect_right
+                                    u16 + 3 / aspect_bottom
+                                }
+                            }
                             0x0100 {
                                 offset + maker_notes {
                                     u16 + 0 / white_balance_r
@@ -65,6 +82,47 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     })
 });
 
+impl General {
+    /// The size of the raw data, high resolution shots are decoded at the composite size
+    ///
+    /// The composite of some high resolution shots is twice as wide and as high as the size in
+    /// their tags, it is only used when the data has exactly that many 16 bit samples.
+    fn get_raw_size(&self) -> Result<(usize, usize), DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let strip_len = self.info.usize("strip_len")?;
+
+        // StackedImage is 8 for tripod and 11 for hand-held high resolution shots
+        let is_high_res = matches!(self.info.u32("stacked_image"), Ok(8) | Ok(11));
+        if is_high_res && strip_len == width * height * 8 {
+            return Ok((width * 2, height * 2));
+        }
+        if strip_len >= width * height * 4 {
+            return Err(DecodingError::InvalidRawData(
+                "ORF data does not match the image size".to_owned(),
+            ));
+        }
+        Ok((width, height))
+    }
+    /// The AspectFrame tag keeps the first and the last pixel of the frame
+    fn get_aspect_frame(&self) -> Option<Crop> {
+        let left = self.info.u32("aspect_left").ok()?;
+        let top = self.info.u32("aspect_top").ok()?;
+        let right = self.info.u32("aspect_right").ok()?;
+        let bottom = self.info.u32("aspect_bottom").ok()?;
+        if right <= left || bottom <= top {
+            return None;
+        }
+
+        Some(Crop {
+            x: left,
+            y: top,
+            width: right - left + 1,
+            height: bottom - top + 1,
+        })
+    }
+}
+
 impl RawDecoder for General {
     fn new(info: quickexif::ParsedInfo) -> Self {
         General { info }
@@ -82,6 +140,17 @@ impl RawDecoder for General {
         let width = self.info.u32("crop_width").ok()?;
         let height = self.info.u32("crop_height").ok()?;
 
+        // the aspect frame narrows the valid area down to the aspect ratio of the shot
+        if let Some(aspect) = self.get_aspect_frame() {
+            if aspect.x >= x
+                && aspect.y >= y
+                && aspect.x + aspect.width <= x + width
+                && aspect.y + aspect.height <= y + height
+            {
+                return Some(aspect);
+            }
+        }
+
         Some(Crop {
             x,
             y,
@@ -89,25 +158,46 @@ impl RawDecoder for General {
             height,
         })
     }
+    fn get_image_size(&self, _buffer: &[u8]) -> Result<(usize, usize), DecodingError> {
+        self.get_raw_size()
+    }
+    fn get_black_level(&self) -> [u16; 4] {
+        let black_level = self.info.u16("black_level").unwrap_or(0);
+        let bps_scale = self.get_bps_scale().unwrap_or(1);
+        [bps_scale.saturating_mul(black_level); 4]
+    }
     fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
-        let width = self.info.usize("width")?;
-        let height = self.info.usize("height")?;
+        let (width, height) = self.get_raw_size()?;
         let strip_offset = self.info.usize("strip")?;
         let strip_len = self.info.usize("strip_len")?;
-        let buffer = &buffer[strip_offset..];
+        let buffer = buffer
+            .get(strip_offset..)
+            .ok_or_else(|| DecodingError::InvalidRawData("ORF strip is out of range".to_owned()))?;
+        let strip = buffer.get(..strip_len).unwrap_or(buffer);
 
-        let image = if strip_len >= width * height / 10 * 16 {
+        // uncompressed images are told apart by the size of their data
+        let image: Vec<u16> = if strip_len >= width * height * 2 {
+            utility::to_16bit_iter(strip, self.info.is_le)
+                .take(width * height)
+                .collect()
+        } else if strip_len >= width * height / 10 * 16 {
             load_12bit_raw(buffer, width, height)?
+        } else if strip_len >= width * height * 3 / 2 {
+            utility::to_12bit_iter_packed(strip, self.info.is_le)
+                .take(width * height)
+                .collect()
         } else {
             load_compressed_raw(buffer, width, height)?
         };
+        if image.len() < width * height {
+            return Err(DecodingError::InvalidDecodedImageSize(
+                image.len(),
+                width * height,
+            ));
+        }
 
-        let black_level = self.info.u16("black_level")?;
         let bps_scale = self.get_bps_scale()?;
-        Ok(image
-            .iter()
-            .map(|x| bps_scale.saturating_mul(x.saturating_sub(black_level)))
-            .collect())
+        Ok(image.iter().map(|x| bps_scale.saturating_mul(*x)).collect())
     }
     fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
         let base = self.info.usize("maker_notes")?;
@@ -0,0 +1,541 @@
+use super::utility::GetNumFromBytes;
+use super::DecodingError;
+
+const WARP_RECTILINEAR: u32 = 1;
+const FIX_VIGNETTE_RADIAL: u32 = 3;
+const FIX_BAD_PIXELS_CONSTANT: u32 = 4;
+const FIX_BAD_PIXELS_LIST: u32 = 5;
+const GAIN_MAP: u32 = 9;
+
+// the flag of the opcodes which may be skipped by the readers not knowing them
+const FLAG_OPTIONAL: u32 = 1;
+
+/// The opcodes of a DNG opcode list, the unknown ones are skipped when they are optional
+pub(super) enum Opcode {
+    WarpRectilinear(WarpRectilinear),
+    FixVignetteRadial(FixVignetteRadial),
+    FixBadPixels(FixBadPixels),
+    GainMap(GainMap),
+}
+
+/// Corrects the lens distortion with a radial and a tangential model for every plane, the
+/// pixels are moved from the positions of the model
+pub(super) struct WarpRectilinear {
+    coefficients: Vec<[f64; 6]>,
+    center_h: f64,
+    center_v: f64,
+}
+
+/// Multiplies every pixel by a polynomial of its distance to the optical center, the
+/// distance is 1 at the farthest corner
+pub(super) struct FixVignetteRadial {
+    k: [f64; 5],
+    center_h: f64,
+    center_v: f64,
+}
+
+/// Replaces the bad pixels of a Bayer image by the average of their neighbors of the same
+/// color, the bad pixels are either the ones of a constant value or the listed points and
+/// rectangles
+pub(super) struct FixBadPixels {
+    bayer_phase: usize,
+    constant: Option<u16>,
+    points: Vec<[usize; 2]>,
+    rects: Vec<[usize; 4]>,
+}
+
+/// Multiplies an area of the image by a map of gains, mostly used for lens shading.
+///
+/// The map is placed relative to the size of the image and interpolated between its points.
+pub(super) struct GainMap {
+    top: usize,
+    left: usize,
+    bottom: usize,
+    right: usize,
+    plane: usize,
+    planes: usize,
+    row_pitch: usize,
+    col_pitch: usize,
+    points_v: usize,
+    points_h: usize,
+    spacing_v: f64,
+    spacing_h: f64,
+    origin_v: f64,
+    origin_h: f64,
+    map_planes: usize,
+    gains: Vec<f32>,
+}
+
+/// Reads the values of an opcode, which are always big endian
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodingError> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or_else(|| {
+            DecodingError::InvalidRawData("DNG opcode list is out of range".to_owned())
+        })?;
+        self.pos += len;
+        Ok(bytes)
+    }
+    fn u32(&mut self) -> Result<u32, DecodingError> {
+        Ok(self.bytes(4)?.u32be(0))
+    }
+    fn usize(&mut self) -> Result<usize, DecodingError> {
+        Ok(self.u32()? as usize)
+    }
+    fn f32(&mut self) -> Result<f32, DecodingError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+    fn f64(&mut self) -> Result<f64, DecodingError> {
+        let bytes = self.bytes(8)?;
+        Ok(f64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+pub(super) fn parse_opcode_list(data: &[u8]) -> Result<Vec<Opcode>, DecodingError> {
+    let mut reader = Reader { data, pos: 0 };
+    let count = reader.u32()?;
+
+    let mut opcodes = vec![];
+    for _ in 0..count {
+        let id = reader.u32()?;
+        // the version of the DNG spec
+        reader.u32()?;
+        let flags = reader.u32()?;
+        let len = reader.usize()?;
+        let params = reader.bytes(len)?;
+        match id {
+            WARP_RECTILINEAR => {
+                opcodes.push(Opcode::WarpRectilinear(WarpRectilinear::parse(params)?))
+            }
+            FIX_VIGNETTE_RADIAL => {
+                opcodes.push(Opcode::FixVignetteRadial(FixVignetteRadial::parse(params)?))
+            }
+            FIX_BAD_PIXELS_CONSTANT => {
+                opcodes.push(Opcode::FixBadPixels(FixBadPixels::parse_constant(params)?))
+            }
+            FIX_BAD_PIXELS_LIST => {
+                opcodes.push(Opcode::FixBadPixels(FixBadPixels::parse_list(params)?))
+            }
+            GAIN_MAP => opcodes.push(Opcode::GainMap(GainMap::parse(params)?)),
+            _ if flags & FLAG_OPTIONAL != 0 => {}
+            _ => {
+                return Err(DecodingError::InvalidRawData(format!(
+                    "DNG opcode {} is not supported",
+                    id
+                )))
+            }
+        }
+    }
+    Ok(opcodes)
+}
+
+/// The part of the image the opcodes work on, it is given as top, left, bottom and right
+/// and the coordinates of the opcodes are relative to it
+type Area = [usize; 4];
+
+/// The colors of the 2x2 CFA block at the origin of the image, as the planes of R, G and B.
+/// It is `None` for the images whose samples are planes of their own.
+pub(super) type CfaColors = Option<[usize; 4]>;
+
+/// Applies the opcodes to an image of 16 bit samples, `samples_per_pixel` of them interleaved
+pub(super) fn apply_opcode_list(
+    opcodes: &[Opcode],
+    image: &mut [u16],
+    width: usize,
+    samples_per_pixel: usize,
+    area: Area,
+    cfa_colors: CfaColors,
+) {
+    for opcode in opcodes {
+        match opcode {
+            Opcode::WarpRectilinear(warp) => {
+                warp.apply(image, width, samples_per_pixel, area, cfa_colors)
+            }
+            Opcode::FixVignetteRadial(vignette) => {
+                vignette.apply(image, width, samples_per_pixel, area)
+            }
+            Opcode::FixBadPixels(bad_pixels) => {
+                if samples_per_pixel == 1 {
+                    bad_pixels.apply(image, width, area)
+                }
+            }
+            Opcode::GainMap(gain_map) => gain_map.apply(image, width, samples_per_pixel, area),
+        }
+    }
+}
+
+fn multiply(x: &mut u16, gain: f64) {
+    *x = (*x as f64 * gain).round().clamp(0.0, u16::MAX as f64) as u16;
+}
+
+impl FixVignetteRadial {
+    fn parse(params: &[u8]) -> Result<Self, DecodingError> {
+        let mut reader = Reader {
+            data: params,
+            pos: 0,
+        };
+        Ok(FixVignetteRadial {
+            k: [
+                reader.f64()?,
+                reader.f64()?,
+                reader.f64()?,
+                reader.f64()?,
+                reader.f64()?,
+            ],
+            center_h: reader.f64()?,
+            center_v: reader.f64()?,
+        })
+    }
+
+    fn apply(&self, image: &mut [u16], width: usize, samples_per_pixel: usize, area: Area) {
+        let [top, left, bottom, right] = area;
+        let center_h = left as f64 + self.center_h * (right - left) as f64;
+        let center_v = top as f64 + self.center_v * (bottom - top) as f64;
+        let max_h = (center_h - left as f64).max(right as f64 - center_h);
+        let max_v = (center_v - top as f64).max(bottom as f64 - center_v);
+        let max_distance2 = max_h * max_h + max_v * max_v;
+        if max_distance2 <= 0.0 {
+            return;
+        }
+
+        for row in top..bottom {
+            // the distances are measured from the centers of the pixels
+            let v = row as f64 + 0.5 - center_v;
+            for col in left..right {
+                let h = col as f64 + 0.5 - center_h;
+                let r2 = (h * h + v * v) / max_distance2;
+                let gain = self.k.iter().rev().fold(0.0, |acc, k| (acc + k) * r2) + 1.0;
+                let start = (row * width + col) * samples_per_pixel;
+                image[start..start + samples_per_pixel]
+                    .iter_mut()
+                    .for_each(|x| multiply(x, gain));
+            }
+        }
+    }
+}
+
+impl GainMap {
+    fn parse(params: &[u8]) -> Result<Self, DecodingError> {
+        let mut reader = Reader {
+            data: params,
+            pos: 0,
+        };
+        let mut gain_map = GainMap {
+            top: reader.usize()?,
+            left: reader.usize()?,
+            bottom: reader.usize()?,
+            right: reader.usize()?,
+            plane: reader.usize()?,
+            planes: reader.usize()?,
+            row_pitch: reader.usize()?.max(1),
+            col_pitch: reader.usize()?.max(1),
+            points_v: reader.usize()?,
+            points_h: reader.usize()?,
+            spacing_v: reader.f64()?,
+            spacing_h: reader.f64()?,
+            origin_v: reader.f64()?,
+            origin_h: reader.f64()?,
+            map_planes: reader.usize()?,
+            gains: vec![],
+        };
+        let count = gain_map.points_v * gain_map.points_h * gain_map.map_planes;
+        if count == 0 {
+            return Err(DecodingError::InvalidRawData(
+                "DNG gain map is empty".to_owned(),
+            ));
+        }
+        gain_map.gains = (0..count).map(|_| reader.f32()).collect::<Result<_, _>>()?;
+        Ok(gain_map)
+    }
+
+    /// The position of a pixel in the points of the map, with the weight of the next point
+    fn index(pos: f64, origin: f64, spacing: f64, points: usize) -> (usize, usize, f32) {
+        let index = if spacing > 0.0 {
+            ((pos - origin) / spacing).clamp(0.0, (points - 1) as f64)
+        } else {
+            0.0
+        };
+        let first = index as usize;
+        let second = (first + 1).min(points - 1);
+        (first, second, (index - first as f64) as f32)
+    }
+
+    fn gain(&self, v: (usize, usize, f32), h: (usize, usize, f32), plane: usize) -> f32 {
+        let point = |row: usize, col: usize| {
+            self.gains[(row * self.points_h + col) * self.map_planes + plane]
+        };
+        let top = point(v.0, h.0) * (1.0 - h.2) + point(v.0, h.1) * h.2;
+        let bottom = point(v.1, h.0) * (1.0 - h.2) + point(v.1, h.1) * h.2;
+        top * (1.0 - v.2) + bottom * v.2
+    }
+
+    fn apply(&self, image: &mut [u16], width: usize, samples_per_pixel: usize, area: Area) {
+        let [area_top, area_left, area_bottom, area_right] = area;
+        let (height, area_width) = (area_bottom - area_top, area_right - area_left);
+        let bottom = self.bottom.min(height);
+        let right = self.right.min(area_width);
+        let last_plane = (self.plane + self.planes).min(samples_per_pixel);
+
+        for row in (self.top..bottom).step_by(self.row_pitch) {
+            let v = Self::index(
+                row as f64 / height as f64,
+                self.origin_v,
+                self.spacing_v,
+                self.points_v,
+            );
+            for col in (self.left..right).step_by(self.col_pitch) {
+                let h = Self::index(
+                    col as f64 / area_width as f64,
+                    self.origin_h,
+                    self.spacing_h,
+                    self.points_h,
+                );
+                for plane in self.plane..last_plane {
+                    // a single map plane is shared by all the planes
+                    let gain = self.gain(v, h, (plane - self.plane).min(self.map_planes - 1));
+                    let pos = (area_top + row) * width + area_left + col;
+                    let x = &mut image[pos * samples_per_pixel + plane];
+                    *x = (*x as f32 * gain).round().clamp(0.0, u16::MAX as f32) as u16;
+                }
+            }
+        }
+    }
+}
+
+impl WarpRectilinear {
+    fn parse(params: &[u8]) -> Result<Self, DecodingError> {
+        let mut reader = Reader {
+            data: params,
+            pos: 0,
+        };
+        let planes = reader.usize()?;
+        if planes == 0 {
+            return Err(DecodingError::InvalidRawData(
+                "DNG WarpRectilinear has no planes".to_owned(),
+            ));
+        }
+        let mut coefficients = vec![];
+        for _ in 0..planes {
+            let mut plane = [0.0; 6];
+            for x in plane.iter_mut() {
+                *x = reader.f64()?;
+            }
+            coefficients.push(plane);
+        }
+        Ok(WarpRectilinear {
+            coefficients,
+            center_h: reader.f64()?,
+            center_v: reader.f64()?,
+        })
+    }
+
+    /// The distances are relative to the farthest corner from the optical center. A CFA
+    /// image is sampled on the pixels of the same color, which are 2 pixels apart.
+    fn apply(
+        &self,
+        image: &mut [u16],
+        width: usize,
+        samples_per_pixel: usize,
+        area: Area,
+        cfa_colors: CfaColors,
+    ) {
+        let [top, left, bottom, right] = area;
+        let center_h = left as f64 + self.center_h * (right - left) as f64;
+        let center_v = top as f64 + self.center_v * (bottom - top) as f64;
+        let max_h = (center_h - left as f64).max(right as f64 - center_h);
+        let max_v = (center_v - top as f64).max(bottom as f64 - center_v);
+        let max_distance = (max_h * max_h + max_v * max_v).sqrt();
+        if max_distance <= 0.0 {
+            return;
+        }
+
+        let source = image.to_vec();
+        let step = if cfa_colors.is_some() { 2 } else { 1 };
+        for row in top..bottom {
+            let v = (row as f64 + 0.5 - center_v) / max_distance;
+            for col in left..right {
+                let h = (col as f64 + 0.5 - center_h) / max_distance;
+                let r2 = h * h + v * v;
+                for sample in 0..samples_per_pixel {
+                    let plane = match cfa_colors {
+                        Some(colors) => colors[(row % 2) * 2 + col % 2],
+                        None => sample,
+                    };
+                    let [kr0, kr1, kr2, kr3, kt0, kt1] =
+                        self.coefficients[plane.min(self.coefficients.len() - 1)];
+                    let radial = kr0 + r2 * (kr1 + r2 * (kr2 + r2 * kr3));
+                    let dh = radial * h + kt0 * 2.0 * h * v + kt1 * (r2 + 2.0 * h * h);
+                    let dv = radial * v + kt1 * 2.0 * h * v + kt0 * (r2 + 2.0 * v * v);
+                    let grid = Grid {
+                        image: &source,
+                        width,
+                        samples_per_pixel,
+                        area,
+                        step,
+                        phase: (row % step, col % step),
+                    };
+                    let pos = [
+                        center_v + dv * max_distance - 0.5,
+                        center_h + dh * max_distance - 0.5,
+                    ];
+                    if let Some(value) = grid.sample(pos, sample) {
+                        image[(row * width + col) * samples_per_pixel + sample] = value;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The pixels of an area which are `step` pixels apart from the ones of a phase
+struct Grid<'a> {
+    image: &'a [u16],
+    width: usize,
+    samples_per_pixel: usize,
+    area: Area,
+    step: usize,
+    phase: (usize, usize),
+}
+
+impl<'a> Grid<'a> {
+    /// The first and the second pixel around a position on an axis, with the weight of
+    /// the second one
+    fn axis(&self, pos: f64, range: (usize, usize), phase: usize) -> Option<(usize, usize, f64)> {
+        let ((start, end), step) = (range, self.step);
+        let first = start + (phase + step - start % step) % step;
+        if first >= end {
+            return None;
+        }
+        let last = (end - 1 - first) / step;
+        let index = ((pos - first as f64) / step as f64).clamp(0.0, last as f64);
+        let i = index as usize;
+        let next = (i + 1).min(last);
+        Some((first + i * step, first + next * step, index - i as f64))
+    }
+
+    /// Interpolates a sample at a position of row and column
+    fn sample(&self, pos: [f64; 2], sample: usize) -> Option<u16> {
+        let [top, left, bottom, right] = self.area;
+        let (row0, row1, v) = self.axis(pos[0], (top, bottom), self.phase.0)?;
+        let (col0, col1, h) = self.axis(pos[1], (left, right), self.phase.1)?;
+        let value = |row: usize, col: usize| {
+            self.image[(row * self.width + col) * self.samples_per_pixel + sample] as f64
+        };
+        let upper = value(row0, col0) * (1.0 - h) + value(row0, col1) * h;
+        let lower = value(row1, col0) * (1.0 - h) + value(row1, col1) * h;
+        let value = upper * (1.0 - v) + lower * v;
+        Some(value.round().clamp(0.0, u16::MAX as f64) as u16)
+    }
+}
+
+impl FixBadPixels {
+    fn parse_constant(params: &[u8]) -> Result<Self, DecodingError> {
+        let mut reader = Reader {
+            data: params,
+            pos: 0,
+        };
+        let constant = reader.u32()?.min(u16::MAX as u32) as u16;
+        Ok(FixBadPixels {
+            bayer_phase: reader.usize()?,
+            constant: Some(constant),
+            points: vec![],
+            rects: vec![],
+        })
+    }
+
+    fn parse_list(params: &[u8]) -> Result<Self, DecodingError> {
+        let mut reader = Reader {
+            data: params,
+            pos: 0,
+        };
+        let bayer_phase = reader.usize()?;
+        let point_count = reader.usize()?;
+        let rect_count = reader.usize()?;
+        let points = (0..point_count)
+            .map(|_| Ok([reader.usize()?, reader.usize()?]))
+            .collect::<Result<_, DecodingError>>()?;
+        let rects = (0..rect_count)
+            .map(|_| {
+                Ok([
+                    reader.usize()?,
+                    reader.usize()?,
+                    reader.usize()?,
+                    reader.usize()?,
+                ])
+            })
+            .collect::<Result<_, DecodingError>>()?;
+        Ok(FixBadPixels {
+            bayer_phase,
+            constant: None,
+            points,
+            rects,
+        })
+    }
+
+    /// The greens take their diagonal neighbors and the reds and blues the ones 2 pixels
+    /// away, the neighbors which are bad as well are left out
+    fn apply(&self, image: &mut [u16], width: usize, area: Area) {
+        let [top, left, bottom, right] = area;
+        let (area_width, area_height) = (right - left, bottom - top);
+        let mut is_bad = vec![false; area_width * area_height];
+        if let Some(constant) = self.constant {
+            for row in 0..area_height {
+                for col in 0..area_width {
+                    is_bad[row * area_width + col] =
+                        image[(top + row) * width + left + col] == constant;
+                }
+            }
+        }
+        for &[row, col] in self.points.iter() {
+            if row < area_height && col < area_width {
+                is_bad[row * area_width + col] = true;
+            }
+        }
+        for &[rect_top, rect_left, rect_bottom, rect_right] in self.rects.iter() {
+            for row in rect_top..rect_bottom.min(area_height) {
+                for col in rect_left..rect_right.min(area_width) {
+                    is_bad[row * area_width + col] = true;
+                }
+            }
+        }
+
+        // the phases 1 and 2 have their greens on the even sums of row and column
+        let green_parity = match self.bayer_phase {
+            1 | 2 => 0,
+            _ => 1,
+        };
+        let neighbors = |row: usize, col: usize| -> [(isize, isize); 4] {
+            if (row + col) % 2 == green_parity {
+                [(-1, -1), (-1, 1), (1, -1), (1, 1)]
+            } else {
+                [(-2, 0), (2, 0), (0, -2), (0, 2)]
+            }
+        };
+        for row in 0..area_height {
+            for col in 0..area_width {
+                if !is_bad[row * area_width + col] {
+                    continue;
+                }
+                let (mut sum, mut count) = (0u32, 0u32);
+                for (dv, dh) in neighbors(row, col) {
+                    let (v, h) = (row as isize + dv, col as isize + dh);
+                    if v < 0 || h < 0 || v as usize >= area_height || h as usize >= area_width {
+                        continue;
+                    }
+                    let (v, h) = (v as usize, h as usize);
+                    if !is_bad[v * area_width + h] {
+                        sum += image[(top + v) * width + left + h] as u32;
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    image[(top + row) * width + left + col] = (sum / count) as u16;
+                }
+            }
+        }
+    }
+}
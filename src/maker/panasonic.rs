@@ -19,6 +19,7 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
         0x0003 / height
         0x0009 / cfa_pattern
         0x000a / bps
+        0x000e? / white_level
         0x001c / black_level_r
         0x001d / black_level_g
         0x001e / black_level_b
@@ -27,19 +28,20 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
         0x0026 / white_balance_b
         0x0118 / strip
         0x0117 / strip_len
+        0x002d? / raw_format
         0x002f? / crop_top
         0x0030? / crop_left
         0x0031? / crop_bottom
         0x0032? / crop_right
         0x0112 / orientation
-        0x002e {
+        0x002e? {
             offset + 12 {
                 tiff {
                     0x8769 {
                         0x927c {
                             offset + 12 {
-                                0x004b / cropped_width
-                                0x004c / cropped_height
+                                0x004b? / cropped_width
+                                0x004c? / cropped_height
                             }
                         }
                     }
@@ -49,6 +51,30 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     })
 });
 
+impl General {
+    /// The black levels of the 2x2 CFA block, which are given per color
+    fn get_black_levels(&self) -> Result<[u16; 4], DecodingError> {
+        let r = self.info.u16("black_level_r")?;
+        let g = self.info.u16("black_level_g")?;
+        let b = self.info.u16("black_level_b")?;
+        let result = match self.get_cfa_pattern()? {
+            CFAPattern::GRBG => [g, r, b, g],
+            CFAPattern::GBRG => [g, b, r, g],
+            CFAPattern::BGGR => [b, g, g, r],
+            _ => [r, g, g, b],
+        };
+        Ok(result)
+    }
+    /// Uses the linearity limit when it is given, since the data of some bodies never
+    /// reaches the maximum of its bit depth
+    fn get_white_level_scale(&self) -> Result<u16, DecodingError> {
+        match self.info.u16("white_level") {
+            Ok(white_level) if white_level > 0 => Ok(u16::MAX / white_level),
+            _ => self.get_bps_scale(),
+        }
+    }
+}
+
 impl RawDecoder for General {
     fn new(info: quickexif::ParsedInfo) -> Self {
         General { info }
@@ -59,6 +85,17 @@ impl RawDecoder for General {
     fn into_info(self) -> quickexif::ParsedInfo {
         self.info
     }
+    /// The linearity limit less the highest black level, scaled like the samples
+    fn get_white_level(&self) -> u16 {
+        let white_level = match self.info.u16("white_level") {
+            Ok(white_level) if white_level > 0 => white_level as u32,
+            _ => (1u32 << self.info.u16("bps").unwrap_or(16).clamp(1, 16)) - 1,
+        };
+        let black_levels = self.get_black_levels().unwrap_or_default();
+        let black_level = black_levels.into_iter().max().unwrap_or(0);
+        let scale = self.get_white_level_scale().unwrap_or(1) as u32;
+        (white_level.saturating_sub(black_level as u32) * scale).min(u16::MAX as u32) as u16
+    }
 
     fn get_crop(&self) -> Option<Crop> {
         let x = self.info.u32("crop_left").ok()?;
@@ -73,14 +110,24 @@ impl RawDecoder for General {
             height: bottom - y,
         })
     }
+    fn get_black_level(&self) -> [u16; 4] {
+        let level_scale = self.get_white_level_scale().unwrap_or(1);
+        let black_levels = self.get_black_levels().unwrap_or_default();
+        black_levels.map(|x| level_scale.saturating_mul(x))
+    }
+    /// The levels are applied in place, the high resolution composites of S1R are too big
+    /// to keep a second copy of the image
     fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
-        let image = load_raw(&self.info, buffer)?;
-        let black_level = self.info.u16("black_level_r")?;
-        let bps_scale = self.get_bps_scale()?;
-        Ok(image
-            .iter()
-            .map(|x| bps_scale.saturating_mul(x.saturating_sub(black_level)))
-            .collect())
+        let mut image = match self.info.u16("raw_format") {
+            Ok(5) => load_raw_v5(&self.info, buffer)?,
+            Ok(6) => load_raw_v6(&self.info, buffer)?,
+            _ => load_raw(&self.info, buffer)?,
+        };
+        let level_scale = self.get_white_level_scale()?;
+        image
+            .iter_mut()
+            .for_each(|x| *x = level_scale.saturating_mul(*x));
+        Ok(image)
     }
     fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
         let cfa_pattern = self.info.u16("cfa_pattern")?;
@@ -100,6 +147,132 @@ impl RawDecoder for General {
     }
 }
 
+/// Decodes the data of RawFormat 5, which packs 10 samples of 12 bits or 9 samples of 14 bits
+/// into every 16 bytes, little endian
+///
+/// The data is read in blocks of 0x4000 bytes and every block starts at its byte 0x1ff8.
+fn load_raw_v5(info: &quickexif::ParsedInfo, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+    const BLOCK_SIZE: usize = 0x4000;
+    const SPLIT: usize = 0x1ff8;
+
+    let width = info.usize("width")?;
+    let height = info.usize("height")?;
+    let offset = info.usize("strip")?;
+    let bps = info.u16("bps")? as usize;
+    let samples_per_chunk = if bps == 12 { 10 } else { 9 };
+    let chunks_per_row = width.div_ceil(samples_per_chunk);
+
+    let buf = buffer
+        .get(offset..)
+        .ok_or_else(|| DecodingError::InvalidRawData("RW2 strip is out of range".to_owned()))?;
+    if buf.len() < height * chunks_per_row * 16 {
+        return Err(DecodingError::InvalidRawData(
+            "RW2 data is shorter than the image".to_owned(),
+        ));
+    }
+    let byte = |pos: usize| {
+        let block = pos / BLOCK_SIZE * BLOCK_SIZE;
+        buf.get(block + (pos % BLOCK_SIZE + SPLIT) % BLOCK_SIZE)
+            .copied()
+            .unwrap_or(0)
+    };
+
+    let mask = (1u128 << bps) - 1;
+    let mut out = vec![0u16; width * height];
+    out.chunks_exact_mut(width)
+        .enumerate()
+        .for_each(|(row, out)| {
+            for (chunk, out) in out.chunks_mut(samples_per_chunk).enumerate() {
+                let pos = (row * chunks_per_row + chunk) * 16;
+                let bits = (0..16).fold(0u128, |acc, i| acc | (byte(pos + i) as u128) << (i * 8));
+                for (i, x) in out.iter_mut().enumerate() {
+                    *x = (bits >> (i * bps) & mask) as u16;
+                }
+            }
+        });
+    Ok(out)
+}
+
+/// Decodes the data of RawFormat 6, where every 16 bytes hold 11 pixels of 14 bits or 14 pixels
+/// of 12 bits
+///
+/// The first two pixels of a block are stored as they are, the others come in groups of three
+/// which share a 2 bit exponent. A pixel of value 0 repeats the last non zero pixel of its parity.
+fn load_raw_v6(info: &quickexif::ParsedInfo, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+    const FIELDS_14: [u32; 14] = [14, 14, 2, 10, 10, 10, 2, 10, 10, 10, 2, 10, 10, 10];
+    const FIELDS_12: [u32; 18] = [12, 12, 2, 8, 8, 8, 2, 8, 8, 8, 2, 8, 8, 8, 2, 8, 8, 8];
+
+    let width = info.usize("width")?;
+    let height = info.usize("height")?;
+    let offset = info.usize("strip")?;
+    let is_14bit = info.u16("bps")? == 14;
+    let (fields, pixels_per_block, base_shift, max): (&[u32], usize, u32, u16) = if is_14bit {
+        (&FIELDS_14, 11, 0x200, 0x3fff)
+    } else {
+        (&FIELDS_12, 14, 0x80, 0xfff)
+    };
+    let blocks_per_row = width.div_ceil(pixels_per_block);
+
+    let buf = buffer
+        .get(offset..)
+        .ok_or_else(|| DecodingError::InvalidRawData("RW2 strip is out of range".to_owned()))?;
+    if buf.len() < height * blocks_per_row * 16 {
+        return Err(DecodingError::InvalidRawData(
+            "RW2 data is shorter than the image".to_owned(),
+        ));
+    }
+
+    let mut out = vec![0u16; width * height];
+    out.chunks_exact_mut(width)
+        .zip(buf.chunks_exact(blocks_per_row * 16))
+        .for_each(|(out, row)| {
+            for (out, block) in out.chunks_mut(pixels_per_block).zip(row.chunks_exact(16)) {
+                let mut bits = u128::from_le_bytes(block.try_into().unwrap());
+                let mut values = fields.iter().map(|&n| {
+                    let value = (bits >> (128 - n)) as u32;
+                    bits <<= n;
+                    value
+                });
+
+                let mut first = [0u32; 2];
+                let mut last = [0u32; 2];
+                let (mut multiplier, mut base) = (0, 0);
+                for (pix, x) in out.iter_mut().enumerate() {
+                    if pix % 3 == 2 {
+                        let exponent = match values.next().unwrap_or(0) {
+                            3 => 4,
+                            e => e,
+                        };
+                        base = base_shift << exponent;
+                        multiplier = 1 << exponent;
+                    }
+                    let mut value = values.next().unwrap_or(0);
+                    let parity = pix % 2;
+                    if first[parity] != 0 {
+                        value *= multiplier;
+                        if base < base_shift * 16 && last[parity] > base {
+                            value += last[parity] - base;
+                        }
+                        last[parity] = value;
+                    } else {
+                        first[parity] = value;
+                        if value != 0 {
+                            last[parity] = value;
+                        } else {
+                            value = last[parity];
+                        }
+                    }
+                    *x = match value.checked_sub(0xf) {
+                        Some(v) if v <= 0xffff => v as u16,
+                        Some(_) => max,
+                        None => 0,
+                    };
+                }
+            }
+        });
+    Ok(out)
+}
+
 fn load_raw(info: &quickexif::ParsedInfo, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
     const SPLIT: bool = true;
     const BLOCK_LINES: usize = 5;
@@ -108,10 +281,19 @@ fn load_raw(info: &quickexif::ParsedInfo, buffer: &[u8]) -> Result<Vec<u16>, Dec
     let height = info.usize("height")?;
     let offset = info.usize("strip")?;
 
-    let buf = &buffer[offset..];
+    let buf = buffer
+        .get(offset..)
+        .ok_or_else(|| DecodingError::InvalidRawData("RW2 strip is out of range".to_owned()))?;
+    // 14 pixels are packed in 16 bytes
+    if buf.len() < width * height / 14 * 16 {
+        return Err(DecodingError::InvalidRawData(
+            "RW2 data is shorter than the image".to_owned(),
+        ));
+    }
     let mut out: Vec<u16> = vec![0u16; width * height];
 
-    out.chunks_exact_mut(width * BLOCK_LINES)
+    // the last block may have fewer lines, e.g. the 11168 lines of the high resolution mode
+    out.chunks_mut(width * BLOCK_LINES)
         .enumerate()
         .for_each(|(index, out)| {
             let row = index * BLOCK_LINES;
@@ -0,0 +1,365 @@
+use super::*;
+use once_cell::sync::Lazy;
+use std::borrow::Cow;
+
+use super::{
+    decode_utility::bit_pump::*, decode_utility::byte_stream::*, decode_utility::huffman::*,
+    utility::*,
+};
+
+pub(super) struct General {
+    info: quickexif::ParsedInfo,
+}
+
+pub(super) static THUMBNAIL_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 : u16 / orientation
+        0x8769 {
+            0x927c {
+                offset + 6 {
+                    0x0004 / thumbnail
+                    0x0003 / thumbnail_len
+                }
+            }
+        }
+    })
+});
+
+pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 : u16 / orientation
+        0x8769 {
+            0xa302? {
+                u32 + 1 / cfa_pattern
+            }
+            0x927c {
+                offset + 6 {
+                    0x0034? / drive_mode
+                    0x0038? {
+                        u16 + 0 / crop_left
+                        u16 + 1 / crop_top
+                    }
+                    0x0039? {
+                        u16 + 0 / crop_width
+                        u16 + 1 / crop_height
+                    }
+                    0x0200? {
+                        u16 + 0 / black_level_r
+                        u16 + 1 / black_level_g
+                        u16 + 3 / black_level_b
+                    }
+                    0x0201 {
+                        u16 + 0 / white_balance_r
+                        u16 + 1 / white_balance_g
+                        u16 + 3 / white_balance_b
+                    }
+                    0x0220? / huffman_table(huffman_table_len)
+                }
+            }
+        }
+    })
+});
+
+// the tags of the raw IFD, which is found by its NewSubfileType
+macro_rules! raw_ifd_template {
+    () => {
+        quickexif::describe_rule!(template {
+            0x0100 / width
+            0x0101 / height
+            0x0102 : u16 / bps
+            0x0103 : u16 / compression
+            0x0111 / strip(strip_count)
+            0x0117 / strip_len
+        })
+    };
+}
+
+pub(super) static RAW_IFD_RULES: Lazy<RawIfdRules> =
+    Lazy::new(|| raw_ifd_rules!(raw_ifd_template!()));
+
+/// The last byte of DriveMode of the shots of Pixel Shift Resolution, 16 in the DriveMode of
+/// ExifTool's Pentax tags, next to 4, 8 and 12 of the strong HDR modes
+const PIXEL_SHIFT_RESOLUTION: u8 = 0x10;
+/// The frames of a Pixel Shift Resolution file, one strip each
+const PIXEL_SHIFT_FRAMES: usize = 4;
+
+impl General {
+    /// The black levels of the 2x2 CFA block, the maker notes give them in the order of R, G, G and B
+    fn get_black_levels(&self) -> [u16; 4] {
+        let level = |name: &str| self.info.u16(name).unwrap_or(0);
+        let (r, g, b) = (
+            level("black_level_r"),
+            level("black_level_g"),
+            level("black_level_b"),
+        );
+        match self.get_cfa_pattern() {
+            Ok(CFAPattern::GRBG) => [g, r, b, g],
+            Ok(CFAPattern::GBRG) => [g, b, r, g],
+            Ok(CFAPattern::BGGR) => [b, g, g, r],
+            _ => [r, g, g, b],
+        }
+    }
+    /// Pixel Shift Resolution files are told by the DriveMode of the maker notes, or by their 4
+    /// strips which each hold a whole image, the strips of the rows of a single image are far
+    /// smaller
+    fn is_pixel_shift(&self, buffer: &[u8]) -> bool {
+        let is_drive_mode = self
+            .info
+            .u8a4("drive_mode")
+            .map(|x| x[3] == PIXEL_SHIFT_RESOLUTION)
+            .unwrap_or(false);
+        is_drive_mode || self.has_frame_strips(buffer)
+    }
+    fn has_frame_strips(&self, buffer: &[u8]) -> bool {
+        let frame_bits = match (
+            self.info.usize("width"),
+            self.info.usize("height"),
+            self.info.u16("bps"),
+        ) {
+            (Ok(width), Ok(height), Ok(bps)) => width * height * bps as usize,
+            _ => return false,
+        };
+        matches!(self.info.usize("strip_count"), Ok(PIXEL_SHIFT_FRAMES))
+            && (0..PIXEL_SHIFT_FRAMES).all(|index| {
+                self.strip(buffer, index)
+                    .map(|(_, len)| len * 8 >= frame_bits)
+                    .unwrap_or(false)
+            })
+    }
+    /// The offset and the length of a strip of several, the fields hold the addresses of the
+    /// offset and length arrays
+    fn strip(&self, buffer: &[u8], index: usize) -> Result<(usize, usize), DecodingError> {
+        let is_le = self.info.is_le;
+        let entry = |addr: usize| {
+            buffer
+                .get(addr + index * 4..addr + index * 4 + 4)
+                .map(|x| x.u32(is_le, 0) as usize)
+        };
+        entry(self.info.usize("strip")?)
+            .zip(entry(self.info.usize("strip_len")?))
+            .ok_or_else(|| DecodingError::InvalidRawData("PEF strip is out of range".to_owned()))
+    }
+    /// The data of the frame from its first strip on and its length. Pixel Shift Resolution
+    /// files keep every frame in a strip of its own, the strips of the other files are the
+    /// rows of the single image one after another.
+    fn get_frame_data<'a>(
+        &self,
+        buffer: &'a [u8],
+        frame: usize,
+    ) -> Result<(Cow<'a, [u8]>, usize), DecodingError> {
+        if frame >= self.get_frame_count(buffer) {
+            return Err(DecodingError::FrameNotFound(frame));
+        }
+        let out_of_range = || DecodingError::InvalidRawData("PEF strip is out of range".to_owned());
+        let count = self.info.usize("strip_count").unwrap_or(1);
+        let offset = self.info.usize("strip")?;
+        let len = self.info.usize("strip_len")?;
+        if count == 1 {
+            let data = buffer.get(offset..).ok_or_else(out_of_range)?;
+            return Ok((Cow::Borrowed(data), len));
+        }
+
+        if self.is_pixel_shift(buffer) {
+            let (offset, len) = self.strip(buffer, frame)?;
+            let data = buffer.get(offset..).ok_or_else(out_of_range)?;
+            return Ok((Cow::Borrowed(data), len));
+        }
+        let mut data = vec![];
+        for index in 0..count {
+            let (offset, len) = self.strip(buffer, index)?;
+            data.extend_from_slice(buffer.get(offset..offset + len).ok_or_else(out_of_range)?);
+        }
+        let len = data.len();
+        // the bit pump reads ahead by 4 bytes
+        data.extend([0; 4]);
+        Ok((Cow::Owned(data), len))
+    }
+    /// The huffman table of maker note tag 0x220, the early models without it share a fixed table
+    fn huffman_table(&self, buffer: &[u8]) -> Result<HuffTable, DecodingError> {
+        let offset = match self.info.usize("huffman_table") {
+            Ok(offset) => offset,
+            Err(_) => return create_legacy_hufftable(),
+        };
+        let len = self.info.usize("huffman_table_len")?;
+        let meta = buffer.get(offset..offset + len).ok_or_else(|| {
+            DecodingError::InvalidRawData("PEF huffman table is out of range".to_owned())
+        })?;
+        create_hufftable(meta, self.info.is_le)
+    }
+}
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        let x = self.info.u32("crop_left").ok()?;
+        let y = self.info.u32("crop_top").ok()?;
+        let width = self.info.u32("crop_width").ok()?;
+        let height = self.info.u32("crop_height").ok()?;
+
+        Some(Crop {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+    fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        let offset = self.info.usize("thumbnail")?;
+        let len = self.info.usize("thumbnail_len")?;
+        buffer
+            .get(offset..offset + len)
+            .ok_or_else(|| DecodingError::InvalidRawData("PEF preview is out of range".to_owned()))
+    }
+    fn get_black_level(&self) -> [u16; 4] {
+        let bps_scale = self.get_bps_scale().unwrap_or(1);
+        self.get_black_levels().map(|x| bps_scale.saturating_mul(x))
+    }
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        self.decode_frame_with_preprocess(buffer, 0)
+    }
+    fn get_frame_count(&self, buffer: &[u8]) -> usize {
+        match self.is_pixel_shift(buffer) {
+            true => self.info.usize("strip_count").unwrap_or(1),
+            false => 1,
+        }
+    }
+    fn decode_frame_with_preprocess(
+        &self,
+        buffer: &[u8],
+        frame: usize,
+    ) -> Result<Vec<u16>, DecodingError> {
+        let (buf, strip_len) = self.get_frame_data(buffer, frame)?;
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let bps = self.info.u16("bps")?;
+        let bps_scale = self.get_bps_scale()?;
+        let compression = self.info.u16("compression")?;
+
+        let image: Vec<u16> = match compression {
+            // the uncompressed data is tagged as PackBits by some models
+            1 | 32773 => {
+                let strip = buf.get(..strip_len).ok_or_else(|| {
+                    DecodingError::InvalidRawData("PEF strip is out of range".to_owned())
+                })?;
+                match bps {
+                    12 if strip_len < width * height * 2 => to_12bit_iter_packed(strip, false)
+                        .take(width * height)
+                        .collect(),
+                    14 if strip_len < width * height * 2 => to_14bit_iter_packed(strip, false)
+                        .take(width * height)
+                        .collect(),
+                    _ => to_16bit_iter(strip, self.info.is_le)
+                        .take(width * height)
+                        .collect(),
+                }
+            }
+            65535 => {
+                let htable = self.huffman_table(buffer)?;
+                load_raw(&buf, &htable, width, height)
+            }
+            _ => {
+                return Err(DecodingError::CompressionNotSupported(format!(
+                    "PEF {}",
+                    compression
+                )))
+            }
+        };
+
+        if image.len() != width * height {
+            return Err(DecodingError::InvalidDecodedImageSize(
+                image.len(),
+                width * height,
+            ));
+        }
+        Ok(image.iter().map(|x| bps_scale.saturating_mul(*x)).collect())
+    }
+}
+
+/// Decodes the Pentax huffman compression, every row predicts its two leading samples
+/// from the row above of the same color and the rest from the samples on their left.
+fn load_raw(src: &[u8], htable: &HuffTable, width: usize, height: usize) -> Vec<u16> {
+    let mut out = vec![0u16; width * height];
+    let mut pump = BitPumpMSB::new(src);
+    let mut pred_up1 = [0i32; 2];
+    let mut pred_up2 = [0i32; 2];
+
+    for (row, out) in out.chunks_exact_mut(width).enumerate() {
+        pred_up1[row & 1] += htable.huff_decode(&mut pump);
+        pred_up2[row & 1] += htable.huff_decode(&mut pump);
+        let mut pred_left1 = pred_up1[row & 1];
+        let mut pred_left2 = pred_up2[row & 1];
+        for (col, out) in out.chunks_exact_mut(2).enumerate() {
+            if col > 0 {
+                pred_left1 += htable.huff_decode(&mut pump);
+                pred_left2 += htable.huff_decode(&mut pump);
+            }
+            out[0] = pred_left1.clamp(0, u16::MAX as i32) as u16;
+            out[1] = pred_left2.clamp(0, u16::MAX as i32) as u16;
+        }
+    }
+    out
+}
+
+/// The table holds the number of codes, the codes themselves left aligned to 12 bits
+/// and the length of every code. The codes are sorted to give the canonical order
+/// of the values, which are the indices of the codes.
+fn create_hufftable(meta: &[u8], is_le: bool) -> Result<HuffTable, DecodingError> {
+    let invalid = || DecodingError::InvalidRawData("PEF huffman table is invalid".to_owned());
+    if meta.len() < 2 {
+        return Err(invalid());
+    }
+    let mut stream = ByteStream::new(meta, is_le);
+    let depth = (stream.get_u16() as usize + 12) & 15;
+    if meta.len() < 14 + depth * 3 {
+        return Err(invalid());
+    }
+    stream.consume_bytes(12);
+
+    let codes: Vec<u32> = (0..depth).map(|_| stream.get_u16() as u32).collect();
+    let lens: Vec<u32> = (0..depth).map(|_| stream.get_u8() as u32).collect();
+    if lens.iter().any(|&len| len == 0 || len > 12) {
+        return Err(invalid());
+    }
+
+    let mut bits = [0u32; 17];
+    lens.iter().for_each(|&len| bits[len as usize] += 1);
+    let mut codes: Vec<(u32, usize)> = codes
+        .iter()
+        .zip(lens.iter())
+        .map(|(code, len)| code >> (12 - len))
+        .enumerate()
+        .map(|(i, code)| (code, i))
+        .collect();
+    codes.sort_by_key(|&(code, i)| (code, std::cmp::Reverse(i)));
+
+    let mut huffval = [0u32; 256];
+    for (value, (_, i)) in huffval.iter_mut().zip(codes) {
+        *value = i as u32;
+    }
+    Ok(HuffTable::new(bits, huffval, false)?)
+}
+
+fn create_legacy_hufftable() -> Result<HuffTable, DecodingError> {
+    let mut bits = [0u32; 17];
+    let mut huffval = [0u32; 256];
+    for (i, x) in PENTAX_TREE[0].iter().enumerate() {
+        bits[i] = *x as u32;
+    }
+    for (i, x) in PENTAX_TREE[1].iter().enumerate() {
+        huffval[i] = *x as u32;
+    }
+    Ok(HuffTable::new(bits, huffval, false)?)
+}
+
+const PENTAX_TREE: [[u8; 16]; 2] = [
+    [0, 0, 2, 3, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0],
+    [3, 4, 2, 5, 1, 6, 0, 7, 8, 9, 10, 11, 12, 0, 0, 0],
+];
@@ -0,0 +1,395 @@
+use super::*;
+use crate::RawFileReadingError;
+use once_cell::sync::Lazy;
+
+use super::decode_utility::bit_pump::{BitPump, BitPumpMSB32};
+use super::utility::{self, GetNumFromBytes};
+
+pub(super) struct General {
+    info: quickexif::ParsedInfo,
+}
+
+// the values read from the Phase One directory, see `parse_info`
+static LAYOUT_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0001 / width
+        0x0002 / height
+        0x0003 / crop_left
+        0x0004 / crop_top
+        0x0005 / crop_width
+        0x0006 / crop_height
+        0x0007 / format
+        0x0008 / data_offset
+        0x0009? / strip_offsets
+        0x000a? / black_level
+        0x000b? / key
+        0x000c? / meta_offset
+        0x000d? / meta_len
+        0x000e / white_balance_r
+        0x000f / white_balance_g
+        0x0010 / white_balance_b
+        0x0112 / orientation
+    })
+});
+
+/// The Phase One directory follows the TIFF header, its offsets are relative to it
+const BASE: usize = 8;
+
+const ORIENTATION: u32 = 0x0100;
+const ROMM_CAM: u32 = 0x0106;
+const WHITE_BALANCE: u32 = 0x0107;
+const RAW_WIDTH: u32 = 0x0108;
+const RAW_HEIGHT: u32 = 0x0109;
+const LEFT_MARGIN: u32 = 0x010a;
+const TOP_MARGIN: u32 = 0x010b;
+const WIDTH: u32 = 0x010c;
+const HEIGHT: u32 = 0x010d;
+const FORMAT: u32 = 0x010e;
+const DATA_OFFSET: u32 = 0x010f;
+const META: u32 = 0x0110;
+const KEY: u32 = 0x0112;
+const STRIP_OFFSETS: u32 = 0x021c;
+const BLACK_LEVEL: u32 = 0x021d;
+const MODEL: u32 = 0x0301;
+const SENSOR_DEFECTS: u32 = 0x0400;
+
+/// The matrix from ROMM (ProPhoto RGB) to XYZ
+const ROMM_TO_XYZ: [f32; 9] = [
+    0.7976749, 0.1351917, 0.0313534, 0.2880402, 0.7118741, 0.0000857, 0.0, 0.0, 0.82521,
+];
+
+struct Entry {
+    tag: u32,
+    len: usize,
+    data: u32,
+}
+
+/// A directory of Phase One entries, which are all little endian. The entries of the
+/// main directory hold a type which the ones of the metadata leave out.
+struct Directory<'a> {
+    buffer: &'a [u8],
+    base: usize,
+    entries: Vec<Entry>,
+}
+
+impl<'a> Directory<'a> {
+    fn new(buffer: &'a [u8], base: usize, entry_len: usize) -> Option<Directory<'a>> {
+        let start = base + buffer.get(base + 8..base + 12)?.u32le(0) as usize;
+        let count = buffer.get(start..start + 4)?.u32le(0) as usize;
+        let entries = (0..count)
+            .map(|i| {
+                let entry =
+                    buffer.get(start + 8 + i * entry_len..start + 8 + (i + 1) * entry_len)?;
+                Some(Entry {
+                    tag: entry.u32le(0),
+                    len: entry.u32le(entry_len - 8) as usize,
+                    data: entry.u32le(entry_len - 4),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Directory {
+            buffer,
+            base,
+            entries,
+        })
+    }
+    fn data(&self, tag: u32) -> Option<u32> {
+        self.entries.iter().find(|x| x.tag == tag).map(|x| x.data)
+    }
+    /// The values of an entry, which are kept at the offset of its data
+    fn get(&self, tag: u32) -> Option<&'a [u8]> {
+        let entry = self.entries.iter().find(|x| x.tag == tag)?;
+        let start = self.base + entry.data as usize;
+        self.buffer.get(start..start + entry.len)
+    }
+    fn f32(&self, tag: u32, index: usize) -> Option<f32> {
+        let bytes = self.get(tag)?;
+        bytes
+            .get(index * 4..index * 4 + 4)
+            .map(|x| f32::from_bits(x.u32le(0)))
+    }
+    fn model(&self) -> Option<&'a str> {
+        let bytes = self.get(MODEL)?;
+        let end = bytes.iter().position(|&x| x == 0).unwrap_or(bytes.len());
+        let model = std::str::from_utf8(&bytes[..end]).ok()?;
+        Some(model.trim_end_matches(" camera").trim())
+    }
+    /// The ColorMatrix of the DNG spec, from XYZ to the camera, derived from the matrix
+    /// of the camera to ROMM
+    fn color_matrix(&self) -> Option<[f32; 9]> {
+        let mut romm_cam = [0f32; 9];
+        for (i, x) in romm_cam.iter_mut().enumerate() {
+            *x = self.f32(ROMM_CAM, i)?;
+        }
+        let matrix = crate::utility::matrix3_mul(&ROMM_TO_XYZ, &romm_cam);
+        Some(crate::utility::matrix3_inverse(&matrix))
+    }
+    fn info_tiff(&self) -> Option<Vec<u8>> {
+        let (r, g, b) = (
+            self.f32(WHITE_BALANCE, 0)?,
+            self.f32(WHITE_BALANCE, 1)?,
+            self.f32(WHITE_BALANCE, 2)?,
+        );
+        let white_balance = |x: f32| (x / g * 512.0).round() as u32;
+        let orientation = [1, 6, 8, 3][self.data(ORIENTATION).unwrap_or(0) as usize & 3];
+
+        let mut builder = utility::TiffBuilder::new()
+            .ascii(0x010f, "Phase One")
+            .ascii(0x0110, self.model()?)
+            .short(0x0112, orientation)
+            .long(0x0001, self.data(RAW_WIDTH)?)
+            .long(0x0002, self.data(RAW_HEIGHT)?)
+            .long(0x0003, self.data(LEFT_MARGIN).unwrap_or(0))
+            .long(0x0004, self.data(TOP_MARGIN).unwrap_or(0))
+            .long(0x0005, self.data(WIDTH).unwrap_or(0))
+            .long(0x0006, self.data(HEIGHT).unwrap_or(0))
+            .long(0x0007, self.data(FORMAT)?)
+            .long(0x0008, BASE as u32 + self.data(DATA_OFFSET)?)
+            .long(0x000e, white_balance(r))
+            .long(0x000f, white_balance(g))
+            .long(0x0010, white_balance(b));
+        if let Some(offset) = self.data(STRIP_OFFSETS) {
+            builder = builder.long(0x0009, BASE as u32 + offset);
+        }
+        if let Some(black_level) = self.data(BLACK_LEVEL) {
+            builder = builder.short(0x000a, black_level as u16);
+        }
+        if let Some(key) = self.data(KEY) {
+            builder = builder.long(0x000b, key);
+        }
+        if let Some(meta) = self.entries.iter().find(|x| x.tag == META) {
+            builder = builder
+                .long(0x000c, BASE as u32 + meta.data)
+                .long(0x000d, meta.len as u32);
+        }
+        if let Some(color_matrix) = self.color_matrix() {
+            builder = builder.srational(0xc621, &color_matrix);
+        }
+        Some(builder.build())
+    }
+}
+
+pub(in super::super) fn is_iiq(buffer: &[u8]) -> bool {
+    buffer.get(BASE..BASE + 4) == Some(&b"IIII"[..])
+}
+
+/// A TIFF holding make, model and color matrix of an IIQ file, for selecting the decoder
+pub(in super::super) fn info_tiff(buffer: &[u8]) -> Option<Vec<u8>> {
+    if !is_iiq(buffer) {
+        return None;
+    }
+    Directory::new(buffer, BASE, 16)?.info_tiff()
+}
+
+pub(super) fn parse_info(
+    buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    let layout = info_tiff(buffer)
+        .ok_or_else(|| DecodingError::InvalidRawData("IIQ directory is not found".to_owned()))?;
+    Ok(quickexif::parse_with_prev_info(
+        &layout,
+        &LAYOUT_RULE,
+        basic_info,
+    )?)
+}
+
+impl General {
+    /// The sensor defects of the metadata, 8 bytes each holding the column, row and type
+    fn sensor_defects<'a>(&self, buffer: &'a [u8]) -> Option<&'a [u8]> {
+        let meta_offset = self.info.usize("meta_offset").ok()?;
+        let meta_len = self.info.usize("meta_len").ok()?;
+        let meta = buffer.get(meta_offset..meta_offset + meta_len)?;
+        Directory::new(meta, 0, 12)?.get(SENSOR_DEFECTS)
+    }
+    /// Uncompressed data of formats 1 and 2 has its sample pairs scrambled with the key
+    fn load_uncompressed(&self, src: &[u8], len: usize) -> Result<Vec<u16>, DecodingError> {
+        let mut image: Vec<u16> = utility::to_16bit_iter(src, true).take(len).collect();
+        let mask = match self.info.u32("format")? {
+            1 => 0x5555,
+            2 => 0x1354,
+            _ => return Ok(image),
+        };
+        let key = self.info.u32("key")?;
+        let keys = [key as u16, (key >> 16) as u16];
+        for pair in image.chunks_exact_mut(2) {
+            let a = pair[0] ^ keys[0];
+            let b = pair[1] ^ keys[1];
+            pair[0] = (a & mask) | (b & !mask);
+            pair[1] = (b & mask) | (a & !mask);
+        }
+        Ok(image)
+    }
+    /// IIQ L data is coded row by row, the offsets of the rows are relative to the raw data
+    fn load_compressed(
+        &self,
+        buffer: &[u8],
+        data_offset: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<u16>, DecodingError> {
+        let strip_offsets = self.info.usize("strip_offsets")?;
+        let table = buffer
+            .get(strip_offsets..strip_offsets + height * 4)
+            .ok_or_else(|| {
+                DecodingError::InvalidRawData("IIQ strips are out of range".to_owned())
+            })?;
+        // the 14 bit samples are scaled to 16 bit except by format 8
+        let shift = if self.info.u32("format")? == 8 { 0 } else { 2 };
+
+        let mut image = vec![0u16; width * height];
+        for (row, out) in image.chunks_exact_mut(width).enumerate() {
+            let offset = data_offset + table.u32le(row * 4) as usize;
+            let src = buffer.get(offset..).ok_or_else(|| {
+                DecodingError::InvalidRawData("IIQ strip is out of range".to_owned())
+            })?;
+            load_row(src, out);
+            out.iter_mut().for_each(|x| *x <<= shift);
+        }
+        Ok(image)
+    }
+}
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        let crop = Crop {
+            x: self.info.u32("crop_left").ok()?,
+            y: self.info.u32("crop_top").ok()?,
+            width: self.info.u32("crop_width").ok()?,
+            height: self.info.u32("crop_height").ok()?,
+        };
+        Some(crop).filter(|crop| crop.width > 0 && crop.height > 0)
+    }
+    fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        Ok(cfa_pattern_from_info(&self.info).unwrap_or(CFAPattern::RGGB))
+    }
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let data_offset = self.info.usize("data_offset")?;
+        let black_level = self.info.u16("black_level").unwrap_or(0);
+
+        let mut image = match self.info.u32("format")? {
+            0..=2 => {
+                let src = buffer.get(data_offset..).ok_or_else(|| {
+                    DecodingError::InvalidRawData("IIQ raw data is out of range".to_owned())
+                })?;
+                self.load_uncompressed(src, width * height)?
+            }
+            _ => self.load_compressed(buffer, data_offset, width, height)?,
+        };
+
+        if image.len() != width * height {
+            return Err(DecodingError::InvalidDecodedImageSize(
+                image.len(),
+                width * height,
+            ));
+        }
+        if let Some(defects) = self.sensor_defects(buffer) {
+            fix_defects(&mut image, width, height, defects);
+        }
+        image
+            .iter_mut()
+            .for_each(|x| *x = x.saturating_sub(black_level));
+        Ok(image)
+    }
+    /// The previews of IIQ files are not referenced by their TIFF, the largest JPEG is taken
+    fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        crate::decode::largest_jpeg_slice(buffer).ok_or_else(|| {
+            DecodingError::RawInfoError(quickexif::parsed_info::Error::FieldNotFound(
+                "thumbnail".into(),
+            ))
+        })
+    }
+}
+
+/// Decodes a row of IIQ L data. The samples of the two columns of the same color are
+/// predicted from the previous one, and the length of their differences is coded in
+/// front of every 8 samples. The last columns are stored as plain 16 bit values.
+fn load_row(src: &[u8], out: &mut [u16]) {
+    const LENGTH: [u32; 10] = [8, 7, 6, 9, 11, 10, 5, 12, 14, 13];
+
+    let mut pump = BitPumpMSB32::new(src);
+    let mut len = [14u32; 2];
+    let mut pred = [0i32; 2];
+    let width = out.len();
+    for (col, out) in out.iter_mut().enumerate() {
+        if col >= width & !7 {
+            len = [14, 14];
+        } else if col & 7 == 0 {
+            for len in len.iter_mut() {
+                let mut j = 0;
+                while j < 5 && pump.get_bits(1) == 0 {
+                    j += 1;
+                }
+                if j > 0 {
+                    *len = LENGTH[(j - 1) * 2 + pump.get_bits(1) as usize];
+                }
+            }
+        }
+
+        let i = len[col & 1];
+        if i == 14 {
+            pred[col & 1] = pump.get_bits(16) as i32;
+        } else {
+            pred[col & 1] += pump.get_bits(i) as i32 + 1 - (1 << (i - 1));
+        }
+        *out = pred[col & 1].clamp(0, u16::MAX as i32) as u16;
+    }
+}
+
+/// Replaces the defective pixels and columns listed in the metadata by the average of
+/// their neighbours of the same color
+fn fix_defects(image: &mut [u16], width: usize, height: usize, defects: &[u8]) {
+    for defect in defects.chunks_exact(8) {
+        let col = defect.u16le(0) as usize;
+        let row = defect.u16le(2) as usize;
+        if col >= width {
+            continue;
+        }
+        match defect.u16le(4) {
+            129 if row < height => {
+                image[row * width + col] = average(image, width, height, row, col, true)
+            }
+            131 | 137 => {
+                for row in 0..height {
+                    image[row * width + col] = average(image, width, height, row, col, false)
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The neighbours in the rows above and below are left out for defective columns
+fn average(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    row: usize,
+    col: usize,
+    with_rows: bool,
+) -> u16 {
+    let mut neighbours = vec![(row, col.wrapping_sub(2)), (row, col + 2)];
+    if with_rows {
+        neighbours.extend([(row.wrapping_sub(2), col), (row + 2, col)]);
+    }
+    let values: Vec<u32> = neighbours
+        .into_iter()
+        .filter(|&(row, col)| row < height && col < width)
+        .map(|(row, col)| image[row * width + col] as u32)
+        .collect();
+    match values.len() {
+        0 => image[row * width + col],
+        len => (values.iter().sum::<u32>() / len as u32) as u16,
+    }
+}
@@ -0,0 +1,165 @@
+use super::*;
+use crate::decode::DecodedImage;
+use crate::RawFileReadingError;
+use once_cell::sync::Lazy;
+use std::sync::{PoisonError, RwLock};
+
+type Decode = Box<
+    dyn Fn(
+            &[u8],
+            quickexif::ParsedInfo,
+            usize,
+            bool,
+            bool,
+            [f32; 9],
+        ) -> Result<DecodedImage, DecodingError>
+        + Send
+        + Sync,
+>;
+type ParseInfo = Box<
+    dyn Fn(&[u8], quickexif::ParsedInfo) -> Result<quickexif::ParsedInfo, DecodingError>
+        + Send
+        + Sync,
+>;
+type Thumbnail = Box<
+    dyn for<'a> Fn(
+            &'a [u8],
+            quickexif::ParsedInfo,
+        ) -> Result<(&'a [u8], Orientation), DecodingError>
+        + Send
+        + Sync,
+>;
+
+struct Registration {
+    make: String,
+    model_predicate: fn(&str) -> bool,
+    decode: Decode,
+    parse_info: ParseInfo,
+    thumbnail: Thumbnail,
+}
+
+static REGISTRY: Lazy<RwLock<Vec<Registration>>> = Lazy::new(Default::default);
+
+/// Registers a decoder for the files of `make` whose model passes `model_predicate`.
+///
+/// The factory gets the file and the parsed make, model, orientation and dimensions, it can
+/// parse the tags it needs with `quickexif` before building the decoder.
+///
+/// The makes with a decoder of their own are always decoded by it, the registered decoders
+/// are tried before the files of the other makes fall back to the generic TIFF decoder.
+/// The first registered decoder matching the file is used.
+pub fn register_decoder<D: RawDecoder + 'static>(
+    make: &str,
+    model_predicate: fn(&str) -> bool,
+    factory: fn(&[u8], quickexif::ParsedInfo) -> Result<D, DecodingError>,
+) {
+    let registration = Registration {
+        make: make.to_owned(),
+        model_predicate,
+        decode: Box::new(
+            move |buffer, info, frame, apply_opcodes, keeps_black_level, cam_matrix| {
+                let decoder = factory(buffer, info)?;
+                selector::decode_with(
+                    decoder,
+                    buffer,
+                    frame,
+                    apply_opcodes,
+                    keeps_black_level,
+                    cam_matrix,
+                )
+            },
+        ),
+        parse_info: Box::new(move |buffer, info| Ok(factory(buffer, info)?.into_info())),
+        thumbnail: thumbnail(move |buffer, info| {
+            let decoder = factory(buffer, info)?;
+            Ok((decoder.get_thumbnail(buffer)?, decoder.get_orientation()))
+        }),
+    };
+    REGISTRY
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(registration);
+}
+
+/// Boxes the closure with the signature the returned thumbnail borrows the buffer by
+fn thumbnail<F>(f: F) -> Thumbnail
+where
+    F: for<'a> Fn(
+            &'a [u8],
+            quickexif::ParsedInfo,
+        ) -> Result<(&'a [u8], Orientation), DecodingError>
+        + Send
+        + Sync
+        + 'static,
+{
+    Box::new(f)
+}
+
+/// The make and the model of the file, owned since the info is moved into the decoder
+fn make_and_model(
+    basic_info: &quickexif::ParsedInfo,
+) -> Result<(String, String), RawFileReadingError> {
+    let make = basic_info
+        .str("make")
+        .map_err(|_| RawFileReadingError::CannotReadMake)?;
+    let model = basic_info.str("model").unwrap_or_default();
+    Ok((make.to_owned(), model.to_owned()))
+}
+
+fn registered<T>(make: &str, model: &str, f: impl FnOnce(&Registration) -> T) -> Option<T> {
+    let registry = REGISTRY.read().unwrap_or_else(PoisonError::into_inner);
+    let registration = registry
+        .iter()
+        .find(|x| x.make == make && (x.model_predicate)(model))?;
+    Some(f(registration))
+}
+
+pub(super) fn is_registered(basic_info: &quickexif::ParsedInfo) -> bool {
+    match make_and_model(basic_info) {
+        Ok((make, model)) => registered(&make, &model, |_| ()).is_some(),
+        Err(_) => false,
+    }
+}
+
+pub(super) fn decode(
+    file_buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+    frame: usize,
+    apply_opcodes: bool,
+    keeps_black_level: bool,
+    cam_matrix: [f32; 9],
+) -> Result<DecodedImage, RawFileReadingError> {
+    let (make, model) = make_and_model(&basic_info)?;
+    registered(&make, &model, |x| {
+        (x.decode)(
+            file_buffer,
+            basic_info,
+            frame,
+            apply_opcodes,
+            keeps_black_level,
+            cam_matrix,
+        )
+    })
+    .ok_or(RawFileReadingError::MakerIsNotSupportedYet(make))?
+    .map_err(Into::into)
+}
+
+pub(super) fn parse_info(
+    file_buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    let (make, model) = make_and_model(&basic_info)?;
+    registered(&make, &model, |x| (x.parse_info)(file_buffer, basic_info))
+        .ok_or(RawFileReadingError::MakerIsNotSupportedYet(make))?
+        .map_err(Into::into)
+}
+
+pub(super) fn get_thumbnail(
+    file_buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+) -> Result<(&[u8], Orientation), RawFileReadingError> {
+    let (make, model) = make_and_model(&basic_info)?;
+    registered(&make, &model, |x| (x.thumbnail)(file_buffer, basic_info))
+        .ok_or(RawFileReadingError::MakerIsNotSupportedYet(make))?
+        .map_err(Into::into)
+}
@@ -0,0 +1,121 @@
+use super::utility::{self, GetNumFromBytes};
+use crate::RawFileReadingError;
+use once_cell::sync::Lazy;
+
+static MAKER_NOTE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x8769? {
+            0x927c? / maker_note(maker_note_len)
+        }
+    })
+});
+
+// the values read from the maker notes, see `parse_info`
+static LAYOUT_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0001? / maker_note_crop_left
+        0x0002? / maker_note_crop_top
+        0x0003? / maker_note_crop_width
+        0x0004? / maker_note_crop_height
+        0x0005? / maker_note_white_balance_r
+        0x0006? / maker_note_white_balance_g
+        0x0007? / maker_note_white_balance_b
+    })
+});
+
+/// The GR III bodies write DNG files natively, they are decoded as the other DNG files
+pub(super) fn is_ricoh(make: &str) -> bool {
+    make.starts_with("RICOH")
+}
+
+/// The maker notes start with "RICOH", a null byte and their byte order, the offsets of
+/// their IFD are relative to the start of the maker notes
+struct MakerNote<'a> {
+    buffer: &'a [u8],
+    is_le: bool,
+    entries: Vec<(u16, u16, usize, &'a [u8])>,
+}
+
+impl<'a> MakerNote<'a> {
+    fn new(buffer: &'a [u8]) -> Option<MakerNote<'a>> {
+        let is_le = match buffer.get(..8)? {
+            b"RICOH\0II" => true,
+            b"RICOH\0MM" => false,
+            _ => return None,
+        };
+        let count = buffer.get(8..10)?.u16(is_le, 0) as usize;
+        let entries = (0..count)
+            .map(|i| {
+                let entry = buffer.get(10 + i * 12..22 + i * 12)?;
+                Some((
+                    entry.u16(is_le, 0),
+                    entry.u16(is_le, 2),
+                    entry.u32(is_le, 4) as usize,
+                    &entry[8..],
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(MakerNote {
+            buffer,
+            is_le,
+            entries,
+        })
+    }
+    /// The SHORT values of a tag, which are kept in the entry when they fit in 4 bytes
+    fn shorts(&self, tag: u16) -> Option<Vec<u16>> {
+        let &(.., count, value) = self.entries.iter().find(|x| x.0 == tag && x.1 == 3)?;
+        let data = if count <= 2 {
+            value
+        } else {
+            let offset = value.u32(self.is_le, 0) as usize;
+            self.buffer.get(offset..offset + count * 2)?
+        };
+        Some((0..count).map(|i| data.u16(self.is_le, i * 2)).collect())
+    }
+    /// The crop of the selected aspect ratio is given by the ImageAreaOffset and the
+    /// RawImageSize tags, the white balance by the RGGB levels of tag 0x201
+    fn info_tiff(&self) -> Vec<u8> {
+        let mut tiff = utility::TiffBuilder::new();
+        if let (Some(offset), Some(size)) = (self.shorts(0x0038), self.shorts(0x0039)) {
+            if offset.len() == 2 && size.len() == 2 && size[0] > 0 && size[1] > 0 {
+                tiff = tiff
+                    .long(0x0001, offset[0] as u32)
+                    .long(0x0002, offset[1] as u32)
+                    .long(0x0003, size[0] as u32)
+                    .long(0x0004, size[1] as u32);
+            }
+        }
+        if let Some(levels) = self.shorts(0x0201).filter(|x| x.len() == 4 && x[1] > 0) {
+            let white_balance = |x: u16| x as u32 * 512 / levels[1] as u32;
+            tiff = tiff
+                .long(0x0005, white_balance(levels[0]))
+                .long(0x0006, 512)
+                .long(0x0007, white_balance(levels[3]));
+        }
+        tiff.build()
+    }
+}
+
+/// Adds the crop and the white balance of the maker notes, the files without them are
+/// left as they are
+pub(super) fn parse_info(
+    buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    let location = quickexif::parse(buffer, &MAKER_NOTE_RULE)?;
+    let maker_note = match (
+        location.usize("maker_note"),
+        location.usize("maker_note_len"),
+    ) {
+        (Ok(offset), Ok(len)) => buffer.get(offset..offset + len).and_then(MakerNote::new),
+        _ => None,
+    };
+    match maker_note {
+        Some(maker_note) => Ok(quickexif::parse_with_prev_info(
+            &maker_note.info_tiff(),
+            &LAYOUT_RULE,
+            basic_info,
+        )?),
+        None => Ok(basic_info),
+    }
+}
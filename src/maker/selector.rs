@@ -21,18 +21,22 @@ fn prepare(
     let cam_matrix = if only_thumbnail {
         [0f32; 9]
     } else {
-        match dng_version {
-            None => *data::CAM_XYZ_MAP
-                .get(model.as_str())
-                .ok_or_else(|| RawFileReadingError::ModelIsNotSupportedYet(model.clone()))?,
-            Some(_) => {
-                let mut matrix = [0f32; 9];
-                for (i, item) in matrix.iter_mut().enumerate() {
-                    *item = basic_info.f64(format!("c{}", i).as_str())? as f32;
+        // the matrix of the model is used for DNG files without ColorMatrix, e.g. from Leica,
+        // and the ColorMatrix of other files only for the models without a matrix, e.g. 3FR
+        let color_matrix = color_matrix(basic_info)
+            .filter(|_| dng_version.is_some() || !data::CAM_XYZ_MAP.contains_key(model.as_str()));
+        if let Some(color_matrix) = color_matrix {
+            let mut matrix = crate::utility::matrix3_inverse(&color_matrix);
+            utility::matrix3_normalize(&mut matrix);
+            matrix
+        } else {
+            match data::CAM_XYZ_MAP.get(model.as_str()) {
+                Some(matrix) => *matrix,
+                // the generic decoder of the other makers goes without a camera matrix
+                None if dng_version.is_none() && maker_exif_rule(make).is_none() => {
+                    [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]
                 }
-                utility::matrix3_inverse(&mut matrix);
-                utility::matrix3_normalize(&mut matrix);
-                matrix
+                None => return Err(RawFileReadingError::ModelIsNotSupportedYet(model)),
             }
         }
     };
@@ -40,30 +44,112 @@ fn prepare(
     Ok((make, dng_version, cam_matrix))
 }
 
+/// The ColorMatrix of the file from XYZ to the camera colors, the one of the second illuminant
+/// when there are two
+fn color_matrix(basic_info: &quickexif::ParsedInfo) -> Option<[f32; 9]> {
+    let mut matrix = [0f32; 9];
+    for (i, item) in matrix.iter_mut().enumerate() {
+        *item = basic_info.f64(format!("c{}", i).as_str()).ok()? as f32;
+    }
+    Some(matrix)
+}
+
+/// The rules of the makers with a decoder of their own, the files of the other makers are
+/// decoded as plain TIFF raws
+fn maker_exif_rule(make: &str) -> Option<&'static quickexif::ParsingRule> {
+    match make {
+        "Canon" | "CANON" | "Canon Inc." => Some(&canon::EXIF_RULE),
+        "NIKON" | "NIKON CORPORATION" => Some(&nikon::IMAGE_RULE),
+        "SONY" => Some(&sony::IMAGE_RULE),
+        "Panasonic" => Some(&panasonic::IMAGE_RULE),
+        "OLYMPUS CORPORATION" | "OLYMPUS IMAGING CORP." | "OLYMPUS" | "OM Digital Solutions" => {
+            Some(&olympus::IMAGE_RULE)
+        }
+        "FUJIFILM" => Some(&fujifilm::IMAGE_RULE),
+        "PENTAX Corporation" | "PENTAX" | "RICOH IMAGING COMPANY, LTD." => {
+            Some(&pentax::IMAGE_RULE)
+        }
+        "Hasselblad" => Some(&hasselblad::IMAGE_RULE),
+        "Kodak" | "KODAK" | "EASTMAN KODAK COMPANY" => Some(&kodak::IMAGE_RULE),
+        "SEIKO EPSON CORP." => Some(&epson::IMAGE_RULE),
+        "Minolta Co., Ltd." | "MINOLTA" | "KONICA MINOLTA" => Some(&minolta::EXIF_RULE),
+        _ => None,
+    }
+}
+
+/// The rules of the raw IFD for the files keeping it at different places, which is found by
+/// its NewSubfileType
+fn raw_ifd_rules(dng_version: Option<u16>, make: &str) -> Option<&'static utility::RawIfdRules> {
+    match (dng_version, make) {
+        (Some(_), _) => Some(&adobe::RAW_IFD_RULES),
+        (None, "NIKON" | "NIKON CORPORATION") => Some(&nikon::RAW_IFD_RULES),
+        (None, "Hasselblad") => Some(&hasselblad::RAW_IFD_RULES),
+        (None, "PENTAX Corporation" | "PENTAX" | "RICOH IMAGING COMPANY, LTD.") => {
+            Some(&pentax::RAW_IFD_RULES)
+        }
+        (None, make) if maker_exif_rule(make).is_none() => Some(&tiff::RAW_IFD_RULES),
+        _ => None,
+    }
+}
+
+fn parse_raw_ifd(
+    file_buffer: &[u8],
+    raw_ifd_rules: Option<&utility::RawIfdRules>,
+    info: quickexif::ParsedInfo,
+) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    match raw_ifd_rules {
+        Some(rules) => Ok(quickexif::parse_with_prev_info(
+            file_buffer,
+            rules.select(file_buffer),
+            info,
+        )?),
+        None => Ok(info),
+    }
+}
+
 pub(in super::super) fn select_and_decode_exif_info(
     file_buffer: &[u8],
     basic_info: quickexif::ParsedInfo,
 ) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
     let (make, dng_version, _) = prepare(&basic_info, true)?;
+    let is_leica = dng_version.is_some() && leica::is_leica(make);
+    let raw_ifd_rules = raw_ifd_rules(dng_version, make);
+    if phase_one::is_iiq(file_buffer) {
+        return phase_one::parse_info(file_buffer, basic_info);
+    }
+    if dng_version.is_none() && sony::is_a100(&basic_info) {
+        return sony::parse_a100_info(file_buffer, basic_info);
+    }
+    if dng_version.is_none() && make == "SONY" && sony::is_srf(&basic_info) {
+        return Err(RawFileReadingError::ModelIsNotSupportedYet(
+            basic_info.str("model")?.to_owned(),
+        ));
+    }
 
-    let rule = match dng_version {
-        None => match make {
-            "Canon" | "CANON" | "Canon Inc." => Ok(&canon::IMAGE_RULE),
-            "NIKON" | "NIKON CORPORATION" => Ok(&nikon::IMAGE_RULE),
-            "SONY" => Ok(&sony::IMAGE_RULE),
-            "Panasonic" => Ok(&panasonic::IMAGE_RULE),
-            "OLYMPUS CORPORATION" | "OLYMPUS IMAGING CORP." => Ok(&olympus::IMAGE_RULE),
-            "FUJIFILM" => Ok(&fujifilm::IMAGE_RULE),
-            _ => Err(RawFileReadingError::MakerIsNotSupportedYet(make.to_owned())),
+    if dng_version.is_none()
+        && maker_exif_rule(make).is_none()
+        && registry::is_registered(&basic_info)
+    {
+        return registry::parse_info(file_buffer, basic_info);
+    }
+
+    let rule: &quickexif::ParsingRule = match dng_version {
+        None => match maker_exif_rule(make) {
+            Some(rule) => rule,
+            None if tiff::is_tiff(file_buffer) => &tiff::IMAGE_RULE,
+            None => return Err(RawFileReadingError::MakerIsNotSupportedYet(make.to_owned())),
         },
-        Some(_version) => Ok(&adobe::IMAGE_RULE),
-    }?;
+        Some(_version) => &adobe::IMAGE_RULE,
+    };
+    // the lens of Leica bodies is only in their maker notes
+    let basic_info = if is_leica {
+        quickexif::parse_with_prev_info(file_buffer, &leica::MAKER_NOTE_RULE, basic_info)?
+    } else {
+        basic_info
+    };
 
-    Ok(quickexif::parse_with_prev_info(
-        file_buffer,
-        rule,
-        basic_info,
-    )?)
+    let info = quickexif::parse_with_prev_info(file_buffer, rule, basic_info)?;
+    parse_raw_ifd(file_buffer, raw_ifd_rules, info)
 }
 
 #[allow(dead_code)]
@@ -90,8 +176,20 @@ pub(in super::super) fn select_and_decode_thumbnail(
             "NIKON" | "NIKON CORPORATION" => Ok(decode!(nikon)),
             "SONY" => Ok(decode!(sony)),
             "Panasonic" => Ok(decode!(panasonic)),
-            "OLYMPUS CORPORATION" | "OLYMPUS IMAGING CORP." => Ok(decode!(olympus)),
+            "OLYMPUS CORPORATION"
+            | "OLYMPUS IMAGING CORP."
+            | "OLYMPUS"
+            | "OM Digital Solutions" => Ok(decode!(olympus)),
             "FUJIFILM" => Ok(decode!(fujifilm)),
+            "PENTAX Corporation" | "PENTAX" | "RICOH IMAGING COMPANY, LTD." => Ok(decode!(pentax)),
+            "Hasselblad" => Ok(decode!(hasselblad)),
+            "Kodak" | "KODAK" | "EASTMAN KODAK COMPANY" => Ok(decode!(kodak)),
+            "SEIKO EPSON CORP." => Ok(decode!(epson)),
+            "Minolta Co., Ltd." | "MINOLTA" | "KONICA MINOLTA" => Ok(decode!(minolta)),
+            _ if registry::is_registered(&basic_info) => {
+                registry::get_thumbnail(file_buffer, basic_info)
+            }
+            _ if tiff::is_tiff(file_buffer) => Ok(decode!(tiff)),
             _ => Err(RawFileReadingError::MakerIsNotSupportedYet(make.to_owned())),
         },
         Some(_version) => Ok(decode!(adobe)),
@@ -101,48 +199,149 @@ pub(in super::super) fn select_and_decode_thumbnail(
 pub(in super::super) fn select_and_decode(
     file_buffer: &[u8],
     basic_info: quickexif::ParsedInfo,
+    frame: usize,
+    apply_opcodes: bool,
+    keeps_black_level: bool,
 ) -> Result<DecodedImage, RawFileReadingError> {
     let (make, dng_version, cam_matrix) = prepare(&basic_info, false)?;
+    let raw_ifd_rules = raw_ifd_rules(dng_version, make);
 
     macro_rules! decode {
         ($t:ident) => {{
             let raw_info =
                 quickexif::parse_with_prev_info(file_buffer, &$t::IMAGE_RULE, basic_info)?;
-            let width = raw_info.usize("width")?;
-            let height = raw_info.usize("height")?;
-
-            let decoder = $t::General::new(raw_info);
-            let cfa_pattern = decoder.get_cfa_pattern().unwrap_or(CFAPattern::RGGB);
-            let crop = decoder.get_crop();
-            let orientation = decoder.get_orientation();
-            let white_balance = decoder.get_white_balance()?;
-            let image = decoder.decode_with_preprocess(file_buffer)?;
-
-            DecodedImage {
-                image,
-                width,
-                height,
-                cfa_pattern,
-                crop,
-                orientation,
-                white_balance,
+            decode!($t, parse_raw_ifd(file_buffer, raw_ifd_rules, raw_info)?)
+        }};
+        ($t:ident, $raw_info:expr) => {{
+            let decoder = $t::General::new($raw_info);
+            decode_with(
+                decoder,
+                file_buffer,
+                frame,
+                apply_opcodes,
+                keeps_black_level,
                 cam_matrix,
-                parsed_info: decoder.into_info()
-            }
+            )?
         }};
     }
 
     let decoded_image = match dng_version {
         None => match make {
+            _ if phase_one::is_iiq(file_buffer) => Ok(decode!(
+                phase_one,
+                phase_one::parse_info(file_buffer, basic_info)?
+            )),
+            _ if minolta::is_mrw(file_buffer) => Ok(decode!(
+                minolta,
+                minolta::parse_info(file_buffer, basic_info)?
+            )),
+            "Canon" | "CANON" | "Canon Inc." if cr3::is_cr3(file_buffer) => {
+                Ok(decode!(cr3, cr3::parse_info(file_buffer, basic_info)?))
+            }
+            "Canon" | "CANON" | "Canon Inc." if crw::is_crw(file_buffer) => {
+                Ok(decode!(crw, crw::parse_info(file_buffer, basic_info)?))
+            }
+            "Canon" | "CANON" | "Canon Inc." => Ok(decode!(canon)),
             "NIKON" | "NIKON CORPORATION" => Ok(decode!(nikon)),
+            "SONY" if sony::is_a100(&basic_info) => Ok(decode!(
+                sony,
+                sony::parse_a100_info(file_buffer, basic_info)?
+            )),
+            "SONY" if sony::is_srf(&basic_info) => Err(
+                RawFileReadingError::ModelIsNotSupportedYet(basic_info.str("model")?.to_owned()),
+            ),
             "SONY" => Ok(decode!(sony)),
             "Panasonic" => Ok(decode!(panasonic)),
-            "OLYMPUS CORPORATION" | "OLYMPUS IMAGING CORP." => Ok(decode!(olympus)),
-            "FUJIFILM" => Ok(decode!(fujifilm)),
+            "OLYMPUS CORPORATION"
+            | "OLYMPUS IMAGING CORP."
+            | "OLYMPUS"
+            | "OM Digital Solutions" => Ok(decode!(olympus)),
+            "FUJIFILM" => Ok(decode!(
+                fujifilm,
+                fujifilm::parse_info(file_buffer, basic_info)?
+            )),
+            "PENTAX Corporation" | "PENTAX" | "RICOH IMAGING COMPANY, LTD." => Ok(decode!(pentax)),
+            "Hasselblad" => Ok(decode!(hasselblad)),
+            "Kodak" | "KODAK" | "EASTMAN KODAK COMPANY" => Ok(decode!(kodak)),
+            "SEIKO EPSON CORP." => Ok(decode!(epson)),
+            _ if registry::is_registered(&basic_info) => registry::decode(
+                file_buffer,
+                basic_info,
+                frame,
+                apply_opcodes,
+                keeps_black_level,
+                cam_matrix,
+            ),
+            _ if tiff::is_tiff(file_buffer) => Ok(decode!(tiff)),
             _ => Err(RawFileReadingError::MakerIsNotSupportedYet(make.to_owned())),
         },
+        Some(_version) if ricoh::is_ricoh(make) => Ok(decode!(
+            adobe,
+            parse_raw_ifd(
+                file_buffer,
+                raw_ifd_rules,
+                quickexif::parse_with_prev_info(
+                    file_buffer,
+                    &adobe::IMAGE_RULE,
+                    ricoh::parse_info(file_buffer, basic_info)?
+                )?
+            )?
+        )),
         Some(_version) => Ok(decode!(adobe)),
     }?;
 
     Ok(decoded_image)
 }
+
+/// Decodes the frame with the decoder, which is built from the info of the file. The black
+/// level is subtracted before the opcodes, which expect it to be
+pub(super) fn decode_with<D: RawDecoder>(
+    decoder: D,
+    file_buffer: &[u8],
+    frame: usize,
+    apply_opcodes: bool,
+    keeps_black_level: bool,
+    cam_matrix: [f32; 9],
+) -> Result<DecodedImage, DecodingError> {
+    if frame >= decoder.get_frame_count(file_buffer) {
+        return Err(DecodingError::FrameNotFound(frame));
+    }
+    let (width, height) = decoder.get_image_size(file_buffer)?;
+    let cfa_pattern = decoder.get_cfa_pattern().unwrap_or(CFAPattern::RGGB);
+    let crop = decoder.get_crop();
+    let orientation = decoder.get_orientation();
+    let white_balance = decoder.get_white_balance(file_buffer)?;
+    let white_level = decoder.get_white_level();
+    let black_level = decoder.get_black_level();
+    let baseline_exposure = decoder.get_baseline_exposure();
+    let tone_curve = decoder.get_tone_curve(file_buffer)?;
+    let is_generic_fallback = decoder.is_generic_fallback();
+    let color_matrix = color_matrix(decoder.get_info());
+    let mut image = decoder.decode_frame_with_preprocess(file_buffer, frame)?;
+    if !keeps_black_level {
+        crate::pass::sub_black_level(&mut image, width, &black_level);
+    }
+    let image = if apply_opcodes {
+        decoder.apply_opcodes(file_buffer, image)?
+    } else {
+        image
+    };
+
+    Ok(DecodedImage {
+        image,
+        width,
+        height,
+        cfa_pattern,
+        crop,
+        orientation,
+        white_balance,
+        white_level,
+        black_level,
+        baseline_exposure,
+        tone_curve,
+        cam_matrix,
+        color_matrix,
+        is_generic_fallback,
+        parsed_info: decoder.into_info(),
+    })
+}
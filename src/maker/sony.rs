@@ -1,9 +1,13 @@
 use super::*;
 
-use super::{decode_utility::bit_pump::*, decode_utility::lookup_table::*, utility::to_14bit_iter};
+use super::{
+    decode_utility::bit_pump::*, decode_utility::ljpeg::LjpegDecompressor,
+    decode_utility::lookup_table::*, utility::to_14bit_iter, utility::to_16bit_iter,
+};
 use std::cmp;
 
-use super::utility::GetNumFromBytes;
+use super::utility::{self, GetNumFromBytes, RawIfdRules};
+use crate::RawFileReadingError;
 
 use once_cell::sync::Lazy;
 
@@ -32,10 +36,25 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
             0x0100 / width
             0x0101 / height
             0x0102 / bps
-            0x828e / cfa_pattern
-            0x0111 / strip
-            0x0117 / strip_len
+            0x0115? / samples_per_pixel
+            0x828e? / cfa_pattern
+            0x0111? / strip
+            if strip ?
+            {
+                0x0117 / strip_len
+            }
+            else
+            {
+                0x0144 / tile_offsets(tile_offsets_count)
+                0x0145 / tile_byte_counts
+                0x0142 / tile_width
+                0x0143 / tile_len
+            }
             0x7010? / tone_curve_addr
+            0x7038? {
+                u32 + 0 / raw_image_width
+                u32 + 1 / raw_image_height
+            }
             0xc61f? {
                 u32 + 0 / crop_x
                 u32 + 1 / crop_y
@@ -47,15 +66,24 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
         }
         0xc634 {
             sony_decrypt / 0x7200 / 0x7201 / 0x7221 {
-                0x7310 {
+                0x7000? : u16 / raw_file_type
+                0x7300? {
+                    u16 + 0 / sr2_black_level
+                }
+                0x7303? {
+                    u16 + 0 / sr2_white_balance_g
+                    u16 + 1 / sr2_white_balance_r
+                    u16 + 2 / sr2_white_balance_b
+                }
+                0x7310? {
                     u16 + 0 / black_level
                 }
-                0x7312 {
+                0x7312? {
                     u16 + 0 / white_balance_r
                     u16 + 1 / white_balance_g
                     u16 + 3 / white_balance_b
                 }
-                0x787f / legacy_white_level {
+                0x787f? / legacy_white_level {
                     u16 + 0 / white_level
                 }
             }
@@ -63,8 +91,229 @@ pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     })
 });
 
+/// The A100 keeps its white balance in the Minolta blocks of DNGPrivateData instead of
+/// SR2Private
+static A100_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112 / orientation
+        0xc634 / minolta_blocks
+    })
+});
+
+// the tags of the raw IFD of the A100, which is found by its NewSubfileType
+macro_rules! a100_raw_ifd_template {
+    () => {
+        quickexif::describe_rule!(template {
+            0x0100 / width
+            0x0101 / height
+            0x828e? / cfa_pattern
+            0x0111 / strip
+            0x0117 / strip_len
+        })
+    };
+}
+
+static A100_RAW_IFD_RULES: Lazy<RawIfdRules> =
+    Lazy::new(|| raw_ifd_rules!(a100_raw_ifd_template!()));
+
+// the white balance read from the Minolta blocks, see `parse_a100_info`
+static A100_LAYOUT_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0001 / white_balance_r
+        0x0002 / white_balance_g
+        0x0003 / white_balance_b
+    })
+});
+
+/// The rows of the A100 are 3872 pixels apart, no matter the ImageWidth
+const A100_ROW_STRIDE: usize = 3872;
+const A100_WBG: u32 = 0x00574247;
+
+/// The widths of the image data of the SR2 models, whose last columns are not part of the
+/// image, as set by `identify()` of dcraw
+const SR2_IMAGE_WIDTHS: [(&str, u32); 1] = [("DSC-R1", 3925)];
+
+pub(super) fn is_a100(info: &quickexif::ParsedInfo) -> bool {
+    info.str("model").map(|x| x == "DSLR-A100").unwrap_or(false)
+}
+
+/// The SRF files of DSC-F828 and DSC-V3 encrypt their raw IFD and data with keys of their own,
+/// they are not supported
+pub(super) fn is_srf(info: &quickexif::ParsedInfo) -> bool {
+    info.str("model")
+        .map(|x| x == "DSC-F828" || x == "DSC-V3")
+        .unwrap_or(false)
+}
+
+/// The Minolta blocks start with "\0MRI", their names are big endian and their lengths
+/// little endian. The WBG block gives the RGGB levels after 4 bytes.
+fn a100_white_balance(buffer: &[u8]) -> Option<[u16; 3]> {
+    if !buffer.starts_with(b"\0MRI") {
+        return None;
+    }
+    let end = (buffer.get(4..8)?.u32le(0) as usize + 8).min(buffer.len());
+    let mut pos = 8;
+    while pos + 8 <= end {
+        let name = buffer.u32be(pos);
+        let len = buffer.u32le(pos + 4) as usize;
+        if name == A100_WBG {
+            let levels = buffer.get(pos + 12..pos + 20)?;
+            return Some([levels.u16le(0), levels.u16le(2), levels.u16le(6)]);
+        }
+        pos += len + 8;
+    }
+    None
+}
+
+/// The oldest ARW generation is told by the model, its raw IFD and its Minolta blocks are
+/// read instead of the tags of the later bodies
+pub(super) fn parse_a100_info(
+    buffer: &[u8],
+    basic_info: quickexif::ParsedInfo,
+) -> Result<quickexif::ParsedInfo, RawFileReadingError> {
+    let info = quickexif::parse_with_prev_info(buffer, &A100_RULE, basic_info)?;
+    let info = quickexif::parse_with_prev_info(buffer, A100_RAW_IFD_RULES.select(buffer), info)?;
+
+    let [r, g, b] = info
+        .usize("minolta_blocks")
+        .ok()
+        .and_then(|offset| a100_white_balance(buffer.get(offset..)?))
+        .unwrap_or([1, 1, 1]);
+    let white_balance = |x: u16| x as u32 * 512 / g.max(1) as u32;
+    let layout = utility::TiffBuilder::new()
+        .long(0x0001, white_balance(r))
+        .long(0x0002, white_balance(g))
+        .long(0x0003, white_balance(b))
+        .build();
+    Ok(quickexif::parse_with_prev_info(
+        &layout,
+        &A100_LAYOUT_RULE,
+        info,
+    )?)
+}
+
 impl General {
+    fn strip<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        let offset = self.info.usize("strip")?;
+        let len = self.info.usize("strip_len")?;
+        buffer
+            .get(offset..offset + len)
+            .ok_or_else(|| DecodingError::InvalidRawData("ARW strip is out of range".to_owned()))
+    }
+    /// The offset and the size of every tile, a single tile has them as the tag values
+    fn tiles(&self, buffer: &[u8]) -> Result<Vec<(usize, usize)>, DecodingError> {
+        let offsets = self.info.usize("tile_offsets")?;
+        let byte_counts = self.info.usize("tile_byte_counts")?;
+        let count = self.info.usize("tile_offsets_count")?;
+        if count == 1 {
+            return Ok(vec![(offsets, byte_counts)]);
+        }
+
+        let is_le = self.info.is_le;
+        let table = |addr: usize| {
+            buffer
+                .get(addr..addr + count * 4)
+                .map(|x| x.chunks_exact(4).map(move |x| x.u32(is_le, 0) as usize))
+                .ok_or_else(|| {
+                    DecodingError::InvalidRawData("ARW tiles are out of range".to_owned())
+                })
+        };
+        Ok(table(offsets)?.zip(table(byte_counts)?).collect())
+    }
+    /// The lossless files of the M and S sizes are smaller than the sensor, their size is
+    /// given by SonyRawImageSize
+    fn raw_size(&self) -> Result<(usize, usize), DecodingError> {
+        let is_lossless = matches!(self.info.u32("compression"), Ok(7));
+        match (
+            self.info.usize("raw_image_width"),
+            self.info.usize("raw_image_height"),
+        ) {
+            (Ok(width), Ok(height)) if is_lossless && width > 0 && height > 0 => {
+                Ok((width, height))
+            }
+            _ => Ok((self.info.usize("width")?, self.info.usize("height")?)),
+        }
+    }
+    /// Pixel shift composites (ARQ) keep 4 samples per pixel in the order of R, G, G, B
+    fn is_arq(&self) -> bool {
+        self.info
+            .u16("samples_per_pixel")
+            .map(|x| x == 4)
+            .unwrap_or(false)
+    }
+    /// The SR2 files of DSC-R1 keep 14 bit samples in big endian, no matter the byte order of
+    /// the TIFF, and their SR2Private IFD has the older black level and white balance tags
+    fn is_sr2(&self) -> bool {
+        self.sr2_image_width().is_some()
+    }
+    fn sr2_image_width(&self) -> Option<u32> {
+        let model = self.info.str("model").ok()?;
+        SR2_IMAGE_WIDTHS
+            .iter()
+            .find(|&&(x, _)| x == model)
+            .map(|&(_, width)| width)
+    }
+    /// The ARW2 files keep 11 bit deltas which the tone curve expands to 14 bits, whatever
+    /// their BitsPerSample says
+    fn is_arw2(&self) -> bool {
+        matches!(self.info.u32("compression"), Ok(0x7fff))
+            && !self.is_arq()
+            && !self.is_uncompressed()
+    }
+    /// SonyRawFileType 0 and 1 are the uncompressed 14 and 12 bit files, the uncompressed
+    /// files of the high resolution bodies are only told by their Compression
+    fn is_uncompressed(&self) -> bool {
+        matches!(self.info.u16("raw_file_type"), Ok(0 | 1))
+            || matches!(self.info.u32("compression"), Ok(1))
+    }
+    /// Every sample takes a little endian 16 bit container, and the strip of the high
+    /// resolution bodies pads every row, the padding is told by the size of the strip
+    fn load_uncompressed(
+        &self,
+        buffer: &[u8],
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<u16>, DecodingError> {
+        let strip = self.strip(buffer)?;
+        let row_len = width * 2;
+        let stride = (strip.len() / height.max(1)).max(row_len);
+        if strip.len() < stride * (height.max(1) - 1) + row_len {
+            return Err(DecodingError::InvalidRawData(
+                "ARW strip is too short".to_owned(),
+            ));
+        }
+        let mask = ((1u32 << self.info.u32("bps")?.min(16)) - 1) as u16;
+
+        let mut image = Vec::with_capacity(width * height);
+        for row in strip.chunks(stride).take(height) {
+            image.extend(to_16bit_iter(&row[..row_len], self.info.is_le).map(|x| x & mask));
+        }
+        Ok(image)
+    }
+    /// Minolta style 12 bit samples packed in big endian, the last pixels of every row are
+    /// left out of the image
+    fn load_a100(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        if width > A100_ROW_STRIDE {
+            return Err(DecodingError::InvalidRawData(format!(
+                "A100 width {} is wider than its rows",
+                width
+            )));
+        }
+        let strip = self.strip(buffer)?;
+        let row_len = A100_ROW_STRIDE * 3 / 2;
+        let mut image = Vec::with_capacity(width * height);
+        for row in strip.chunks_exact(row_len).take(height) {
+            let row = utility::to_12bit_iter_packed(row, false).take(width);
+            image.extend(row.map(|x| x << 4));
+        }
+        Ok(image)
+    }
     fn get_white_level_scale(&self) -> Result<u16, quickexif::parsed_info::Error> {
+        if self.is_sr2() {
+            return Ok(4);
+        }
         let legacy_white_level = self.info.u16("legacy_white_level")?;
         let result = match legacy_white_level {
             15360 => 4,
@@ -96,7 +345,38 @@ impl RawDecoder for General {
         Ok(result)
     }
 
+    fn get_image_size(&self, _buffer: &[u8]) -> Result<(usize, usize), DecodingError> {
+        self.raw_size()
+    }
+    fn get_white_level(&self) -> u16 {
+        let bps = match self.is_arw2() {
+            true => 14,
+            false => self.info.u16("bps").unwrap_or(16).clamp(1, 16),
+        };
+        let scale = self.get_white_level_scale().unwrap_or(1) as u32;
+        (((1u32 << bps) - 1) * scale).min(u16::MAX as u32) as u16
+    }
+    fn get_white_balance(&self, _buffer: &[u8]) -> Result<[i32; 3], DecodingError> {
+        let wb = |name: &str| {
+            self.info
+                .i32(name)
+                .or_else(|_| self.info.i32(&format!("sr2_{}", name)))
+        };
+        Ok([
+            wb("white_balance_r")?,
+            wb("white_balance_g")?,
+            wb("white_balance_b")?,
+        ])
+    }
     fn get_crop(&self) -> Option<Crop> {
+        if let Some(image_width) = self.sr2_image_width() {
+            return Some(Crop {
+                x: 0,
+                y: 0,
+                width: image_width.min(self.info.u32("width").ok()?),
+                height: self.info.u32("height").ok()?,
+            });
+        }
         let x = self.info.u32("crop_x").ok()?;
         let y = self.info.u32("crop_y").ok()?;
         let width = self.info.u32("crop_w").ok()?;
@@ -109,20 +389,69 @@ impl RawDecoder for General {
             height,
         })
     }
+    /// The A100 has no black level tag, its samples are left as they are
+    fn get_black_level(&self) -> [u16; 4] {
+        if is_a100(&self.info) {
+            return [0; 4];
+        }
+        let black_level = self
+            .info
+            .u16("black_level")
+            .or_else(|_| self.info.u16("sr2_black_level"))
+            .unwrap_or(0);
+        let level_scale = self.get_white_level_scale().unwrap_or(1);
+        [level_scale.saturating_mul(black_level); 4]
+    }
+    fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        if self.is_arq() {
+            return Ok(CFAPattern::None);
+        }
+        if is_a100(&self.info) {
+            return Ok(cfa_pattern_from_info(&self.info).unwrap_or(CFAPattern::GRBG));
+        }
+        cfa_pattern_from_info(&self.info)
+    }
     fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
-        let width = self.info.usize("width")?;
-        let height = self.info.usize("height")?;
-        let black_level = self.info.u16("black_level")?;
-        let strip_offset = self.info.usize("strip")?;
-        let strip_len = self.info.usize("strip_len")?;
+        let (width, height) = self.raw_size()?;
+        if is_a100(&self.info) {
+            let image = self.load_a100(buffer)?;
+            if image.len() != width * height {
+                return Err(DecodingError::InvalidDecodedImageSize(
+                    image.len(),
+                    width * height,
+                ));
+            }
+            return Ok(image);
+        }
         let compression = self.info.u32("compression")?;
         let level_scale = self.get_white_level_scale()?;
-        let buf = &buffer[strip_offset..strip_offset + strip_len];
 
-        let black_level_sub = |v: u16| level_scale.saturating_mul(v.saturating_sub(black_level));
+        let level_scale_up = |v: u16| level_scale.saturating_mul(v);
 
         let image: Vec<u16> = match compression {
+            // every pixel already has all the colors, the two greens are averaged
+            _ if self.is_arq() => to_16bit_iter(self.strip(buffer)?, self.info.is_le)
+                .collect::<Vec<_>>()
+                .chunks_exact(4)
+                .flat_map(|x| [x[0], ((x[1] as u32 + x[2] as u32) / 2) as u16, x[3]])
+                .map(level_scale_up)
+                .collect(),
+            // lossless compressed, every tile is a lossless JPEG
+            7 => {
+                let tiles = self.tiles(buffer)?;
+                let tile_width = self.info.usize("tile_width")?;
+                let tile_len = self.info.usize("tile_len")?;
+                let image = load_lossless(buffer, &tiles, width, height, tile_width, tile_len)?;
+                image.iter().copied().map(level_scale_up).collect()
+            }
+            // the 16 bit containers are read in place, as the strips of 61 MP files are huge
+            _ if self.is_uncompressed() && !self.is_sr2() => {
+                let mut image = self.load_uncompressed(buffer, width, height)?;
+                image.iter_mut().for_each(|x| *x = level_scale_up(*x));
+                image
+            }
             0x7fffu32 => {
+                let buf = self.strip(buffer)?;
                 let tone_curve_addr = self.info.usize("tone_curve_addr")?;
                 let tone_curve = buffer[tone_curve_addr..tone_curve_addr + 8]
                     .chunks_exact(2)
@@ -132,17 +461,20 @@ impl RawDecoder for General {
                 load_raw8(buf, &tone_curve, width, height)
                     .iter()
                     .copied()
-                    .map(black_level_sub)
+                    .map(level_scale_up)
                     .collect()
             }
-            7 => unimplemented!(),
-            _ => to_14bit_iter(buf, self.info.is_le)
-                .map(black_level_sub)
+            _ => to_14bit_iter(self.strip(buffer)?, self.info.is_le && !self.is_sr2())
+                .map(level_scale_up)
                 .collect(),
         };
 
-        if image.len() != width * height {
-            Err(DecodingError::InvalidDecodedImageSize(image.len(), width * height))
+        let sample_count = match self.is_arq() {
+            true => width * height * 3,
+            false => width * height,
+        };
+        if image.len() != sample_count {
+            Err(DecodingError::InvalidDecodedImageSize(image.len(), sample_count))
         } else {
             Ok(image)
         }
@@ -155,11 +487,86 @@ impl RawDecoder for General {
     }
 }
 
+/// Stitches the lossless JPEG tiles into the image, a tile with 4 components keeps every
+/// 2x2 block of the bayer pattern as one sample. The tiles have to cover the image, so
+/// a wrong size is an error instead of wrapped rows.
+fn load_lossless(
+    buffer: &[u8],
+    tiles: &[(usize, usize)],
+    width: usize,
+    height: usize,
+    tile_width: usize,
+    tile_len: usize,
+) -> Result<Vec<u16>, DecodingError> {
+    let mut out = vec![0u16; width * height];
+    let tiles_per_row = width.div_ceil(tile_width.max(1));
+    let tiles_per_column = height.div_ceil(tile_len.max(1));
+    if tiles.len() != tiles_per_row * tiles_per_column {
+        return Err(DecodingError::InvalidRawData(format!(
+            "{} ARW tiles do not cover {}x{} pixels",
+            tiles.len(),
+            width,
+            height
+        )));
+    }
+
+    for (index, &(offset, size)) in tiles.iter().enumerate() {
+        let src = buffer
+            .get(offset..offset + size)
+            .ok_or_else(|| DecodingError::InvalidRawData("ARW tile is out of range".to_owned()))?;
+        let decompressor = LjpegDecompressor::new(src)?;
+        let jpeg_width = decompressor.width();
+        let jpeg_height = decompressor.height();
+        let components = decompressor.components();
+        let (covered_width, covered_height) = match components {
+            4 => (jpeg_width / 2, jpeg_height * 2),
+            _ => (jpeg_width, jpeg_height),
+        };
+        if (covered_width, covered_height) != (tile_width, tile_len) {
+            return Err(DecodingError::InvalidRawData(format!(
+                "ARW tile of {}x{} pixels is not {}x{}",
+                covered_width, covered_height, tile_width, tile_len
+            )));
+        }
+        let mut tile = vec![0u16; jpeg_width * jpeg_height];
+        decompressor.decode(&mut tile, 0, jpeg_width, jpeg_width, jpeg_height)?;
+
+        let tile_x = index % tiles_per_row * tile_width;
+        let tile_y = index / tiles_per_row * tile_len;
+        let mut put = |x: usize, y: usize, value: u16| {
+            let (x, y) = (tile_x + x, tile_y + y);
+            if x < width && y < height && x < tile_x + tile_width && y < tile_y + tile_len {
+                out[y * width + x] = value;
+            }
+        };
+        for (row, data) in tile.chunks_exact(jpeg_width).enumerate() {
+            if components == 4 {
+                for (col, block) in data.chunks_exact(4).enumerate() {
+                    put(col * 2, row * 2, block[0]);
+                    put(col * 2 + 1, row * 2, block[1]);
+                    put(col * 2, row * 2 + 1, block[2]);
+                    put(col * 2 + 1, row * 2 + 1, block[3]);
+                }
+            } else {
+                for (col, &value) in data.iter().enumerate() {
+                    put(col, row, value);
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// The ARW2 tone curve is linear up to the first knot of tag 0x7010, and the step
+/// doubles after every other knot up to 16 in the last segment which ends at 4095.
 fn gen_curve_lut(tone_curve: &[u16]) -> LookupTable {
     let mut curve: [usize; 6] = [0, 0, 0, 0, 0, 4095];
 
     for i in 0..4 {
-        curve[i + 1] = ((tone_curve[i] as u32 >> 2) & 0xfff) as usize;
+        // the knots have to be ascending, otherwise the later segments would be skipped
+        let knot = ((tone_curve[i] as u32 >> 2) & 0xfff) as usize;
+        curve[i + 1] = knot.max(curve[i]);
     }
 
     let mut table = vec![0u16; curve[5] + 1];
@@ -201,6 +608,7 @@ fn load_raw8(buf: &[u8], tone_curve: &[u16], width: usize, height: usize) -> Vec
                         } else if i == imin {
                             min
                         } else {
+                            // the dropped bits are left at 0 like dcraw, LibRaw and rawspeed
                             cmp::min(0x7ff, (pump.get_bits(7) << delta_shift) + min)
                         };
                         out[j + (i * 2)] = curve.dither((val << 1) as u16, &mut random);
@@ -0,0 +1,132 @@
+use super::*;
+use once_cell::sync::Lazy;
+
+use super::utility::*;
+
+pub(super) struct General {
+    info: quickexif::ParsedInfo,
+}
+
+pub(super) static THUMBNAIL_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112? : u16 / orientation
+    })
+});
+
+pub(super) static IMAGE_RULE: Lazy<quickexif::ParsingRule> = Lazy::new(|| {
+    quickexif::describe_rule!(tiff {
+        0x0112? : u16 / orientation
+    })
+});
+
+// the tags of the raw IFD, which is found by its NewSubfileType or by its size
+macro_rules! raw_ifd_template {
+    () => {
+        quickexif::describe_rule!(template {
+            0x0100 / width
+            0x0101 / height
+            0x0102 : u16 / bps
+            0x0103 : u16 / compression
+            0x828d? / cfa_repeat_dim
+            0x828e? / cfa_pattern(cfa_pattern_len)
+            if cfa_pattern ? {
+                if cfa_pattern_len == 36 {
+                    0x828e { // the first row of a 6x6 pattern
+                        u32 + 0 / cfa_pattern_row
+                        u16 + 2 / cfa_pattern_row_tail
+                    }
+                }
+            }
+            0x0111 / strip(strip_count)
+            0x0117 / strip_len
+            0x0116? / rows_per_strip
+        })
+    };
+}
+
+pub(super) static RAW_IFD_RULES: Lazy<RawIfdRules> =
+    Lazy::new(|| raw_ifd_rules!(raw_ifd_template!()));
+
+/// The files of the makers without a decoder are tried as plain TIFF raws
+pub(super) fn is_tiff(buffer: &[u8]) -> bool {
+    buffer.starts_with(b"II*\0") || buffer.starts_with(b"MM\0*")
+}
+
+impl RawDecoder for General {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        General { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    /// Daylight is taken as neutral, as the camera matrix is the identity
+    fn get_white_balance(&self, _buffer: &[u8]) -> Result<[i32; 3], DecodingError> {
+        Ok([512, 512, 512])
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        None
+    }
+    fn get_cfa_pattern(&self) -> Result<CFAPattern, DecodingError> {
+        cfa_pattern_from_info(&self.info)
+    }
+    fn is_generic_fallback(&self) -> bool {
+        true
+    }
+    /// The preview is taken as the largest JPEG of the file
+    fn get_thumbnail<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        crate::decode::largest_jpeg_slice(buffer).ok_or_else(|| {
+            DecodingError::RawInfoError(quickexif::parsed_info::Error::FieldNotFound(
+                "thumbnail".into(),
+            ))
+        })
+    }
+    /// Only the uncompressed strips of a Bayer sensor are read, the samples are scaled to
+    /// 16 bits
+    fn decode_with_preprocess(&self, buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        let width = self.info.usize("width")?;
+        let height = self.info.usize("height")?;
+        let bps = self.info.u16("bps")?;
+        let compression = self.info.u16("compression")?;
+        if compression != 1 {
+            return Err(DecodingError::CompressionNotSupported(format!(
+                "TIFF {}",
+                compression
+            )));
+        }
+        // a CFAPattern tells the raw data from the RGB images
+        self.get_cfa_pattern()?;
+
+        let offsets = self.info.usize("strip")?;
+        let byte_counts = self.info.usize("strip_len")?;
+        let count = self.info.usize("strip_count")?;
+        let strips = if count == 1 {
+            vec![(offsets, byte_counts)]
+        } else {
+            let is_le = self.info.is_le;
+            let table = |addr: usize| {
+                buffer
+                    .get(addr..addr + count * 4)
+                    .map(|x| x.chunks_exact(4).map(move |x| x.u32(is_le, 0) as usize))
+                    .ok_or_else(|| {
+                        DecodingError::InvalidRawData("TIFF strips are out of range".to_owned())
+                    })
+            };
+            table(offsets)?.zip(table(byte_counts)?).collect()
+        };
+        let layout = adobe::Layout {
+            width,
+            height,
+            samples_per_pixel: 1,
+            tiles: strips,
+            tile_width: width,
+            tile_height: self.info.usize("rows_per_strip").unwrap_or(height).max(1),
+        };
+
+        let image = adobe::load_uncompressed(buffer, &layout, bps as u32, self.info.is_le)?;
+        let scale = u16::MAX / ((1u32 << bps.min(16)) - 1) as u16;
+        Ok(image.into_iter().map(|x| scale.saturating_mul(x)).collect())
+    }
+}
@@ -145,35 +145,265 @@ pub(super) fn matrix3_normalize(x: &mut [f32]) {
     });
 }
 
-pub(super) fn matrix3_inverse(x: &mut [f32]) {
-    assert!(x.len() == 9);
-    let m11 = x[0];
-    let m12 = x[3];
-    let m13 = x[6];
+/// Builds a minimal little-endian TIFF with a single IFD, so metadata read from
+/// non-TIFF containers can go through `quickexif` like any other raw file.
+#[derive(Default)]
+pub(super) struct TiffBuilder {
+    entries: Vec<(u16, u16, u32, Vec<u8>)>,
+}
+
+impl TiffBuilder {
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+    pub(super) fn short(mut self, tag: u16, value: u16) -> Self {
+        self.entries.push((tag, 3, 1, value.to_le_bytes().to_vec()));
+        self
+    }
+    pub(super) fn long(mut self, tag: u16, value: u32) -> Self {
+        self.entries.push((tag, 4, 1, value.to_le_bytes().to_vec()));
+        self
+    }
+    pub(super) fn srational(mut self, tag: u16, values: &[f32]) -> Self {
+        let data = values
+            .iter()
+            .flat_map(|x| [(x * 10000.0).round() as i32, 10000])
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        self.entries.push((tag, 10, values.len() as u32, data));
+        self
+    }
+    pub(super) fn ascii(mut self, tag: u16, value: &str) -> Self {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        self.entries.push((tag, 2, data.len() as u32, data));
+        self
+    }
+    pub(super) fn build(mut self) -> Vec<u8> {
+        self.entries.sort_by_key(|&(tag, ..)| tag);
+
+        let ifd_len = 2 + self.entries.len() * 12 + 4;
+        let mut out = vec![0x49, 0x49, 0x2a, 0x00, 0x08, 0x00, 0x00, 0x00];
+        let mut data = vec![];
+        out.extend((self.entries.len() as u16).to_le_bytes());
+        for (tag, tiff_type, count, value) in self.entries {
+            out.extend(tag.to_le_bytes());
+            out.extend(tiff_type.to_le_bytes());
+            out.extend(count.to_le_bytes());
+            if value.len() <= 4 {
+                let mut inline = [0u8; 4];
+                inline[..value.len()].copy_from_slice(&value);
+                out.extend(inline);
+            } else {
+                out.extend(((8 + ifd_len + data.len()) as u32).to_le_bytes());
+                data.extend(value);
+                if data.len() % 2 == 1 {
+                    data.push(0);
+                }
+            }
+        }
+        out.extend(0u32.to_le_bytes());
+        out.extend(data);
+        out.extend([0u8; 16]);
+        out
+    }
+}
+
+/// The IFD holding the raw data of a TIFF based file, the SubIFDs are those of IFD0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum RawIfd {
+    Ifd0,
+    SubIfd { index: usize, count: usize },
+    Ifd1,
+}
+
+/// An IFD which may hold the raw data, with the tags telling it from the previews
+struct IfdCandidate {
+    place: RawIfd,
+    sub_file_type: Option<usize>,
+    photometric: Option<u16>,
+    data_len: usize,
+}
+
+/// A tag as its id, type, count and the position of its value
+type IfdTag = (u16, u16, usize, usize);
+
+/// Reads the tags of the IFD at `offset` and the offset of the next IFD
+fn read_ifd(buffer: &[u8], is_le: bool, offset: usize) -> Option<(Vec<IfdTag>, usize)> {
+    let count = buffer.get(offset..offset + 2)?.u16(is_le, 0) as usize;
+    let entries = buffer.get(offset + 2..offset + 2 + count * 12)?;
+    let tags = entries
+        .chunks_exact(12)
+        .enumerate()
+        .map(|(i, entry)| {
+            let tiff_type = entry.u16(is_le, 2);
+            let count = entry.u32(is_le, 4) as usize;
+            let size = match tiff_type {
+                3 | 8 => 2,
+                4 | 9 | 11 | 13 => 4,
+                5 | 10 | 12 => 8,
+                _ => 1,
+            };
+            let value = offset + 2 + i * 12 + 8;
+            let value = if size * count <= 4 {
+                value
+            } else {
+                entry.u32(is_le, 8) as usize
+            };
+            (entry.u16(is_le, 0), tiff_type, count, value)
+        })
+        .collect();
+    let next = offset + 2 + count * 12;
+    let next = buffer
+        .get(next..next + 4)
+        .map_or(0, |x| x.u32(is_le, 0) as usize);
+    Some((tags, next))
+}
 
-    let m21 = x[1];
-    let m22 = x[4];
-    let m23 = x[7];
+fn ifd_candidate(buffer: &[u8], is_le: bool, offset: usize, place: RawIfd) -> Option<IfdCandidate> {
+    let (tags, _) = read_ifd(buffer, is_le, offset)?;
+    let value = |tag: u16, index: usize| -> Option<usize> {
+        let &(_, tiff_type, count, pos) = tags.iter().find(|x| x.0 == tag)?;
+        if index >= count {
+            return None;
+        }
+        let size = if tiff_type == 3 { 2 } else { 4 };
+        let bytes = buffer.get(pos + index * size..pos + (index + 1) * size)?;
+        match size {
+            2 => Some(bytes.u16(is_le, 0) as usize),
+            _ => Some(bytes.u32(is_le, 0) as usize),
+        }
+    };
+    let data_len = [0x0117, 0x0145]
+        .iter()
+        .filter_map(|&tag| {
+            let &(.., count, _) = tags.iter().find(|x| x.0 == tag)?;
+            Some((0..count).filter_map(|i| value(tag, i)).sum::<usize>())
+        })
+        .sum();
+    Some(IfdCandidate {
+        place,
+        sub_file_type: value(0x00fe, 0),
+        photometric: value(0x0106, 0).map(|x| x as u16),
+        data_len,
+    })
+}
 
-    let m31 = x[2];
-    let m32 = x[5];
-    let m33 = x[8];
+/// Finds the full resolution raw data by its NewSubfileType of 0 among IFD0, its SubIFDs
+/// and IFD1, skipping the RGB and YCbCr previews. The IFD with the most strip or tile data
+/// is taken when none of them is marked as raw.
+pub(super) fn find_raw_ifd(buffer: &[u8]) -> Option<RawIfd> {
+    let is_le = match buffer.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let ifd0 = buffer.get(4..8)?.u32(is_le, 0) as usize;
+    let (tags, ifd1) = read_ifd(buffer, is_le, ifd0)?;
 
-    let minor_m12_m23 = m22 * m33 - m32 * m23;
-    let minor_m11_m23 = m21 * m33 - m31 * m23;
-    let minor_m11_m22 = m21 * m32 - m31 * m22;
+    let mut candidates = vec![ifd_candidate(buffer, is_le, ifd0, RawIfd::Ifd0)?];
+    if let Some(&(.., count, pos)) = tags.iter().find(|x| x.0 == 0x014a) {
+        for index in 0..count {
+            let place = RawIfd::SubIfd { index, count };
+            let offset = buffer
+                .get(pos + index * 4..pos + index * 4 + 4)
+                .map(|x| x.u32(is_le, 0) as usize);
+            candidates.extend(offset.and_then(|x| ifd_candidate(buffer, is_le, x, place)));
+        }
+    }
+    if ifd1 != 0 {
+        candidates.extend(ifd_candidate(buffer, is_le, ifd1, RawIfd::Ifd1));
+    }
 
-    let determinant = m11 * minor_m12_m23 - m12 * minor_m11_m23 + m13 * minor_m11_m22;
+    let is_raw = |x: &&IfdCandidate| {
+        x.sub_file_type == Some(0) && !matches!(x.photometric, Some(2 | 6)) && x.data_len > 0
+    };
+    candidates
+        .iter()
+        .find(is_raw)
+        .or_else(|| candidates.iter().max_by_key(|x| x.data_len))
+        .map(|x| x.place)
+}
 
-    x[0] = minor_m12_m23 / determinant;
-    x[1] = (m13 * m32 - m33 * m12) / determinant;
-    x[2] = (m12 * m23 - m22 * m13) / determinant;
+/// The rules reading the tags of the raw IFD wherever it is, the first four SubIFDs can
+/// be reached. They are built by `raw_ifd_rules!`.
+pub(super) struct RawIfdRules {
+    pub(super) ifd0: quickexif::ParsingRule,
+    pub(super) single_sub_ifd: quickexif::ParsingRule,
+    pub(super) sub_ifds: [quickexif::ParsingRule; 4],
+    pub(super) ifd1: quickexif::ParsingRule,
+}
 
-    x[3] = -minor_m11_m23 / determinant;
-    x[4] = (m11 * m33 - m31 * m13) / determinant;
-    x[5] = (m13 * m21 - m23 * m11) / determinant;
+/// Builds the `RawIfdRules` of a template rule holding the tags of the raw IFD
+macro_rules! raw_ifd_rules {
+    ($template:expr) => {{
+        let (t0, t1, t2, t3, t4, t5, t6) = (
+            $template, $template, $template, $template, $template, $template, $template,
+        );
+        super::utility::RawIfdRules {
+            ifd0: quickexif::describe_rule!(tiff {
+                load(t0)
+            }),
+            single_sub_ifd: quickexif::describe_rule!(tiff {
+                0x014a {
+                    load(t1)
+                }
+            }),
+            sub_ifds: [
+                quickexif::describe_rule!(tiff {
+                    0x014a {
+                        offset address {
+                            load(t2)
+                        }
+                    }
+                }),
+                quickexif::describe_rule!(tiff {
+                    0x014a {
+                        offset + 4 {
+                            offset address {
+                                load(t3)
+                            }
+                        }
+                    }
+                }),
+                quickexif::describe_rule!(tiff {
+                    0x014a {
+                        offset + 8 {
+                            offset address {
+                                load(t4)
+                            }
+                        }
+                    }
+                }),
+                quickexif::describe_rule!(tiff {
+                    0x014a {
+                        offset + 12 {
+                            offset address {
+                                load(t5)
+                            }
+                        }
+                    }
+                }),
+            ],
+            ifd1: quickexif::describe_rule!(tiff {
+                next {
+                    load(t6)
+                }
+            }),
+        }
+    }};
+}
 
-    x[6] = minor_m11_m22 / determinant;
-    x[7] = (m12 * m31 - m32 * m11) / determinant;
-    x[8] = (m11 * m22 - m21 * m12) / determinant;
+impl RawIfdRules {
+    /// The rule of the raw IFD of the file, IFD0 is assumed when it cannot be found
+    pub(super) fn select(&self, buffer: &[u8]) -> &quickexif::ParsingRule {
+        match find_raw_ifd(buffer) {
+            Some(RawIfd::SubIfd { count: 1, .. }) => &self.single_sub_ifd,
+            Some(RawIfd::SubIfd { index, .. }) if index < self.sub_ifds.len() => {
+                &self.sub_ifds[index]
+            }
+            Some(RawIfd::Ifd1) => &self.ifd1,
+            _ => &self.ifd0,
+        }
+    }
 }
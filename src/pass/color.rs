@@ -1,21 +1,45 @@
 #![allow(dead_code)]
 
+use crate::{GamutMapping, ToneMapping, TransferFunction};
 use std::cmp;
 
 const BIT_SHIFT: u32 = 13u32;
 const CLIP_LIMIT_I32: i32 = 65535;
 const CLIP_RANGE: (i32, i32) = (0, CLIP_LIMIT_I32);
 
+/// The value multiplied by the fixed point multiplier, in 64 bits so that the multipliers of 4
+/// or more don't overflow, saturated to the range of `i32`
+#[inline(always)]
+fn scale(value: i32, multiplier: i32) -> i32 {
+    ((value as i64 * multiplier as i64) >> BIT_SHIFT).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// The row of the fixed point matrix multiplied by the color, in 64 bits like `scale`
+#[inline(always)]
+fn mix(row: &[i32], rgb: [i32; 3]) -> i32 {
+    let sum = row.iter().zip(rgb).map(|(&c, x)| c as i64 * x as i64).sum::<i64>();
+    (sum >> BIT_SHIFT).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
 #[inline(always)]
 pub fn white_balance_fix<'a>(
     iter: impl Iterator<Item = [i32; 3]> + 'a,
     white_balance: &'a [i32; 3],
 ) -> impl Iterator<Item = [i32; 3]> + 'a {
-    iter.map(move |[r, g, b]| {
-        let r = cmp::min((r * white_balance[0]) >> BIT_SHIFT, CLIP_LIMIT_I32);
-        let g = cmp::min((g * white_balance[1]) >> BIT_SHIFT, CLIP_LIMIT_I32);
-        let b = cmp::min((b * white_balance[2]) >> BIT_SHIFT, CLIP_LIMIT_I32);
-        [r, g, b]
+    iter.map(move |pixel| {
+        [0, 1, 2].map(|c| cmp::min(scale(pixel[c], white_balance[c]), CLIP_LIMIT_I32))
+    })
+}
+
+/// The white balance of the values which clip at `limit`
+#[inline(always)]
+pub fn white_balance_clip_fix<'a>(
+    iter: impl Iterator<Item = [i32; 3]> + 'a,
+    white_balance: &'a [i32; 3],
+    limit: &'a i32,
+) -> impl Iterator<Item = [i32; 3]> + 'a {
+    iter.map(move |pixel| {
+        [0, 1, 2].map(|c| cmp::min(scale(pixel[c], white_balance[c]), *limit))
     })
 }
 
@@ -26,13 +50,59 @@ pub fn color_convert<'a>(
 ) -> impl Iterator<Item = [u16; 3]> + 'a {
     iter.map(move |[r, g, b]| {
         [
-            limit_to_range((c[0] * r + c[1] * g + c[2] * b) >> BIT_SHIFT, CLIP_RANGE) as u16,
-            limit_to_range((c[3] * r + c[4] * g + c[5] * b) >> BIT_SHIFT, CLIP_RANGE) as u16,
-            limit_to_range((c[6] * r + c[7] * g + c[8] * b) >> BIT_SHIFT, CLIP_RANGE) as u16,
+            limit_to_range(mix(&c[0..3], [r, g, b]), CLIP_RANGE) as u16,
+            limit_to_range(mix(&c[3..6], [r, g, b]), CLIP_RANGE) as u16,
+            limit_to_range(mix(&c[6..9], [r, g, b]), CLIP_RANGE) as u16,
         ]
     })
 }
 
+/// Converts the colors by the matrix and maps the ones outside of the range into it
+#[inline(always)]
+pub fn color_convert_gamut<'a>(
+    iter: impl Iterator<Item = [i32; 3]> + 'a,
+    c: &'a [i32; 9],
+    gamut_mapping: GamutMapping,
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    iter.map(move |[r, g, b]| {
+        let rgb = [0, 3, 6].map(|i| mix(&c[i..i + 3], [r, g, b]));
+        match gamut_mapping {
+            GamutMapping::Clip => rgb.map(|x| limit_to_range(x, CLIP_RANGE) as u16),
+            _ => map_gamut(rgb.map(|x| x as f32 / 65535.), gamut_mapping)
+                .map(|x| (x * 65535.).round() as u16),
+        }
+    })
+}
+
+/// Maps the color, where 1 is white, from 0 to 1 by the gamut mapping. The chroma is scaled
+/// toward the Rec. 709 luma, which is clipped first, by how far it reaches to the border.
+pub fn map_gamut(rgb: [f32; 3], gamut_mapping: GamutMapping) -> [f32; 3] {
+    const KNEE: f32 = 0.8;
+    let [r, g, b] = rgb;
+    let luma = (0.2126 * r + 0.7152 * g + 0.0722 * b).clamp(0.0, 1.0);
+    // 1 on the border of the gamut, infinite when only gray fits at the luma
+    let reach = rgb
+        .into_iter()
+        .map(|x| match x - luma {
+            d if d > 0.0 => d / (1.0 - luma),
+            d if d < 0.0 => -d / luma,
+            _ => 0.0,
+        })
+        .fold(0.0, f32::max);
+    let scale = match gamut_mapping {
+        GamutMapping::Clip => 1.0,
+        GamutMapping::DesaturateToFit => 1.0 / reach.max(1.0),
+        GamutMapping::Perceptual if reach <= KNEE => 1.0,
+        GamutMapping::Perceptual => {
+            // the shoulder has the slope 1 at the knee and reaches the border at infinity
+            let over = reach - KNEE;
+            let compressed = KNEE + (1.0 - KNEE) * (1.0 - (1.0 - KNEE) / (over + 1.0 - KNEE));
+            compressed / reach
+        }
+    };
+    rgb.map(|x| (luma + (x - luma) * scale).clamp(0.0, 1.0))
+}
+
 #[inline(always)]
 pub fn color_convert_rgba<'a>(
     iter: impl Iterator<Item = [i32; 3]> + 'a,
@@ -40,9 +110,9 @@ pub fn color_convert_rgba<'a>(
 ) -> impl Iterator<Item = [u16; 4]> + 'a {
     iter.map(move |[r, g, b]| {
         [
-            limit_to_range((c[0] * r + c[1] * g + c[2] * b) >> BIT_SHIFT, CLIP_RANGE) as u16,
-            limit_to_range((c[3] * r + c[4] * g + c[5] * b) >> BIT_SHIFT, CLIP_RANGE) as u16,
-            limit_to_range((c[6] * r + c[7] * g + c[8] * b) >> BIT_SHIFT, CLIP_RANGE) as u16,
+            limit_to_range(mix(&c[0..3], [r, g, b]), CLIP_RANGE) as u16,
+            limit_to_range(mix(&c[3..6], [r, g, b]), CLIP_RANGE) as u16,
+            limit_to_range(mix(&c[6..9], [r, g, b]), CLIP_RANGE) as u16,
             u16::MAX
         ]
     })
@@ -62,17 +132,416 @@ pub fn gamma_correct<'a>(
     })
 }
 
+#[inline(always)]
+pub fn tone_curve_correct<'a>(
+    iter: impl Iterator<Item = [u16; 3]> + 'a,
+    tone_lut: &'a [u16],
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    iter.map(|[r, g, b]| {
+        [
+            tone_lut[r as usize],
+            tone_lut[g as usize],
+            tone_lut[b as usize],
+        ]
+    })
+}
+
+/// Maps the red, green and blue values by their own lookup tables
+#[inline(always)]
+pub fn channel_curve_correct<'a>(
+    iter: impl Iterator<Item = [u16; 3]> + 'a,
+    [r_lut, g_lut, b_lut]: &'a [Vec<u16>; 3],
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    iter.map(move |[r, g, b]| [r_lut[r as usize], g_lut[g as usize], b_lut[b as usize]])
+}
+
+/// Mixes the colors with their Rec. 709 luma, which is kept, by the saturation, 1 keeps the
+/// colors and 0 leaves the luma only. The vibrance adds to it by how unsaturated the colors
+/// are, which are decoded by `linear_lut` for it, up to the border of the range.
+#[inline(always)]
+pub fn saturate<'a>(
+    iter: impl Iterator<Item = [u16; 3]> + 'a,
+    saturation: f32,
+    vibrance: f32,
+    linear_lut: &'a [f32],
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    iter.map(move |rgb| {
+        let [r, g, b] = rgb.map(|x| x as f32);
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let scale = if vibrance == 0.0 {
+            saturation
+        } else {
+            let linear = rgb.map(|x| linear_lut[x as usize]);
+            // the scale of the chroma which reaches the border of the range
+            let reach = rgb
+                .into_iter()
+                .map(|x| match x as f32 - luma {
+                    d if d > 0.0 => (65535. - luma) / d,
+                    d if d < 0.0 => -luma / d,
+                    _ => f32::INFINITY,
+                })
+                .fold(f32::INFINITY, f32::min);
+            let scale = saturation * vibrance_scale(linear, vibrance);
+            scale.min(reach.max(saturation))
+        };
+        rgb.map(|x| {
+            let x = luma + (x as f32 - luma) * scale;
+            x.round().clamp(0., 65535.) as u16
+        })
+    })
+}
+
+/// The scale of the chroma of the linear color by the vibrance, which weights it by how
+/// unsaturated the color is and rolls it off around the hues of skin
+pub fn vibrance_scale([r, g, b]: [f32; 3], vibrance: f32) -> f32 {
+    const SKIN_HUE: f32 = 25.0;
+    const SKIN_WIDTH: f32 = 35.0;
+    const SKIN_PROTECTION: f32 = 0.7;
+    let max = r.max(g).max(b);
+    if max <= 0.0 {
+        return 1.0;
+    }
+    let saturation = (max - r.min(g).min(b)) / max;
+    // the hue in degrees, 0 for red, 60 for yellow and 120 for green
+    let hue = (3f32.sqrt() * (g - b)).atan2(2.0 * r - g - b).to_degrees();
+    let skin = (1.0 - (hue - SKIN_HUE).abs() / SKIN_WIDTH).max(0.0);
+    let weight = (1.0 - saturation) * (1.0 - SKIN_PROTECTION * skin * skin);
+    (1.0 + vibrance * weight).max(0.0)
+}
+
+/// Looks up the colors in the 3D LUT, which is left out when there is none
+#[inline(always)]
+pub fn lut3d_correct<'a>(
+    iter: impl Iterator<Item = [u16; 3]> + 'a,
+    lut3d: Option<&'a crate::Lut3d>,
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    iter.map(move |rgb| match lut3d {
+        Some(lut3d) => lut3d
+            .apply(rgb.map(|x| x as f32 / 65535.))
+            .map(|x| (x * 65535.).round().clamp(0., 65535.) as u16),
+        None => rgb,
+    })
+}
+
+/// The lookup table of every 16 bit value from the tone curve, whose entries are spread evenly
+/// over the range and interpolated linearly. None for the curves of fewer than 2 entries.
+pub fn gen_tone_curve_lut(tone_curve: &[u16]) -> Option<Vec<u16>> {
+    if tone_curve.len() < 2 {
+        return None;
+    }
+    let step = (tone_curve.len() - 1) as f64 / 65535.;
+    let lut = (0..65536)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = (position as usize).min(tone_curve.len() - 2);
+            let t = position - index as f64;
+            let (a, b) = (tone_curve[index] as f64, tone_curve[index + 1] as f64);
+            (a + (b - a) * t).round() as u16
+        })
+        .collect();
+    Some(lut)
+}
+
+/// The lookup table of the contrast of the encoded values, an S-curve through `middle_gray` of
+/// the slope of `1 + contrast`, which keeps them at 0 and makes them flat at -1. The curve is a
+/// power on both sides of middle gray.
+pub fn gen_contrast_lut(contrast: f32, middle_gray: f32) -> Vec<u16> {
+    let middle_gray = middle_gray.clamp(0.001, 0.999);
+    let slope = (1. + contrast).max(0.);
+    (0..65536)
+        .map(|i| {
+            let x = i as f32 / 65535.;
+            let y = if x < middle_gray {
+                middle_gray * (x / middle_gray).powf(slope)
+            } else {
+                1. - (1. - middle_gray) * ((1. - x) / (1. - middle_gray)).powf(slope)
+            };
+            (y * 65535.).round() as u16
+        })
+        .collect()
+}
+
 #[inline(always)]
 pub fn gen_gamma_lut(gamma: f32) -> [u16; 65536] {
+    gen_exposure_gamma_lut(gamma, 0.0)
+}
+
+/// The gamma curve of the linear values multiplied by `2^exposure_ev`, which clip at the white
+/// point
+#[inline(always)]
+pub fn gen_exposure_gamma_lut(gamma: f32, exposure_ev: f32) -> [u16; 65536] {
+    gen_exposure_curve_lut(&[gamma, 0.0, 0.0, 0.0], exposure_ev)
+}
+
+/// The transfer curve of `gen_power_curve` for the linear values multiplied by `2^exposure_ev`,
+/// which clip at the white point
+#[inline(always)]
+pub fn gen_exposure_curve_lut(curve: &[f32; 4], exposure_ev: f32) -> [u16; 65536] {
+    gen_exposure_transfer_lut(|l| encode_curve(l, curve), exposure_ev)
+}
+
+/// The transfer function `encode` from 0 to 1 of the linear values multiplied by
+/// `2^exposure_ev`, which clip at the white point. It is evaluated once for every value of
+/// the table.
+pub fn gen_exposure_transfer_lut(encode: impl Fn(f32) -> f32, exposure_ev: f32) -> [u16; 65536] {
+    gen_tone_mapped_transfer_lut(encode, exposure_ev, ToneMapping::None)
+}
+
+/// The transfer function `encode` of the linear values multiplied by `2^exposure_ev`, which
+/// are compressed from 0 to 1 by the tone mapping
+pub fn gen_tone_mapped_transfer_lut(
+    encode: impl Fn(f32) -> f32,
+    exposure_ev: f32,
+    tone_mapping: ToneMapping,
+) -> [u16; 65536] {
+    let factor = 2f32.powf(exposure_ev);
     let mut lut = [0u16; 65536];
     for (i, elem) in lut.iter_mut().enumerate() {
-        let l = i as f32 / 65535.;
-        *elem = (l.powf(gamma) * 65535.) as u16;
+        let l = tone_map(i as f32 * factor / 65535., tone_mapping);
+        *elem = (encode(l).clamp(0.0, 1.0) * 65535.) as u16;
     }
     lut
 }
 
+/// Compresses the linear value, where 1 is the white level, from 0 to 1 by the tone mapping
+#[inline(always)]
+pub fn tone_map(l: f32, tone_mapping: ToneMapping) -> f32 {
+    const MIDDLE_GRAY: f32 = 0.18;
+    match tone_mapping {
+        ToneMapping::None => l.min(1.0),
+        ToneMapping::Filmic { contrast, shoulder } => {
+            let exponent = (1.0 + contrast).max(0.0);
+            let toe = |l: f32| MIDDLE_GRAY * (l / MIDDLE_GRAY).powf(exponent);
+            // the extended Reinhard curve, which is white at the shoulder
+            let (l, white) = (toe(l), toe(shoulder.max(1.0)));
+            (l * (1.0 + l / (white * white)) / (1.0 + l)).min(1.0)
+        }
+        ToneMapping::Aces => {
+            (l * (2.51 * l + 0.03) / (l * (2.43 * l + 0.59) + 0.14)).clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// The power curve of `exponent` with a linear toe of `toe_slope`, like the gamma curves of
+/// dcraw. The toe ends where the curve continues smoothly, as the exponent, the slope, the end
+/// of the toe and the offset of the power segment. A pure power curve without a slope.
+pub fn gen_power_curve(exponent: f32, toe_slope: f32) -> [f32; 4] {
+    let (exponent, toe_slope) = (exponent as f64, toe_slope as f64);
+    if toe_slope <= 0.0 || exponent <= 0.0 || (toe_slope - 1.0) * (exponent - 1.0) > 0.0 {
+        return [exponent as f32, 0.0, 0.0, 0.0];
+    }
+    // bisects the value of the toe where its end meets the power segment
+    let mut bounds = match toe_slope >= 1.0 {
+        true => [0.0, 1.0],
+        false => [1.0, 0.0],
+    };
+    let mut toe_value = 0.0;
+    for _ in 0..48 {
+        toe_value = (bounds[0] + bounds[1]) / 2.0;
+        let is_above =
+            ((toe_value / toe_slope).powf(-exponent) - 1.0) / exponent - 1.0 / toe_value > -1.0;
+        bounds[is_above as usize] = toe_value;
+    }
+    let toe_end = toe_value / toe_slope;
+    let offset = toe_value * (1.0 / exponent - 1.0);
+    [
+        exponent as f32,
+        toe_slope as f32,
+        toe_end as f32,
+        offset as f32,
+    ]
+}
+
+/// The curve of `gen_power_curve` of the transfer function, the custom, the log and the HDR
+/// ones are sampled instead and left linear here
+pub fn transfer_curve(transfer_function: TransferFunction) -> [f32; 4] {
+    match transfer_function {
+        TransferFunction::PowerCurve([exponent, toe_slope]) => gen_power_curve(exponent, toe_slope),
+        TransferFunction::Srgb => [1.0 / 2.4, 12.92, 0.0031308, 0.055],
+        // the toe solved at full precision gives the constants of BT.2020 as well
+        TransferFunction::Rec709 | TransferFunction::Rec2020 => gen_power_curve(0.45, 4.5),
+        TransferFunction::Linear
+        | TransferFunction::Log { .. }
+        | TransferFunction::FlatLog
+        | TransferFunction::Pq { .. }
+        | TransferFunction::Hlg
+        | TransferFunction::Custom(_) => gen_power_curve(1.0, 0.0),
+    }
+}
+
+/// Encodes the linear value from 0 to 1 by the transfer function, `curve` is its
+/// `transfer_curve`
+#[inline(always)]
+pub fn encode_transfer(l: f32, transfer_function: TransferFunction, curve: &[f32; 4]) -> f32 {
+    match transfer_function {
+        TransferFunction::Custom(encode) => encode(l),
+        TransferFunction::Log {
+            black_offset,
+            slope,
+        } => encode_log(l, black_offset, slope),
+        TransferFunction::FlatLog => encode_log(l, 0.08, 0.8),
+        TransferFunction::Pq { peak_nits } => encode_pq(l, peak_nits),
+        TransferFunction::Hlg => encode_hlg(l),
+        _ => encode_curve(l, curve),
+    }
+}
+
+/// Encodes the linear value from 0 to 1 by the log curve of `TransferFunction::Log`
+#[inline(always)]
+pub fn encode_log(l: f32, black_offset: f32, slope: f32) -> f32 {
+    1.0 + slope * ((l + black_offset) / (1.0 + black_offset)).log10()
+}
+
+/// Encodes the linear value from 0 to 1 by the PQ curve of SMPTE ST 2084, 1 is `peak_nits`
+#[inline(always)]
+pub fn encode_pq(l: f32, peak_nits: f32) -> f32 {
+    const M1: f32 = 2610. / 16384.;
+    const M2: f32 = 2523. / 4096. * 128.;
+    const C1: f32 = 3424. / 4096.;
+    const C2: f32 = 2413. / 4096. * 32.;
+    const C3: f32 = 2392. / 4096. * 32.;
+    let y = (l * peak_nits / 10000.).max(0.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+/// Encodes the linear value from 0 to 1 by the HLG curve of BT.2100
+#[inline(always)]
+pub fn encode_hlg(l: f32) -> f32 {
+    const A: f32 = 0.17883277;
+    const B: f32 = 1. - 4. * A;
+    const C: f32 = 0.5599107;
+    if l <= 1. / 12. {
+        (3.0 * l.max(0.0)).sqrt()
+    } else {
+        A * (12.0 * l - B).ln() + C
+    }
+}
+
+/// Encodes the linear value from 0 to 1 by the curve of `gen_power_curve`
+#[inline(always)]
+pub fn encode_curve(l: f32, &[exponent, toe_slope, toe_end, offset]: &[f32; 4]) -> f32 {
+    if l < toe_end {
+        l * toe_slope
+    } else {
+        l.powf(exponent) * (1.0 + offset) - offset
+    }
+}
+
+/// Decodes the value from 0 to 1 encoded by the curve of `gen_power_curve` to the linear one
+#[inline(always)]
+pub fn decode_curve(v: f32, &[exponent, toe_slope, toe_end, offset]: &[f32; 4]) -> f32 {
+    if v < toe_end * toe_slope {
+        v / toe_slope
+    } else {
+        ((v + offset) / (1.0 + offset)).powf(1.0 / exponent)
+    }
+}
+
+/// The lookup table from the values encoded by the curve `from` to the ones encoded by `to`,
+/// both of `gen_power_curve`
+pub fn gen_curve_conversion_lut(from: &[f32; 4], to: &[f32; 4]) -> Vec<u16> {
+    (0..=u16::MAX)
+        .map(|i| match from == to {
+            true => i,
+            false => {
+                let l = decode_curve(i as f32 / 65535., from);
+                (encode_curve(l, to).clamp(0.0, 1.0) * 65535.).round() as u16
+            }
+        })
+        .collect()
+}
+
 #[inline(always)]
 fn limit_to_range<T: Ord>(v: T, (left, right): (T, T)) -> T {
     cmp::min(cmp::max(v, left), right)
 }
+
+/// Estimates the white balance of a demosaiced image by the gray world, the multipliers of red,
+/// green and blue relative to green make the means of the channels equal. The pixels near the
+/// maximum of the image may be clipped and the ones near black are mostly noise, both are left
+/// out. None when no pixel is left or a channel is black.
+pub fn gray_world(pixels: &[[u16; 3]]) -> Option<[f32; 3]> {
+    let max = pixels.iter().flatten().copied().max()? as u32;
+    let (low, high) = (max / 50, max - max / 20);
+    let (sums, count) = pixels
+        .iter()
+        .filter(|pixel| pixel.iter().all(|&v| (low..high).contains(&(v as u32))))
+        .fold(([0u64; 3], 0u64), |(sums, count), pixel| {
+            ([0, 1, 2].map(|c| sums[c] + pixel[c] as u64), count + 1)
+        });
+    if count == 0 || sums.contains(&0) {
+        return None;
+    }
+    Some(sums.map(|sum| sums[1] as f32 / sum as f32))
+}
+
+/// The white balance which renders the patch of a demosaiced image around the point neutral,
+/// the multipliers of red, green and blue relative to green make the means of the channels of
+/// the pixels up to `radius` away equal. The pixels where a channel reaches the white level are
+/// left out. None when the point is outside of the image, no pixel is left or a channel is
+/// black.
+pub fn patch_white_balance(
+    pixels: &[[u16; 3]],
+    width: usize,
+    (x, y): (usize, usize),
+    radius: usize,
+    white_level: u16,
+) -> Option<[f32; 3]> {
+    let means = patch_mean(pixels, width, (x, y), radius, white_level)?;
+    Some(means.map(|mean| means[1] / mean))
+}
+
+/// The means of the channels of the pixels around `(x, y)` within `radius`, the ones which
+/// clip at `white_level` are left out. None when the point is outside of the image, no pixel
+/// is left or a channel is black.
+pub fn patch_mean(
+    pixels: &[[u16; 3]],
+    width: usize,
+    (x, y): (usize, usize),
+    radius: usize,
+    white_level: u16,
+) -> Option<[f32; 3]> {
+    let height = pixels.len() / width.max(1);
+    if x >= width || y >= height {
+        return None;
+    }
+    let columns = x.saturating_sub(radius)..cmp::min(x + radius + 1, width);
+    let (sums, count) = (y.saturating_sub(radius)..cmp::min(y + radius + 1, height))
+        .flat_map(|row| columns.clone().map(move |column| pixels[row * width + column]))
+        .filter(|pixel| pixel.iter().all(|&v| v < white_level))
+        .fold(([0u64; 3], 0u64), |(sums, count), pixel| {
+            ([0, 1, 2].map(|c| sums[c] + pixel[c] as u64), count + 1)
+        });
+    if count == 0 || sums.contains(&0) {
+        return None;
+    }
+    Some(sums.map(|sum| sum as f32 / count as f32))
+}
+
+const MAX_AUTO_BRIGHTNESS_EV: f32 = 3.0;
+
+/// The exposure in EV which brings the `percentile` of the luminance of the white balanced
+/// pixels to white, from 0 to 3 EV. The luminance is weighted like Rec. 709.
+pub fn auto_brightness_ev(pixels: &[[u16; 3]], white_balance: &[i32; 3], percentile: f32) -> f32 {
+    let mut histogram = vec![0usize; CLIP_LIMIT_I32 as usize + 1];
+    for pixel in pixels {
+        let [r, g, b] = [0, 1, 2]
+            .map(|c| cmp::min(scale(pixel[c] as i32, white_balance[c]), CLIP_LIMIT_I32));
+        histogram[((r * 2126 + g * 7152 + b * 722) / 10000) as usize] += 1;
+    }
+    let count = (pixels.len() as f32 * percentile.clamp(0.0, 100.0) / 100.0).ceil() as usize;
+    let mut sum = 0;
+    let luminance = histogram
+        .iter()
+        .position(|x| {
+            sum += x;
+            sum >= count.max(1)
+        })
+        .unwrap_or(0);
+    if luminance == 0 {
+        return MAX_AUTO_BRIGHTNESS_EV;
+    }
+    (CLIP_LIMIT_I32 as f32 / luminance as f32)
+        .log2()
+        .clamp(0.0, MAX_AUTO_BRIGHTNESS_EV)
+}
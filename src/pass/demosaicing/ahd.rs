@@ -0,0 +1,206 @@
+use super::*;
+
+// the rows demosaiced at once, the planes of a band hold 3 more rows and columns on every side
+const BAND_HEIGHT: usize = 64;
+const MARGIN: usize = 3;
+
+const HORIZONTAL: usize = 0;
+const VERTICAL: usize = 1;
+
+/// The image with the out of range coordinates mirrored, which keeps their CFA color
+struct Mosaic<'a> {
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    colors: [usize; 4],
+}
+
+impl Mosaic<'_> {
+    #[inline(always)]
+    fn get(&self, x: isize, y: isize) -> i32 {
+        get_mirrored_pixel(self.image, self.width, self.height, x, y) as i32
+    }
+    #[inline(always)]
+    fn color(&self, x: isize, y: isize) -> usize {
+        self.colors[((y & 1) * 2 + (x & 1)) as usize]
+    }
+}
+
+/// The planes of both directions for the rows of a band, indexed by `(x + MARGIN, row)`
+struct Planes {
+    width: usize,
+    green: [Vec<i32>; 2],
+    rgb: [Vec<[i32; 3]>; 2],
+    lab: [Vec<[f32; 3]>; 2],
+    homogeneity: [Vec<u8>; 2],
+}
+
+/// Adaptive Homogeneity-Directed demosaicing of a bayer image.
+///
+/// Green is interpolated horizontally and vertically, red and blue follow the green of each
+/// direction. Every pixel takes the direction whose neighbors are closer in CIELab, or the
+/// average of both when they are equally homogeneous.
+pub(super) fn demosaic(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: [usize; 4],
+) -> Vec<[u16; 3]> {
+    let mosaic = Mosaic {
+        image,
+        width,
+        height,
+        colors,
+    };
+    let mut out = vec![[0u16; 3]; width * height];
+    if out.is_empty() {
+        return out;
+    }
+    let cbrt = cbrt_lut();
+
+    for (band, out) in out.chunks_mut(width * BAND_HEIGHT).enumerate() {
+        let y0 = (band * BAND_HEIGHT) as isize - MARGIN as isize;
+        let rows = out.len() / width + MARGIN * 2;
+        let planes_width = width + MARGIN * 2;
+        let len = planes_width * rows;
+        let mut planes = Planes {
+            width: planes_width,
+            green: [vec![0; len], vec![0; len]],
+            rgb: [vec![[0; 3]; len], vec![[0; 3]; len]],
+            lab: [vec![[0.0; 3]; len], vec![[0.0; 3]; len]],
+            homogeneity: [vec![0; len], vec![0; len]],
+        };
+
+        for dir in [HORIZONTAL, VERTICAL] {
+            interpolate_green(&mosaic, &mut planes, dir, y0, rows);
+            interpolate_red_blue(&mosaic, &mut planes, dir, y0, rows, &cbrt);
+        }
+        build_homogeneity_maps(&mut planes, rows);
+
+        for (row, out) in out.chunks_exact_mut(width).enumerate() {
+            for (x, out) in out.iter_mut().enumerate() {
+                *out = select_direction(&planes, x + MARGIN, row + MARGIN);
+            }
+        }
+    }
+    out
+}
+
+fn interpolate_green(mosaic: &Mosaic, planes: &mut Planes, dir: usize, y0: isize, rows: usize) {
+    let (dx, dy) = if dir == HORIZONTAL { (1, 0) } else { (0, 1) };
+    for row in 0..rows {
+        let y = y0 + row as isize;
+        for px in 0..planes.width {
+            let x = px as isize - MARGIN as isize;
+            let v = mosaic.get(x, y);
+            planes.green[dir][row * planes.width + px] = if mosaic.color(x, y) == 1 {
+                v
+            } else {
+                let n1 = mosaic.get(x - dx, y - dy);
+                let n2 = mosaic.get(x + dx, y + dy);
+                let f1 = mosaic.get(x - 2 * dx, y - 2 * dy);
+                let f2 = mosaic.get(x + 2 * dx, y + 2 * dy);
+                let g = ((n1 + v + n2) * 2 - f1 - f2) >> 2;
+                g.clamp(n1.min(n2), n1.max(n2))
+            };
+        }
+    }
+}
+
+fn interpolate_red_blue(
+    mosaic: &Mosaic,
+    planes: &mut Planes,
+    dir: usize,
+    y0: isize,
+    rows: usize,
+    cbrt: &[f32],
+) {
+    let w = planes.width;
+    for row in 1..rows - 1 {
+        let y = y0 + row as isize;
+        for px in 1..w - 1 {
+            let x = px as isize - MARGIN as isize;
+            let i = row * w + px;
+            let green = &planes.green[dir];
+            let g = green[i];
+            // the difference of a CFA sample to the green interpolated at its place
+            let diff = |dx: isize, dy: isize| {
+                let j = (i as isize + dy * w as isize + dx) as usize;
+                mosaic.get(x + dx, y + dy) - green[j]
+            };
+
+            let mut rgb = [0i32; 3];
+            rgb[1] = g;
+            match mosaic.color(x, y) {
+                1 => {
+                    rgb[mosaic.color(x + 1, y)] = g + ((diff(-1, 0) + diff(1, 0)) >> 1);
+                    rgb[mosaic.color(x, y + 1)] = g + ((diff(0, -1) + diff(0, 1)) >> 1);
+                }
+                c => {
+                    rgb[c] = mosaic.get(x, y);
+                    let diagonals = diff(-1, -1) + diff(1, -1) + diff(-1, 1) + diff(1, 1);
+                    rgb[2 - c] = g + ((diagonals + 1) >> 2);
+                }
+            }
+            let rgb = rgb.map(|v| v.clamp(0, u16::MAX as i32));
+            planes.rgb[dir][i] = rgb;
+            planes.lab[dir][i] = to_lab(rgb, cbrt);
+        }
+    }
+}
+
+fn build_homogeneity_maps(planes: &mut Planes, rows: usize) {
+    let w = planes.width;
+    let neighbors = [-1, 1, -(w as isize), w as isize];
+    for row in 2..rows - 2 {
+        for px in 2..w - 2 {
+            let i = row * w + px;
+            let mut l_diff = [[0f32; 4]; 2];
+            let mut ab_diff = [[0f32; 4]; 2];
+            for dir in [HORIZONTAL, VERTICAL] {
+                let [l, a, b] = planes.lab[dir][i];
+                for (k, offset) in neighbors.iter().enumerate() {
+                    let [nl, na, nb] = planes.lab[dir][(i as isize + offset) as usize];
+                    l_diff[dir][k] = (l - nl).abs();
+                    ab_diff[dir][k] = (a - na).powi(2) + (b - nb).powi(2);
+                }
+            }
+            // the horizontal interpolation is judged by its left and right neighbors and
+            // the vertical one by its upper and lower neighbors
+            let l_eps = l_diff[HORIZONTAL][0]
+                .max(l_diff[HORIZONTAL][1])
+                .min(l_diff[VERTICAL][2].max(l_diff[VERTICAL][3]));
+            let ab_eps = ab_diff[HORIZONTAL][0]
+                .max(ab_diff[HORIZONTAL][1])
+                .min(ab_diff[VERTICAL][2].max(ab_diff[VERTICAL][3]));
+            for dir in [HORIZONTAL, VERTICAL] {
+                planes.homogeneity[dir][i] = (0..4)
+                    .filter(|&k| l_diff[dir][k] <= l_eps && ab_diff[dir][k] <= ab_eps)
+                    .count() as u8;
+            }
+        }
+    }
+}
+
+fn select_direction(planes: &Planes, px: usize, row: usize) -> [u16; 3] {
+    let w = planes.width;
+    let [h, v] = [HORIZONTAL, VERTICAL].map(|dir| {
+        let mut sum = 0u32;
+        for y in row - 1..=row + 1 {
+            for x in px - 1..=px + 1 {
+                sum += planes.homogeneity[dir][y * w + x] as u32;
+            }
+        }
+        sum
+    });
+
+    let i = row * w + px;
+    let [rgb_h, rgb_v] = [planes.rgb[HORIZONTAL][i], planes.rgb[VERTICAL][i]];
+    if h > v {
+        rgb_h.map(|x| x as u16)
+    } else if h < v {
+        rgb_v.map(|x| x as u16)
+    } else {
+        [0, 1, 2].map(|c| ((rgb_h[c] + rgb_v[c]) >> 1) as u16)
+    }
+}
@@ -0,0 +1,737 @@
+use super::*;
+
+// the tiles are demosaiced apart, only their inner part is kept as the outer rows and columns
+// lack the neighbors of the wider interpolations
+const TS: usize = 160;
+const BORDER: usize = 16;
+const STEP: usize = TS - BORDER * 2;
+
+// the offsets to the neighbors in a tile, `P` along the rising and `M` along the falling diagonal
+const V1: isize = TS as isize;
+const V2: isize = V1 * 2;
+const P1: isize = 1 - V1;
+const P2: isize = P1 * 2;
+const M1: isize = 1 + V1;
+const M2: isize = M1 * 2;
+
+const EPS: f32 = 1e-5;
+const EPS_SQ: f32 = 1e-10;
+// the ratios of the colors are trusted while they are closer to 1
+const AR_THRESHOLD: f32 = 0.75;
+// the test of the Nyquist texture weighs the gradients by it
+const NYQUIST_THRESHOLD: f32 = 0.5;
+// the values are normalized, beyond 0.8 Hamilton-Adams replaces the ratios
+const CLIP: f32 = 1.0;
+const NEAR_CLIP: f32 = CLIP * 0.8;
+
+const GAUSS_ODD: [f32; 4] = [0.146_597_28, 0.103_592_71, 0.073_203_61, 0.036_554_355];
+const GAUSS_GRAD: [f32; 6] = [
+    0.073_844_12,
+    0.062_075_12,
+    0.052_181_82,
+    0.036_874_194,
+    0.030_997_322,
+    0.018_413_194,
+];
+const GAUSS_EVEN: [f32; 2] = [0.137_194_94, 0.056_402_527];
+const GAUSS_QUINCUNX: [f32; 4] = [0.169_917, 0.108_947, 0.069_855, 0.028_718_2];
+
+#[inline(always)]
+fn at(i: usize, offset: isize) -> usize {
+    (i as isize + offset) as usize
+}
+
+/// `x` limited to the range of `a` and `b`
+#[inline(always)]
+fn limit(x: f32, a: f32, b: f32) -> f32 {
+    x.clamp(a.min(b), a.max(b))
+}
+
+/// The coordinate `v` of an axis of `n` pixels mirrored as many times as it takes, which keeps
+/// the CFA color even on the images narrower than the borders of a tile
+#[inline(always)]
+fn reflect(v: isize, n: usize) -> usize {
+    let period = 2 * (n as isize - 1);
+    if period == 0 {
+        return 0;
+    }
+    let v = v.rem_euclid(period);
+    (if v > period / 2 { period - v } else { v }) as usize
+}
+
+/// The planes of a tile indexed by `row * TS + column`, most of them are only set at the red
+/// and blue or only at the green pixels
+struct Tile {
+    colors: [usize; 4],
+    // the position of the tile in the image, which starts `BORDER` pixels before the image
+    x0: isize,
+    y0: isize,
+    rows: usize,
+    cols: usize,
+    cfa: Vec<f32>,
+    green: Vec<f32>,
+    // the weights of the vertical and horizontal directions by their gradients
+    vertical_weights: Vec<f32>,
+    horizontal_weights: Vec<f32>,
+    // the sum of the squared horizontal and vertical gradients
+    gradients_squared: Vec<f32>,
+    // the gradients along the rising and the falling diagonal at red and blue
+    rising: Vec<f32>,
+    falling: Vec<f32>,
+    // the squared differences to the diagonal neighbors at green
+    rising_squared: Vec<f32>,
+    falling_squared: Vec<f32>,
+    // green minus the other color by the vertical and horizontal interpolations, by the ratios
+    // of the colors and by Hamilton-Adams
+    vcd: Vec<f32>,
+    hcd: Vec<f32>,
+    vcd_alt: Vec<f32>,
+    hcd_alt: Vec<f32>,
+    // the squared differences of the interpolations from both sides of each axis
+    vertical_fluctuations: Vec<f32>,
+    horizontal_fluctuations: Vec<f32>,
+    // the squared difference of the vertical and horizontal color differences at red and blue
+    cd_difference_squared: Vec<f32>,
+    // the weight of the vertical interpolation at red and blue
+    vertical_weight: Vec<f32>,
+    nyquist: Vec<bool>,
+    nyquist_area: Vec<bool>,
+    // the horizontal and vertical curvature of green at red and blue in Nyquist texture
+    curvature: Vec<[f32; 2]>,
+    // green minus the color at red and blue
+    green_difference: Vec<f32>,
+    // the other color at red and blue along the falling and the rising diagonal, and the
+    // weight of the rising one
+    falling_color: Vec<f32>,
+    rising_color: Vec<f32>,
+    rising_weight: Vec<f32>,
+    // the average of red and blue at red and blue
+    red_blue: Vec<f32>,
+    // green minus red and green minus blue
+    chroma: [Vec<f32>; 2],
+}
+
+/// Aliasing Minimization and Zipper Elimination of Emil Martinec, for the bayer images.
+///
+/// Green is interpolated by the ratios of the colors or by Hamilton-Adams along the axis whose
+/// color differences vary less, with the areas of Nyquist texture weighed by their curvature
+/// and a correction along the diagonals. The color differences to green are interpolated along
+/// the diagonals and the axes at last. The image is demosaiced in tiles, whose bands run on
+/// every available thread.
+pub(super) fn demosaic(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: [usize; 4],
+) -> Vec<[u16; 3]> {
+    let mut out = vec![[0u16; 3]; width * height];
+    if out.is_empty() {
+        return out;
+    }
+
+    let mut bands = out.chunks_mut(width * STEP).enumerate().collect::<Vec<_>>();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if threads == 1 || bands.len() == 1 {
+        demosaic_bands(&mut bands, image, width, height, colors);
+    } else {
+        let per_thread = bands.len().div_ceil(threads);
+        std::thread::scope(|scope| {
+            for bands in bands.chunks_mut(per_thread) {
+                scope.spawn(move || demosaic_bands(bands, image, width, height, colors));
+            }
+        });
+    }
+    out
+}
+
+/// Demosaics the tiles of the bands of `STEP` rows, each with its index
+fn demosaic_bands(
+    bands: &mut [(usize, &mut [[u16; 3]])],
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: [usize; 4],
+) {
+    let mut tile = Tile::new(colors);
+    for (band, out) in bands.iter_mut() {
+        for x in (0..width).step_by(STEP) {
+            tile.load(image, width, height, x, *band * STEP);
+            tile.demosaic();
+            tile.store(out, width, x);
+        }
+    }
+}
+
+impl Tile {
+    fn new(colors: [usize; 4]) -> Self {
+        let plane = || vec![0f32; TS * TS];
+        Tile {
+            colors,
+            x0: 0,
+            y0: 0,
+            rows: 0,
+            cols: 0,
+            cfa: plane(),
+            green: plane(),
+            vertical_weights: plane(),
+            horizontal_weights: plane(),
+            gradients_squared: plane(),
+            rising: plane(),
+            falling: plane(),
+            rising_squared: plane(),
+            falling_squared: plane(),
+            vcd: plane(),
+            hcd: plane(),
+            vcd_alt: plane(),
+            hcd_alt: plane(),
+            vertical_fluctuations: plane(),
+            horizontal_fluctuations: plane(),
+            cd_difference_squared: plane(),
+            vertical_weight: plane(),
+            nyquist: vec![false; TS * TS],
+            nyquist_area: vec![false; TS * TS],
+            curvature: vec![[0.0; 2]; TS * TS],
+            green_difference: plane(),
+            falling_color: plane(),
+            rising_color: plane(),
+            rising_weight: plane(),
+            red_blue: plane(),
+            chroma: [plane(), plane()],
+        }
+    }
+
+    #[inline(always)]
+    fn color(&self, row: usize, col: usize) -> usize {
+        let (y, x) = (self.y0 + row as isize, self.x0 + col as isize);
+        self.colors[((y & 1) * 2 + (x & 1)) as usize]
+    }
+
+    /// The indexes and the colors of the pixels at least `inset` pixels inside the tile
+    fn pixels(&self, inset: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (inset..self.rows.saturating_sub(inset)).flat_map(move |row| {
+            (inset..self.cols.saturating_sub(inset))
+                .map(move |col| (row * TS + col, self.color(row, col)))
+        })
+    }
+
+    /// The indexes and the colors of the red and blue pixels at least `inset` pixels inside
+    fn red_blue_pixels(&self, inset: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.pixels(inset).filter(|&(_, c)| c != 1)
+    }
+
+    /// Loads the tile whose inner part starts at `(x, y)` of the image
+    fn load(&mut self, image: &[u16], width: usize, height: usize, x: usize, y: usize) {
+        self.x0 = x as isize - BORDER as isize;
+        self.y0 = y as isize - BORDER as isize;
+        self.cols = (width - x + BORDER * 2).min(TS);
+        self.rows = (height - y + BORDER * 2).min(TS);
+
+        for plane in [
+            &mut self.vertical_weights,
+            &mut self.horizontal_weights,
+            &mut self.gradients_squared,
+            &mut self.rising,
+            &mut self.falling,
+            &mut self.rising_squared,
+            &mut self.falling_squared,
+            &mut self.vcd,
+            &mut self.hcd,
+            &mut self.vcd_alt,
+            &mut self.hcd_alt,
+            &mut self.vertical_fluctuations,
+            &mut self.horizontal_fluctuations,
+            &mut self.cd_difference_squared,
+            &mut self.vertical_weight,
+            &mut self.green_difference,
+            &mut self.falling_color,
+            &mut self.rising_color,
+            &mut self.rising_weight,
+            &mut self.red_blue,
+        ]
+        .into_iter()
+        .chain(&mut self.chroma)
+        {
+            plane.fill(0.0);
+        }
+        self.nyquist.fill(false);
+        self.nyquist_area.fill(false);
+        self.curvature.fill([0.0; 2]);
+
+        for row in 0..self.rows {
+            let y = reflect(self.y0 + row as isize, height);
+            for col in 0..self.cols {
+                let x = reflect(self.x0 + col as isize, width);
+                let v = get_pixel(image, y * width + x) as f32 / u16::MAX as f32;
+                self.cfa[row * TS + col] = v;
+                self.green[row * TS + col] = v;
+            }
+        }
+    }
+
+    /// Stores the inner part of the tile into the rows of a band starting at column `x`
+    fn store(&self, out: &mut [[u16; 3]], width: usize, x: usize) {
+        let to_u16 = |v: f32| (v * u16::MAX as f32).round().clamp(0.0, u16::MAX as f32) as u16;
+        for (row, out) in out.chunks_exact_mut(width).enumerate() {
+            let row = row + BORDER;
+            for col in BORDER..self.cols - BORDER {
+                let i = row * TS + col;
+                let g = self.green[i];
+                let rgb = match self.color(row, col) {
+                    1 => {
+                        // the differences of the red and blue neighbors, weighted by the axis
+                        let weights = [-V1, -1, 1, V1].map(|d| {
+                            let w = self.vertical_weight[at(i, d)];
+                            if d.abs() == 1 {
+                                1.0 - w
+                            } else {
+                                w
+                            }
+                        });
+                        let sum = weights.iter().sum::<f32>();
+                        let [r, b] = [0, 1].map(|k| {
+                            let chroma = &self.chroma[k];
+                            [-V1, -1, 1, V1]
+                                .iter()
+                                .zip(weights)
+                                .map(|(&d, w)| w * chroma[at(i, d)])
+                                .sum::<f32>()
+                                / sum
+                        });
+                        [g - r, g, g - b]
+                    }
+                    c => {
+                        let mut rgb = [g - self.chroma[0][i], g, g - self.chroma[1][i]];
+                        rgb[c] = self.cfa[i];
+                        rgb
+                    }
+                };
+                out[x + col - BORDER] = rgb.map(to_u16);
+            }
+        }
+    }
+
+    fn demosaic(&mut self) {
+        self.measure_gradients();
+        self.interpolate_color_differences();
+        self.bound_color_differences();
+        self.weigh_axes();
+        self.find_nyquist_texture();
+        self.interpolate_green();
+        self.weigh_diagonals();
+        self.correct_green_along_diagonals();
+        self.interpolate_chroma();
+    }
+
+    fn measure_gradients(&mut self) {
+        for (i, c) in self.pixels(2).collect::<Vec<_>>() {
+            let cfa = &self.cfa;
+            let v = |d: isize| cfa[at(i, d)];
+            let horizontal = (v(1) - v(-1)).abs();
+            let vertical = (v(V1) - v(-V1)).abs();
+            self.vertical_weights[i] =
+                EPS + (v(V2) - v(0)).abs() + (v(0) - v(-V2)).abs() + vertical;
+            self.horizontal_weights[i] =
+                EPS + (v(2) - v(0)).abs() + (v(0) - v(-2)).abs() + horizontal;
+            self.gradients_squared[i] = horizontal * horizontal + vertical * vertical;
+            if c == 1 {
+                self.rising_squared[i] = (v(0) - v(-P1)).powi(2) + (v(0) - v(P1)).powi(2);
+                self.falling_squared[i] = (v(0) - v(-M1)).powi(2) + (v(0) - v(M1)).powi(2);
+            } else {
+                self.rising[i] = (v(P1) - v(-P1)).abs();
+                self.falling[i] = (v(M1) - v(-M1)).abs();
+            }
+        }
+    }
+
+    /// Green minus the other color of both axes, by the ratios of the colors and by
+    /// Hamilton-Adams
+    fn interpolate_color_differences(&mut self) {
+        for (i, c) in self.pixels(4).collect::<Vec<_>>() {
+            let cfa = &self.cfa;
+            let v = |d: isize| cfa[at(i, d)];
+            let [vw, hw] = [&self.vertical_weights, &self.horizontal_weights];
+
+            // the ratio of the neighbor to the pixel along `d`, weighted by the gradients
+            let ratio = |weights: &[f32], d: isize| {
+                let (w0, w2) = (weights[i], weights[at(i, 2 * d)]);
+                v(d) * (w2 + w0) / (w2 * (EPS + v(0)) + w0 * (EPS + v(2 * d)))
+            };
+            let hamilton_adams = |d: isize| v(d) + (v(0) - v(2 * d)) / 2.0;
+            let by_ratio = |ratio: f32, d: isize| {
+                if (1.0 - ratio).abs() < AR_THRESHOLD {
+                    v(0) * ratio
+                } else {
+                    hamilton_adams(d)
+                }
+            };
+
+            let [mut up, mut down] = [(vw, -V1), (vw, V1)].map(|(w, d)| by_ratio(ratio(w, d), d));
+            let [mut left, mut right] = [(hw, -1), (hw, 1)].map(|(w, d)| by_ratio(ratio(w, d), d));
+            let [up_ha, down_ha, left_ha, right_ha] = [-V1, V1, -1, 1].map(hamilton_adams);
+
+            let horizontal_weight = hw[at(i, -1)] / (hw[at(i, -1)] + hw[at(i, 1)]);
+            let vertical_weight = vw[at(i, -V1)] / (vw[at(i, V1)] + vw[at(i, -V1)]);
+
+            let vertical_ha = vertical_weight * down_ha + (1.0 - vertical_weight) * up_ha;
+            let horizontal_ha = horizontal_weight * right_ha + (1.0 - horizontal_weight) * left_ha;
+            let vertical = vertical_weight * down + (1.0 - vertical_weight) * up;
+            let horizontal = horizontal_weight * right + (1.0 - horizontal_weight) * left;
+
+            // green minus the other color, at green the interpolation is of the other color
+            let sign = if c == 1 { -1.0 } else { 1.0 };
+            self.vcd[i] = sign * (vertical - v(0));
+            self.hcd[i] = sign * (horizontal - v(0));
+            self.vcd_alt[i] = sign * (vertical_ha - v(0));
+            self.hcd_alt[i] = sign * (horizontal_ha - v(0));
+
+            if v(0) > NEAR_CLIP || vertical_ha > NEAR_CLIP || horizontal_ha > NEAR_CLIP {
+                // the ratios are unreliable near the highlights
+                (up, down, left, right) = (up_ha, down_ha, left_ha, right_ha);
+                self.vcd[i] = self.vcd_alt[i];
+                self.hcd[i] = self.hcd_alt[i];
+            }
+
+            self.vertical_fluctuations[i] = (up_ha - down_ha).powi(2).min((up - down).powi(2));
+            self.horizontal_fluctuations[i] =
+                (left_ha - right_ha).powi(2).min((left - right).powi(2));
+        }
+    }
+
+    /// Takes the smoother of both interpolations and bounds them in the saturated areas
+    fn bound_color_differences(&mut self) {
+        for (i, c) in self.pixels(4).collect::<Vec<_>>() {
+            for (cd, cd_alt, d) in [
+                (&mut self.hcd, &self.hcd_alt, 1),
+                (&mut self.vcd, &self.vcd_alt, V1),
+            ] {
+                let variance = |cd: &[f32]| {
+                    let [a, b, c] = [-2 * d, 0, 2 * d].map(|o| cd[at(i, o)]);
+                    3.0 * (a * a + b * b + c * c) - (a + b + c).powi(2)
+                };
+                if variance(cd_alt) < variance(cd) {
+                    cd[i] = cd_alt[i];
+                }
+
+                let v = self.cfa[i];
+                let neighbors = (self.cfa[at(i, -d)], self.cfa[at(i, d)]);
+                // green at red and blue, or the other color at green
+                let (sign, estimate) = if c == 1 {
+                    (-1.0, v - cd[i])
+                } else {
+                    (1.0, v + cd[i])
+                };
+                let bounded = sign * (limit(estimate, neighbors.0, neighbors.1) - v);
+                if sign * cd[i] < 0.0 {
+                    cd[i] = if -3.0 * sign * cd[i] > estimate + v {
+                        bounded
+                    } else {
+                        let w = 1.0 + 3.0 * sign * cd[i] / (EPS + estimate + v);
+                        w * cd[i] + (1.0 - w) * bounded
+                    };
+                }
+                if estimate > CLIP {
+                    cd[i] = bounded;
+                }
+            }
+            if c != 1 {
+                self.cd_difference_squared[i] = (self.vcd[i] - self.hcd[i]).powi(2);
+            }
+        }
+    }
+
+    /// The weight of the vertical interpolation at red and blue by the variances of the color
+    /// differences and the fluctuations of the interpolations
+    fn weigh_axes(&mut self) {
+        for (i, _) in self.red_blue_pixels(6).collect::<Vec<_>>() {
+            let variance = |cd: &[f32], d: isize| {
+                let values = [0, d, 2 * d, 3 * d].map(|o| cd[at(i, o)]);
+                let average = values.iter().sum::<f32>() / 4.0;
+                values.iter().map(|x| (x - average).powi(2)).sum::<f32>()
+            };
+            let sum = |plane: &[f32], d: isize| plane[i] + plane[at(i, d)] + plane[at(i, 2 * d)];
+            let [hw, vw] = [&self.horizontal_weights, &self.vertical_weights];
+            let horizontal_weight = hw[at(i, -1)] / (hw[at(i, -1)] + hw[at(i, 1)]);
+            let vertical_weight = vw[at(i, -V1)] / (vw[at(i, V1)] + vw[at(i, -V1)]);
+            let weigh =
+                |w: f32, (before, after): (f32, f32)| EPS_SQ + w * after + (1.0 - w) * before;
+
+            let vertical_variance = weigh(
+                vertical_weight,
+                (variance(&self.vcd, -V1), variance(&self.vcd, V1)),
+            );
+            let horizontal_variance = weigh(
+                horizontal_weight,
+                (variance(&self.hcd, -1), variance(&self.hcd, 1)),
+            );
+            let vertical_fluctuation = weigh(
+                vertical_weight,
+                (
+                    sum(&self.vertical_fluctuations, -V1),
+                    sum(&self.vertical_fluctuations, V1),
+                ),
+            );
+            let horizontal_fluctuation = weigh(
+                horizontal_weight,
+                (
+                    sum(&self.horizontal_fluctuations, -1),
+                    sum(&self.horizontal_fluctuations, 1),
+                ),
+            );
+
+            let by_variance = horizontal_variance / (vertical_variance + horizontal_variance);
+            let by_fluctuation =
+                horizontal_fluctuation / (vertical_fluctuation + horizontal_fluctuation);
+            // when both agree take the stronger discrimination, else the fluctuations decide
+            self.vertical_weight[i] = if (0.5 - by_variance) * (0.5 - by_fluctuation) > 0.0
+                && (0.5 - by_fluctuation).abs() < (0.5 - by_variance).abs()
+            {
+                by_variance
+            } else {
+                by_fluctuation
+            };
+        }
+    }
+
+    fn find_nyquist_texture(&mut self) {
+        for (i, _) in self.red_blue_pixels(6).collect::<Vec<_>>() {
+            let cd = |d: isize| self.cd_difference_squared[at(i, d)];
+            let gradients = |d: isize| self.gradients_squared[at(i, d)];
+            let texture = GAUSS_ODD[0] * cd(0)
+                + GAUSS_ODD[1] * (cd(-M1) + cd(P1) + cd(-P1) + cd(M1))
+                + GAUSS_ODD[2] * (cd(-V2) + cd(-2) + cd(2) + cd(V2))
+                + GAUSS_ODD[3] * (cd(-M2) + cd(P2) + cd(-P2) + cd(M2));
+            let gradient = GAUSS_GRAD[0] * gradients(0)
+                + GAUSS_GRAD[1] * (gradients(-V1) + gradients(1) + gradients(-1) + gradients(V1))
+                + GAUSS_GRAD[2] * (gradients(-M1) + gradients(P1) + gradients(-P1) + gradients(M1))
+                + GAUSS_GRAD[3] * (gradients(-V2) + gradients(-2) + gradients(2) + gradients(V2))
+                + GAUSS_GRAD[4]
+                    * [
+                        -V2 - 1,
+                        -V2 + 1,
+                        -V1 - 2,
+                        -V1 + 2,
+                        V1 - 2,
+                        V1 + 2,
+                        V2 - 1,
+                        V2 + 1,
+                    ]
+                    .map(gradients)
+                    .iter()
+                    .sum::<f32>()
+                + GAUSS_GRAD[5] * (gradients(-M2) + gradients(P2) + gradients(-P2) + gradients(M2));
+            self.nyquist[i] = texture - NYQUIST_THRESHOLD * gradient > 0.0;
+        }
+
+        // the pixels most of whose neighbors are in Nyquist texture are too
+        for (i, _) in self.red_blue_pixels(8).collect::<Vec<_>>() {
+            let count = [-V2, -M1, P1, -2, 0, 2, -P1, M1, V2]
+                .iter()
+                .filter(|&&d| self.nyquist[at(i, d)])
+                .count();
+            self.nyquist_area[i] = count > 4;
+        }
+
+        // the weight of the axes in Nyquist texture by the variances of the whole area
+        for (i, _) in self.red_blue_pixels(8).collect::<Vec<_>>() {
+            if !self.nyquist_area[i] {
+                continue;
+            }
+            let (mut sums, mut squares, mut count) = ([0f32; 2], [0f32; 2], 0f32);
+            for dy in (-6..=6).step_by(2) {
+                for dx in (-6..=6).step_by(2) {
+                    let j = at(i, dy * V1 + dx);
+                    if !self.nyquist_area[j] {
+                        continue;
+                    }
+                    let v = |d: isize| self.cfa[at(j, d)];
+                    for (k, d) in [1, V1].into_iter().enumerate() {
+                        sums[k] += v(0) - (v(-d) + v(d)) / 2.0;
+                        squares[k] += ((v(0) - v(-d)).powi(2) + (v(0) - v(d)).powi(2)) / 2.0;
+                    }
+                    count += 1.0;
+                }
+            }
+            let [horizontal, vertical] =
+                [0, 1].map(|k| EPS_SQ + (count * squares[k] - sums[k] * sums[k]).max(0.0));
+            self.vertical_weight[i] = horizontal / (vertical + horizontal);
+        }
+    }
+
+    fn interpolate_green(&mut self) {
+        for (i, _) in self.red_blue_pixels(8).collect::<Vec<_>>() {
+            // the diagonal neighbors may discriminate the axes better
+            let weights = &self.vertical_weight;
+            let alternative = (weights[at(i, -M1)]
+                + weights[at(i, P1)]
+                + weights[at(i, -P1)]
+                + weights[at(i, M1)])
+                / 4.0;
+            if (0.5 - weights[i]).abs() < (0.5 - alternative).abs() {
+                self.vertical_weight[i] = alternative;
+            }
+            let w = self.vertical_weight[i];
+            self.green_difference[i] = self.hcd[i] * (1.0 - w) + self.vcd[i] * w;
+            self.green[i] = self.cfa[i] + self.green_difference[i];
+
+            if self.nyquist_area[i] {
+                let g = |d: isize| self.green[at(i, d)];
+                self.curvature[i] = [1, V1].map(|d| (g(0) - (g(-d) + g(d)) / 2.0).powi(2));
+            }
+        }
+
+        // in Nyquist texture the axes are weighted by the curvature of green
+        for (i, _) in self.red_blue_pixels(12).collect::<Vec<_>>() {
+            if !self.nyquist_area[i] {
+                continue;
+            }
+            let [horizontal, vertical] = [0, 1].map(|k| {
+                let c = |d: isize| self.curvature[at(i, d)][k];
+                EPS_SQ
+                    + GAUSS_QUINCUNX[0] * c(0)
+                    + GAUSS_QUINCUNX[1] * (c(-M1) + c(P1) + c(-P1) + c(M1))
+                    + GAUSS_QUINCUNX[2] * (c(-V2) + c(-2) + c(2) + c(V2))
+                    + GAUSS_QUINCUNX[3] * (c(-M2) + c(P2) + c(-P2) + c(M2))
+            });
+            self.green_difference[i] =
+                (self.hcd[i] * vertical + self.vcd[i] * horizontal) / (vertical + horizontal);
+            self.green[i] = self.cfa[i] + self.green_difference[i];
+        }
+    }
+
+    /// The other color at red and blue along both diagonals, and the weight of the rising one
+    fn weigh_diagonals(&mut self) {
+        for (i, _) in self.red_blue_pixels(6).collect::<Vec<_>>() {
+            let cfa = &self.cfa;
+            let v = |d: isize| cfa[at(i, d)];
+            // the other color from the diagonal neighbor along `d`
+            let estimate = |d: isize| {
+                let ratio = 2.0 * v(d) / (EPS + v(0) + v(2 * d));
+                if (1.0 - ratio).abs() < AR_THRESHOLD {
+                    v(0) * ratio
+                } else {
+                    v(d) + (v(0) - v(2 * d)) / 2.0
+                }
+            };
+            let weight = |gradients: &[f32], d: isize| {
+                EPS + gradients[i] + gradients[at(i, d)] + gradients[at(i, 2 * d)]
+            };
+            let [south_east, north_west] =
+                [M1, -M1].map(|d| (estimate(d), weight(&self.falling, d)));
+            let [north_east, south_west] =
+                [P1, -P1].map(|d| (estimate(d), weight(&self.rising, d)));
+            self.falling_color[i] = (south_east.1 * north_west.0 + north_west.1 * south_east.0)
+                / (south_east.1 + north_west.1);
+            self.rising_color[i] = (north_east.1 * south_west.0 + south_west.1 * north_east.0)
+                / (north_east.1 + south_west.1);
+
+            let variance = |squares: &[f32]| {
+                let s = |d: isize| squares[at(i, d)];
+                EPS_SQ
+                    + GAUSS_EVEN[0] * (s(-V1) + s(-1) + s(1) + s(V1))
+                    + GAUSS_EVEN[1]
+                        * [
+                            -V2 - 1,
+                            -V2 + 1,
+                            -V1 - 2,
+                            -V1 + 2,
+                            V1 - 2,
+                            V1 + 2,
+                            V2 - 1,
+                            V2 + 1,
+                        ]
+                        .map(s)
+                        .iter()
+                        .sum::<f32>()
+            };
+            let falling = variance(&self.falling_squared);
+            self.rising_weight[i] = falling / (variance(&self.rising_squared) + falling);
+        }
+
+        for (i, _) in self.red_blue_pixels(8).collect::<Vec<_>>() {
+            let weights = &self.rising_weight;
+            let alternative = (weights[at(i, -M1)]
+                + weights[at(i, P1)]
+                + weights[at(i, -P1)]
+                + weights[at(i, M1)])
+                / 4.0;
+            if (0.5 - weights[i]).abs() < (0.5 - alternative).abs() {
+                self.rising_weight[i] = alternative;
+            }
+            let w = self.rising_weight[i];
+            self.red_blue[i] =
+                (self.cfa[i] + self.falling_color[i] * (1.0 - w) + self.rising_color[i] * w) / 2.0;
+        }
+    }
+
+    /// Green from the average of red and blue where the diagonals discriminate better
+    fn correct_green_along_diagonals(&mut self) {
+        for (i, _) in self.red_blue_pixels(10).collect::<Vec<_>>() {
+            let w = self.vertical_weight[i];
+            if (0.5 - self.rising_weight[i]).abs() < (0.5 - w).abs() {
+                continue;
+            }
+            let cfa = &self.cfa;
+            let red_blue = &self.red_blue;
+            let estimate = |d: isize| {
+                let ratio = 2.0 * cfa[at(i, d)] / (EPS + red_blue[i] + red_blue[at(i, 2 * d)]);
+                if (1.0 - ratio).abs() < AR_THRESHOLD {
+                    red_blue[i] * ratio
+                } else {
+                    cfa[at(i, d)] + (red_blue[i] - red_blue[at(i, 2 * d)]) / 2.0
+                }
+            };
+            let [vw, hw] = [&self.vertical_weights, &self.horizontal_weights];
+            let vertical = (vw[at(i, -V1)] * estimate(V1) + vw[at(i, V1)] * estimate(-V1))
+                / (vw[at(i, -V1)] + vw[at(i, V1)]);
+            let horizontal = (hw[at(i, -1)] * estimate(1) + hw[at(i, 1)] * estimate(-1))
+                / (hw[at(i, -1)] + hw[at(i, 1)]);
+
+            // bounded in the saturated areas
+            let [horizontal, vertical] = [(horizontal, 1), (vertical, V1)].map(|(mut g, d)| {
+                let bound = |g: f32| limit(g, cfa[at(i, -d)], cfa[at(i, d)]);
+                if g < red_blue[i] {
+                    g = if 2.0 * g < red_blue[i] {
+                        bound(g)
+                    } else {
+                        let w = 2.0 * (red_blue[i] - g) / (EPS + g + red_blue[i]);
+                        w * g + (1.0 - w) * bound(g)
+                    };
+                }
+                if g > CLIP {
+                    g = bound(g);
+                }
+                g
+            });
+
+            self.green[i] = horizontal * (1.0 - w) + vertical * w;
+            self.green_difference[i] = self.green[i] - self.cfa[i];
+        }
+    }
+
+    /// Green minus red and blue at red and blue, the missing one along the diagonals
+    fn interpolate_chroma(&mut self) {
+        for (i, c) in self.red_blue_pixels(8).collect::<Vec<_>>() {
+            self.chroma[c / 2][i] = self.green_difference[i];
+        }
+        for (i, c) in self.red_blue_pixels(10).collect::<Vec<_>>() {
+            let chroma = &self.chroma[1 - c / 2];
+            let d = |o: isize| chroma[at(i, o)];
+            let mut sum = 0.0;
+            let mut weights = 0.0;
+            for (diagonal, dx, dy) in [(-M1, -1, -V1), (P1, 1, -V1), (-P1, -1, V1), (M1, 1, V1)] {
+                let w = 1.0
+                    / (EPS
+                        + (d(diagonal) - d(-diagonal)).abs()
+                        + (d(diagonal) - d(3 * diagonal)).abs()
+                        + (d(-diagonal) - d(3 * diagonal)).abs());
+                sum += w
+                    * (1.325 * d(diagonal)
+                        - 0.175 * d(3 * diagonal)
+                        - 0.075 * d(diagonal + 2 * dx)
+                        - 0.075 * d(diagonal + 2 * dy));
+                weights += w;
+            }
+            self.chroma[1 - c / 2][i] = sum / weights;
+        }
+    }
+}
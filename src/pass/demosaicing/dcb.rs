@@ -0,0 +1,273 @@
+use super::*;
+
+const MAX: f32 = u16::MAX as f32;
+
+/// Demosaicing with DCB of Jacek Gozdz, for the bayer images.
+///
+/// Green is taken from the horizontal or the vertical interpolation, whichever follows the
+/// variation of the CFA samples, and corrected `iterations` times by the map of the smoother
+/// direction. The color differences are smoothed, which suppresses the false colors in fine
+/// detail, and `refine` corrects green once more by the ratios of the colors.
+pub(super) fn demosaic(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: [usize; 4],
+    iterations: u8,
+    refine: bool,
+) -> Vec<[u16; 3]> {
+    let grid = Grid {
+        width,
+        height,
+        colors,
+    };
+    let mut rgb = vec![[0f32; 3]; width * height];
+    restore(&grid, image, &mut rgb);
+
+    let mut horizontal = rgb.clone();
+    interpolate_green(&grid, &mut horizontal, true);
+    interpolate_red_blue(&grid, &mut horizontal);
+    let mut vertical = rgb.clone();
+    interpolate_green(&grid, &mut vertical, false);
+    interpolate_red_blue(&grid, &mut vertical);
+    decide(&grid, &mut rgb, &horizontal, &vertical);
+    drop((horizontal, vertical));
+
+    let mut map = vec![0u8; width * height];
+    for _ in 1..iterations {
+        for _ in 0..3 {
+            nyquist(&grid, &mut rgb);
+        }
+        build_map(&grid, &rgb, &mut map);
+        correct(&grid, &mut rgb, &map);
+    }
+
+    interpolate_red_blue(&grid, &mut rgb);
+    smooth_color_differences(&grid, &mut rgb);
+    build_map(&grid, &rgb, &mut map);
+    correct_with_colors(&grid, &mut rgb, &map);
+    for _ in 0..3 {
+        build_map(&grid, &rgb, &mut map);
+        correct(&grid, &mut rgb, &map);
+    }
+    build_map(&grid, &rgb, &mut map);
+    restore(&grid, image, &mut rgb);
+    interpolate_red_blue(&grid, &mut rgb);
+
+    if refine {
+        refine_green(&grid, &mut rgb, &map);
+        interpolate_red_blue(&grid, &mut rgb);
+    }
+
+    rgb.into_iter()
+        .map(|x| x.map(|v| v.round().clamp(0.0, MAX) as u16))
+        .collect()
+}
+
+/// Puts the CFA samples back to their colors
+fn restore(grid: &Grid, image: &[u16], rgb: &mut [[f32; 3]]) {
+    for (x, y, i) in grid.pixels() {
+        rgb[i][grid.color(x, y)] = get_pixel(image, i) as f32;
+    }
+}
+
+fn interpolate_green(grid: &Grid, rgb: &mut [[f32; 3]], horizontal: bool) {
+    let (dx, dy) = if horizontal { (1, 0) } else { (0, 1) };
+    for (x, y, i, _) in grid.red_blue() {
+        rgb[i][1] = (rgb[grid.at(x, y, -dx, -dy)][1] + rgb[grid.at(x, y, dx, dy)][1]) / 2.0;
+    }
+}
+
+/// Red and blue from their CFA samples and the differences of green
+fn interpolate_red_blue(grid: &Grid, rgb: &mut [[f32; 3]]) {
+    for (x, y, i) in grid.pixels() {
+        let get = |dx, dy, c: usize| rgb[grid.at(x, y, dx, dy)][c];
+        let g = rgb[i][1];
+        match grid.color(x, y) {
+            1 => {
+                let c = grid.color(x + 1, y);
+                let horizontal =
+                    (get(-1, 0, c) + get(1, 0, c) + 2.0 * g - get(-1, 0, 1) - get(1, 0, 1)) / 2.0;
+                let vertical =
+                    (get(0, -1, 2 - c) + get(0, 1, 2 - c) + 2.0 * g - get(0, -1, 1) - get(0, 1, 1))
+                        / 2.0;
+                rgb[i][c] = horizontal.clamp(0.0, MAX);
+                rgb[i][2 - c] = vertical.clamp(0.0, MAX);
+            }
+            c => {
+                let diagonals = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+                let other = diagonals
+                    .iter()
+                    .map(|&(dx, dy)| get(dx, dy, 2 - c) - get(dx, dy, 1))
+                    .sum::<f32>();
+                rgb[i][2 - c] = (g + other / 4.0).clamp(0.0, MAX);
+            }
+        }
+    }
+}
+
+/// Takes green of the direction whose colors vary like the CFA samples around the pixel
+fn decide(grid: &Grid, rgb: &mut [[f32; 3]], horizontal: &[[f32; 3]], vertical: &[[f32; 3]]) {
+    let cross = [(0, -2), (0, 2), (-2, 0), (2, 0)];
+    let diagonals = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+    let variation = |plane: &[[f32; 3]], x, y, c: usize, d: usize| {
+        range(cross.map(|(dx, dy)| plane[grid.at(x, y, dx, dy)][c]))
+            + range(diagonals.map(|(dx, dy)| plane[grid.at(x, y, dx, dy)][d]))
+    };
+    for (x, y, i, c) in grid.red_blue() {
+        let d = 2 - c;
+        let current = variation(rgb, x, y, c, d);
+        let h = variation(horizontal, x, y, d, c);
+        let v = variation(vertical, x, y, d, c);
+        rgb[i][1] = if (current - h).abs() < (current - v).abs() {
+            horizontal[i][1]
+        } else {
+            vertical[i][1]
+        };
+    }
+}
+
+/// Green as the average of the red or blue pixels around plus the difference to their color
+fn nyquist(grid: &Grid, rgb: &mut [[f32; 3]]) {
+    let cross = [(0, -2), (0, 2), (-2, 0), (2, 0)];
+    for (x, y, i, c) in grid.red_blue() {
+        let [g, own] = [1, c].map(|c| {
+            cross
+                .iter()
+                .map(|&(dx, dy)| rgb[grid.at(x, y, dx, dy)][c])
+                .sum::<f32>()
+                / 4.0
+        });
+        rgb[i][1] = (g + rgb[i][c] - own).clamp(0.0, MAX);
+    }
+}
+
+/// Marks the pixels whose green is smoother vertically than horizontally
+fn build_map(grid: &Grid, rgb: &[[f32; 3]], map: &mut [u8]) {
+    for (x, y, i) in grid.pixels() {
+        let [left, right, up, down] =
+            [(-1, 0), (1, 0), (0, -1), (0, 1)].map(|(dx, dy)| rgb[grid.at(x, y, dx, dy)][1]);
+        let is_vertical = if rgb[i][1] > (left + right + up + down) / 4.0 {
+            left.min(right) + left + right < up.min(down) + up + down
+        } else {
+            left.max(right) + left + right > up.max(down) + up + down
+        };
+        map[i] = is_vertical as u8;
+    }
+}
+
+/// The weight of the vertical interpolation in sixteenths, from the map around the pixel
+#[inline(always)]
+fn vertical_weight(grid: &Grid, map: &[u8], x: usize, y: usize) -> f32 {
+    let get = |dx, dy| map[grid.at(x, y, dx, dy)] as f32;
+    4.0 * get(0, 0)
+        + 2.0 * (get(0, -1) + get(0, 1) + get(-1, 0) + get(1, 0))
+        + get(0, -2)
+        + get(0, 2)
+        + get(-2, 0)
+        + get(2, 0)
+}
+
+fn correct(grid: &Grid, rgb: &mut [[f32; 3]], map: &[u8]) {
+    for (x, y, i, _) in grid.red_blue() {
+        let get = |dx, dy| rgb[grid.at(x, y, dx, dy)][1];
+        let weight = vertical_weight(grid, map, x, y);
+        let horizontal = (get(-1, 0) + get(1, 0)) / 2.0;
+        let vertical = (get(0, -1) + get(0, 1)) / 2.0;
+        rgb[i][1] = ((16.0 - weight) * horizontal + weight * vertical) / 16.0;
+    }
+}
+
+/// Corrects green like `correct` with the laplacian of the color of the pixel
+fn correct_with_colors(grid: &Grid, rgb: &mut [[f32; 3]], map: &[u8]) {
+    for (x, y, i, c) in grid.red_blue() {
+        let get = |dx, dy, c: usize| rgb[grid.at(x, y, dx, dy)][c];
+        let weight = vertical_weight(grid, map, x, y);
+        let own = rgb[i][c];
+        let horizontal =
+            (get(-1, 0, 1) + get(1, 0, 1)) / 2.0 + own - (get(-2, 0, c) + get(2, 0, c)) / 2.0;
+        let vertical =
+            (get(0, -1, 1) + get(0, 1, 1)) / 2.0 + own - (get(0, -2, c) + get(0, 2, c)) / 2.0;
+        rgb[i][1] = (((16.0 - weight) * horizontal + weight * vertical) / 16.0).clamp(0.0, MAX);
+    }
+}
+
+/// Replaces red and blue with the average of the pixels around plus the difference of green
+fn smooth_color_differences(grid: &Grid, rgb: &mut [[f32; 3]]) {
+    let neighbors = [
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (-1, 0),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+    ];
+    for (x, y, i) in grid.pixels() {
+        let [r, g, b] = [0, 1, 2].map(|c| {
+            neighbors
+                .iter()
+                .map(|&(dx, dy)| rgb[grid.at(x, y, dx, dy)][c])
+                .sum::<f32>()
+                / 8.0
+        });
+        let diff = rgb[i][1] - g;
+        rgb[i][0] = (r + diff).clamp(0.0, MAX);
+        rgb[i][2] = (b + diff).clamp(0.0, MAX);
+    }
+}
+
+/// Corrects green by its ratio to the color of the pixel along both directions, limited to
+/// the green around
+fn refine_green(grid: &Grid, rgb: &mut [[f32; 3]], map: &[u8]) {
+    for (x, y, i, c) in grid.red_blue() {
+        let get = |dx, dy, c: usize| rgb[grid.at(x, y, dx, dy)][c];
+        let own = rgb[i][c];
+        let g = if own > 1.0 {
+            // the weighted ratios of green to the color along an axis
+            let ratio = |dx: isize, dy: isize| {
+                let center = (get(-dx, -dy, 1) + get(dx, dy, 1)) / (2.0 * own);
+                let [before, after] = [-1, 1].map(|s| {
+                    let other = get(2 * s * dx, 2 * s * dy, c);
+                    if other > 0.0 {
+                        [
+                            2.0 * get(s * dx, s * dy, 1) / (other + own),
+                            (get(s * dx, s * dy, 1) + get(3 * s * dx, 3 * s * dy, 1))
+                                / (2.0 * other),
+                        ]
+                    } else {
+                        [center; 2]
+                    }
+                });
+                (5.0 * center + 3.0 * before[0] + before[1] + 3.0 * after[0] + after[1]) / 13.0
+            };
+            let weight = vertical_weight(grid, map, x, y);
+            own * (weight * ratio(0, 1) + (16.0 - weight) * ratio(1, 0)) / 16.0
+        } else {
+            own
+        };
+
+        let around = [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ]
+        .map(|(dx, dy)| get(dx, dy, 1));
+        let min = around.iter().copied().fold(MAX, f32::min);
+        let max = around.iter().copied().fold(0.0, f32::max);
+        rgb[i][1] = g.clamp(min, max);
+    }
+}
+
+#[inline(always)]
+fn range(values: [f32; 4]) -> f32 {
+    let max = values.iter().copied().fold(f32::MIN, f32::max);
+    let min = values.iter().copied().fold(f32::MAX, f32::min);
+    max - min
+}
@@ -0,0 +1,85 @@
+use super::*;
+
+// the position in the RGGB pattern: red, green on a red row, green on a blue row and blue
+const RED: usize = 0;
+const GREEN_RED_ROW: usize = 1;
+const GREEN_BLUE_ROW: usize = 2;
+
+/// Demosaics a pixel linearly with green directed by the gradients.
+///
+/// Green at red and blue is the Hamilton-Adams interpolation along the axis of the smaller
+/// gradient, or the average of both axes when they are equal. Red and blue follow green with
+/// the bilinear average of their differences to green, the samples beyond the borders are
+/// mirrored.
+#[inline(always)]
+fn pixel(i: usize, v: u16, image: &[u16], w: usize, h: usize, shift: (usize, usize)) -> [u16; 3] {
+    let (x, y) = (i % w, i / w);
+    let position = ((y + shift.1) & 1) * 2 + ((x + shift.0) & 1);
+    let get = |dx: isize, dy: isize| {
+        get_mirrored_pixel(image, w, h, x as isize + dx, y as isize + dy) as i32
+    };
+    // green at the red or blue pixel at `(dx, dy)` in quarters
+    let green = |dx: isize, dy: isize| {
+        let at = |t: isize, (ax, ay): (isize, isize)| get(dx + t * ax, dy + t * ay);
+        let [horizontal, vertical] = [(1, 0), (0, 1)].map(|axis| {
+            let (near, far) = (at(-1, axis) + at(1, axis), at(-2, axis) + at(2, axis));
+            let laplacian = 2 * at(0, axis) - far;
+            let gradient = (at(-1, axis) - at(1, axis)).abs() + laplacian.abs();
+            (near * 2 + laplacian, gradient)
+        });
+        match horizontal.1.cmp(&vertical.1) {
+            std::cmp::Ordering::Less => horizontal.0,
+            std::cmp::Ordering::Greater => vertical.0,
+            std::cmp::Ordering::Equal => (horizontal.0 + vertical.0) / 2,
+        }
+    };
+    // the color of the neighbors at `offsets` minus their green, in quarters
+    let difference = |offsets: &[(isize, isize)]| {
+        let sum = offsets
+            .iter()
+            .map(|&(dx, dy)| get(dx, dy) * 4 - green(dx, dy))
+            .sum::<i32>();
+        sum / offsets.len() as i32
+    };
+    let clamp = |quarters: i32| ((quarters + 2) >> 2).clamp(0, u16::MAX as i32) as u16;
+
+    const ROW: [(isize, isize); 2] = [(-1, 0), (1, 0)];
+    const COLUMN: [(isize, isize); 2] = [(0, -1), (0, 1)];
+    const DIAGONALS: [(isize, isize); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+    match position {
+        RED => {
+            let g = green(0, 0);
+            [v, clamp(g), clamp(g + difference(&DIAGONALS))]
+        }
+        GREEN_RED_ROW | GREEN_BLUE_ROW => {
+            let g = v as i32 * 4;
+            let (row, column) = (clamp(g + difference(&ROW)), clamp(g + difference(&COLUMN)));
+            match position {
+                GREEN_RED_ROW => [row, v, column],
+                _ => [column, v, row],
+            }
+        }
+        // blue
+        _ => {
+            let g = green(0, 0);
+            [clamp(g + difference(&DIAGONALS)), clamp(g), v]
+        }
+    }
+}
+
+#[inline(always)]
+pub(super) fn rggb(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (0, 0))
+}
+#[inline(always)]
+pub(super) fn grbg(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (1, 0))
+}
+#[inline(always)]
+pub(super) fn gbrg(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (0, 1))
+}
+#[inline(always)]
+pub(super) fn bggr(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (1, 1))
+}
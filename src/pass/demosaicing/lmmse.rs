@@ -0,0 +1,193 @@
+use super::*;
+
+const GAMMA: f32 = 2.2;
+// the median smoothing of the color differences applied after the interpolation
+const SMOOTHING_PASSES: usize = 2;
+
+/// Directional linear minimum mean square error demosaicing of Zhang and Wu, for the bayer
+/// images.
+///
+/// The differences of green to red and blue are estimated horizontally and vertically in a
+/// gamma space, each is denoised against its low pass by its local variance and both
+/// directions are merged by the inverse of their errors. Red and blue follow the differences
+/// and get smoothed by the median of the differences around.
+pub(super) fn demosaic(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: [usize; 4],
+) -> Vec<[u16; 3]> {
+    let grid = Grid {
+        width,
+        height,
+        colors,
+    };
+    let lut = (0..=u16::MAX)
+        .map(|v| (v as f32 / u16::MAX as f32).powf(1.0 / GAMMA))
+        .collect::<Vec<_>>();
+    let cfa = (0..width * height)
+        .map(|i| lut[get_pixel(image, i) as usize])
+        .collect::<Vec<_>>();
+
+    let mut rgb = vec![[0f32; 3]; width * height];
+    for (x, y, i) in grid.pixels() {
+        rgb[i][grid.color(x, y)] = cfa[i];
+    }
+    interpolate_green(&grid, &cfa, &mut rgb);
+    interpolate_red_blue(&grid, &mut rgb);
+    for _ in 0..SMOOTHING_PASSES {
+        smooth(&grid, &mut rgb);
+    }
+
+    grid.pixels()
+        .map(|(x, y, i)| {
+            let mut pixel =
+                rgb[i].map(|v| (v.clamp(0.0, 1.0).powf(GAMMA) * u16::MAX as f32).round() as u16);
+            pixel[grid.color(x, y)] = get_pixel(image, i);
+            pixel
+        })
+        .collect()
+}
+
+/// Green minus the color of every pixel, interpolated along `(dx, dy)`
+fn differences(grid: &Grid, cfa: &[f32], (dx, dy): (isize, isize)) -> Vec<f32> {
+    grid.pixels()
+        .map(|(x, y, i)| {
+            let get = |t: isize| cfa[grid.at(x, y, t * dx, t * dy)];
+            let estimation = 0.5 * (get(-1) + get(0) + get(1)) - 0.25 * (get(-2) + get(2));
+            if grid.color(x, y) == 1 {
+                cfa[i] - estimation.clamp(0.0, 1.0)
+            } else {
+                // the overshoots of green on bright pixels are limited to its neighbors
+                let diagonals = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+                    .iter()
+                    .map(|&(dx, dy)| cfa[grid.at(x, y, dx, dy)])
+                    .sum::<f32>();
+                let luminance = 0.0625 * diagonals + 0.25 * cfa[i] + 0.5 * estimation;
+                let green = if cfa[i] > 1.75 * luminance {
+                    median(&mut [estimation, get(-1), get(1)])
+                } else {
+                    estimation.clamp(0.0, 1.0)
+                };
+                green - cfa[i]
+            }
+        })
+        .collect()
+}
+
+/// The gaussian low pass of the differences along `(dx, dy)`
+fn low_pass(grid: &Grid, differences: &[f32], (dx, dy): (isize, isize)) -> Vec<f32> {
+    let weights = [0, 1, 2, 3, 4].map(|t: i32| (-((t * t) as f32) / 8.0).exp());
+    let sum = weights[0] + 2.0 * weights[1..].iter().sum::<f32>();
+    grid.pixels()
+        .map(|(x, y, _)| {
+            (-4..=4isize)
+                .map(|t| weights[t.unsigned_abs()] * differences[grid.at(x, y, t * dx, t * dy)])
+                .sum::<f32>()
+                / sum
+        })
+        .collect()
+}
+
+fn interpolate_green(grid: &Grid, cfa: &[f32], rgb: &mut [[f32; 3]]) {
+    let [horizontal, vertical] = [(1, 0), (0, 1)].map(|d| {
+        let differences = differences(grid, cfa, d);
+        let low_pass = low_pass(grid, &differences, d);
+        (d, differences, low_pass)
+    });
+
+    for (x, y, i, c) in grid.red_blue() {
+        // the estimation of a direction and its error variance
+        let estimate =
+            |((dx, dy), differences, low_pass): &((isize, isize), Vec<f32>, Vec<f32>)| {
+                let window = (-4..=4isize).map(|t| grid.at(x, y, t * dx, t * dy));
+                let mean = window.clone().map(|j| low_pass[j]).sum::<f32>() / 9.0;
+                let signal = 1e-7
+                    + window
+                        .clone()
+                        .map(|j| (low_pass[j] - mean).powi(2))
+                        .sum::<f32>();
+                let noise = 1e-7
+                    + window
+                        .map(|j| (low_pass[j] - differences[j]).powi(2))
+                        .sum::<f32>();
+                let estimation = (differences[i] * signal + low_pass[i] * noise) / (signal + noise);
+                (estimation, signal * noise / (signal + noise))
+            };
+        let (h, h_variance) = estimate(&horizontal);
+        let (v, v_variance) = estimate(&vertical);
+        let difference = (h * v_variance + v * h_variance) / (h_variance + v_variance);
+        rgb[i][1] = (rgb[i][c] + difference).clamp(0.0, 1.0);
+    }
+}
+
+/// Red and blue from the differences to green around, at red and blue then at green
+fn interpolate_red_blue(grid: &Grid, rgb: &mut [[f32; 3]]) {
+    for (x, y, i, own) in grid.red_blue() {
+        let c = 2 - own;
+        let difference = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+            .iter()
+            .map(|&(dx, dy)| {
+                let pixel = rgb[grid.at(x, y, dx, dy)];
+                pixel[c] - pixel[1]
+            })
+            .sum::<f32>()
+            / 4.0;
+        rgb[i][c] = rgb[i][1] + difference;
+    }
+    for (x, y, i) in grid.pixels() {
+        if grid.color(x, y) != 1 {
+            continue;
+        }
+        for c in [0, 2] {
+            let difference = [(0, -1), (-1, 0), (1, 0), (0, 1)]
+                .iter()
+                .map(|&(dx, dy)| {
+                    let pixel = rgb[grid.at(x, y, dx, dy)];
+                    pixel[c] - pixel[1]
+                })
+                .sum::<f32>()
+                / 4.0;
+            rgb[i][c] = rgb[i][1] + difference;
+        }
+    }
+}
+
+/// Rebuilds the interpolated colors with the medians of red and blue minus green around
+fn smooth(grid: &Grid, rgb: &mut [[f32; 3]]) {
+    let differences = rgb
+        .iter()
+        .map(|[r, g, b]| [r - g, b - g])
+        .collect::<Vec<_>>();
+    for (x, y, i) in grid.pixels() {
+        let [red, blue] = [0, 1].map(|k| {
+            let mut window = [0f32; 9];
+            for (j, value) in window.iter_mut().enumerate() {
+                let (dx, dy) = (j as isize % 3 - 1, j as isize / 3 - 1);
+                *value = differences[grid.at(x, y, dx, dy)][k];
+            }
+            median(&mut window)
+        });
+        let [r, g, b] = &mut rgb[i];
+        match grid.color(x, y) {
+            0 => {
+                *g = *r - red;
+                *b = *g + blue;
+            }
+            1 => {
+                *r = *g + red;
+                *b = *g + blue;
+            }
+            _ => {
+                *g = *b - blue;
+                *r = *g + red;
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_unstable_by(|a, b| a.total_cmp(b));
+    values[values.len() / 2]
+}
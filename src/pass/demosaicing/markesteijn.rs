@@ -0,0 +1,440 @@
+use super::*;
+
+// the rows demosaiced at once, the planes of a band hold 8 more rows and columns on every side
+const BAND_HEIGHT: usize = 64;
+const MARGIN: usize = 8;
+
+/// A (row, column) offset
+type Offset = (isize, isize);
+
+// the rotations of the hexagons, a rotation is the matrix of 4 values from an even index
+const ORTH: [isize; 12] = [1, 0, 0, 1, -1, 0, 0, -1, 1, 0, 0, 1];
+// the hexagons of green around red and blue, and of red and blue around green
+const PATT: [[isize; 16]; 2] = [
+    [0, 1, 0, -1, 2, 0, -1, 0, 1, 1, 1, -1, 0, 0, 0, 0],
+    [0, 1, 0, -2, 1, 0, -2, 0, 1, 1, -2, -2, 1, -1, -1, 1],
+];
+// the directions of the derivatives: horizontal, vertical and both diagonals
+const DIRECTIONS: [Offset; 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+/// The image with the out of range coordinates moved by whole patterns, which keeps their
+/// CFA color
+struct Mosaic<'a> {
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    colors: [[usize; 6]; 6],
+    // the hexagon of every pixel of a 3x3 block
+    hexagons: [[[Offset; 8]; 3]; 3],
+    // the row and the column in a 3x3 block of the green pixel without green neighbors
+    solitary: (isize, isize),
+}
+
+impl Mosaic<'_> {
+    #[inline(always)]
+    fn color(&self, x: isize, y: isize) -> usize {
+        self.colors[y.rem_euclid(6) as usize][x.rem_euclid(6) as usize]
+    }
+    #[inline(always)]
+    fn hexagon(&self, x: isize, y: isize) -> &[Offset; 8] {
+        &self.hexagons[y.rem_euclid(3) as usize][x.rem_euclid(3) as usize]
+    }
+    #[inline(always)]
+    fn get(&self, x: isize, y: isize) -> i32 {
+        let wrap = |v: isize, n: usize| {
+            let n = n as isize;
+            let v = if v < 0 {
+                v + (5 - v) / 6 * 6
+            } else if v >= n {
+                v - (v - n + 6) / 6 * 6
+            } else {
+                v
+            };
+            v.clamp(0, n - 1) as usize
+        };
+        get_pixel(
+            self.image,
+            wrap(y, self.height) * self.width + wrap(x, self.width),
+        ) as i32
+    }
+    /// The sample of the pixel at its color, red and blue pixels also get the minimum of the
+    /// green around them as green and its maximum as a fourth channel
+    #[inline(always)]
+    fn pixel(&self, x: isize, y: isize) -> [i32; 4] {
+        let mut pixel = [0; 4];
+        let c = self.color(x, y);
+        pixel[c] = self.get(x, y);
+        if c != 1 {
+            let greens = self.hexagon(x, y)[..6]
+                .iter()
+                .map(|&(dy, dx)| self.get(x + dx, y + dy));
+            pixel[1] = greens.clone().min().unwrap_or(0);
+            pixel[3] = greens.max().unwrap_or(0);
+        }
+        pixel
+    }
+    /// Whether the pixels of the row are interpolated vertically first
+    #[inline(always)]
+    fn is_solitary_row(&self, y: isize) -> bool {
+        (y - self.solitary.0).rem_euclid(3) == 0
+    }
+}
+
+/// Maps the hexagon of green around every red and blue pixel and of red and blue around every
+/// green pixel, and finds the green pixel without green neighbors
+fn hexagons(colors: &[[usize; 6]; 6]) -> ([[[Offset; 8]; 3]; 3], (isize, isize)) {
+    let color = |y: isize, x: isize| colors[y.rem_euclid(6) as usize][x.rem_euclid(6) as usize];
+    let mut hexagons = [[[(0, 0); 8]; 3]; 3];
+    let mut solitary = (0, 0);
+    for row in 0..3 {
+        for col in 0..3 {
+            let g = (color(row, col) == 1) as usize;
+            let mut ng = 0;
+            for d in (0..10).step_by(2) {
+                if color(row + ORTH[d], col + ORTH[d + 2]) == 1 {
+                    ng = 0;
+                } else {
+                    ng += 1;
+                }
+                if ng == 4 {
+                    solitary = (row, col);
+                }
+                if ng == g + 1 {
+                    for c in 0..8 {
+                        let (a, b) = (PATT[g][c * 2], PATT[g][c * 2 + 1]);
+                        let v = ORTH[d] * a + ORTH[d + 1] * b;
+                        let h = ORTH[d + 2] * a + ORTH[d + 3] * b;
+                        hexagons[row as usize][col as usize][c ^ (g * 2 & d)] = (v, h);
+                    }
+                }
+            }
+        }
+    }
+    (hexagons, solitary)
+}
+
+/// The interpolations of every direction for the rows of a band, indexed by
+/// `(row, x + MARGIN)` where `row` starts `MARGIN` rows above the band
+struct Planes {
+    width: usize,
+    rows: usize,
+    x0: isize,
+    y0: isize,
+    rgb: Vec<Vec<[i32; 3]>>,
+}
+
+impl Planes {
+    #[inline(always)]
+    fn at(&self, i: usize, (dy, dx): Offset) -> usize {
+        (i as isize + dy * self.width as isize + dx) as usize
+    }
+    /// The image coordinates and the index of the pixels at least `inset` from the sides
+    fn pixels(&self, inset: usize) -> impl Iterator<Item = (isize, isize, usize)> {
+        let (width, x0, y0) = (self.width, self.x0, self.y0);
+        (inset..self.rows - inset).flat_map(move |row| {
+            (inset..width - inset)
+                .map(move |px| (x0 + px as isize, y0 + row as isize, row * width + px))
+        })
+    }
+}
+
+/// Markesteijn demosaicing of an X-Trans image, with the colors of its 6x6 pattern.
+///
+/// Green is interpolated at red and blue along the horizontal, the vertical and both diagonals
+/// of the hexagon of green around them, limited to the green of the hexagon. Red and blue
+/// follow the color differences of every direction, and every pixel averages the directions
+/// whose neighbors are the most homogeneous in CIELab. More than one pass interpolates green
+/// again from the closer pixels and compares both passes.
+pub(super) fn demosaic(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: [[usize; 6]; 6],
+    passes: u8,
+) -> Vec<[u16; 3]> {
+    let (hexagons, solitary) = hexagons(&colors);
+    let mosaic = Mosaic {
+        image,
+        width,
+        height,
+        colors,
+        hexagons,
+        solitary,
+    };
+    let mut out = vec![[0u16; 3]; width * height];
+    if out.is_empty() {
+        return out;
+    }
+    let cbrt = cbrt_lut();
+    let directions = if passes > 1 { 8 } else { 4 };
+
+    for (band, out) in out.chunks_mut(width * BAND_HEIGHT).enumerate() {
+        let rows = out.len() / width + MARGIN * 2;
+        let planes_width = width + MARGIN * 2;
+        let mut planes = Planes {
+            width: planes_width,
+            rows,
+            x0: -(MARGIN as isize),
+            y0: (band * BAND_HEIGHT) as isize - MARGIN as isize,
+            rgb: vec![vec![[0; 3]; planes_width * rows]; directions],
+        };
+
+        interpolate_green(&mosaic, &mut planes);
+        for pass in 0..passes.max(1) as usize {
+            // the passes after the first refine a copy of the 4 directions
+            let first = if pass == 0 { 0 } else { 4 };
+            if pass == 1 {
+                let (first_pass, second_pass) = planes.rgb.split_at_mut(4);
+                second_pass.clone_from_slice(first_pass);
+            }
+            if pass > 0 {
+                interpolate_green_again(&mosaic, &mut planes, first);
+            }
+            interpolate_solitary_green(&mosaic, &mut planes, first);
+            interpolate_red_blue_at_blue_red(&mosaic, &mut planes, first);
+            interpolate_green_blocks(&mosaic, &mut planes, first);
+        }
+
+        let homogeneity = build_homogeneity_maps(&planes, &cbrt);
+        for (row, out) in out.chunks_exact_mut(width).enumerate() {
+            for (x, out) in out.iter_mut().enumerate() {
+                let i = (row + MARGIN) * planes_width + x + MARGIN;
+                *out = average_directions(&planes, &homogeneity, i);
+            }
+        }
+    }
+    out
+}
+
+/// Green at red and blue for the 4 directions, limited to the green of their hexagon
+fn interpolate_green(mosaic: &Mosaic, planes: &mut Planes) {
+    let pixels = planes.pixels(0).collect::<Vec<_>>();
+    for (x, y, i) in pixels {
+        let pixel = mosaic.pixel(x, y);
+        for plane in planes.rgb.iter_mut().take(4) {
+            plane[i] = [pixel[0], pixel[1], pixel[2]];
+        }
+        let f = mosaic.color(x, y);
+        if f == 1 {
+            continue;
+        }
+
+        let hex = mosaic.hexagon(x, y);
+        let get = |(dy, dx): Offset, t: isize, c: usize| mosaic.pixel(x + t * dx, y + t * dy)[c];
+        let mut color = [0; 4];
+        color[0] = 174 * (get(hex[1], 1, 1) + get(hex[0], 1, 1))
+            - 46 * (get(hex[1], 2, 1) + get(hex[0], 2, 1));
+        color[1] =
+            223 * get(hex[3], 1, 1) + 33 * get(hex[2], 1, 1) + 92 * (pixel[f] - get(hex[2], -1, f));
+        for c in 0..2 {
+            color[2 + c] = 164 * get(hex[4 + c], 1, 1)
+                + 92 * get(hex[4 + c], -2, 1)
+                + 33 * (2 * pixel[f] - get(hex[4 + c], 3, f) - get(hex[4 + c], -3, f));
+        }
+        let swap = mosaic.is_solitary_row(y) as usize;
+        for (c, &v) in color.iter().enumerate() {
+            planes.rgb[c ^ swap][i][1] = (v >> 8).min(pixel[3]).max(pixel[1]);
+        }
+    }
+}
+
+/// Green at red and blue from the interpolated values of the closer pixels
+fn interpolate_green_again(mosaic: &Mosaic, planes: &mut Planes, first: usize) {
+    let pixels = planes.pixels(2).collect::<Vec<_>>();
+    for (x, y, i) in pixels {
+        let f = mosaic.color(x, y);
+        if f == 1 {
+            continue;
+        }
+        let pixel = mosaic.pixel(x, y);
+        let hex = mosaic.hexagon(x, y);
+        let swap = mosaic.is_solitary_row(y) as usize;
+        for (d, &(dy, dx)) in hex.iter().enumerate().take(6).skip(3) {
+            let plane = &mut planes.rgb[first + ((d - 2) ^ swap)];
+            let near = plane[(i as isize + dy * planes.width as isize + dx) as usize];
+            let far = plane[(i as isize - 2 * (dy * planes.width as isize + dx)) as usize];
+            let v = far[1] + 2 * near[1] - far[f] - 2 * near[f] + 3 * plane[i][f];
+            plane[i][1] = (v / 3).min(pixel[3]).max(pixel[1]);
+        }
+    }
+}
+
+/// Red and blue at the green pixels without green neighbors, horizontally and vertically, then
+/// along the direction of the smaller color differences for the diagonals
+fn interpolate_solitary_green(mosaic: &Mosaic, planes: &mut Planes, first: usize) {
+    let pixels = planes.pixels(2).collect::<Vec<_>>();
+    for (x, y, i) in pixels {
+        if !mosaic.is_solitary_row(y) || (x - mosaic.solitary.1).rem_euclid(3) != 0 {
+            continue;
+        }
+        let mut h = mosaic.color(x + 1, y);
+        let mut diff = [0i64; 6];
+        let mut color = [[0i32; 6]; 3];
+        let mut plane = first;
+        for d in 0..6 {
+            let direction = if d % 2 == 0 { (0, 1) } else { (1, 0) };
+            let rgb = &planes.rgb[plane];
+            for t in 1..=2 {
+                let offset = (direction.0 * t, direction.1 * t);
+                let after = rgb[planes.at(i, offset)];
+                let before = rgb[planes.at(i, (-offset.0, -offset.1))];
+                let g = 2 * rgb[i][1] - after[1] - before[1];
+                color[h][d] = g + after[h] + before[h];
+                if d > 1 {
+                    let v = (after[1] - before[1] - after[h] + before[h]) as i64;
+                    diff[d] += v * v + (g as i64) * (g as i64);
+                }
+                h ^= 2;
+            }
+            h ^= 2;
+            if d > 1 && d % 2 == 1 && diff[d - 1] < diff[d] {
+                for c in [0, 2] {
+                    color[c][d] = color[c][d - 1];
+                }
+            }
+            if d < 2 || d % 2 == 1 {
+                for c in [0, 2] {
+                    planes.rgb[plane][i][c] = (color[c][d] / 2).clamp(0, u16::MAX as i32);
+                }
+                plane += 1;
+            }
+        }
+    }
+}
+
+/// Red at blue and blue at red from the color differences along the closer or the smoother
+/// axis
+fn interpolate_red_blue_at_blue_red(mosaic: &Mosaic, planes: &mut Planes, first: usize) {
+    let pixels = planes.pixels(3).collect::<Vec<_>>();
+    for (x, y, i) in pixels {
+        let f = 2 - mosaic.color(x, y);
+        if f == 1 {
+            continue;
+        }
+        let vertical = !mosaic.is_solitary_row(y);
+        let (near, far) = if vertical {
+            ((1, 0), (0, 3))
+        } else {
+            ((0, 1), (3, 0))
+        };
+        for d in 0..4 {
+            let rgb = &planes.rgb[first + d];
+            let get = |(dy, dx): Offset, t: isize| rgb[planes.at(i, (t * dy, t * dx))];
+            let g = rgb[i][1];
+            let gradient = |offset| (g - get(offset, 1)[1]).abs() + (g - get(offset, -1)[1]).abs();
+            let offset = if d > 1 || (d == 1) == vertical || gradient(near) < 2 * gradient(far) {
+                near
+            } else {
+                far
+            };
+            let (after, before) = (get(offset, 1), get(offset, -1));
+            let v = (after[f] + before[f] + 2 * g - after[1] - before[1]) / 2;
+            planes.rgb[first + d][i][f] = v.clamp(0, u16::MAX as i32);
+        }
+    }
+}
+
+/// Red and blue at the 2x2 blocks of green from the pixels of their hexagon, for the 4
+/// directions of the pass
+fn interpolate_green_blocks(mosaic: &Mosaic, planes: &mut Planes, first: usize) {
+    let pixels = planes.pixels(2).collect::<Vec<_>>();
+    for (x, y, i) in pixels {
+        if mosaic.is_solitary_row(y) || (x - mosaic.solitary.1).rem_euclid(3) == 0 {
+            continue;
+        }
+        let hex = mosaic.hexagon(x, y);
+        for (plane, d) in (0..8).step_by(2).enumerate() {
+            let rgb = &planes.rgb[first + plane];
+            let (a, b) = (rgb[planes.at(i, hex[d])], rgb[planes.at(i, hex[d + 1])]);
+            let g = rgb[i][1];
+            let is_opposite = hex[d].0 == -hex[d + 1].0 && hex[d].1 == -hex[d + 1].1;
+            let values = [0, 2].map(|c| {
+                let v = if is_opposite {
+                    (2 * g - a[1] - b[1] + a[c] + b[c]) / 2
+                } else {
+                    (3 * g - 2 * a[1] - b[1] + 2 * a[c] + b[c]) / 3
+                };
+                v.clamp(0, u16::MAX as i32)
+            });
+            planes.rgb[first + plane][i][0] = values[0];
+            planes.rgb[first + plane][i][2] = values[1];
+        }
+    }
+}
+
+/// Counts for every direction the neighbors whose derivative in CIELab is within 8 times the
+/// smallest derivative of the pixel
+fn build_homogeneity_maps(planes: &Planes, cbrt: &[f32]) -> Vec<Vec<u8>> {
+    let len = planes.width * planes.rows;
+    let derivatives = planes
+        .rgb
+        .iter()
+        .enumerate()
+        .map(|(d, rgb)| {
+            let mut lab = vec![[0f32; 3]; len];
+            for (_, _, i) in planes.pixels(2) {
+                lab[i] = to_lab(rgb[i], cbrt);
+            }
+            let offset = DIRECTIONS[d % 4];
+            let mut derivative = vec![0f32; len];
+            for (_, _, i) in planes.pixels(3) {
+                let (after, before) = (
+                    lab[planes.at(i, offset)],
+                    lab[planes.at(i, (-offset.0, -offset.1))],
+                );
+                let laplacian = |c: usize| 2.0 * lab[i][c] - after[c] - before[c];
+                let g = laplacian(0);
+                derivative[i] = g * g
+                    + (laplacian(1) + g * 500.0 / 232.0).powi(2)
+                    + (laplacian(2) - g * 500.0 / 580.0).powi(2);
+            }
+            derivative
+        })
+        .collect::<Vec<_>>();
+
+    let mut homogeneity = vec![vec![0u8; len]; derivatives.len()];
+    for (_, _, i) in planes.pixels(4) {
+        let threshold = 8.0 * derivatives.iter().map(|x| x[i]).fold(f32::MAX, f32::min);
+        for (homogeneity, derivative) in homogeneity.iter_mut().zip(&derivatives) {
+            homogeneity[i] = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (dy, dx)))
+                .filter(|&offset| derivative[planes.at(i, offset)] <= threshold)
+                .count() as u8;
+        }
+    }
+    homogeneity
+}
+
+/// The average of the directions which are the most homogeneous around the pixel, the sample
+/// is kept at its color
+fn average_directions(planes: &Planes, homogeneity: &[Vec<u8>], i: usize) -> [u16; 3] {
+    let mut sums = homogeneity
+        .iter()
+        .map(|homogeneity| {
+            (-2..=2)
+                .flat_map(|dy| (-2..=2).map(move |dx| (dy, dx)))
+                .map(|offset| homogeneity[planes.at(i, offset)] as u32)
+                .sum::<u32>()
+        })
+        .collect::<Vec<_>>();
+    // a direction of the first pass only competes with the same direction of the second
+    for d in 0..sums.len().saturating_sub(4) {
+        if sums[d] < sums[d + 4] {
+            sums[d] = 0;
+        } else if sums[d] > sums[d + 4] {
+            sums[d + 4] = 0;
+        }
+    }
+    let max = sums.iter().copied().max().unwrap_or(0);
+    let max = max - (max >> 3);
+
+    let mut sum = [0i32; 3];
+    let mut count = 0;
+    for (rgb, _) in planes.rgb.iter().zip(&sums).filter(|(_, &s)| s >= max) {
+        for (sum, v) in sum.iter_mut().zip(rgb[i]) {
+            *sum += v;
+        }
+        count += 1;
+    }
+    sum.map(|v| (v / count.max(1)) as u16)
+}
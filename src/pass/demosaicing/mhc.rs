@@ -0,0 +1,111 @@
+use super::*;
+
+// the position in the RGGB pattern: red, green on a red row, green on a blue row and blue
+const RED: usize = 0;
+const GREEN_RED_ROW: usize = 1;
+const GREEN_BLUE_ROW: usize = 2;
+
+/// The weights of a 5x5 kernel in sixteenths, as (row, column, weight) around the pixel
+type Kernel = [(isize, isize, i32); 9];
+
+// green at red and blue
+const GREEN_AT_RB: Kernel = [
+    (0, 0, 8),
+    (-1, 0, 4),
+    (1, 0, 4),
+    (0, -1, 4),
+    (0, 1, 4),
+    (-2, 0, -2),
+    (2, 0, -2),
+    (0, -2, -2),
+    (0, 2, -2),
+];
+// red or blue at green, when the color is on the left and right of the pixel
+const RB_AT_G_ROW: Kernel = [
+    (0, 0, 10),
+    (0, -1, 8),
+    (0, 1, 8),
+    (0, -2, -2),
+    (0, 2, -2),
+    (-1, -1, -2),
+    (-1, 1, -2),
+    (1, -1, -2),
+    (1, 1, -2),
+];
+const RB_AT_G_ROW_FAR: [(isize, isize, i32); 2] = [(-2, 0, 1), (2, 0, 1)];
+// red at blue and blue at red
+const RB_AT_BR: Kernel = [
+    (0, 0, 12),
+    (-1, -1, 4),
+    (-1, 1, 4),
+    (1, -1, 4),
+    (1, 1, 4),
+    (-2, 0, -3),
+    (2, 0, -3),
+    (0, -2, -3),
+    (0, 2, -3),
+];
+
+/// Demosaics a pixel with the gradient-corrected linear interpolation of Malvar, He and Cutler.
+///
+/// The bilinear average of every missing color is corrected by the laplacian of the color of
+/// the pixel, the samples beyond the borders are mirrored.
+#[inline(always)]
+fn pixel(i: usize, v: u16, image: &[u16], w: usize, h: usize, shift: (usize, usize)) -> [u16; 3] {
+    let (x, y) = (i % w, i / w);
+    let position = ((y + shift.1) & 1) * 2 + ((x + shift.0) & 1);
+    let get = |dy: isize, dx: isize| {
+        get_mirrored_pixel(image, w, h, x as isize + dx, y as isize + dy) as i32
+    };
+    let apply = |kernel: &[(isize, isize, i32)], transposed: bool| {
+        kernel
+            .iter()
+            .map(|&(dy, dx, weight)| match transposed {
+                true => get(dx, dy) * weight,
+                false => get(dy, dx) * weight,
+            })
+            .sum::<i32>()
+    };
+    let at_g = |transposed: bool| {
+        let sum = apply(&RB_AT_G_ROW, transposed) + apply(&RB_AT_G_ROW_FAR, transposed);
+        clamp(sum)
+    };
+
+    match position {
+        RED => [
+            v,
+            clamp(apply(&GREEN_AT_RB, false)),
+            clamp(apply(&RB_AT_BR, false)),
+        ],
+        GREEN_RED_ROW => [at_g(false), v, at_g(true)],
+        GREEN_BLUE_ROW => [at_g(true), v, at_g(false)],
+        // blue
+        _ => [
+            clamp(apply(&RB_AT_BR, false)),
+            clamp(apply(&GREEN_AT_RB, false)),
+            v,
+        ],
+    }
+}
+
+#[inline(always)]
+fn clamp(sixteenths: i32) -> u16 {
+    ((sixteenths + 8) >> 4).clamp(0, u16::MAX as i32) as u16
+}
+
+#[inline(always)]
+pub(super) fn rggb(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (0, 0))
+}
+#[inline(always)]
+pub(super) fn grbg(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (1, 0))
+}
+#[inline(always)]
+pub(super) fn gbrg(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (0, 1))
+}
+#[inline(always)]
+pub(super) fn bggr(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (1, 1))
+}
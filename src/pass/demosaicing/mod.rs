@@ -1,7 +1,21 @@
 #![allow(dead_code)]
 
+mod ahd;
+#[cfg(feature = "amaze")]
+mod amaze;
+mod dcb;
+mod directed;
 mod enhanced_linear;
 mod linear;
+mod lmmse;
+mod markesteijn;
+mod mhc;
+mod ppg;
+mod rcd;
+mod vng;
+mod xtrans_fast;
+
+use crate::decode::CFAPattern;
 
 #[inline(always)]
 pub fn none<'a>(
@@ -10,6 +24,16 @@ pub fn none<'a>(
     iter.map(|(_, v)| [v; 3])
 }
 
+/// Groups the samples of an image which is already interleaved RGB into pixels
+#[inline(always)]
+pub fn rgb<'a>(
+    iter: impl Iterator<Item = (usize, u16)> + 'a,
+    image: &'a [u16],
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    iter.step_by(3)
+        .map(move |(i, _)| [0, 1, 2].map(|c| get_pixel(image, i + c)))
+}
+
 macro_rules! gen_linear {
     ($name:ident, $fn:expr) => {
         #[inline(always)]
@@ -36,10 +60,286 @@ gen_linear!(elinear_bggr, enhanced_linear::bggr);
 gen_linear!(elinear_grbg, enhanced_linear::grbg);
 gen_linear!(elinear_gbrg, enhanced_linear::gbrg);
 
+gen_linear!(mhc_rggb, mhc::rggb);
+gen_linear!(mhc_bggr, mhc::bggr);
+gen_linear!(mhc_grbg, mhc::grbg);
+gen_linear!(mhc_gbrg, mhc::gbrg);
+
+gen_linear!(directed_rggb, directed::rggb);
+gen_linear!(directed_bggr, directed::bggr);
+gen_linear!(directed_grbg, directed::grbg);
+gen_linear!(directed_gbrg, directed::gbrg);
+
+gen_linear!(vng_rggb, vng::rggb);
+gen_linear!(vng_bggr, vng::bggr);
+gen_linear!(vng_grbg, vng::grbg);
+gen_linear!(vng_gbrg, vng::gbrg);
+
+/// Demosaics a bayer image with AHD, which needs the whole image before the first pixel
+#[inline(always)]
+pub fn ahd<'a>(
+    iter: impl Iterator<Item = (usize, u16)> + 'a,
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    let colors = bayer_colors(cfa_pattern).unwrap_or([0, 1, 1, 2]);
+    let pixels = ahd::demosaic(image, width, height, colors);
+    iter.map(move |(i, _)| pixels[i])
+}
+
+/// Demosaics a bayer image with DCB, which needs the whole image before the first pixel
+#[inline(always)]
+pub fn dcb<'a>(
+    iter: impl Iterator<Item = (usize, u16)> + 'a,
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+    iterations: u8,
+    refine: bool,
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    let colors = bayer_colors(cfa_pattern).unwrap_or([0, 1, 1, 2]);
+    let pixels = dcb::demosaic(image, width, height, colors, iterations, refine);
+    iter.map(move |(i, _)| pixels[i])
+}
+
+/// Demosaics a bayer image with RCD, which needs the whole image before the first pixel
+#[inline(always)]
+pub fn rcd<'a>(
+    iter: impl Iterator<Item = (usize, u16)> + 'a,
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    let colors = bayer_colors(cfa_pattern).unwrap_or([0, 1, 1, 2]);
+    let pixels = rcd::demosaic(image, width, height, colors);
+    iter.map(move |(i, _)| pixels[i])
+}
+
+/// Demosaics a bayer image with AMaZE, which needs the whole image before the first pixel
+#[cfg(feature = "amaze")]
+#[inline(always)]
+pub fn amaze<'a>(
+    iter: impl Iterator<Item = (usize, u16)> + 'a,
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    let colors = bayer_colors(cfa_pattern).unwrap_or([0, 1, 1, 2]);
+    let pixels = amaze::demosaic(image, width, height, colors);
+    iter.map(move |(i, _)| pixels[i])
+}
+
+/// Demosaics a bayer image with PPG, which needs the whole image before the first pixel
+#[inline(always)]
+pub fn ppg<'a>(
+    iter: impl Iterator<Item = (usize, u16)> + 'a,
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    let colors = bayer_colors(cfa_pattern).unwrap_or([0, 1, 1, 2]);
+    let pixels = ppg::demosaic(image, width, height, colors);
+    iter.map(move |(i, _)| pixels[i])
+}
+
+/// Demosaics a bayer image with LMMSE, which needs the whole image before the first pixel
+#[inline(always)]
+pub fn lmmse<'a>(
+    iter: impl Iterator<Item = (usize, u16)> + 'a,
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    let colors = bayer_colors(cfa_pattern).unwrap_or([0, 1, 1, 2]);
+    let pixels = lmmse::demosaic(image, width, height, colors);
+    iter.map(move |(i, _)| pixels[i])
+}
+
+/// Demosaics an X-Trans image with Markesteijn, which needs the whole image before the first
+/// pixel. `origin` is the position of the image in its pattern, which moves with a crop.
+#[inline(always)]
+pub fn markesteijn<'a>(
+    iter: impl Iterator<Item = (usize, u16)> + 'a,
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+    passes: u8,
+    origin: (usize, usize),
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    let colors = xtrans_colors(cfa_pattern, origin).unwrap_or(XTRANS);
+    let pixels = markesteijn::demosaic(image, width, height, colors, passes);
+    iter.map(move |(i, _)| pixels[i])
+}
+
+/// Demosaics an X-Trans image quickly for previews, which needs the whole image before the
+/// first pixel
+#[inline(always)]
+pub fn xtrans_fast<'a>(
+    iter: impl Iterator<Item = (usize, u16)> + 'a,
+    image: &'a [u16],
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+) -> impl Iterator<Item = [u16; 3]> + 'a {
+    let colors = xtrans_colors(cfa_pattern, (0, 0)).unwrap_or(XTRANS);
+    let pixels = xtrans_fast::demosaic(image, width, height, colors);
+    iter.map(move |(i, _)| pixels[i])
+}
+
+/// Bins every 2x2 cell of a bayer image into a pixel of an image of half the width and the
+/// height, with the interleaved RGB samples which `rgb` takes. The last row and column of the
+/// odd sizes are dropped.
+pub fn super_pixel(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    cfa_pattern: CFAPattern,
+) -> Option<(Vec<u16>, usize, usize)> {
+    let colors = bayer_colors(cfa_pattern)?;
+    let (half_width, half_height) = (width / 2, height / 2);
+    let mut out = Vec::with_capacity(half_width * half_height * 3);
+    for y in 0..half_height {
+        for x in 0..half_width {
+            let mut rgb = [0u32; 3];
+            for (k, &c) in colors.iter().enumerate() {
+                rgb[c] += get_pixel(image, (y * 2 + k / 2) * width + x * 2 + k % 2) as u32;
+            }
+            rgb[1] /= 2;
+            out.extend(rgb.map(|v| v as u16));
+        }
+    }
+    Some((out, half_width, half_height))
+}
+
+/// The colors of the 2x2 block of a bayer pattern, 0 is red, 1 green and 2 blue
+pub(self) fn bayer_colors(cfa_pattern: CFAPattern) -> Option<[usize; 4]> {
+    match cfa_pattern {
+        CFAPattern::RGGB => Some([0, 1, 1, 2]),
+        CFAPattern::GRBG => Some([1, 0, 2, 1]),
+        CFAPattern::GBRG => Some([1, 2, 0, 1]),
+        CFAPattern::BGGR => Some([2, 1, 1, 0]),
+        _ => None,
+    }
+}
+
+// the 6x6 block of the X-Trans pattern starting with RBGBRG
+const XTRANS: [[usize; 6]; 6] = [
+    [0, 2, 1, 2, 0, 1],
+    [1, 1, 0, 1, 1, 2],
+    [1, 1, 2, 1, 1, 0],
+    [2, 0, 1, 0, 2, 1],
+    [1, 1, 2, 1, 1, 0],
+    [1, 1, 0, 1, 1, 2],
+];
+
+/// The colors of the 6x6 block of an X-Trans pattern for the image starting at `x` and `y` of
+/// the pattern
+pub(self) fn xtrans_colors(
+    cfa_pattern: CFAPattern,
+    (x, y): (usize, usize),
+) -> Option<[[usize; 6]; 6]> {
+    let first_row = match cfa_pattern {
+        CFAPattern::XTrans0 => 0,
+        CFAPattern::XTrans1 => 1,
+        _ => return None,
+    };
+    Some(std::array::from_fn(|row| {
+        std::array::from_fn(|col| XTRANS[(first_row + y + row) % 6][(x + col) % 6])
+    }))
+}
+
+/// The geometry of a bayer image, the coordinates beyond the borders are mirrored
+pub(self) struct Grid {
+    width: usize,
+    height: usize,
+    colors: [usize; 4],
+}
+
+impl Grid {
+    #[inline(always)]
+    fn at(&self, x: usize, y: usize, dx: isize, dy: isize) -> usize {
+        mirror(y as isize + dy, self.height) * self.width + mirror(x as isize + dx, self.width)
+    }
+    #[inline(always)]
+    fn color(&self, x: usize, y: usize) -> usize {
+        self.colors[(y & 1) * 2 + (x & 1)]
+    }
+    /// The coordinates and the index of every pixel
+    fn pixels(&self) -> impl Iterator<Item = (usize, usize, usize)> {
+        let width = self.width;
+        (0..self.width * self.height).map(move |i| (i % width, i / width, i))
+    }
+    /// The coordinates, the index and the color of the red and the blue pixels
+    fn red_blue(&self) -> impl Iterator<Item = (usize, usize, usize, usize)> + '_ {
+        self.pixels()
+            .map(|(x, y, i)| (x, y, i, self.color(x, y)))
+            .filter(|&(_, _, _, c)| c != 1)
+    }
+}
+
+// linear sRGB to XYZ relative to the D65 white point
+pub(self) const RGB_TO_XYZ: [f32; 9] = [
+    0.412453 / 0.950456,
+    0.357580 / 0.950456,
+    0.180423 / 0.950456,
+    0.212671,
+    0.715160,
+    0.072169,
+    0.019334 / 1.088754,
+    0.119193 / 1.088754,
+    0.950227 / 1.088754,
+];
+
+/// The CIELab function of every 16 bit value
+pub(self) fn cbrt_lut() -> Vec<f32> {
+    (0..=u16::MAX as usize)
+        .map(|i| {
+            let t = i as f32 / u16::MAX as f32;
+            if t > 0.008856 {
+                t.cbrt()
+            } else {
+                7.787 * t + 16.0 / 116.0
+            }
+        })
+        .collect()
+}
+
+#[inline(always)]
+pub(self) fn to_lab(rgb: [i32; 3], cbrt: &[f32]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|x| x as f32);
+    let [x, y, z] = [0, 3, 6].map(|row| {
+        let v = RGB_TO_XYZ[row] * r + RGB_TO_XYZ[row + 1] * g + RGB_TO_XYZ[row + 2] * b;
+        cbrt[(v as usize).min(u16::MAX as usize)]
+    });
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
 #[inline(always)]
 pub(self) fn get_pixel(image: &[u16], i: usize) -> u16 {
     unsafe { *image.get_unchecked(i) }
 }
+/// The pixel at `x` and `y`, the coordinates out of the image are mirrored at its borders,
+/// which keeps the CFA color of bayer images
+#[inline(always)]
+pub(self) fn get_mirrored_pixel(image: &[u16], w: usize, h: usize, x: isize, y: isize) -> u16 {
+    get_pixel(image, mirror(y, h) * w + mirror(x, w))
+}
+/// The coordinate `v` of an axis of `n` pixels mirrored at both ends
+#[inline(always)]
+pub(self) fn mirror(v: isize, n: usize) -> usize {
+    let last = n as isize - 1;
+    let v = if v < 0 { -v } else { v };
+    let v = if v > last { 2 * last - v } else { v };
+    v.clamp(0, last) as usize
+}
 #[inline(always)]
 pub(self) fn avg<const N: usize>(image: &[u16], indexes: &[usize; N]) -> u16 {
     let mut sum = 0;
@@ -0,0 +1,114 @@
+use super::*;
+
+const MAX: i32 = u16::MAX as i32;
+
+/// Patterned Pixel Grouping of Chuan-kai Lin, for the bayer images.
+///
+/// Green at red and blue is interpolated along the axis of the smaller gradient. Red and blue
+/// at green follow the color differences of their neighbors, red at blue and blue at red follow
+/// the diagonal of the smaller gradient. At last green is refined by the color differences
+/// along the axis it was interpolated on.
+pub(super) fn demosaic(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: [usize; 4],
+) -> Vec<[u16; 3]> {
+    let grid = Grid {
+        width,
+        height,
+        colors,
+    };
+    let mut rgb = vec![[0i32; 3]; width * height];
+    for (x, y, i) in grid.pixels() {
+        rgb[i][grid.color(x, y)] = get_pixel(image, i) as i32;
+    }
+
+    let axes = interpolate_green(&grid, &mut rgb);
+    interpolate_red_blue_at_green(&grid, &mut rgb);
+    interpolate_red_blue_at_blue_red(&grid, &mut rgb);
+    refine_green(&grid, &mut rgb, &axes);
+
+    rgb.into_iter()
+        .map(|x| x.map(|v| v.clamp(0, MAX) as u16))
+        .collect()
+}
+
+/// Green at red and blue along the smoother axis, which is returned for every pixel
+fn interpolate_green(grid: &Grid, rgb: &mut [[i32; 3]]) -> Vec<(isize, isize)> {
+    let mut axes = vec![(1, 0); rgb.len()];
+    for (x, y, i, c) in grid.red_blue() {
+        let get =
+            |t: isize, (dx, dy): (isize, isize), c: usize| rgb[grid.at(x, y, t * dx, t * dy)][c];
+        // the estimation and the gradient along the axis
+        let estimate = |d| {
+            let guess =
+                (get(-1, d, 1) + get(0, d, c) + get(1, d, 1)) * 2 - get(-2, d, c) - get(2, d, c);
+            let gradient = ((get(-2, d, c) - get(0, d, c)).abs()
+                + (get(2, d, c) - get(0, d, c)).abs()
+                + (get(-1, d, 1) - get(1, d, 1)).abs())
+                * 3
+                + ((get(3, d, 1) - get(1, d, 1)).abs() + (get(-3, d, 1) - get(-1, d, 1)).abs()) * 2;
+            (guess, gradient)
+        };
+        let (horizontal, vertical) = (estimate((1, 0)), estimate((0, 1)));
+        let (guess, axis) = if horizontal.1 > vertical.1 {
+            (vertical.0, (0, 1))
+        } else {
+            (horizontal.0, (1, 0))
+        };
+        let (a, b) = (get(-1, axis, 1), get(1, axis, 1));
+        rgb[i][1] = (guess >> 2).clamp(a.min(b), a.max(b));
+        axes[i] = axis;
+    }
+    axes
+}
+
+fn interpolate_red_blue_at_green(grid: &Grid, rgb: &mut [[i32; 3]]) {
+    for (x, y, i) in grid.pixels() {
+        if grid.color(x, y) != 1 {
+            continue;
+        }
+        let c = grid.color(x + 1, y);
+        for (c, (dx, dy)) in [(c, (1, 0)), (2 - c, (0, 1))] {
+            let (before, after) = (rgb[grid.at(x, y, -dx, -dy)], rgb[grid.at(x, y, dx, dy)]);
+            let v = (before[c] + after[c] + 2 * rgb[i][1] - before[1] - after[1]) >> 1;
+            rgb[i][c] = v.clamp(0, MAX);
+        }
+    }
+}
+
+fn interpolate_red_blue_at_blue_red(grid: &Grid, rgb: &mut [[i32; 3]]) {
+    for (x, y, i, own) in grid.red_blue() {
+        let c = 2 - own;
+        let g = rgb[i][1];
+        // the estimation and the gradient along the diagonal
+        let [first, second] = [(1, 1), (-1, 1)].map(|(dx, dy)| {
+            let (before, after) = (rgb[grid.at(x, y, -dx, -dy)], rgb[grid.at(x, y, dx, dy)]);
+            let gradient =
+                (before[c] - after[c]).abs() + (before[1] - g).abs() + (after[1] - g).abs();
+            (
+                before[c] + after[c] + 2 * g - before[1] - after[1],
+                gradient,
+            )
+        });
+        let v = match first.1.cmp(&second.1) {
+            std::cmp::Ordering::Less => first.0 >> 1,
+            std::cmp::Ordering::Greater => second.0 >> 1,
+            std::cmp::Ordering::Equal => (first.0 + second.0) >> 2,
+        };
+        rgb[i][c] = v.clamp(0, MAX);
+    }
+}
+
+/// Green at red and blue from the differences of green to their color at the neighbors along
+/// the axis of the interpolation, limited to the green of the neighbors
+fn refine_green(grid: &Grid, rgb: &mut [[i32; 3]], axes: &[(isize, isize)]) {
+    for (x, y, i, c) in grid.red_blue() {
+        let (dx, dy) = axes[i];
+        let (before, after) = (rgb[grid.at(x, y, -dx, -dy)], rgb[grid.at(x, y, dx, dy)]);
+        let difference = (before[1] - before[c] + after[1] - after[c]) >> 1;
+        let (a, b) = (before[1], after[1]);
+        rgb[i][1] = (rgb[i][c] + difference).clamp(a.min(b), a.max(b));
+    }
+}
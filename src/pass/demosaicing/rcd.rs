@@ -0,0 +1,175 @@
+use super::*;
+
+const EPS: f32 = 1e-5;
+const EPS_SQ: f32 = 1e-10;
+
+/// Ratio Corrected Demosaicing of Luis Sanz Rodríguez, for the bayer images.
+///
+/// The horizontal and vertical directions are discriminated by the high pass of the CFA
+/// samples, green is the estimation of both directions corrected by the ratio of a low pass
+/// filter, then red and blue follow the color differences along the diagonals and the cardinal
+/// directions weighted the same way.
+pub(super) fn demosaic(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: [usize; 4],
+) -> Vec<[u16; 3]> {
+    let grid = Grid {
+        width,
+        height,
+        colors,
+    };
+    let cfa = (0..width * height)
+        .map(|i| get_pixel(image, i) as f32 / u16::MAX as f32)
+        .collect::<Vec<_>>();
+
+    let vh_dir = directions(&grid, &cfa);
+    let lpf = low_pass(&grid, &cfa);
+
+    let mut rgb = vec![[0f32; 3]; width * height];
+    for (x, y, i) in grid.pixels() {
+        rgb[i][grid.color(x, y)] = cfa[i];
+    }
+    interpolate_green(&grid, &cfa, &vh_dir, &lpf, &mut rgb);
+    drop(lpf);
+    interpolate_red_blue_at_red_blue(&grid, &cfa, &mut rgb);
+    interpolate_red_blue_at_green(&grid, &vh_dir, &mut rgb);
+
+    rgb.into_iter()
+        .map(|x| x.map(|v| (v * u16::MAX as f32).round().clamp(0.0, u16::MAX as f32) as u16))
+        .collect()
+}
+
+/// The squares of the high pass of the color differences along `(dx, dy)`, summed over the
+/// pixel and its neighbors in the same direction
+#[inline(always)]
+fn high_pass(grid: &Grid, cfa: &[f32], x: usize, y: usize, (dx, dy): (isize, isize)) -> f32 {
+    let get = |t: isize| cfa[grid.at(x, y, t * dx, t * dy)];
+    let square = |t: isize| {
+        let v = (get(t - 3) - get(t - 1) - get(t + 1) + get(t + 3))
+            - 3.0 * (get(t - 2) + get(t + 2))
+            + 6.0 * get(t);
+        v * v
+    };
+    (square(-1) + square(0) + square(1)).max(EPS_SQ)
+}
+
+/// The estimations of both ways along a direction, each weighted by the gradient of the other
+#[inline(always)]
+fn weigh((gradient_1, estimation_1): (f32, f32), (gradient_2, estimation_2): (f32, f32)) -> f32 {
+    (gradient_2 * estimation_1 + gradient_1 * estimation_2) / (gradient_1 + gradient_2)
+}
+
+/// The weight of the horizontal direction, from 0 to 1
+fn directions(grid: &Grid, cfa: &[f32]) -> Vec<f32> {
+    grid.pixels()
+        .map(|(x, y, _)| {
+            let vertical = high_pass(grid, cfa, x, y, (0, 1));
+            let horizontal = high_pass(grid, cfa, x, y, (1, 0));
+            vertical / (vertical + horizontal)
+        })
+        .collect()
+}
+
+/// The low pass of red and blue with the samples around them
+fn low_pass(grid: &Grid, cfa: &[f32]) -> Vec<f32> {
+    let mut lpf = vec![0f32; cfa.len()];
+    for (x, y, i, _) in grid.red_blue() {
+        let get = |dx, dy| cfa[grid.at(x, y, dx, dy)];
+        lpf[i] = get(0, 0)
+            + 0.5 * (get(0, -1) + get(0, 1) + get(-1, 0) + get(1, 0))
+            + 0.25 * (get(-1, -1) + get(1, -1) + get(-1, 1) + get(1, 1));
+    }
+    lpf
+}
+
+/// The direction of the pixel, or of its diagonal neighbors when they are more decisive
+#[inline(always)]
+fn discrimination(grid: &Grid, dir: &[f32], x: usize, y: usize) -> f32 {
+    let central = dir[grid.at(x, y, 0, 0)];
+    let neighborhood = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+        .iter()
+        .map(|&(dx, dy)| dir[grid.at(x, y, dx, dy)])
+        .sum::<f32>()
+        / 4.0;
+    if (0.5 - central).abs() < (0.5 - neighborhood).abs() {
+        neighborhood
+    } else {
+        central
+    }
+}
+
+fn interpolate_green(grid: &Grid, cfa: &[f32], vh_dir: &[f32], lpf: &[f32], rgb: &mut [[f32; 3]]) {
+    for (x, y, i, _) in grid.red_blue() {
+        let get = |t: isize, (dx, dy): (isize, isize)| cfa[grid.at(x, y, t * dx, t * dy)];
+        let lpf_at = |t: isize, (dx, dy): (isize, isize)| lpf[grid.at(x, y, t * dx, t * dy)];
+        // the gradient and the ratio corrected estimation towards the direction
+        let estimate = |d: (isize, isize)| {
+            let gradient = EPS
+                + (get(1, d) - get(-1, d)).abs()
+                + (get(0, d) - get(2, d)).abs()
+                + (get(1, d) - get(3, d)).abs()
+                + (get(2, d) - get(4, d)).abs();
+            let ratio = (lpf_at(0, d) - lpf_at(2, d)) / (EPS + lpf_at(0, d) + lpf_at(2, d));
+            (gradient, get(1, d) * (1.0 + ratio))
+        };
+        let along = |(dx, dy): (isize, isize)| weigh(estimate((dx, dy)), estimate((-dx, -dy)));
+        let disc = discrimination(grid, vh_dir, x, y);
+        let g = disc * along((1, 0)) + (1.0 - disc) * along((0, 1));
+        rgb[i][1] = g.clamp(0.0, 1.0);
+    }
+}
+
+fn interpolate_red_blue_at_red_blue(grid: &Grid, cfa: &[f32], rgb: &mut [[f32; 3]]) {
+    let mut pq_dir = vec![0f32; cfa.len()];
+    for (x, y, i, _) in grid.red_blue() {
+        let p = high_pass(grid, cfa, x, y, (1, 1));
+        let q = high_pass(grid, cfa, x, y, (1, -1));
+        pq_dir[i] = p / (p + q);
+    }
+
+    for (x, y, i, own) in grid.red_blue() {
+        let c = 2 - own;
+        let get =
+            |t: isize, (dx, dy): (isize, isize), c: usize| rgb[grid.at(x, y, t * dx, t * dy)][c];
+        // the gradient and the color difference towards the diagonal
+        let estimate = |d: (isize, isize)| {
+            let gradient = EPS
+                + (get(1, d, c) - get(-1, d, c)).abs()
+                + (get(1, d, c) - get(3, d, c)).abs()
+                + (get(0, d, 1) - get(2, d, 1)).abs();
+            (gradient, get(1, d, c) - get(1, d, 1))
+        };
+        let along = |(dx, dy): (isize, isize)| weigh(estimate((dx, dy)), estimate((-dx, -dy)));
+        let disc = discrimination(grid, &pq_dir, x, y);
+        let v = rgb[i][1] + (1.0 - disc) * along((-1, -1)) + disc * along((1, -1));
+        rgb[i][c] = v.clamp(0.0, 1.0);
+    }
+}
+
+fn interpolate_red_blue_at_green(grid: &Grid, vh_dir: &[f32], rgb: &mut [[f32; 3]]) {
+    for (x, y, i) in grid.pixels() {
+        if grid.color(x, y) != 1 {
+            continue;
+        }
+        let get =
+            |t: isize, (dx, dy): (isize, isize), c: usize| rgb[grid.at(x, y, t * dx, t * dy)][c];
+        let disc = discrimination(grid, vh_dir, x, y);
+        let values = [0, 2].map(|c| {
+            // the gradient and the color difference towards the direction
+            let estimate = |d: (isize, isize)| {
+                let gradient = EPS
+                    + (get(0, d, 1) - get(2, d, 1)).abs()
+                    + (get(-1, d, c) - get(1, d, c)).abs()
+                    + (get(1, d, c) - get(3, d, c)).abs();
+                (gradient, get(1, d, c) - get(1, d, 1))
+            };
+            let along = |(dx, dy): (isize, isize)| weigh(estimate((dx, dy)), estimate((-dx, -dy)));
+            let v = rgb[i][1] + (1.0 - disc) * along((0, -1)) + disc * along((-1, 0));
+            v.clamp(0.0, 1.0)
+        });
+        rgb[i][0] = values[0];
+        rgb[i][2] = values[1];
+    }
+}
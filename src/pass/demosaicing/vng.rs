@@ -0,0 +1,141 @@
+use super::*;
+use once_cell::sync::Lazy;
+
+/// An offset of (row, column) from the demosaiced pixel
+type Offset = (isize, isize);
+
+// N, NE, E, SE, S, SW, W and NW
+const DIRECTIONS: [Offset; 8] = [
+    (-1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+];
+const RGGB: [usize; 4] = [0, 1, 1, 2];
+
+struct Direction {
+    /// Samples of the same color two pixels apart along the direction, with their weight in
+    /// halves, the gradient is the weighted sum of their differences
+    pairs: Vec<(Offset, Offset, i32)>,
+    /// The samples of every color closest to the axis of the direction
+    samples: [Vec<Offset>; 3],
+}
+
+/// The directions of the four positions of the RGGB pattern, the other patterns are shifted
+static POSITIONS: Lazy<[[Direction; 8]; 4]> =
+    Lazy::new(|| [0, 1, 2, 3].map(|position| DIRECTIONS.map(|d| direction(position, d))));
+
+fn color_at(position: usize, (y, x): Offset) -> usize {
+    let row = (((position >> 1) as isize + y) & 1) as usize;
+    let column = (((position & 1) as isize + x) & 1) as usize;
+    RGGB[row * 2 + column]
+}
+
+fn direction(position: usize, (dy, dx): Offset) -> Direction {
+    let is_diagonal = dy != 0 && dx != 0;
+    // the perpendicular of the cardinal directions
+    let (py, px) = (dx, dy);
+
+    let pairs = if is_diagonal {
+        vec![
+            ((-dy, -dx), (dy, dx), 2),
+            ((0, 0), (2 * dy, 2 * dx), 2),
+            ((0, -dx), (2 * dy, dx), 1),
+            ((-dy, 0), (dy, 2 * dx), 1),
+        ]
+    } else {
+        let mut pairs = vec![];
+        for t in 1..=2 {
+            for s in -1..=1 {
+                let from = (t * dy + s * py, t * dx + s * px);
+                let to = ((t - 2) * dy + s * py, (t - 2) * dx + s * px);
+                pairs.push((from, to, if s == 0 { 2 } else { 1 }));
+            }
+        }
+        pairs
+    };
+
+    // the 3x3 block from the pixel towards the direction
+    let block = (0..=2).flat_map(|i| {
+        (-1..=1).map(move |j| match is_diagonal {
+            true => (i * dy, (j + 1) * dx),
+            false => (i * dy + j * py, i * dx + j * px),
+        })
+    });
+    let distance = |(y, x): Offset| (y * dx - x * dy).abs();
+    let mut samples: [Vec<Offset>; 3] = Default::default();
+    for offset in block {
+        let samples = &mut samples[color_at(position, offset)];
+        match samples.first().map(|&first| distance(first)) {
+            Some(d) if d < distance(offset) => {}
+            Some(d) if d == distance(offset) => samples.push(offset),
+            _ => *samples = vec![offset],
+        }
+    }
+
+    Direction { pairs, samples }
+}
+
+/// Demosaics a pixel with Variable Number of Gradients.
+///
+/// The gradients of the eight directions are taken from the 5x5 neighborhood of the pixel,
+/// the directions under the threshold are averaged and the missing colors are the pixel plus
+/// their average difference to its color. Nothing but the output is allocated.
+#[inline(always)]
+fn pixel(i: usize, v: u16, image: &[u16], w: usize, h: usize, shift: (usize, usize)) -> [u16; 3] {
+    let (x, y) = (i % w, i / w);
+    let position = ((y + shift.1) & 1) * 2 + ((x + shift.0) & 1);
+    let get =
+        |(dy, dx): Offset| get_mirrored_pixel(image, w, h, x as isize + dx, y as isize + dy) as i32;
+
+    let directions = &POSITIONS[position];
+    let gradients: [i32; 8] = std::array::from_fn(|k| {
+        directions[k]
+            .pairs
+            .iter()
+            .map(|&(from, to, weight)| (get(from) - get(to)).abs() * weight)
+            .sum::<i32>()
+    });
+    let min = gradients.iter().copied().min().unwrap_or(0);
+    let max = gradients.iter().copied().max().unwrap_or(0);
+    let threshold = min + (max >> 1);
+
+    let mut sums = [0i32; 3];
+    let mut count = 0;
+    for (direction, &gradient) in directions.iter().zip(gradients.iter()) {
+        if gradient > threshold {
+            continue;
+        }
+        for (sum, samples) in sums.iter_mut().zip(direction.samples.iter()) {
+            *sum += samples.iter().map(|&offset| get(offset)).sum::<i32>() / samples.len() as i32;
+        }
+        count += 1;
+    }
+
+    let own = RGGB[position];
+    [0, 1, 2].map(|c| match c == own {
+        true => v,
+        false => (v as i32 + (sums[c] - sums[own]) / count).clamp(0, u16::MAX as i32) as u16,
+    })
+}
+
+#[inline(always)]
+pub(super) fn rggb(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (0, 0))
+}
+#[inline(always)]
+pub(super) fn grbg(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (1, 0))
+}
+#[inline(always)]
+pub(super) fn gbrg(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (0, 1))
+}
+#[inline(always)]
+pub(super) fn bggr(i: usize, v: u16, image: &[u16], w: usize, h: usize) -> [u16; 3] {
+    pixel(i, v, image, w, h, (1, 1))
+}
@@ -0,0 +1,142 @@
+use super::*;
+
+/// The 3x3 blocks of the pattern along an axis, the pixels of a partial block at either end
+/// join the full block next to it
+struct Blocks {
+    count: usize,
+    // the block of every pixel
+    block: Vec<usize>,
+    // the blocks whose centers surround every pixel and the weight of the second in thirds
+    between: Vec<(usize, usize, i32)>,
+}
+
+impl Blocks {
+    fn new(n: usize, start: usize) -> Self {
+        let count = (n.saturating_sub(start) / 3).max(1);
+        let clamp = |k: isize| k.clamp(0, count as isize - 1) as usize;
+        let block = (0..n)
+            .map(|v| clamp((v as isize - start as isize).div_euclid(3)))
+            .collect();
+        let between = (0..n)
+            .map(|v| {
+                let t = v as isize - start as isize - 1;
+                let k = t.div_euclid(3);
+                (clamp(k), clamp(k + 1), t.rem_euclid(3) as i32)
+            })
+            .collect();
+        Blocks {
+            count,
+            block,
+            between,
+        }
+    }
+}
+
+/// Fast demosaicing of an X-Trans image for previews, with the colors of its 6x6 pattern.
+///
+/// Red and blue pixels take the average of the green pixels next to them. The missing colors
+/// follow green with the differences of the colors averaged over the 3x3 blocks of the pattern,
+/// each of which holds 2 red, 5 green and 2 blue pixels, interpolated bilinearly between the
+/// centers of the blocks.
+pub(super) fn demosaic(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: [[usize; 6]; 6],
+) -> Vec<[u16; 3]> {
+    // a block starts with a red and a blue pixel, which no other row of the pattern has
+    let (x0, y0) = (0..9)
+        .map(|k| (k % 3, k / 3))
+        .find(|&(x, y)| colors[y][x] != 1 && colors[y][x + 1] != 1)
+        .unwrap_or((0, 0));
+    let columns = Blocks::new(width, x0);
+    let rows = Blocks::new(height, y0);
+    let differences = color_differences(image, width, height, &colors, &columns, &rows);
+
+    let mut out = Vec::with_capacity(width * height);
+    for y in 0..height {
+        // the differences of the blocks interpolated between the rows of their centers
+        let (top, bottom, wy) = rows.between[y];
+        let row_differences = (0..columns.count)
+            .map(|k| {
+                let [top, bottom] = [top, bottom].map(|row| differences[row * columns.count + k]);
+                [0, 1].map(|c| top[c] * (3 - wy) + bottom[c] * wy)
+            })
+            .collect::<Vec<_>>();
+        let row = &image[y * width..(y + 1) * width];
+        let above = y.checked_sub(1).map(|y| &image[y * width..(y + 1) * width]);
+        let below = (y + 1 < height).then(|| &image[(y + 1) * width..(y + 2) * width]);
+        let pattern = &colors[y % 6];
+        let [pattern_above, pattern_below] = [5, 1].map(|dy| &colors[(y + dy) % 6]);
+
+        for (x, &v) in row.iter().enumerate() {
+            let c = pattern[x % 6];
+            let v = v as i32;
+            let (left, right, wx) = columns.between[x];
+            let [red, blue] = [0, 1].map(|k| {
+                (row_differences[left][k] * (3 - wx) + row_differences[right][k] * wx) / 9
+            });
+
+            let g = if c == 1 {
+                v
+            } else {
+                let mut greens = [0; 2];
+                let mut add = |g: u16| {
+                    greens[0] += g as i32;
+                    greens[1] += 1;
+                };
+                if x > 0 && pattern[(x + 5) % 6] == 1 {
+                    add(row[x - 1]);
+                }
+                if x + 1 < width && pattern[(x + 1) % 6] == 1 {
+                    add(row[x + 1]);
+                }
+                match above {
+                    Some(above) if pattern_above[x % 6] == 1 => add(above[x]),
+                    _ => {}
+                }
+                match below {
+                    Some(below) if pattern_below[x % 6] == 1 => add(below[x]),
+                    _ => {}
+                }
+                match greens {
+                    [_, 0] => v - if c == 0 { red } else { blue },
+                    [sum, count] => sum / count,
+                }
+            };
+            let mut rgb = [g + red, g, g + blue];
+            rgb[c] = v;
+            out.push(rgb.map(|v| v.clamp(0, u16::MAX as i32) as u16));
+        }
+    }
+    out
+}
+
+/// Red minus green and blue minus green averaged over every block
+fn color_differences(
+    image: &[u16],
+    width: usize,
+    height: usize,
+    colors: &[[usize; 6]; 6],
+    columns: &Blocks,
+    rows: &Blocks,
+) -> Vec<[i32; 2]> {
+    let mut sums = vec![[0u32; 3]; columns.count * rows.count];
+    let mut counts = vec![[0u32; 3]; columns.count * rows.count];
+    for y in 0..height {
+        let pattern = &colors[y % 6];
+        let blocks = rows.block[y] * columns.count;
+        for x in 0..width {
+            let (j, c) = (blocks + columns.block[x], pattern[x % 6]);
+            sums[j][c] += get_pixel(image, y * width + x) as u32;
+            counts[j][c] += 1;
+        }
+    }
+    sums.iter()
+        .zip(&counts)
+        .map(|(sum, count)| {
+            let [r, g, b] = [0, 1, 2].map(|c| (sum[c] / count[c].max(1)) as i32);
+            [r - g, b - g]
+        })
+        .collect()
+}
@@ -17,13 +17,18 @@ pub fn u16rgb_to_u16rgba(iter: impl Iterator<Item = [u16; 3]>) -> impl Iterator<
 }
 
 
-// #[inline(always)]
-// pub fn sub_black_level(
-//     iter: impl Iterator<Item = u16>,
-//     black_level: u16,
-// ) -> impl Iterator<Item = u16> {
-//     iter.map(move |v| v.saturating_sub(black_level))
-// }
+/// Subtracts the black levels of the positions of the 2x2 CFA block in place, the samples
+/// below them clamp to zero
+#[inline(always)]
+pub fn sub_black_level(image: &mut [u16], width: usize, black_level: &[u16; 4]) {
+    if black_level.iter().all(|&x| x == 0) {
+        return;
+    }
+    let width = width.max(1);
+    image.iter_mut().enumerate().for_each(|(i, x)| {
+        *x = x.saturating_sub(black_level[(i / width % 2) * 2 + i % width % 2]);
+    });
+}
 
 // #[inline(always)]
 // pub fn level_scale_up(
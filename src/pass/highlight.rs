@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+
+const BIT_SHIFT: u32 = 13u32;
+// the neighbors pass their hue into the clipped areas one pixel at a time
+const MAX_SPREADING_PASSES: usize = 64;
+
+/// Reconstructs the channels of the pixels which clip at `white_level` by the hue of their
+/// neighbors, like the highlight recovery of dcraw. The ratios of red and blue to green of the
+/// unclipped pixels, white balanced, spread into the clipped areas as the average of the
+/// neighbors. The clipped channels are raised to follow them as far as the 16 bits go, the
+/// white balance itself is left to the passes after.
+pub fn reconstruct_highlights(
+    pixels: &mut [[u16; 3]],
+    width: usize,
+    height: usize,
+    white_level: u16,
+    white_balance: &[i32; 3],
+) {
+    let is_clipped = |pixel: &[u16; 3]| pixel.iter().any(|&x| x >= white_level);
+    if !pixels.iter().any(is_clipped) {
+        return;
+    }
+    let scale = white_balance.map(|x| x as f32 / (1 << BIT_SHIFT) as f32);
+
+    let mut ratios = pixels
+        .iter()
+        .map(|pixel| {
+            let [r, g, b] = [0, 1, 2].map(|c| pixel[c] as f32 * scale[c]);
+            if is_clipped(pixel) || g <= 0.0 {
+                None
+            } else {
+                Some([r / g, b / g])
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut unknown = (0..pixels.len())
+        .filter(|&i| ratios[i].is_none())
+        .collect::<Vec<_>>();
+    for _ in 0..MAX_SPREADING_PASSES {
+        let found = unknown
+            .iter()
+            .filter_map(|&i| {
+                let (x, y) = (i % width, i / width);
+                let neighbors = (y.saturating_sub(1)..(y + 2).min(height))
+                    .flat_map(|y| (x.saturating_sub(1)..(x + 2).min(width)).map(move |x| (x, y)))
+                    .filter_map(|(x, y)| ratios[y * width + x]);
+                let (sum, count) = neighbors.fold(([0f32; 2], 0), |(sum, count), ratio| {
+                    ([sum[0] + ratio[0], sum[1] + ratio[1]], count + 1)
+                });
+                (count > 0).then(|| (i, sum.map(|x| x / count as f32)))
+            })
+            .collect::<Vec<_>>();
+        if found.is_empty() {
+            break;
+        }
+        for &(i, ratio) in &found {
+            ratios[i] = Some(ratio);
+        }
+        unknown.retain(|&i| ratios[i].is_none());
+    }
+
+    for (pixel, ratio) in pixels.iter_mut().zip(ratios) {
+        if !is_clipped(pixel) {
+            continue;
+        }
+        // the areas without any unclipped pixel are neutral
+        let [r, b] = ratio.unwrap_or([1.0; 2]);
+        let hue = [r, 1.0, b].map(|x| x.max(f32::EPSILON));
+        let balanced = [0, 1, 2].map(|c| pixel[c] as f32 * scale[c]);
+        let brightness = (0..3).map(|c| balanced[c] / hue[c]).fold(0f32, f32::max);
+        for c in 0..3 {
+            if pixel[c] >= white_level {
+                let value = (brightness * hue[c] / scale[c]).max(pixel[c] as f32);
+                pixel[c] = value.min(u16::MAX as f32).round() as u16;
+            }
+        }
+    }
+}
+
+// the saturated samples spread a little below the maximum by the black levels of the channels
+const PLATEAU_RANGE_DIVISOR: u16 = 64;
+const PLATEAU_SAMPLE_DIVISOR: usize = 10000;
+
+/// Finds the white level by the plateau of the histogram where the saturated samples pile up,
+/// within 1/64 below the maximum. The lowest value holding at least 1/10000 of the samples is
+/// the level, the channels with the higher black levels saturate first. None when the
+/// samples don't pile up, e.g. nothing saturates.
+pub fn detect_white_level(image: &[u16]) -> Option<u16> {
+    let max = image.iter().copied().max()?;
+    let low = max - max / PLATEAU_RANGE_DIVISOR;
+    let mut histogram = vec![0usize; (max - low) as usize + 1];
+    for &x in image.iter().filter(|&&x| x >= low) {
+        histogram[(x - low) as usize] += 1;
+    }
+    let threshold = (image.len() / PLATEAU_SAMPLE_DIVISOR).max(2);
+    histogram
+        .iter()
+        .position(|&count| count >= threshold)
+        .map(|i| low + i as u16)
+}
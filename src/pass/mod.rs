@@ -1,10 +1,16 @@
 mod color;
 mod demosaicing;
 mod general;
+mod highlight;
+mod negative;
+mod sharpen;
 
 pub use color::*;
 pub use demosaicing::*;
 pub use general::*;
+pub use highlight::*;
+pub use negative::*;
+pub use sharpen::*;
 
 #[macro_export]
 macro_rules! iters_to_vec {
@@ -37,8 +43,8 @@ macro_rules! iters_to_vec {
     };
 
     // match accumulator
-    [@acc($($x:tt)*) @body($($body:tt)*) @match($target:expr) @match_acc($($r:tt)*) $(,)? $p:pat => . $fn:ident ( $($params:tt)* ) $($rules:tt)* ] => {
-        iters_to_vec!(@acc($($x)*) @body($($body)*) @match($target) @match_acc($($r)* $p => iters_to_vec!(@acc($fn($($x)*, $($params)*)) $($body)*),) $($rules)* )
+    [@acc($($x:tt)*) @body($($body:tt)*) @match($target:expr) @match_acc($($r:tt)*) $(,)? $(#[$attr:meta])* $p:pat => . $fn:ident ( $($params:tt)* ) $($rules:tt)* ] => {
+        iters_to_vec!(@acc($($x)*) @body($($body)*) @match($target) @match_acc($($r)* $(#[$attr])* $p => iters_to_vec!(@acc($fn($($x)*, $($params)*)) $($body)*),) $($rules)* )
     };
     [@acc($($x:tt)*) @body($($body:tt)*) @match($target:expr) @match_acc($($r:tt)*)] => {
         match $target {
@@ -0,0 +1,61 @@
+#![allow(dead_code)]
+
+// the film base is found in the brightest 0.5% of the negative
+const FILM_BASE_FRACTION: f32 = 0.005;
+// the densest 0.1% of every channel of the negative turns white
+const WHITE_PERCENTILE: f32 = 99.9;
+
+/// Estimates the film base of a demosaiced negative by the mean of its brightest pixels, where
+/// the film is the least dense, e.g. the border between the frames. The pixels which clip at
+/// `white_level` are left out. None when every pixel clips.
+pub fn estimate_film_base(pixels: &[[u16; 3]], white_level: u16) -> Option<[f32; 3]> {
+    let is_unclipped = |pixel: &&[u16; 3]| pixel.iter().all(|&v| v < white_level);
+    let brightness = |pixel: &[u16; 3]| pixel.iter().map(|&v| v as u32).sum::<u32>();
+    let mut values = pixels
+        .iter()
+        .filter(is_unclipped)
+        .map(brightness)
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        return None;
+    }
+    let count = ((values.len() as f32 * FILM_BASE_FRACTION).ceil() as usize).max(1);
+    let index = values.len() - count;
+    let (_, &mut threshold, _) = values.select_nth_unstable(index);
+
+    let (sums, count) = pixels
+        .iter()
+        .filter(is_unclipped)
+        .filter(|pixel| brightness(pixel) >= threshold)
+        .fold(([0u64; 3], 0u64), |(sums, count), pixel| {
+            ([0, 1, 2].map(|c| sums[c] + pixel[c] as u64), count + 1)
+        });
+    Some(sums.map(|sum| sum as f32 / count as f32))
+}
+
+/// Inverts a demosaiced negative of the film `base` into a positive in linear light. Every
+/// channel is the inverse of its transmittance relative to the base, scaled on its own so the
+/// base turns black and the densest 0.1% turn `white_level`, which takes out the orange mask
+/// of color negatives.
+pub fn invert_negative(pixels: &mut [[u16; 3]], base: [f32; 3], white_level: u16) {
+    if pixels.is_empty() {
+        return;
+    }
+    // 1 at the film base and growing with the density
+    let positive = |pixel: &[u16; 3], c: usize| base[c] / (pixel[c] as f32).max(1.0);
+    let whites = [0, 1, 2].map(|c| {
+        let mut values = pixels.iter().map(|pixel| positive(pixel, c)).collect::<Vec<_>>();
+        let index = ((values.len() - 1) as f32 * WHITE_PERCENTILE / 100.0) as usize;
+        let (_, &mut white, _) = values.select_nth_unstable_by(index, f32::total_cmp);
+        white
+    });
+
+    let white_level = white_level as f32;
+    for pixel in pixels.iter_mut() {
+        *pixel = [0, 1, 2].map(|c| {
+            let range = (whites[c] - 1.0).max(f32::EPSILON);
+            let value = (positive(pixel, c) - 1.0) / range * white_level;
+            value.round().clamp(0.0, white_level) as u16
+        });
+    }
+}
@@ -0,0 +1,76 @@
+#![allow(dead_code)]
+
+// the gaussian reaches out to 3 times its radius
+const KERNEL_EXTENT: f32 = 3.0;
+
+/// The luma of Rec. 709 of the interleaved RGB image
+fn luma(image: &[u16]) -> Vec<f32> {
+    image
+        .chunks_exact(3)
+        .map(|rgb| 0.2126 * rgb[0] as f32 + 0.7152 * rgb[1] as f32 + 0.0722 * rgb[2] as f32)
+        .collect()
+}
+
+/// The normalized weights of the gaussian of `radius`, from its center outward
+fn gaussian_kernel(radius: f32) -> Vec<f32> {
+    let len = (radius * KERNEL_EXTENT).ceil() as usize + 1;
+    let weights = (0..len)
+        .map(|i| (-((i * i) as f32) / (2.0 * radius * radius)).exp())
+        .collect::<Vec<_>>();
+    let sum = weights[0] + 2.0 * weights[1..].iter().sum::<f32>();
+    weights.into_iter().map(|x| x / sum).collect()
+}
+
+/// Blurs the plane of `width` by the kernel in both directions, the edges are repeated
+fn gaussian_blur(plane: &[f32], width: usize, height: usize, kernel: &[f32]) -> Vec<f32> {
+    let blur = |plane: &[f32], at: &dyn Fn(usize, isize) -> f32| {
+        (0..plane.len())
+            .map(|i| {
+                let taps = kernel.iter().enumerate().skip(1);
+                taps.fold(kernel[0] * plane[i], |sum, (offset, weight)| {
+                    let offset = offset as isize;
+                    sum + weight * (at(i, offset) + at(i, -offset))
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let horizontal = blur(plane, &|i, offset| {
+        let (x, y) = (i % width, i / width);
+        let x = (x as isize + offset).clamp(0, width as isize - 1) as usize;
+        plane[y * width + x]
+    });
+    blur(&horizontal, &|i, offset| {
+        let (x, y) = (i % width, i / width);
+        let y = (y as isize + offset).clamp(0, height as isize - 1) as usize;
+        horizontal[y * width + x]
+    })
+}
+
+/// Sharpens the interleaved RGB image of `width` and `height` by an unsharp mask on its luma,
+/// which leaves the chroma and its noise alone. The difference of the luma to its gaussian blur
+/// of `radius` is added by `amount` to the channels, where it reaches the `threshold` in the
+/// range from 0 to 1 so the smooth areas don't get halos.
+pub fn unsharp_mask(
+    image: &mut [u16],
+    width: usize,
+    height: usize,
+    amount: f32,
+    radius: f32,
+    threshold: f32,
+) {
+    if image.len() != width * height * 3 || amount == 0.0 {
+        return;
+    }
+    let luma = luma(image);
+    let blurred = gaussian_blur(&luma, width, height, &gaussian_kernel(radius));
+    let threshold = threshold * u16::MAX as f32;
+    for ((rgb, luma), blurred) in image.chunks_exact_mut(3).zip(luma).zip(blurred) {
+        let detail = luma - blurred;
+        if detail.abs() < threshold {
+            continue;
+        }
+        for x in rgb {
+            *x = (*x as f32 + amount * detail).round().clamp(0.0, u16::MAX as f32) as u16;
+        }
+    }
+}
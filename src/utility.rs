@@ -45,6 +45,23 @@ pub(super) fn matrix3_mul(a: &[f32; 9], b: &[f32; 9]) -> [f32; 9] {
     ]
 }
 
+/// The inverse by the adjugate, its values are not finite when `a` is singular
+pub(super) fn matrix3_inverse(a: &[f32; 9]) -> [f32; 9] {
+    let adjugate = [
+        a[4] * a[8] - a[5] * a[7],
+        a[2] * a[7] - a[1] * a[8],
+        a[1] * a[5] - a[2] * a[4],
+        a[5] * a[6] - a[3] * a[8],
+        a[0] * a[8] - a[2] * a[6],
+        a[2] * a[3] - a[0] * a[5],
+        a[3] * a[7] - a[4] * a[6],
+        a[1] * a[6] - a[0] * a[7],
+        a[0] * a[4] - a[1] * a[3],
+    ];
+    let determinant = a[0] * adjugate[0] + a[1] * adjugate[3] + a[2] * adjugate[6];
+    adjugate.map(|x| x / determinant)
+}
+
 pub(super) static BASIC_INFO_RULE : Lazy<quickexif::ParsingRule> = Lazy::new(|| {
     quickexif::describe_rule!(tiff {
         0x010f {
@@ -59,20 +76,35 @@ pub(super) static BASIC_INFO_RULE : Lazy<quickexif::ParsingRule> = Lazy::new(||
             0xc614 {
                 str + 0 / make_model
             }
+            0xc622? / color_matrix_2
             if cfa_pattern ? {
-                0xc622 { // for normal dng
-                    r64 + 0 / c0
-                    r64 + 1 / c1
-                    r64 + 2 / c2
-                    r64 + 3 / c3
-                    r64 + 4 / c4
-                    r64 + 5 / c5
-                    r64 + 6 / c6
-                    r64 + 7 / c7
-                    r64 + 8 / c8
+                if color_matrix_2 ? {
+                    0xc622 { // for normal dng
+                        r64 + 0 / c0
+                        r64 + 1 / c1
+                        r64 + 2 / c2
+                        r64 + 3 / c3
+                        r64 + 4 / c4
+                        r64 + 5 / c5
+                        r64 + 6 / c6
+                        r64 + 7 / c7
+                        r64 + 8 / c8
+                    }
+                } else {
+                    0xc621? { // single illuminant dng
+                        r64 + 0 / c0
+                        r64 + 1 / c1
+                        r64 + 2 / c2
+                        r64 + 3 / c3
+                        r64 + 4 / c4
+                        r64 + 5 / c5
+                        r64 + 6 / c6
+                        r64 + 7 / c7
+                        r64 + 8 / c8
+                    }
                 }
             } else {
-                0xc621 { // for Apple ProRaw
+                0xc621? { // for Apple ProRaw
                     r64 + 0 / c0
                     r64 + 1 / c1
                     r64 + 2 / c2
@@ -84,6 +116,18 @@ pub(super) static BASIC_INFO_RULE : Lazy<quickexif::ParsingRule> = Lazy::new(||
                     r64 + 8 / c8
                 }
             }
+        } else {
+            0xc621? { // other raw files carrying the DNG tag, e.g. 3FR
+                r64 + 0 / c0
+                r64 + 1 / c1
+                r64 + 2 / c2
+                r64 + 3 / c3
+                r64 + 4 / c4
+                r64 + 5 / c5
+                r64 + 6 / c6
+                r64 + 7 / c7
+                r64 + 8 / c8
+            }
         }
     })
 });
\ No newline at end of file
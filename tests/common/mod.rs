@@ -0,0 +1,110 @@
+//! The fixtures shared by the tests of synthetic raw files.
+
+// every test uses only some of them
+#![allow(dead_code)]
+
+pub mod tiff;
+
+/// Every pixel holds its own position in the rows of `width`, counted from `first`
+pub fn position(x: usize, y: usize, width: usize, first: usize) -> u16 {
+    (y * width + x + first) as u16
+}
+
+/// Writes a little endian IFD of the entries `(tag, type, count, value)` at `at`, followed by
+/// the offset of the `next` one
+pub fn write_ifd(buffer: &mut [u8], at: usize, entries: &[(u16, u16, u32, u32)], next: u32) {
+    buffer[at..at + 2].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (i, &(tag, kind, count, value)) in entries.iter().enumerate() {
+        let pos = at + 2 + i * 12;
+        buffer[pos..pos + 2].copy_from_slice(&tag.to_le_bytes());
+        buffer[pos + 2..pos + 4].copy_from_slice(&kind.to_le_bytes());
+        buffer[pos + 4..pos + 8].copy_from_slice(&count.to_le_bytes());
+        buffer[pos + 8..pos + 12].copy_from_slice(&value.to_le_bytes());
+    }
+    let pos = at + 2 + entries.len() * 12;
+    buffer[pos..pos + 4].copy_from_slice(&next.to_le_bytes());
+}
+
+/// Writes the bits of a lossless JPEG stream from the most significant one on, a byte of 0xff
+/// is followed by a stuffed 0
+#[derive(Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u8,
+    len: u32,
+}
+
+impl BitWriter {
+    pub fn put(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1) as u8;
+            self.len += 1;
+            if self.len == 8 {
+                self.bytes.push(self.acc);
+                if self.acc == 0xff {
+                    self.bytes.push(0);
+                }
+                self.acc = 0;
+                self.len = 0;
+            }
+        }
+    }
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.put(0xff, 8 - self.len);
+        }
+        self.bytes
+    }
+}
+
+/// A lossless JPEG with predictor 1 of the `samples`, interleaved in rows of `width` times
+/// the `components`. The huffman code of every difference category is the category itself in
+/// 5 bits.
+pub fn ljpeg(
+    samples: &[u16],
+    width: usize,
+    height: usize,
+    components: usize,
+    precision: u8,
+) -> Vec<u8> {
+    let row_len = width * components;
+    let mut writer = BitWriter::default();
+    for (i, &sample) in samples.iter().enumerate() {
+        let (row, col) = (i / row_len, i % row_len);
+        let prediction = match (row, col) {
+            (0, col) if col < components => 1 << (precision - 1),
+            (row, col) if col < components => samples[(row - 1) * row_len + col],
+            _ => samples[i - components],
+        };
+        let diff = sample as i32 - prediction as i32;
+        let category = 32 - diff.unsigned_abs().leading_zeros();
+        writer.put(category, 5);
+        if category > 0 {
+            let bits = if diff < 0 { diff - 1 } else { diff };
+            writer.put(bits as u32 & ((1 << category) - 1), category);
+        }
+    }
+
+    let mut out = vec![0xff, 0xd8];
+    // SOF3
+    out.extend([0xff, 0xc3, 0, 8 + 3 * components as u8, precision]);
+    out.extend((height as u16).to_be_bytes());
+    out.extend((width as u16).to_be_bytes());
+    out.push(components as u8);
+    for id in 1..=components as u8 {
+        out.extend([id, 0x11, 0]);
+    }
+    // DHT
+    out.extend([0xff, 0xc4, 0, 36, 0]);
+    out.extend([0, 0, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    out.extend(0..17u8);
+    // SOS
+    out.extend([0xff, 0xda, 0, 6 + 2 * components as u8, components as u8]);
+    for id in 1..=components as u8 {
+        out.extend([id, 0]);
+    }
+    out.extend([1, 0, 0]);
+    out.extend(writer.finish());
+    out.extend([0xff, 0xd9]);
+    out
+}
@@ -0,0 +1,59 @@
+//! A synthetic uncompressed TIFF raw of a maker without a decoder of its own, which the tests
+//! of the generic decoder and of the rendering share.
+
+pub const WIDTH: usize = 6;
+pub const HEIGHT: usize = 4;
+
+/// Every pixel holds its own position in thousands
+pub fn scene(x: usize, y: usize) -> u16 {
+    super::position(x, y, WIDTH, 0) * 1000
+}
+
+/// The scene in two strips of 16 bit samples under a GRBG pattern
+pub fn tiff() -> Vec<u8> {
+    const MAKE: u32 = 200;
+    const MODEL: u32 = 220;
+    const OFFSETS: u32 = 240;
+    const BYTE_COUNTS: u32 = 250;
+    const DATA: u32 = 300;
+    let strip_len = (WIDTH * HEIGHT / 2 * 2) as u32;
+
+    let entries: [(u16, u16, u32, u32); 12] = [
+        (0x00fe, 4, 1, 0),
+        (0x0100, 4, 1, WIDTH as u32),
+        (0x0101, 4, 1, HEIGHT as u32),
+        (0x0102, 3, 1, 16),
+        (0x0103, 3, 1, 1),
+        (0x0106, 3, 1, 32803),
+        (0x010f, 2, 5, MAKE),
+        (0x0110, 2, 6, MODEL),
+        (0x0111, 4, 2, OFFSETS),
+        (0x0116, 4, 1, HEIGHT as u32 / 2),
+        (0x0117, 4, 2, BYTE_COUNTS),
+        (0x828e, 1, 4, u32::from_le_bytes([1, 0, 2, 1])),
+    ];
+    let mut buffer = vec![0u8; DATA as usize + WIDTH * HEIGHT * 2];
+    buffer[..8].copy_from_slice(&[b'I', b'I', 0x2a, 0, 8, 0, 0, 0]);
+    super::write_ifd(&mut buffer, 8, &entries, 0);
+
+    buffer[MAKE as usize..MAKE as usize + 5].copy_from_slice(b"Acme\0");
+    buffer[MODEL as usize..MODEL as usize + 6].copy_from_slice(b"Astro\0");
+    // the strips are stored in reverse order
+    let strips = [DATA + strip_len, DATA];
+    for (i, offset) in strips.into_iter().enumerate() {
+        let pos = OFFSETS as usize + i * 4;
+        buffer[pos..pos + 4].copy_from_slice(&offset.to_le_bytes());
+        let pos = BYTE_COUNTS as usize + i * 4;
+        buffer[pos..pos + 4].copy_from_slice(&strip_len.to_le_bytes());
+
+        let samples = (0..WIDTH * HEIGHT / 2).map(|j| {
+            let index = i * WIDTH * HEIGHT / 2 + j;
+            scene(index % WIDTH, index / WIDTH)
+        });
+        for (j, sample) in samples.enumerate() {
+            let pos = offset as usize + j * 2;
+            buffer[pos..pos + 2].copy_from_slice(&sample.to_le_bytes());
+        }
+    }
+    buffer
+}
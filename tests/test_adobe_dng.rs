@@ -0,0 +1,613 @@
+//! Decodes synthetic 16 bit DNGs whose tiles and strips do not divide the image size.
+
+mod common;
+
+const WIDTH: usize = 10;
+const HEIGHT: usize = 7;
+// the padding of the partial tiles must not show up in the image
+const PADDING: u16 = 0xffff;
+
+/// Every pixel holds its own position
+fn scene(x: usize, y: usize) -> u16 {
+    common::position(x, y, WIDTH, 1)
+}
+
+/// Cuts the scene in tiles from left to right and top to bottom, the tiles on the right
+/// and the bottom edges are padded to the full tile size
+fn tiles(tile_width: usize, tile_height: usize) -> Vec<Vec<u8>> {
+    let mut tiles = vec![];
+    for tile_y in (0..HEIGHT).step_by(tile_height) {
+        for tile_x in (0..WIDTH).step_by(tile_width) {
+            let mut tile = vec![];
+            for y in tile_y..tile_y + tile_height {
+                for x in tile_x..tile_x + tile_width {
+                    let value = if x < WIDTH && y < HEIGHT {
+                        scene(x, y)
+                    } else {
+                        PADDING
+                    };
+                    tile.extend(value.to_le_bytes());
+                }
+            }
+            tiles.push(tile);
+        }
+    }
+    tiles
+}
+
+/// Strips are as wide as the image and the last one only holds the remaining rows
+fn strips(rows_per_strip: usize) -> Vec<Vec<u8>> {
+    (0..HEIGHT)
+        .step_by(rows_per_strip)
+        .map(|strip_y| {
+            (strip_y..(strip_y + rows_per_strip).min(HEIGHT))
+                .flat_map(|y| (0..WIDTH).map(move |x| scene(x, y)))
+                .flat_map(|value| value.to_le_bytes())
+                .collect()
+        })
+        .collect()
+}
+
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+const UNDEFINED: u16 = 7;
+const SRATIONAL: u16 = 10;
+const FLOAT: u16 = 11;
+
+/// A tag with its type, count and data, the data is written after the image unless it fits
+/// in the entry. It takes the place of the default tag of the same id.
+type Tag = (u16, u16, u32, Vec<u8>);
+
+enum Layout {
+    Tiles(usize, usize),
+    Strips(usize),
+}
+
+/// OpcodeList2 with a single GainMap over the whole image, the gains of the 2x2 map go
+/// from 1 on the left to 3 on the right
+fn opcode_list_2() -> Tag {
+    gain_map_list([0, 0, HEIGHT as u32, WIDTH as u32, 1, 1], [1.0, 3.0, 1.0, 3.0])
+}
+
+/// OpcodeList2 with a single GainMap of 2x2 points, spaced over the whole image, on the
+/// samples from the top and the left at the pitches of the rows and the columns until the
+/// bottom and the right
+fn gain_map_list(
+    [top, left, bottom, right, row_pitch, col_pitch]: [u32; 6],
+    gains: [f32; 4],
+) -> Tag {
+    let mut params = vec![];
+    // top, left, bottom, right, plane, planes, row pitch, column pitch and the map points
+    for value in [top, left, bottom, right, 0, 1, row_pitch, col_pitch, 2, 2] {
+        params.extend(value.to_be_bytes());
+    }
+    // spacing and origin
+    for value in [1f64, 1.0, 0.0, 0.0] {
+        params.extend(value.to_be_bytes());
+    }
+    params.extend(1u32.to_be_bytes());
+    for gain in gains {
+        params.extend(gain.to_be_bytes());
+    }
+
+    let mut list = vec![];
+    // the opcode count, the GainMap id, the DNG version, the flags and the size
+    for value in [1, 9, 0x01030000, 0, params.len() as u32] {
+        list.extend(value.to_be_bytes());
+    }
+    list.extend(params);
+    (0xc741, UNDEFINED, list.len() as u32, list)
+}
+
+fn dng(layout: Layout, tags: &[Tag]) -> Vec<u8> {
+    const MAKE: u32 = 400;
+    const MODEL: u32 = 420;
+    const COLOR_MATRIX: u32 = 440;
+    const NEUTRAL: u32 = 520;
+    const OFFSETS: u32 = 560;
+    const BYTE_COUNTS: u32 = 660;
+    const DATA: u32 = 800;
+
+    let (chunks, layout_entries) = match layout {
+        Layout::Tiles(tile_width, tile_height) => (
+            tiles(tile_width, tile_height),
+            vec![
+                (0x0142, LONG, 1, tile_width as u32),
+                (0x0143, LONG, 1, tile_height as u32),
+                (0x0144, LONG, 0, OFFSETS),
+                (0x0145, LONG, 0, BYTE_COUNTS),
+            ],
+        ),
+        Layout::Strips(rows_per_strip) => (
+            strips(rows_per_strip),
+            vec![
+                (0x0111, LONG, 0, OFFSETS),
+                (0x0116, LONG, 1, rows_per_strip as u32),
+                (0x0117, LONG, 0, BYTE_COUNTS),
+            ],
+        ),
+    };
+    let count = chunks.len() as u32;
+    let data_len = chunks.iter().map(|x| x.len()).sum::<usize>();
+    let tags_len = tags.iter().map(|(.., data)| data.len()).sum::<usize>();
+    let mut buffer = vec![0u8; DATA as usize + data_len + tags_len];
+    buffer[..8].copy_from_slice(&[b'I', b'I', 0x2a, 0, 8, 0, 0, 0]);
+
+    let mut entries = vec![
+        (0x00fe, LONG, 1, 0),
+        (0x0100, LONG, 1, WIDTH as u32),
+        (0x0101, LONG, 1, HEIGHT as u32),
+        (0x0102, SHORT, 1, 16),
+        (0x0103, SHORT, 1, 1),
+        (0x0106, SHORT, 1, 32803),
+        (0x010f, ASCII, 5, MAKE),
+        (0x0110, ASCII, 6, MODEL),
+        (0x0112, SHORT, 1, 1),
+        (0x0115, SHORT, 1, 1),
+    ];
+    // the offsets and the byte counts hold the address of their arrays
+    entries.extend(
+        layout_entries
+            .into_iter()
+            .map(|(tag, kind, n, value)| (tag, kind, if n == 0 { count } else { n }, value)),
+    );
+    entries.extend([
+        (0x828e, BYTE, 4, u32::from_le_bytes([0, 1, 1, 2])),
+        (0xc612, BYTE, 4, u32::from_le_bytes([1, 4, 0, 0])),
+        (0xc614, ASCII, 6, MODEL),
+        (0xc61d, SHORT, 1, 0xffff),
+        (0xc621, SRATIONAL, 9, COLOR_MATRIX),
+        (0xc628, RATIONAL, 3, NEUTRAL),
+    ]);
+    let mut pos = DATA as usize + data_len;
+    for (tag, kind, count, data) in tags {
+        entries.retain(|entry| entry.0 != *tag);
+        if data.len() <= 4 {
+            let mut value = [0u8; 4];
+            value[..data.len()].copy_from_slice(data);
+            entries.push((*tag, *kind, *count, u32::from_le_bytes(value)));
+        } else {
+            entries.push((*tag, *kind, *count, pos as u32));
+            buffer[pos..pos + data.len()].copy_from_slice(data);
+            pos += data.len();
+        }
+    }
+    entries.sort_by_key(|&(tag, ..)| tag);
+    common::write_ifd(&mut buffer, 8, &entries, 0);
+
+    buffer[MAKE as usize..MAKE as usize + 5].copy_from_slice(b"Test\0");
+    buffer[MODEL as usize..MODEL as usize + 6].copy_from_slice(b"Tiled\0");
+    for i in 0..9 {
+        let pos = COLOR_MATRIX as usize + i * 8;
+        let numerator: i32 = if i % 4 == 0 { 10000 } else { 0 };
+        buffer[pos..pos + 4].copy_from_slice(&numerator.to_le_bytes());
+        buffer[pos + 4..pos + 8].copy_from_slice(&10000i32.to_le_bytes());
+    }
+    for i in 0..3 {
+        let pos = NEUTRAL as usize + i * 8;
+        buffer[pos..pos + 4].copy_from_slice(&1u32.to_le_bytes());
+        buffer[pos + 4..pos + 8].copy_from_slice(&1u32.to_le_bytes());
+    }
+
+    let mut pos = DATA as usize;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let offset = OFFSETS as usize + i * 4;
+        buffer[offset..offset + 4].copy_from_slice(&(pos as u32).to_le_bytes());
+        let byte_count = BYTE_COUNTS as usize + i * 4;
+        buffer[byte_count..byte_count + 4].copy_from_slice(&(chunk.len() as u32).to_le_bytes());
+        buffer[pos..pos + chunk.len()].copy_from_slice(chunk);
+        pos += chunk.len();
+    }
+
+    buffer
+}
+
+/// Moves the raw IFD to the SubIFD of a new IFD0 holding an RGB preview, like the DNG files
+/// of most cameras
+fn with_preview(mut buffer: Vec<u8>) -> Vec<u8> {
+    let count = u16::from_le_bytes([buffer[8], buffer[9]]) as usize;
+    let layout_tags = [0x0111, 0x0116, 0x0117, 0x0142, 0x0143, 0x0144, 0x0145];
+    let mut entries: Vec<(u16, u16, u32, u32)> = buffer[10..10 + count * 12]
+        .chunks_exact(12)
+        .map(|entry| {
+            (
+                u16::from_le_bytes([entry[0], entry[1]]),
+                u16::from_le_bytes([entry[2], entry[3]]),
+                u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]),
+                u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]),
+            )
+        })
+        .filter(|entry| !layout_tags.contains(&entry.0))
+        .map(|(tag, kind, count, value)| match tag {
+            0x00fe => (tag, kind, count, 1),
+            0x0106 => (tag, kind, count, 2),
+            _ => (tag, kind, count, value),
+        })
+        .collect();
+
+    let preview = buffer.len() as u32;
+    buffer.extend([0x80u8; 12]);
+    entries.extend([
+        (0x0111, LONG, 1, preview),
+        (0x0117, LONG, 1, 12),
+        (0x014a, LONG, 1, 8),
+    ]);
+    entries.sort_by_key(|&(tag, ..)| tag);
+
+    let ifd0 = buffer.len();
+    buffer.resize(ifd0 + 2 + entries.len() * 12 + 4, 0);
+    common::write_ifd(&mut buffer, ifd0, &entries, 0);
+    buffer[4..8].copy_from_slice(&(ifd0 as u32).to_le_bytes());
+    buffer
+}
+
+fn expected() -> Vec<u16> {
+    (0..WIDTH * HEIGHT)
+        .map(|i| scene(i % WIDTH, i / WIDTH))
+        .collect()
+}
+
+#[test]
+fn test_dng_tiles() {
+    // 3x3 tiles of 4x3, the last column is 2 pixels wide and the last row 1 pixel high
+    let decoded_image = quickraw::decode_buffer(dng(Layout::Tiles(4, 3), &[])).unwrap();
+
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    assert_eq!(expected(), decoded_image.image);
+}
+
+#[test]
+fn test_dng_tiles_match_strips() {
+    let tiled = quickraw::decode_buffer(dng(Layout::Tiles(4, 3), &[])).unwrap();
+    let stripped = quickraw::decode_buffer(dng(Layout::Strips(3), &[])).unwrap();
+
+    assert_eq!(expected(), stripped.image);
+    assert_eq!(tiled.image, stripped.image);
+}
+
+#[test]
+fn test_dng_raw_in_sub_ifd() {
+    let buffer = with_preview(dng(Layout::Tiles(4, 3), &[]));
+    let decoded_image = quickraw::decode_buffer(buffer).unwrap();
+
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    assert_eq!(expected(), decoded_image.image);
+}
+
+#[test]
+fn test_dng_gain_map() {
+    use quickraw::{data, export};
+
+    let buffer = dng(Layout::Tiles(4, 3), &[opcode_list_2()]);
+    let decoded_image = quickraw::decode_buffer(buffer.clone()).unwrap();
+
+    let gained = (0..WIDTH * HEIGHT)
+        .map(|i| {
+            let x = i % WIDTH;
+            let weight = (x as f64 / WIDTH as f64) as f32;
+            let gain = 1.0 * (1.0 - weight) + 3.0 * weight;
+            (scene(x, i / WIDTH) as f32 * gain).round() as u16
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(gained, decoded_image.image);
+
+    // the untouched data
+    let decoded_image = quickraw::decode_buffer_with_opcodes(buffer.clone(), false).unwrap();
+    assert_eq!(expected(), decoded_image.image);
+    let options = |apply_opcodes| {
+        export::Options::new(1.0, &data::XYZ2RAW, false).with_apply_opcodes(apply_opcodes)
+    };
+    let (image, ..) = export::load_cfa_image_from_buffer(buffer.clone(), options(false)).unwrap();
+    assert_eq!(expected(), image);
+    let (image, ..) = export::load_cfa_image_from_buffer(buffer, options(true)).unwrap();
+    assert_eq!(gained, image);
+}
+
+#[test]
+fn test_dng_gain_map_of_cfa_color() {
+    // the second color of the even rows, interpolated from 1 at the top left to 4 at the
+    // bottom right
+    let gain_map = gain_map_list(
+        [0, 1, HEIGHT as u32, WIDTH as u32, 2, 2],
+        [1.0, 2.0, 3.0, 4.0],
+    );
+    let decoded_image = quickraw::decode_buffer(dng(Layout::Tiles(4, 3), &[gain_map])).unwrap();
+
+    let gained = (0..WIDTH * HEIGHT)
+        .map(|i| {
+            let (x, y) = (i % WIDTH, i / WIDTH);
+            if y % 2 != 0 || x % 2 != 1 {
+                return scene(x, y);
+            }
+            let h = (x as f64 / WIDTH as f64) as f32;
+            let v = (y as f64 / HEIGHT as f64) as f32;
+            let top = 1.0 * (1.0 - h) + 2.0 * h;
+            let bottom = 3.0 * (1.0 - h) + 4.0 * h;
+            let gain = top * (1.0 - v) + bottom * v;
+            (scene(x, y) as f32 * gain).round() as u16
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(gained, decoded_image.image);
+}
+
+#[test]
+fn test_dng_fix_bad_pixels() {
+    let opcode_list = |unknown_flags: u32| {
+        // the Bayer phase, 2 points, no rectangle and the rows and columns of the points
+        let params: Vec<u8> = [0u32, 2, 0, 0, 0, 0, 1]
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect();
+        let mut list = vec![];
+        // the opcode count, then the FixBadPixelsList and an unknown opcode with their id,
+        // DNG version, flags and size
+        for value in [2, 5, 0x01030000, 0, params.len() as u32] {
+            list.extend(value.to_be_bytes());
+        }
+        list.extend(params);
+        for value in [99, 0x01030000, unknown_flags, 0] {
+            list.extend(value.to_be_bytes());
+        }
+        (0xc741, UNDEFINED, list.len() as u32, list)
+    };
+
+    let buffer = dng(Layout::Tiles(4, 3), &[opcode_list(1)]);
+    let decoded_image = quickraw::decode_buffer(buffer).unwrap();
+    // the red takes the reds 2 pixels away and the green its diagonal neighbors
+    let mut expected = expected();
+    expected[0] = (scene(2, 0) + scene(0, 2)) / 2;
+    expected[1] = (scene(0, 1) + scene(2, 1)) / 2;
+    assert_eq!(expected, decoded_image.image);
+
+    // an unknown opcode which is not optional
+    let buffer = dng(Layout::Tiles(4, 3), &[opcode_list(0)]);
+    assert!(quickraw::decode_buffer(buffer).is_err());
+}
+
+#[test]
+fn test_dng_active_area_and_black_levels() {
+    let active_area = [1u32, 2, 6, 9]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    // the black levels of the 2x2 CFA block, 0.5 rounds up to 1 and 2.4 down to 2
+    let black_levels = [(1u32, 2u32), (12, 5), (3, 1), (0, 1)]
+        .iter()
+        .flat_map(|(numerator, denominator)| {
+            [numerator.to_le_bytes(), denominator.to_le_bytes()].concat()
+        })
+        .collect();
+    let buffer = dng(
+        Layout::Tiles(4, 3),
+        &[
+            (0xc61a, RATIONAL, 4, black_levels),
+            (0xc68d, LONG, 4, active_area),
+        ],
+    );
+    let decoded_image = quickraw::decode_buffer(buffer.clone()).unwrap();
+    assert_eq!([1, 2, 3, 0], decoded_image.black_level);
+
+    // the calibration keeps the samples below the black level
+    let kept = quickraw::decode_buffer_keeping_black_level(buffer).unwrap();
+    assert_eq!([1, 2, 3, 0], kept.black_level);
+    let scene_image = (0..WIDTH * HEIGHT)
+        .map(|i| scene(i % WIDTH, i / WIDTH))
+        .collect::<Vec<_>>();
+    assert_eq!(scene_image, kept.image);
+
+    let crop = decoded_image.crop.unwrap();
+    assert_eq!((2, 1, 7, 5), (crop.x, crop.y, crop.width, crop.height));
+    // the RGGB pattern of the ActiveArea starts on an odd row
+    assert_eq!(quickraw::CFAPattern::GBRG, decoded_image.cfa_pattern);
+    let levels = [1, 2, 3, 0];
+    let expected = (0..WIDTH * HEIGHT)
+        .map(|i| {
+            let (x, y) = (i % WIDTH, i / WIDTH);
+            scene(x, y).saturating_sub(levels[(y % 2) * 2 + x % 2])
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(expected, decoded_image.image);
+}
+
+#[test]
+fn test_dng_xtrans_pattern() {
+    let repeat_dim = [6u16, 6].iter().flat_map(|x| x.to_le_bytes()).collect();
+    let pattern = [
+        [1, 1, 0, 1, 1, 2],
+        [1, 1, 2, 1, 1, 0],
+        [2, 0, 1, 0, 2, 1],
+        [1, 1, 2, 1, 1, 0],
+        [1, 1, 0, 1, 1, 2],
+        [0, 2, 1, 2, 0, 1],
+    ]
+    .concat();
+    let buffer = dng(
+        Layout::Tiles(4, 3),
+        &[(0x828d, SHORT, 2, repeat_dim), (0x828e, BYTE, 36, pattern)],
+    );
+    let decoded_image = quickraw::decode_buffer(buffer).unwrap();
+
+    assert_eq!(quickraw::CFAPattern::XTrans1, decoded_image.cfa_pattern);
+    assert_eq!(
+        quickraw::CFAPattern::BGGR,
+        quickraw::CFAPattern::RGGB.shifted(1, 1)
+    );
+}
+
+#[cfg(not(feature = "image"))]
+#[test]
+fn test_dng_lossy_needs_feature() {
+    use quickraw::{DecodingError, RawFileReadingError};
+
+    let compression = (0x0103, SHORT, 1, 34892u16.to_le_bytes().to_vec());
+    let buffer = dng(Layout::Tiles(4, 3), &[compression]);
+
+    assert!(matches!(
+        quickraw::decode_buffer(buffer),
+        Err(RawFileReadingError::DecodingError(DecodingError::CompressionNotSupported(message)))
+            if message.contains("`lossy_dng`")
+    ));
+}
+
+#[test]
+fn test_dng_monochrome() {
+    // LinearRaw with a single sample per pixel
+    let photometric = (0x0106, SHORT, 1, 34892u16.to_le_bytes().to_vec());
+    let decoded_image = quickraw::decode_buffer(dng(Layout::Tiles(4, 3), &[photometric])).unwrap();
+
+    assert!(matches!(
+        decoded_image.cfa_pattern,
+        quickraw::CFAPattern::Monochrome
+    ));
+    assert_eq!(expected(), decoded_image.image);
+}
+
+#[test]
+fn test_dng_forward_matrix() {
+    use quickraw::{data, export};
+
+    // the ForwardMatrix swaps red and blue, the rows sum to the white of D50
+    let forward_matrix = [0, 0, 9642, 0, 10000, 0, 8249, 0, 0]
+        .iter()
+        .flat_map(|&x: &i32| [x.to_le_bytes(), 10000i32.to_le_bytes()].concat())
+        .collect();
+    let buffer = dng(
+        Layout::Tiles(4, 3),
+        &[(0xc714, SRATIONAL, 9, forward_matrix)],
+    );
+    let render = |simple_color: bool| {
+        let options =
+            export::Options::new(1.0, &data::XYZ2RAW, false).with_simple_color(simple_color);
+        export::load_image_from_buffer(buffer.clone(), options)
+            .unwrap()
+            .0
+    };
+    let (color, simple) = (render(false), render(true));
+
+    assert_eq!(simple.len(), color.len());
+    for (color, simple) in color.chunks_exact(3).zip(simple.chunks_exact(3)) {
+        assert_eq!([simple[2], simple[1], simple[0]], color);
+    }
+}
+
+#[test]
+fn test_dng_baseline_exposure() {
+    use quickraw::{data, export};
+
+    let srational = |x: i32| [x.to_le_bytes(), 2i32.to_le_bytes()].concat();
+    // +1 EV with an offset of -0.5 EV
+    let buffer = dng(
+        Layout::Tiles(4, 3),
+        &[
+            (0xc62a, SRATIONAL, 1, srational(2)),
+            (0xc7a5, SRATIONAL, 1, srational(-1)),
+        ],
+    );
+    let decoded_image = quickraw::decode_buffer(buffer.clone()).unwrap();
+    assert_eq!(0.5, decoded_image.baseline_exposure);
+
+    let render = |options: export::Options| {
+        export::load_image_from_buffer(buffer.clone(), options)
+            .unwrap()
+            .0
+    };
+    let options = || export::Options::new(1.0, &data::XYZ2RAW, false);
+    assert_eq!(
+        render(options().with_baseline_exposure(false).with_exposure_ev(0.5)),
+        render(options())
+    );
+    // it adds up with the exposure compensation
+    assert_eq!(
+        render(options().with_baseline_exposure(false).with_exposure_ev(1.5)),
+        render(options().with_exposure_ev(1.0))
+    );
+}
+
+#[test]
+fn test_dng_pro_raw_tone_curve() {
+    use quickraw::{data, export};
+
+    // an identity ProfileToneCurve, which leaves the rendering linear in place of the gamma
+    let tone_curve = [0f32, 0., 1., 1.]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let baseline_exposure = [1i32, 2].map(i32::to_le_bytes).concat();
+    let buffer = dng(
+        Layout::Tiles(4, 3),
+        &[
+            (0xc614, ASCII, 20, b"Apple iPhone 14 Pro\0".to_vec()),
+            (0xc62a, SRATIONAL, 1, baseline_exposure),
+            (0xc6fc, FLOAT, 4, tone_curve),
+        ],
+    );
+    // the samples stay linear, the curve and the baseline exposure are left to the rendering
+    let decoded_image = quickraw::decode_buffer(buffer.clone()).unwrap();
+    assert_eq!(expected(), decoded_image.image);
+    assert_eq!(0.5, decoded_image.baseline_exposure);
+    assert!(decoded_image.tone_curve.is_some());
+
+    let render = |options: export::Options| {
+        export::load_image_from_buffer(buffer.clone(), options)
+            .unwrap()
+            .0
+    };
+    let options = |gamma: f32| export::Options::new(gamma, &data::XYZ2RAW, false);
+    let linear = render(
+        options(2.2)
+            .with_apply_opcodes(false)
+            .with_tone_curve(vec![0, u16::MAX], true),
+    );
+    assert_eq!(linear, render(options(2.2)));
+    assert_ne!(linear, render(options(2.2).with_apply_opcodes(false)));
+}
+
+#[test]
+fn test_dng_as_shot_temperature() {
+    use quickraw::{data, WhiteBalance};
+
+    // the ColorMatrix of D65 keeps the native white balance of the sensor, whose green is the
+    // most sensitive, and the AsShotNeutral is its camera colors of D65
+    let color_matrix = [0.8, -0.2, -0.1, -0.5, 1.3, 0.2, -0.1, 0.2, 0.6];
+    let d65 = [0.95047, 1.0, 1.08883];
+    let neutral = [0, 3, 6].map(|i| (0..3).map(|j| color_matrix[i + j] * d65[j]).sum::<f32>());
+    let neutral = neutral.map(|x| x / neutral[1]);
+    let srational = |values: &[f32]| -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|x| [((x * 10000.0).round() as i32).to_le_bytes(), 10000i32.to_le_bytes()])
+            .flatten()
+            .collect()
+    };
+    let buffer = dng(
+        Layout::Tiles(4, 3),
+        &[
+            (0xc621, SRATIONAL, 9, srational(&color_matrix)),
+            (0xc628, RATIONAL, 3, srational(&neutral)),
+            (0xc65a, SHORT, 1, 21u16.to_le_bytes().to_vec()),
+        ],
+    );
+    let decoded_image = quickraw::decode_buffer(buffer).unwrap();
+    assert_eq!(Some(color_matrix), decoded_image.color_matrix);
+
+    // D65 is about 6504 K and above the Planckian locus
+    let (kelvin, tint) = decoded_image.as_shot_temperature().unwrap();
+    assert!((kelvin - 6504.0).abs() < 6504.0 * 0.01);
+    assert!((tint - 10.0).abs() < 1.5);
+
+    // the daylight preset is the white balance as shot, red and blue are raised
+    let daylight = data::white_balance_preset(
+        decoded_image.color_matrix.as_ref(),
+        WhiteBalance::Daylight,
+    )
+    .unwrap();
+    assert!(daylight[0] > 1.5 && daylight[2] > 1.2);
+    let as_shot = decoded_image.white_balance;
+    for c in 0..3 {
+        let as_shot = as_shot[c] as f32 / as_shot[1] as f32;
+        assert!((daylight[c] - as_shot).abs() < 0.01);
+    }
+}
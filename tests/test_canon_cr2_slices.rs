@@ -0,0 +1,117 @@
+//! Decodes a synthetic CR2 whose LJPEG stream is split into two vertical slices.
+
+mod common;
+
+const WIDTH: usize = 8;
+const HEIGHT: usize = 4;
+// one slice of 2 samples and the last slice of 6 samples
+const SLICES: [u16; 3] = [1, 2, 6];
+
+fn pixel(x: usize, y: usize) -> u16 {
+    100 + (y * WIDTH + x) as u16
+}
+
+/// The LJPEG stream goes through the first slice from top to bottom before the next one
+fn stream_sample(index: usize) -> u16 {
+    let first_len = SLICES[1] as usize * HEIGHT;
+    if index < first_len {
+        let width = SLICES[1] as usize;
+        pixel(index % width, index / width)
+    } else {
+        let index = index - first_len;
+        let width = SLICES[2] as usize;
+        pixel(SLICES[1] as usize + index % width, index / width)
+    }
+}
+
+/// A 12 bit LJPEG with 2 components
+fn ljpeg() -> Vec<u8> {
+    let components = 2;
+    let samples = (0..WIDTH * HEIGHT).map(stream_sample).collect::<Vec<_>>();
+    common::ljpeg(&samples, WIDTH / components, HEIGHT, components, 12)
+}
+
+fn cr2() -> Vec<u8> {
+    const ASCII: u16 = 2;
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    const UNDEFINED: u16 = 7;
+    const IFD1: u32 = 200;
+    const IFD2: u32 = 300;
+    const IFD3: u32 = 400;
+    const EXIF: u32 = 500;
+    const MAKER_NOTE: u32 = 600;
+    const MAKE: u32 = 700;
+    const MODEL: u32 = 720;
+    const SLICE_INFO: u32 = 740;
+    const COLOR_DATA: u32 = 800;
+    const COLOR_DATA_LEN: u32 = 582;
+    const STRIP: u32 = 2000;
+
+    let ljpeg = ljpeg();
+    let mut buffer = vec![0u8; STRIP as usize + ljpeg.len()];
+    buffer[..8].copy_from_slice(&[b'I', b'I', 0x2a, 0, 8, 0, 0, 0]);
+
+    common::write_ifd(
+        &mut buffer,
+        8,
+        &[
+            (0x010f, ASCII, 6, MAKE),
+            (0x0110, ASCII, 13, MODEL),
+            (0x0112, SHORT, 1, 1),
+            (0x8769, LONG, 1, EXIF),
+        ],
+        IFD1,
+    );
+    common::write_ifd(&mut buffer, IFD1 as usize, &[(0x0100, LONG, 1, 0)], IFD2);
+    common::write_ifd(&mut buffer, IFD2 as usize, &[(0x0100, LONG, 1, 0)], IFD3);
+    common::write_ifd(
+        &mut buffer,
+        IFD3 as usize,
+        &[
+            (0x0111, LONG, 1, STRIP),
+            (0x0117, LONG, 1, ljpeg.len() as u32),
+            (0xc640, SHORT, 3, SLICE_INFO),
+        ],
+        0,
+    );
+    common::write_ifd(
+        &mut buffer,
+        EXIF as usize,
+        &[(0x927c, UNDEFINED, 64, MAKER_NOTE)],
+        0,
+    );
+    common::write_ifd(
+        &mut buffer,
+        MAKER_NOTE as usize,
+        &[(0x4001, SHORT, COLOR_DATA_LEN, COLOR_DATA)],
+        0,
+    );
+
+    buffer[MAKE as usize..MAKE as usize + 6].copy_from_slice(b"Canon\0");
+    buffer[MODEL as usize..MODEL as usize + 13].copy_from_slice(b"Canon EOS 5D\0");
+    for (i, value) in SLICES.iter().enumerate() {
+        let pos = SLICE_INFO as usize + i * 2;
+        buffer[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+    }
+    // WB_RGGBLevelsAsShot of the ColorData version with 582 values
+    for (index, value) in [(25, 2048u16), (26, 1024), (28, 1536)] {
+        let pos = COLOR_DATA as usize + index * 2;
+        buffer[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+    }
+    buffer[STRIP as usize..].copy_from_slice(&ljpeg);
+
+    buffer
+}
+
+#[test]
+fn test_cr2_two_slices() {
+    let decoded_image = quickraw::decode_buffer(cr2()).unwrap();
+
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    // 12 bit samples are scaled to 16 bit
+    let expected = (0..WIDTH * HEIGHT)
+        .map(|i| pixel(i % WIDTH, i / WIDTH) * 16)
+        .collect::<Vec<_>>();
+    assert_eq!(expected, decoded_image.image);
+}
@@ -39,3 +39,15 @@ fn thumbnail_from_cr2() {
 fn thumbnail_from_cr3() {
     assert_thumbnail_from("QUICKRAW_TEST_CANON_CR3");
 }
+
+#[test]
+fn thumbnail_from_crw_with_heap_beyond_the_file() {
+    // a CIFF header whose heap starts past the end of the file
+    let mut buffer = b"II".to_vec();
+    buffer.extend_from_slice(&0xffffu32.to_le_bytes());
+    buffer.extend_from_slice(b"HEAPCCDR");
+    buffer.extend_from_slice(&[0; 8]);
+
+    assert!(Export::export_thumbnail_data(&buffer).is_err());
+    assert!(quickraw::decode_buffer(buffer).is_err());
+}
@@ -0,0 +1,22 @@
+//! Checks the predefined matrices of the color spaces.
+
+use quickraw::data;
+
+#[test]
+fn test_color_spaces_keep_white() {
+    let color_spaces = [
+        &data::XYZ2SRGB,
+        &data::XYZ2ADOBE_RGB,
+        &data::XYZ2PROPHOTO,
+        &data::XYZ2DISPLAY_P3,
+        &data::XYZ2REC2020,
+    ];
+    // the white of the camera matrices is the XYZ of (1, 1, 1), which stays white, the
+    // columns of a transposed matrix would sum up to 1 instead
+    for matrix in color_spaces {
+        let white = [0, 1, 2].map(|i| matrix[i * 3..i * 3 + 3].iter().sum::<f32>());
+        for x in white {
+            assert!((x - 1.0).abs() < 1e-5, "{:?}", matrix);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Demosaics synthetic bayer and X-Trans images with the methods beyond Linear.
+
+mod common;
+
+use quickraw::{data, export, CFAPattern, Crop, DemosaicingMethod};
+
+const WIDTH: usize = 8;
+const HEIGHT: usize = 8;
+
+/// An uncompressed 16 bit TIFF raw of a maker without a decoder of its own, with a 2x2 or a
+/// 6x6 CFA pattern
+fn tiff(samples: &[u16], cfa_pattern: &[u8]) -> Vec<u8> {
+    const MAKE: u32 = 200;
+    const MODEL: u32 = 220;
+    const PATTERN: u32 = 240;
+    const DATA: u32 = 300;
+
+    let mut entries = vec![
+        (0x00fe, 4, 1, 0),
+        (0x0100, 4, 1, WIDTH as u32),
+        (0x0101, 4, 1, HEIGHT as u32),
+        (0x0102, 3, 1, 16),
+        (0x0103, 3, 1, 1),
+        (0x0106, 3, 1, 32803),
+        (0x010f, 2, 5, MAKE),
+        (0x0110, 2, 6, MODEL),
+        (0x0111, 4, 1, DATA),
+        (0x0117, 4, 1, samples.len() as u32 * 2),
+    ];
+    if cfa_pattern.len() == 36 {
+        entries.push((0x828d, 3, 2, 6 | 6 << 16));
+        entries.push((0x828e, 1, 36, PATTERN));
+    } else {
+        let value = cfa_pattern.iter().rev().fold(0, |v, &x| v << 8 | x as u32);
+        entries.push((0x828e, 1, 4, value));
+    }
+    let mut buffer = vec![0u8; DATA as usize];
+    buffer[..8].copy_from_slice(&[b'I', b'I', 0x2a, 0, 8, 0, 0, 0]);
+    common::write_ifd(&mut buffer, 8, &entries, 0);
+    buffer[MAKE as usize..MAKE as usize + 5].copy_from_slice(b"Acme\0");
+    buffer[MODEL as usize..MODEL as usize + 6].copy_from_slice(b"Astro\0");
+    if cfa_pattern.len() == 36 {
+        buffer[PATTERN as usize..PATTERN as usize + 36].copy_from_slice(cfa_pattern);
+    }
+    buffer.extend(samples.iter().flat_map(|x| x.to_le_bytes()));
+    buffer
+}
+
+fn demosaic(samples: &[u16], cfa_pattern: &[u8], method: DemosaicingMethod) -> Vec<u16> {
+    let options = export::Options::new(data::GAMMA_SRGB, &data::XYZ2SRGB, false)
+        .with_demosaicing_method(method);
+    let (image, width, height) =
+        export::load_origin_image_from_buffer(tiff(samples, cfa_pattern), options).unwrap();
+    assert_eq!((WIDTH, HEIGHT), (width, height));
+    image
+}
+
+/// A gray image with a horizontal and a vertical edge
+fn edges(vertical: bool) -> Vec<u16> {
+    (0..WIDTH * HEIGHT)
+        .map(|i| {
+            let position = if vertical { i % WIDTH } else { i / WIDTH };
+            if position < 4 {
+                1000
+            } else {
+                5000
+            }
+        })
+        .collect()
+}
+
+/// Demosaics the gray edges with every bayer pattern
+fn assert_keeps_edges(method: DemosaicingMethod) {
+    // RGGB, GRBG, GBRG and BGGR
+    for cfa_pattern in [[0, 1, 1, 2], [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]] {
+        for vertical in [false, true] {
+            let samples = edges(vertical);
+            let image = demosaic(&samples, &cfa_pattern, method);
+            let expected = samples
+                .iter()
+                .flat_map(|&x| [x, x, x, u16::MAX])
+                .collect::<Vec<_>>();
+            assert_eq!(expected, image, "{:?}", method);
+        }
+    }
+}
+
+#[test]
+fn test_ahd_keeps_edges() {
+    assert_keeps_edges(DemosaicingMethod::AHD);
+}
+
+#[test]
+fn test_linear_directed_keeps_edges() {
+    assert_keeps_edges(DemosaicingMethod::LinearDirected);
+}
+
+/// Demosaics an image of a single color with every bayer pattern
+fn assert_keeps_flat_colors(method: DemosaicingMethod) {
+    const RGB: [u16; 3] = [3000, 2000, 1000];
+    for cfa_pattern in [[0, 1, 1, 2], [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]] {
+        let samples = (0..WIDTH * HEIGHT)
+            .map(|i| RGB[cfa_pattern[(i / WIDTH % 2) * 2 + i % 2] as usize])
+            .collect::<Vec<_>>();
+        let image = demosaic(&samples, &cfa_pattern, method);
+        let expected = [RGB[0], RGB[1], RGB[2], u16::MAX].repeat(WIDTH * HEIGHT);
+        assert_eq!(expected, image, "{:?}", method);
+    }
+}
+
+#[test]
+fn test_passthrough_replicates_sensor_values() {
+    let samples = (0..WIDTH * HEIGHT)
+        .map(|i| 1000 + 10 * i as u16)
+        .collect::<Vec<_>>();
+    let image = demosaic(&samples, &[0, 1, 1, 2], DemosaicingMethod::Passthrough);
+    let expected = samples
+        .iter()
+        .flat_map(|&x| [x, x, x, u16::MAX])
+        .collect::<Vec<_>>();
+    assert_eq!(expected, image);
+}
+
+#[test]
+fn test_cfa_image_keeps_single_channel() {
+    let samples = (0..WIDTH * HEIGHT)
+        .map(|i| 1000 + 10 * i as u16)
+        .collect::<Vec<_>>();
+    let options = export::Options::new(data::GAMMA_SRGB, &data::XYZ2SRGB, false);
+    let image = export::load_cfa_image_from_buffer(tiff(&samples, &[1, 0, 2, 1]), options).unwrap();
+    assert_eq!((samples, WIDTH, HEIGHT, CFAPattern::GRBG), image);
+}
+
+#[test]
+fn test_vng_keeps_flat_colors() {
+    assert_keeps_flat_colors(DemosaicingMethod::VNG);
+}
+
+#[test]
+fn test_mhc_keeps_ramps() {
+    for cfa_pattern in [[0, 1, 1, 2], [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]] {
+        for vertical in [false, true] {
+            let samples = (0..WIDTH * HEIGHT)
+                .map(|i| 1000 + 100 * if vertical { i / WIDTH } else { i % WIDTH } as u16)
+                .collect::<Vec<_>>();
+            let image = demosaic(&samples, &cfa_pattern, DemosaicingMethod::LinearMHC);
+            // the samples mirrored beyond the borders bend the ramp
+            for y in 2..HEIGHT - 2 {
+                for x in 2..WIDTH - 2 {
+                    let (i, v) = (y * WIDTH + x, samples[y * WIDTH + x]);
+                    assert_eq!([v, v, v, u16::MAX], image[i * 4..i * 4 + 4]);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_dcb_keeps_flat_colors() {
+    for (iterations, refine) in [(1, false), (3, true)] {
+        assert_keeps_flat_colors(DemosaicingMethod::DCB { iterations, refine });
+    }
+}
+
+/// Demosaics gradients with every bayer pattern and checks the RMS error to the ground truth
+fn assert_follows_gradients(method: DemosaicingMethod) {
+    // the ground truth, the colors rise to the right and to the bottom at different rates
+    let truth = (0..WIDTH * HEIGHT)
+        .map(|i| {
+            let (x, y) = ((i % WIDTH) as u16, (i / WIDTH) as u16);
+            [
+                1000 + 150 * x + 50 * y,
+                2000 + 100 * x + 100 * y,
+                3000 + 50 * x + 150 * y,
+            ]
+        })
+        .collect::<Vec<_>>();
+    for cfa_pattern in [[0, 1, 1, 2], [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]] {
+        let samples = truth
+            .iter()
+            .enumerate()
+            .map(|(i, rgb)| rgb[cfa_pattern[(i / WIDTH % 2) * 2 + i % 2] as usize])
+            .collect::<Vec<_>>();
+        let image = demosaic(&samples, &cfa_pattern, method);
+        let squared_error = image
+            .chunks_exact(4)
+            .zip(truth.iter())
+            .flat_map(|(pixel, rgb)| (0..3).map(move |c| (pixel[c] as f64 - rgb[c] as f64).powi(2)))
+            .sum::<f64>();
+        let rms = (squared_error / (WIDTH * HEIGHT * 3) as f64).sqrt();
+        assert!(rms < 40.0, "{:?}: {}", cfa_pattern, rms);
+    }
+}
+
+#[test]
+fn test_rcd_gradient_rms() {
+    assert_follows_gradients(DemosaicingMethod::RCD);
+}
+
+#[test]
+fn test_ppg_gradient_rms() {
+    assert_follows_gradients(DemosaicingMethod::PPG);
+}
+
+#[test]
+fn test_ppg_keeps_flat_colors() {
+    assert_keeps_flat_colors(DemosaicingMethod::PPG);
+}
+
+#[cfg(feature = "amaze")]
+#[test]
+fn test_amaze_keeps_flat_colors() {
+    assert_keeps_flat_colors(DemosaicingMethod::AMaZE);
+}
+
+/// The standard deviation of red and blue minus green
+fn chroma_noise(image: &[u16]) -> f64 {
+    let differences = image
+        .chunks_exact(4)
+        .flat_map(|x| [x[0] as f64 - x[1] as f64, x[2] as f64 - x[1] as f64])
+        .collect::<Vec<_>>();
+    let mean = differences.iter().sum::<f64>() / differences.len() as f64;
+    let variance = differences.iter().map(|x| (x - mean).powi(2)).sum::<f64>();
+    (variance / differences.len() as f64).sqrt()
+}
+
+#[test]
+fn test_lmmse_reduces_chroma_noise() {
+    // a gray field with the noise of a linear congruential generator
+    let mut seed = 1u32;
+    let samples = (0..WIDTH * HEIGHT)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            20000 + (seed >> 16) as u16 % 4000
+        })
+        .collect::<Vec<_>>();
+    for cfa_pattern in [[0, 1, 1, 2], [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]] {
+        let linear = demosaic(&samples, &cfa_pattern, DemosaicingMethod::Linear);
+        let lmmse = demosaic(&samples, &cfa_pattern, DemosaicingMethod::LMMSE);
+        assert!(chroma_noise(&lmmse) * 2.0 < chroma_noise(&linear));
+    }
+}
+
+/// Demosaics an image of a single color with both X-Trans patterns
+fn assert_keeps_flat_xtrans_colors(method: DemosaicingMethod) {
+    const RGB: [u16; 3] = [3000, 2000, 1000];
+    const XTRANS: [[u8; 6]; 6] = [
+        [0, 2, 1, 2, 0, 1],
+        [1, 1, 0, 1, 1, 2],
+        [1, 1, 2, 1, 1, 0],
+        [2, 0, 1, 0, 2, 1],
+        [1, 1, 2, 1, 1, 0],
+        [1, 1, 0, 1, 1, 2],
+    ];
+    // the patterns starting with RBGBRG and with GGRGGB
+    for first_row in [0, 1] {
+        let cfa_pattern = (0..6)
+            .flat_map(|row| XTRANS[(first_row + row) % 6])
+            .collect::<Vec<_>>();
+        let samples = (0..WIDTH * HEIGHT)
+            .map(|i| RGB[cfa_pattern[i / WIDTH % 6 * 6 + i % WIDTH % 6] as usize])
+            .collect::<Vec<_>>();
+        let image = demosaic(&samples, &cfa_pattern, method);
+        let expected = [RGB[0], RGB[1], RGB[2], u16::MAX].repeat(WIDTH * HEIGHT);
+        assert_eq!(expected, image, "{:?}", method);
+    }
+}
+
+#[test]
+fn test_markesteijn_keeps_flat_colors() {
+    for passes in [1, 3] {
+        assert_keeps_flat_xtrans_colors(DemosaicingMethod::Markesteijn { passes });
+    }
+}
+
+#[test]
+fn test_xtrans_fast_keeps_flat_colors() {
+    assert_keeps_flat_xtrans_colors(DemosaicingMethod::XTransFast);
+}
+
+#[test]
+fn test_super_pixel_halves_the_image() {
+    const RGB: [u16; 3] = [3000, 2000, 1000];
+    for cfa_pattern in [[0, 1, 1, 2], [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]] {
+        let samples = (0..WIDTH * HEIGHT)
+            .map(|i| RGB[cfa_pattern[(i / WIDTH % 2) * 2 + i % 2] as usize])
+            .collect::<Vec<_>>();
+        let options = export::Options::new(data::GAMMA_SRGB, &data::XYZ2SRGB, false)
+            .with_demosaicing_method(DemosaicingMethod::SuperPixel);
+        let (image, width, height) =
+            export::load_origin_image_from_buffer(tiff(&samples, &cfa_pattern), options).unwrap();
+        assert_eq!((WIDTH / 2, HEIGHT / 2), (width, height));
+        let expected = [RGB[0], RGB[1], RGB[2], u16::MAX].repeat(width * height);
+        assert_eq!(expected, image);
+    }
+
+    let crop = Crop {
+        x: 1,
+        y: 2,
+        width: 7,
+        height: 5,
+    }
+    .halved();
+    assert_eq!((1, 1, 3, 2), (crop.x, crop.y, crop.width, crop.height));
+}
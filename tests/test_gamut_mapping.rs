@@ -0,0 +1,80 @@
+//! Maps the colors outside of the gamut of the output into it.
+
+use quickraw::GamutMapping;
+
+fn luma([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+fn hue([r, g, b]: [f32; 3]) -> f32 {
+    (3f32.sqrt() * (g - b)).atan2(2.0 * r - g - b)
+}
+
+fn chroma(rgb: [f32; 3]) -> f32 {
+    let luma = luma(rgb);
+    rgb.map(|x| (x - luma).abs()).into_iter().fold(0.0, f32::max)
+}
+
+fn is_close(a: [f32; 3], b: [f32; 3]) -> bool {
+    a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6)
+}
+
+fn is_in_gamut(rgb: [f32; 3]) -> bool {
+    rgb.iter().all(|x| (0.0..=1.0).contains(x))
+}
+
+/// The gray with the chroma of `direction` times `amount`, which keeps its luma
+fn saturated(gray: f32, direction: [f32; 3], amount: f32) -> [f32; 3] {
+    let luma = luma(direction);
+    direction.map(|x| gray + (x - luma) * amount)
+}
+
+#[test]
+fn test_desaturate_to_fit() {
+    let mapping = GamutMapping::DesaturateToFit;
+    // the colors in the gamut are kept
+    assert!(is_close([0.2, 0.5, 0.9], mapping.apply([0.2, 0.5, 0.9])));
+
+    // the LEDs pushed out of the gamut more and more, beyond 1 and below 0
+    let leds = [
+        (0.4, [1.0, 0.0, 0.1]),
+        (0.6, [0.0, 0.2, 1.0]),
+        (0.2, [0.6, 0.4, 0.0]),
+    ];
+    for (gray, direction) in leds {
+        let mut last_chroma = 0.0;
+        for i in 1..=20 {
+            let color = saturated(gray, direction, i as f32 * 0.2);
+            let mapped = mapping.apply(color);
+            assert!(is_in_gamut(mapped));
+            assert!((luma(mapped) - luma(color)).abs() < 1e-4);
+            assert!((hue(mapped) - hue(color)).abs() < 1e-3);
+            // the chroma grows until the border and stays there
+            assert!(chroma(mapped) >= last_chroma - 1e-4);
+            last_chroma = chroma(mapped);
+        }
+    }
+}
+
+#[test]
+fn test_perceptual() {
+    let mapping = GamutMapping::Perceptual;
+    // the colors far from the border are kept
+    assert!(is_close([0.45, 0.5, 0.55], mapping.apply([0.45, 0.5, 0.55])));
+
+    let mut last_chroma = 0.0;
+    for i in 1..=40 {
+        let color = saturated(0.5, [1.0, 0.5, 0.0], i as f32 * 0.1);
+        let mapped = mapping.apply(color);
+        assert!(is_in_gamut(mapped));
+        assert!((hue(mapped) - hue(color)).abs() < 1e-3);
+        // the saturated colors keep their gradations instead of flattening at the border
+        assert!(chroma(mapped) > last_chroma);
+        last_chroma = chroma(mapped);
+    }
+}
+
+#[test]
+fn test_clip() {
+    assert_eq!([1.0, 0.0, 0.25], GamutMapping::Clip.apply([1.5, -0.5, 0.25]));
+}
@@ -0,0 +1,25 @@
+//! Decodes a synthetic uncompressed TIFF raw of a maker without a decoder of its own.
+
+mod common;
+
+use common::tiff::{scene, tiff, HEIGHT, WIDTH};
+
+#[test]
+fn test_generic_tiff() {
+    let decoded_image = quickraw::decode_buffer(tiff()).unwrap();
+
+    let expected: Vec<u16> = (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| scene(x, y)))
+        .collect();
+    assert_eq!(expected, decoded_image.image);
+    assert!(decoded_image.is_generic_fallback);
+    assert!(matches!(
+        decoded_image.cfa_pattern,
+        quickraw::CFAPattern::GRBG
+    ));
+    assert_eq!([512, 512, 512], decoded_image.white_balance);
+    assert_eq!(
+        [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+        decoded_image.cam_matrix
+    );
+}
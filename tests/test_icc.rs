@@ -0,0 +1,51 @@
+//! Generates the ICC profiles of the output color spaces and embeds them in JPEG images.
+
+use quickraw::{data, TransferFunction};
+
+fn u32_at(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+#[test]
+fn test_gen_icc_profile() {
+    let profile = quickraw::gen_icc_profile(&data::XYZ2SRGB, TransferFunction::Srgb);
+    assert_eq!(profile.len(), u32_at(&profile, 0) as usize);
+    assert_eq!(b"acsp", &profile[36..40]);
+
+    // the colorants of sRGB adapted to D50 like the profiles of the other applications
+    let tag_count = u32_at(&profile, 128) as usize;
+    let red = (0..tag_count)
+        .map(|i| 132 + i * 12)
+        .find(|&entry| &profile[entry..entry + 4] == b"rXYZ")
+        .map(|entry| u32_at(&profile, entry + 4) as usize)
+        .unwrap();
+    let red = [0, 1, 2].map(|i| u32_at(&profile, red + 8 + i * 4) as i32 as f32 / 65536.0);
+    for (expected, value) in [0.4361, 0.2225, 0.0139].iter().zip(red) {
+        assert!((expected - value).abs() < 0.001);
+    }
+}
+
+#[test]
+fn test_embed_icc_profile_in_jpeg() {
+    // SOI, an APP0 of 2 bytes and EOI
+    let jpeg = [0xff, 0xd8, 0xff, 0xe0, 0, 4, 1, 2, 0xff, 0xd9];
+    // the profiles beyond 65519 bytes take several segments
+    let profile = vec![7u8; 70000];
+    let embedded = quickraw::embed_icc_profile_in_jpeg(&jpeg, &profile).unwrap();
+
+    assert_eq!(jpeg[..8], embedded[..8]);
+    assert_eq!([0xff, 0xe2, 0xff, 0xff], embedded[8..12]);
+    assert_eq!(b"ICC_PROFILE\0\x01\x02", &embedded[12..26]);
+    let second = 8 + 2 + 0xffff;
+    assert_eq!([0xff, 0xe2], embedded[second..second + 2]);
+    assert_eq!(b"ICC_PROFILE\0\x02\x02", &embedded[second + 4..second + 18]);
+    assert_eq!(jpeg.len() + profile.len() + 2 * 18, embedded.len());
+    assert_eq!([0xff, 0xd9], embedded[embedded.len() - 2..]);
+
+    assert!(quickraw::embed_icc_profile_in_jpeg(&[0, 1], &profile).is_none());
+}
@@ -0,0 +1,50 @@
+//! Parses the 3D LUTs of `.cube` files and looks up colors in them.
+
+use quickraw::{Lut3d, RawFileReadingError};
+
+/// A cube of size 2 which swaps red and blue
+fn cube(header: &str) -> String {
+    let mut cube = format!(
+        "# swaps red and blue\nTITLE \"Swap\"\n{}LUT_3D_SIZE 2\n",
+        header
+    );
+    for b in 0..2 {
+        for g in 0..2 {
+            for r in 0..2 {
+                cube += &format!("{} {} {}\n", b, g, r);
+            }
+        }
+    }
+    cube
+}
+
+#[test]
+fn test_lut3d() {
+    let lut3d = Lut3d::from_cube_str(&cube("")).unwrap();
+    assert_eq!(Some("Swap"), lut3d.title());
+    assert_eq!(2, lut3d.size());
+    assert_eq!([0.75, 0.5, 0.25], lut3d.apply([0.25, 0.5, 0.75]));
+    // the values outside of the domain take the colors of its border
+    assert_eq!([0.0, 1.0, 1.0], lut3d.apply([2.0, 1.0, -1.0]));
+
+    let lut3d = Lut3d::from_cube_str(&cube("DOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2\n")).unwrap();
+    assert_eq!([0.5, 0.25, 0.125], lut3d.apply([0.25, 0.5, 1.0]));
+}
+
+#[test]
+fn test_malformed_lut3d() {
+    let truncated = cube("").lines().take(8).collect::<Vec<_>>().join("\n");
+    for cube in [
+        truncated,
+        cube("DOMAIN_MIN 0 0\n"),
+        cube("DOMAIN_MIN 1 1 1\nDOMAIN_MAX 0 0 0\n"),
+        cube("LUT_1D_SIZE 1024\n"),
+        "0 0 0\n".to_owned(),
+        "LUT_3D_SIZE 1\n".to_owned(),
+    ] {
+        assert!(matches!(
+            Lut3d::from_cube_str(&cube),
+            Err(RawFileReadingError::InvalidCubeLut(_))
+        ));
+    }
+}
@@ -0,0 +1,422 @@
+//! Decodes synthetic 12 bit NEFs, uncompressed and huffman compressed, and a Coolpix NRW.
+
+mod common;
+
+const WIDTH: usize = 8;
+const HEIGHT: usize = 4;
+const PREDICTORS: [u16; 4] = [2048, 1024, 512, 256];
+
+// the code length counts starting from length 0 and the difference categories
+type Tree = ([u8; 16], [u8; 16]);
+const LOSSY_TREE: Tree = (
+    [0, 0, 1, 5, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0],
+    [5, 4, 3, 6, 2, 7, 1, 0, 8, 9, 11, 10, 12, 0, 0, 0],
+);
+const LOSSLESS_TREE: Tree = (
+    [0, 0, 1, 4, 2, 3, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0],
+    [5, 4, 6, 3, 7, 2, 8, 1, 9, 0, 10, 11, 12, 0, 0, 0],
+);
+
+/// A gradient over the whole 12 bit range
+fn scene(x: usize, y: usize) -> u16 {
+    ((y * WIDTH + x) * 4095 / (WIDTH * HEIGHT - 1)) as u16
+}
+
+/// The lossy files keep the square root of the values and expand them with the curve
+fn lossy_curve(coded: usize) -> u16 {
+    (coded * coded / 4095) as u16
+}
+
+fn lossy_coded(x: usize, y: usize) -> u16 {
+    (scene(x, y) as f64 * 4095.0).sqrt().round() as u16
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u8,
+    len: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1) as u8;
+            self.len += 1;
+            if self.len == 8 {
+                self.bytes.push(self.acc);
+                self.acc = 0;
+                self.len = 0;
+            }
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.put(0, 8 - self.len);
+        }
+        // the bit pump reads ahead by 4 bytes
+        self.bytes.extend([0; 8]);
+        self.bytes
+    }
+}
+
+/// Encodes the values with the same predictors the decoder uses, the first two columns
+/// are predicted from two rows above and the others from two columns on the left.
+fn strip(tree: &Tree, coded: impl Fn(usize, usize) -> u16) -> Vec<u8> {
+    let (bits, huffval) = tree;
+    let mut codes = [(0u32, 0u32); 13];
+    let (mut code, mut pos) = (0, 0);
+    for (len, &count) in bits.iter().enumerate() {
+        for _ in 0..count {
+            let category = huffval[pos] as usize;
+            // the 12-bit lossy tree has two codes of category 0
+            if codes[category].1 == 0 {
+                codes[category] = (code, len as u32);
+            }
+            code += 1;
+            pos += 1;
+        }
+        code <<= 1;
+    }
+
+    let mut writer = BitWriter {
+        bytes: vec![],
+        acc: 0,
+        len: 0,
+    };
+    let mut vpred = [
+        [PREDICTORS[0] as i32, PREDICTORS[1] as i32],
+        [PREDICTORS[2] as i32, PREDICTORS[3] as i32],
+    ];
+    for row in 0..HEIGHT {
+        let mut hpred = [0i32; 2];
+        for col in 0..WIDTH {
+            let value = coded(col, row) as i32;
+            let pred = if col < 2 {
+                vpred[row & 1][col]
+            } else {
+                hpred[col & 1]
+            };
+            if col < 2 {
+                vpred[row & 1][col] = value;
+            }
+            hpred[col & 1] = value;
+
+            let diff = value - pred;
+            let category = 32 - diff.unsigned_abs().leading_zeros();
+            let (code, len) = codes[category as usize];
+            writer.put(code, len);
+            if category > 0 {
+                let bits = if diff < 0 { diff - 1 } else { diff };
+                writer.put(bits as u32 & ((1 << category) - 1), category);
+            }
+        }
+    }
+    writer.finish()
+}
+
+/// The content of maker note tag 0x96: version bytes, predictors and the curve
+fn linearization_table(version: [u8; 2], curve: &[u16]) -> Vec<u8> {
+    let mut table = version.to_vec();
+    for value in PREDICTORS {
+        table.extend(value.to_le_bytes());
+    }
+    table.extend((curve.len() as u16).to_le_bytes());
+    for value in curve {
+        table.extend(value.to_le_bytes());
+    }
+    // the split row of the lossy tree is at byte 562, 0 means no split
+    table.resize(564, 0);
+    table
+}
+
+// the serial number 257 and the shutter count 2 pick 0xbf and 0xc9 from the key tables
+const SERIAL_NUMBER: &[u8; 8] = b"0000257\0";
+const SHUTTER_COUNT: u32 = 2;
+const KEYS: (u8, u8) = (0xbf, 0xc9);
+
+/// Version 0204 of maker note tag 0x97, the white balance levels are at byte 6 of the
+/// encrypted part
+fn color_balance(levels: [u16; 4]) -> Vec<u8> {
+    let mut plain = vec![0u8; 324];
+    for (i, level) in levels.iter().enumerate() {
+        plain[6 + i * 2..8 + i * 2].copy_from_slice(&level.to_le_bytes());
+    }
+    let (ci, mut cj) = KEYS;
+    let mut ck = 0x60u8;
+    for x in plain.iter_mut() {
+        cj = cj.wrapping_add(ci.wrapping_mul(ck));
+        ck = ck.wrapping_add(1);
+        *x ^= cj;
+    }
+
+    let mut data = b"0204".to_vec();
+    data.extend([0; 280]);
+    data.extend(plain);
+    data
+}
+
+/// The NRW data of maker note tag 0x14, the levels of version 0200 are at byte 56
+fn nrw_data(levels: [u32; 4]) -> Vec<u8> {
+    let mut data = b"NRW 0200\0\0".to_vec();
+    data.resize(56, 0);
+    for level in levels {
+        data.extend(level.to_le_bytes());
+    }
+    data
+}
+
+/// An NEF, or an NRW of a Coolpix when the NRW data is given
+fn nef(
+    compression: u16,
+    table: &[u8],
+    color_balance: &[u8],
+    nrw_data: &[u8],
+    strip: &[u8],
+) -> Vec<u8> {
+    const ASCII: u16 = 2;
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    const RATIONAL: u16 = 5;
+    const UNDEFINED: u16 = 7;
+    const SUB_IFDS: u32 = 100;
+    const RAW_IFD: u32 = 120;
+    const EXIF: u32 = 200;
+    const CFA_PATTERN: u32 = 240;
+    const MAKE: u32 = 250;
+    const MODEL: u32 = 270;
+    const MAKER_NOTE: u32 = 300;
+    // the offsets inside the maker note are relative to its TIFF header
+    const MAKER_NOTE_TIFF: u32 = MAKER_NOTE + 10;
+    const WB_LEVELS: u32 = 400;
+    const SERIAL: u32 = 440;
+    const LINEAR_TABLE: u32 = 460;
+    const COLOR_BALANCE: u32 = 1040;
+    const NRW_DATA: u32 = 1400;
+    const STRIP: u32 = 2000;
+
+    let model: &[u8] = if nrw_data.is_empty() {
+        b"NIKON D850\0"
+    } else {
+        b"COOLPIX P7800\0"
+    };
+
+    let mut buffer = vec![0u8; STRIP as usize + strip.len()];
+    buffer[..8].copy_from_slice(&[b'I', b'I', 0x2a, 0, 8, 0, 0, 0]);
+
+    common::write_ifd(
+        &mut buffer,
+        8,
+        &[
+            (0x010f, ASCII, 18, MAKE),
+            (0x0110, ASCII, model.len() as u32, MODEL),
+            (0x0112, SHORT, 1, 1),
+            (0x014a, LONG, 2, SUB_IFDS),
+            (0x8769, LONG, 1, EXIF),
+        ],
+        0,
+    );
+    // the preview IFD is not needed to decode the raw image
+    for (i, ifd) in [RAW_IFD, RAW_IFD].iter().enumerate() {
+        let pos = SUB_IFDS as usize + i * 4;
+        buffer[pos..pos + 4].copy_from_slice(&ifd.to_le_bytes());
+    }
+    common::write_ifd(
+        &mut buffer,
+        RAW_IFD as usize,
+        &[
+            (0x0100, LONG, 1, WIDTH as u32),
+            (0x0101, LONG, 1, HEIGHT as u32),
+            (0x0102, SHORT, 1, 12),
+            (0x0103, SHORT, 1, compression as u32),
+            (0x0111, LONG, 1, STRIP),
+            (0x0117, LONG, 1, strip.len() as u32),
+        ],
+        0,
+    );
+    common::write_ifd(
+        &mut buffer,
+        EXIF as usize,
+        &[
+            (0xa302, UNDEFINED, 8, CFA_PATTERN),
+            (0x927c, UNDEFINED, STRIP - MAKER_NOTE, MAKER_NOTE),
+        ],
+        0,
+    );
+    // NRW files have the NRW data instead of WB_RBLevels
+    let mut maker_note = if nrw_data.is_empty() {
+        vec![(0x000c, RATIONAL, 4, WB_LEVELS - MAKER_NOTE_TIFF)]
+    } else {
+        vec![(
+            0x0014,
+            UNDEFINED,
+            nrw_data.len() as u32,
+            NRW_DATA - MAKER_NOTE_TIFF,
+        )]
+    };
+    if !color_balance.is_empty() {
+        maker_note.push((0x001d, ASCII, 8, SERIAL - MAKER_NOTE_TIFF));
+    }
+    maker_note.push((
+        0x0096,
+        UNDEFINED,
+        table.len() as u32,
+        LINEAR_TABLE - MAKER_NOTE_TIFF,
+    ));
+    if !color_balance.is_empty() {
+        maker_note.push((
+            0x0097,
+            UNDEFINED,
+            color_balance.len() as u32,
+            COLOR_BALANCE - MAKER_NOTE_TIFF,
+        ));
+        maker_note.push((0x00a7, LONG, 1, SHUTTER_COUNT));
+    }
+    common::write_ifd(&mut buffer, MAKER_NOTE as usize + 18, &maker_note, 0);
+
+    buffer[CFA_PATTERN as usize..CFA_PATTERN as usize + 8]
+        .copy_from_slice(&[2, 0, 2, 0, 0, 1, 1, 2]);
+    buffer[MAKE as usize..MAKE as usize + 18].copy_from_slice(b"NIKON CORPORATION\0");
+    buffer[MODEL as usize..MODEL as usize + model.len()].copy_from_slice(model);
+    buffer[MAKER_NOTE as usize..MAKER_NOTE as usize + 18]
+        .copy_from_slice(b"Nikon\0\x02\x10\0\0II*\0\x08\0\0\0");
+    // WB_RBLevels: red, blue and the two greens
+    for (i, (numerator, denominator)) in [(2u32, 1u32), (3, 2), (1, 1), (1, 1)].iter().enumerate() {
+        let pos = WB_LEVELS as usize + i * 8;
+        buffer[pos..pos + 4].copy_from_slice(&numerator.to_le_bytes());
+        buffer[pos + 4..pos + 8].copy_from_slice(&denominator.to_le_bytes());
+    }
+    buffer[SERIAL as usize..SERIAL as usize + 8].copy_from_slice(SERIAL_NUMBER);
+    buffer[LINEAR_TABLE as usize..LINEAR_TABLE as usize + table.len()].copy_from_slice(table);
+    buffer[COLOR_BALANCE as usize..COLOR_BALANCE as usize + color_balance.len()]
+        .copy_from_slice(color_balance);
+    buffer[NRW_DATA as usize..NRW_DATA as usize + nrw_data.len()].copy_from_slice(nrw_data);
+    buffer[STRIP as usize..].copy_from_slice(strip);
+
+    buffer
+}
+
+fn lossless_nef() -> Vec<u8> {
+    let table = linearization_table([0x46, 0x30], &[]);
+    nef(34713, &table, &[], &[], &strip(&LOSSLESS_TREE, scene))
+}
+
+fn lossy_nef() -> Vec<u8> {
+    // 257 points with a step of 16 over the 12 bit range
+    let curve = (0..=4096).step_by(16).map(lossy_curve).collect::<Vec<_>>();
+    let table = linearization_table([0x44, 0x20], &curve);
+    nef(34713, &table, &[], &[], &strip(&LOSSY_TREE, lossy_coded))
+}
+
+/// Packs two samples in 3 bytes with 2 bytes of padding at the end of every row
+fn packed_nef() -> Vec<u8> {
+    let mut strip = vec![];
+    for y in 0..HEIGHT {
+        for x in (0..WIDTH).step_by(2) {
+            let (a, b) = (scene(x, y), scene(x + 1, y));
+            strip.extend([a as u8, ((a >> 8) | (b << 4)) as u8, (b >> 4) as u8]);
+        }
+        strip.extend([0, 0]);
+    }
+    // uncompressed files keep the linearization table as well
+    let table = linearization_table([0x46, 0x30], &[]);
+    nef(1, &table, &[], &[], &strip)
+}
+
+/// The packed samples of NRW are big endian although the file is little endian
+fn packed_nrw() -> Vec<u8> {
+    let mut strip = vec![];
+    for y in 0..HEIGHT {
+        for x in (0..WIDTH).step_by(2) {
+            let (a, b) = (scene(x, y), scene(x + 1, y));
+            strip.extend([(a >> 4) as u8, ((a << 4) | (b >> 8)) as u8, b as u8]);
+        }
+    }
+    let table = linearization_table([0x46, 0x30], &[]);
+    // red, the two greens and blue
+    let nrw_data = nrw_data([384, 512, 512, 128]);
+    nef(1, &table, &[], &nrw_data, &strip)
+}
+
+fn color_balance_nef() -> Vec<u8> {
+    let table = linearization_table([0x46, 0x30], &[]);
+    // red, green, green and blue
+    let color_balance = color_balance([500, 250, 250, 1000]);
+    nef(
+        34713,
+        &table,
+        &color_balance,
+        &[],
+        &strip(&LOSSLESS_TREE, scene),
+    )
+}
+
+fn range(image: &[u16]) -> (u16, u16) {
+    let min = image.iter().copied().min().unwrap();
+    let max = image.iter().copied().max().unwrap();
+    (min, max)
+}
+
+#[test]
+fn test_nef_lossless() {
+    let decoded_image = quickraw::decode_buffer(lossless_nef()).unwrap();
+
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    // 12 bit samples are scaled to 16 bit, the curve dithering may take one off
+    for (i, &value) in decoded_image.image.iter().enumerate() {
+        let expected = scene(i % WIDTH, i / WIDTH) * 16;
+        assert!(value <= expected && expected - value <= 16);
+    }
+}
+
+#[test]
+fn test_nef_lossy_matches_lossless_range() {
+    let lossless = quickraw::decode_buffer(lossless_nef()).unwrap();
+    let lossy = quickraw::decode_buffer(lossy_nef()).unwrap();
+
+    assert_eq!(
+        (lossless.width, lossless.height),
+        (lossy.width, lossy.height)
+    );
+    // a few 12 bit steps for the square root rounding and the dithering
+    let tolerance = 4 * 16;
+    let (lossless_min, lossless_max) = range(&lossless.image);
+    let (lossy_min, lossy_max) = range(&lossy.image);
+    assert!(lossless_min.abs_diff(lossy_min) <= tolerance);
+    assert!(lossless_max.abs_diff(lossy_max) <= tolerance);
+    for (a, b) in lossless.image.iter().zip(lossy.image.iter()) {
+        assert!(a.abs_diff(*b) <= tolerance);
+    }
+}
+
+#[test]
+fn test_nef_uncompressed_packed() {
+    let decoded_image = quickraw::decode_buffer(packed_nef()).unwrap();
+
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    let expected = (0..WIDTH * HEIGHT)
+        .map(|i| scene(i % WIDTH, i / WIDTH) * 16)
+        .collect::<Vec<_>>();
+    assert_eq!(expected, decoded_image.image);
+}
+
+#[test]
+fn test_nrw_packed() {
+    let decoded_image = quickraw::decode_buffer(packed_nrw()).unwrap();
+
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    let expected = (0..WIDTH * HEIGHT)
+        .map(|i| scene(i % WIDTH, i / WIDTH) * 16)
+        .collect::<Vec<_>>();
+    assert_eq!(expected, decoded_image.image);
+    assert_eq!([768, 512, 256], decoded_image.white_balance);
+}
+
+#[test]
+fn test_nef_encrypted_color_balance() {
+    let decoded_image = quickraw::decode_buffer(color_balance_nef()).unwrap();
+    assert_eq!([1024, 512, 2048], decoded_image.white_balance);
+
+    // WB_RBLevels is used without tag 0x97
+    let decoded_image = quickraw::decode_buffer(lossless_nef()).unwrap();
+    assert_eq!([1024, 512, 768], decoded_image.white_balance);
+}
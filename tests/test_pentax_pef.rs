@@ -0,0 +1,142 @@
+//! Decodes synthetic uncompressed PEFs of a K-1, whose maker notes tell the Pixel Shift
+//! Resolution files apart from the files of a single image in several strips.
+
+mod common;
+
+const WIDTH: usize = 8;
+const HEIGHT: usize = 4;
+
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const UNDEFINED: u16 = 7;
+
+// the last byte of DriveMode
+const SINGLE_EXPOSURE: u8 = 0x00;
+const PIXEL_SHIFT_RESOLUTION: u8 = 0x10;
+
+/// Every pixel holds its own position, the frames are told apart by their lowest digits
+fn scene(x: usize, y: usize, frame: usize) -> u16 {
+    common::position(x, y, WIDTH, 0) * 100 + frame as u16
+}
+
+fn expected(frame: usize) -> Vec<u16> {
+    (0..WIDTH * HEIGHT)
+        .map(|i| scene(i % WIDTH, i / WIDTH, frame))
+        .collect()
+}
+
+/// A PEF with the 16 bit `strips` in IFD0 and the `exposure` byte of DriveMode in its maker
+/// notes, whose offsets count from the start of the file
+fn pef(exposure: u8, strips: &[Vec<u8>]) -> Vec<u8> {
+    const EXIF: u32 = 160;
+    const MAKER_NOTES: u32 = 200;
+    const MAKER_NOTES_LEN: u32 = 40;
+    const WB_LEVELS: u32 = 240;
+    const MAKE: u32 = 250;
+    const MODEL: u32 = 260;
+    const STRIP_OFFSETS: u32 = 280;
+    const STRIP_LENS: u32 = 300;
+    const DATA: u32 = 320;
+
+    let data = strips.concat();
+    let mut buffer = vec![0u8; DATA as usize + data.len()];
+    buffer[..8].copy_from_slice(&[b'I', b'I', 0x2a, 0, 8, 0, 0, 0]);
+    common::write_ifd(
+        &mut buffer,
+        8,
+        &[
+            (0x0100, LONG, 1, WIDTH as u32),
+            (0x0101, LONG, 1, HEIGHT as u32),
+            (0x0102, SHORT, 1, 16),
+            (0x0103, SHORT, 1, 1),
+            (0x010f, ASCII, 7, MAKE),
+            (0x0110, ASCII, 11, MODEL),
+            (0x0111, LONG, strips.len() as u32, STRIP_OFFSETS),
+            (0x0112, SHORT, 1, 1),
+            (0x0117, LONG, strips.len() as u32, STRIP_LENS),
+            (0x8769, LONG, 1, EXIF),
+        ],
+        0,
+    );
+    common::write_ifd(
+        &mut buffer,
+        EXIF as usize,
+        &[(0x927c, UNDEFINED, MAKER_NOTES_LEN, MAKER_NOTES)],
+        0,
+    );
+    buffer[MAKER_NOTES as usize..MAKER_NOTES as usize + 6].copy_from_slice(b"AOC\0II");
+    common::write_ifd(
+        &mut buffer,
+        MAKER_NOTES as usize + 6,
+        &[
+            (0x0034, BYTE, 4, u32::from_le_bytes([0, 0, 0, exposure])),
+            (0x0201, SHORT, 4, WB_LEVELS),
+        ],
+        0,
+    );
+    for (i, level) in [2000u16, 1000, 1000, 1500].iter().enumerate() {
+        let pos = WB_LEVELS as usize + i * 2;
+        buffer[pos..pos + 2].copy_from_slice(&level.to_le_bytes());
+    }
+    buffer[MAKE as usize..MAKE as usize + 7].copy_from_slice(b"PENTAX\0");
+    buffer[MODEL as usize..MODEL as usize + 11].copy_from_slice(b"PENTAX K-1\0");
+
+    let mut offset = DATA;
+    for (i, strip) in strips.iter().enumerate() {
+        let pos = i * 4;
+        buffer[STRIP_OFFSETS as usize + pos..STRIP_OFFSETS as usize + pos + 4]
+            .copy_from_slice(&offset.to_le_bytes());
+        buffer[STRIP_LENS as usize + pos..STRIP_LENS as usize + pos + 4]
+            .copy_from_slice(&(strip.len() as u32).to_le_bytes());
+        offset += strip.len() as u32;
+    }
+    buffer[DATA as usize..].copy_from_slice(&data);
+    buffer
+}
+
+fn to_bytes(samples: &[u16]) -> Vec<u8> {
+    samples.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+#[test]
+fn test_pixel_shift_frames() {
+    let frames = (0..4)
+        .map(|frame| to_bytes(&expected(frame)))
+        .collect::<Vec<_>>();
+    let decoded_images =
+        quickraw::decode_buffer_multi(pef(PIXEL_SHIFT_RESOLUTION, &frames)).unwrap();
+
+    assert_eq!(4, decoded_images.len());
+    for (frame, decoded_image) in decoded_images.iter().enumerate() {
+        assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+        assert_eq!(expected(frame), decoded_image.image);
+        assert_eq!([2000, 1000, 1500], decoded_image.white_balance);
+    }
+}
+
+#[test]
+fn test_pixel_shift_frames_without_drive_mode() {
+    // 4 strips of a whole image each are frames, whatever DriveMode says
+    let frames = (0..4)
+        .map(|frame| to_bytes(&expected(frame)))
+        .collect::<Vec<_>>();
+    let decoded_images = quickraw::decode_buffer_multi(pef(SINGLE_EXPOSURE, &frames)).unwrap();
+
+    assert_eq!(4, decoded_images.len());
+    for (frame, decoded_image) in decoded_images.iter().enumerate() {
+        assert_eq!(expected(frame), decoded_image.image);
+    }
+}
+
+#[test]
+fn test_strips_of_a_single_image() {
+    // a strip for every row, which are not frames without the pixel shift in DriveMode
+    let image = expected(0);
+    let rows = image.chunks(WIDTH).map(to_bytes).collect::<Vec<_>>();
+    let decoded_images = quickraw::decode_buffer_multi(pef(SINGLE_EXPOSURE, &rows)).unwrap();
+
+    assert_eq!(1, decoded_images.len());
+    assert_eq!(image, decoded_images[0].image);
+}
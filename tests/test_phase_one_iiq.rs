@@ -0,0 +1,135 @@
+//! Decodes synthetic IIQ files, whose raw data is referenced by the Phase One directory
+//! following the TIFF header rather than by the TIFF itself.
+
+mod common;
+
+const WIDTH: usize = 6;
+const HEIGHT: usize = 4;
+const BASE: usize = 8;
+
+fn scene(x: usize, y: usize) -> u16 {
+    common::position(x, y, WIDTH, 100)
+}
+
+/// An entry holds either its value or the offset of its values, which are appended
+/// behind the directory
+enum Value {
+    Data(u32),
+    Bytes(Vec<u8>),
+}
+
+fn iiq(entries: Vec<(u32, Value)>) -> Vec<u8> {
+    let mut buffer = vec![0x49, 0x49, 0x2a, 0x00, 0x00, 0x00, 0x00, 0x00];
+    buffer.extend(b"IIII\x00waR");
+    // the directory follows its offset and 4 unused bytes
+    buffer.extend(16u32.to_le_bytes());
+    buffer.extend([0u8; 4]);
+
+    let dir_start = buffer.len();
+    buffer.extend((entries.len() as u32).to_le_bytes());
+    buffer.extend(0u32.to_le_bytes());
+    let mut data: Vec<u8> = vec![];
+    let data_start = dir_start + 8 + entries.len() * 16;
+    for (tag, value) in entries {
+        let (len, value) = match value {
+            Value::Data(value) => (4, value),
+            Value::Bytes(bytes) => {
+                let offset = data_start + data.len() - BASE;
+                data.extend(&bytes);
+                (bytes.len() as u32, offset as u32)
+            }
+        };
+        buffer.extend(tag.to_le_bytes());
+        buffer.extend(4u32.to_le_bytes());
+        buffer.extend(len.to_le_bytes());
+        buffer.extend(value.to_le_bytes());
+    }
+    buffer.extend(data);
+    buffer
+}
+
+fn floats(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+/// The entries shared by the tests
+fn entries(format: u32, raw: Vec<u8>) -> Vec<(u32, Value)> {
+    vec![
+        (0x0107, Value::Bytes(floats(&[2.0, 1.0, 1.5]))),
+        (0x0108, Value::Data(WIDTH as u32)),
+        (0x0109, Value::Data(HEIGHT as u32)),
+        (0x010a, Value::Data(1)),
+        (0x010b, Value::Data(1)),
+        (0x010c, Value::Data(4)),
+        (0x010d, Value::Data(2)),
+        (0x010e, Value::Data(format)),
+        (0x010f, Value::Bytes(raw)),
+        (0x0301, Value::Bytes(b"IQ4 150MP camera\0".to_vec())),
+        (
+            0x0106,
+            Value::Bytes(floats(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0])),
+        ),
+    ]
+}
+
+#[test]
+fn test_iiq_uncompressed() {
+    let mut raw: Vec<u16> = (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| scene(x, y)))
+        .collect();
+    // a hot pixel listed as defective
+    raw[WIDTH + 2] = 60000;
+    let raw = raw.iter().flat_map(|x| x.to_le_bytes()).collect();
+
+    let mut meta = b"II\0\0\0\0\0\0".to_vec();
+    meta.extend(12u32.to_le_bytes());
+    meta.extend(1u32.to_le_bytes());
+    meta.extend(0u32.to_le_bytes());
+    meta.extend(0x0400u32.to_le_bytes());
+    meta.extend(8u32.to_le_bytes());
+    meta.extend(32u32.to_le_bytes());
+    meta.extend([2, 0, 1, 0, 129, 0, 0, 0]);
+
+    let mut entries = entries(0, raw);
+    entries.push((0x021d, Value::Data(50)));
+    entries.push((0x0110, Value::Bytes(meta)));
+    let decoded_image = quickraw::decode_buffer(iiq(entries)).unwrap();
+
+    let mut expected: Vec<u16> = (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| scene(x, y) - 50))
+        .collect();
+    expected[WIDTH + 2] = (scene(0, 1) + scene(4, 1) + scene(2, 3)) / 3 - 50;
+
+    assert_eq!(expected, decoded_image.image);
+    assert_eq!([1024, 512, 768], decoded_image.white_balance);
+    let crop = decoded_image.crop.unwrap();
+    assert_eq!((1, 1, 4, 2), (crop.x, crop.y, crop.width, crop.height));
+}
+
+#[test]
+fn test_iiq_l() {
+    // narrower than 8 columns every sample is stored as plain 16 bits
+    let rows: Vec<Vec<u8>> = (0..HEIGHT)
+        .map(|y| {
+            let bits: u128 = (0..WIDTH).fold(0, |bits, x| bits << 16 | scene(x, y) as u128);
+            (0..3)
+                .flat_map(|i| ((bits >> (64 - i * 32)) as u32).to_le_bytes())
+                .collect()
+        })
+        .collect();
+    let mut raw = vec![];
+    let mut offsets = vec![];
+    for row in rows {
+        offsets.extend((raw.len() as u32).to_le_bytes());
+        raw.extend(row);
+    }
+
+    let mut entries = entries(3, raw);
+    entries.push((0x021c, Value::Bytes(offsets)));
+    let decoded_image = quickraw::decode_buffer(iiq(entries)).unwrap();
+
+    let expected: Vec<u16> = (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| scene(x, y) << 2))
+        .collect();
+    assert_eq!(expected, decoded_image.image);
+}
@@ -0,0 +1,75 @@
+//! Decodes the files of a maker quickraw does not know with a registered decoder.
+
+mod common;
+
+use quickraw::{Crop, DecodingError, RawDecoder};
+
+const WIDTH: usize = 4;
+const HEIGHT: usize = 2;
+
+struct Nova {
+    info: quickexif::ParsedInfo,
+}
+
+impl RawDecoder for Nova {
+    fn new(info: quickexif::ParsedInfo) -> Self {
+        Nova { info }
+    }
+    fn get_info(&self) -> &quickexif::ParsedInfo {
+        &self.info
+    }
+    fn into_info(self) -> quickexif::ParsedInfo {
+        self.info
+    }
+    fn get_white_balance(&self, _buffer: &[u8]) -> Result<[i32; 3], DecodingError> {
+        Ok([1024, 512, 768])
+    }
+    fn get_crop(&self) -> Option<Crop> {
+        None
+    }
+    fn get_image_size(&self, _buffer: &[u8]) -> Result<(usize, usize), DecodingError> {
+        Ok((WIDTH, HEIGHT))
+    }
+    fn decode_with_preprocess(&self, _buffer: &[u8]) -> Result<Vec<u16>, DecodingError> {
+        Ok((0..WIDTH * HEIGHT).map(|i| i as u16 * 100).collect())
+    }
+    fn get_thumbnail<'a>(&self, _buffer: &'a [u8]) -> Result<&'a [u8], DecodingError> {
+        Err(DecodingError::InvalidRawData(
+            "Nova files have no thumbnail".to_owned(),
+        ))
+    }
+}
+
+/// A TIFF with nothing but the make and the model
+fn tiff(model: &[u8]) -> Vec<u8> {
+    const MAKE: u32 = 100;
+    const MODEL: u32 = 120;
+
+    let entries: [(u16, u16, u32, u32); 2] =
+        [(0x010f, 2, 5, MAKE), (0x0110, 2, model.len() as u32, MODEL)];
+    let mut buffer = vec![0u8; 200];
+    buffer[..8].copy_from_slice(&[b'I', b'I', 0x2a, 0, 8, 0, 0, 0]);
+    common::write_ifd(&mut buffer, 8, &entries, 0);
+    buffer[MAKE as usize..MAKE as usize + 5].copy_from_slice(b"Acme\0");
+    buffer[MODEL as usize..MODEL as usize + model.len()].copy_from_slice(model);
+    buffer
+}
+
+#[test]
+fn test_registered_decoder() {
+    quickraw::register_decoder::<Nova>(
+        "Acme",
+        |model| model == "Nova",
+        |_, info| Ok(Nova::new(info)),
+    );
+
+    let decoded_image = quickraw::decode_buffer(tiff(b"Nova\0")).unwrap();
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    let expected: Vec<u16> = (0..WIDTH * HEIGHT).map(|i| i as u16 * 100).collect();
+    assert_eq!(expected, decoded_image.image);
+    assert_eq!([1024, 512, 768], decoded_image.white_balance);
+    assert!(!decoded_image.is_generic_fallback);
+
+    // the other models of the make are not taken by the registered decoder
+    assert!(quickraw::decode_buffer(tiff(b"Astro\0")).is_err());
+}
@@ -0,0 +1,736 @@
+//! Renders the synthetic TIFF raw of the generic decoder with the options of the export.
+
+mod common;
+
+use common::tiff::{tiff, HEIGHT, WIDTH};
+use quickraw::{
+    data, export, DemosaicingMethod, HighlightMode, Monochrome, NegativeInversion, Output,
+    OutputType, Sharpen, ToneMapping, TransferFunction, WhiteBalance, WhiteBalanceMethod,
+    WhiteLevel,
+};
+
+// a ColorMatrix from XYZ to the camera colors before the white balance, whose green is the
+// most sensitive like the one of most sensors
+const COLOR_MATRIX: [f32; 9] = [0.8, -0.2, -0.1, -0.5, 1.3, 0.2, -0.1, 0.2, 0.6];
+
+fn render(white_balance: WhiteBalance) -> Vec<u16> {
+    let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+        .with_white_balance(white_balance);
+    let (image, width, height) = export::load_image_from_buffer(tiff(), options).unwrap();
+    assert_eq!((WIDTH, HEIGHT), (width, height));
+    image
+}
+
+#[test]
+fn test_white_balance() {
+    // the as shot white balance of the generic raws is neutral
+    let none = render(WhiteBalance::None);
+    assert_eq!(none, render(WhiteBalance::AsShot));
+
+    let red = render(WhiteBalance::Custom([2.0, 1.0, 1.0]));
+    for (none, red) in none.chunks_exact(3).zip(red.chunks_exact(3)) {
+        assert_eq!(none[1..], red[1..]);
+        assert_eq!((none[0] as u32 * 2).min(u16::MAX as u32), red[0] as u32);
+    }
+    // the multipliers are relative to green
+    assert_eq!(red, render(WhiteBalance::Custom([4.0, 2.0, 2.0])));
+
+    // the large multipliers clip instead of overflowing
+    let red = render(WhiteBalance::Custom([8.0, 1.0, 1.0]));
+    for (none, red) in none.chunks_exact(3).zip(red.chunks_exact(3)) {
+        assert_eq!(none[1..], red[1..]);
+        assert_eq!((none[0] as u32 * 8).min(u16::MAX as u32), red[0] as u32);
+    }
+}
+
+#[test]
+fn test_white_balance_method() {
+    let render = |white_balance, method| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(white_balance)
+            .with_white_balance_method(method);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+
+    // the neutral white of the camera is the white of the output, nothing is adapted
+    let camera = render(WhiteBalance::AsShot, WhiteBalanceMethod::CameraSpace);
+    let bradford = render(WhiteBalance::AsShot, WhiteBalanceMethod::XyzBradford);
+    for (camera, bradford) in camera.iter().zip(bradford.iter()) {
+        assert!(camera.abs_diff(*bradford) <= 1);
+    }
+    // the adaptation mixes the channels where the multipliers only scale red
+    let white_balance = WhiteBalance::Custom([2.0, 1.0, 1.0]);
+    assert_ne!(
+        render(white_balance, WhiteBalanceMethod::CameraSpace),
+        render(white_balance, WhiteBalanceMethod::XyzBradford)
+    );
+}
+
+#[test]
+fn test_invalid_white_balance() {
+    for multipliers in [[2.0, 0.0, 1.0], [2.0, 1.0, -1.0], [f32::NAN, 1.0, 1.0]] {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::Custom(multipliers));
+        assert!(matches!(
+            export::load_image_from_buffer(tiff(), options),
+            Err(quickraw::RawFileReadingError::InvalidWhiteBalance(_))
+        ));
+    }
+}
+
+#[test]
+fn test_auto_white_balance() {
+    let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+        .with_white_balance(WhiteBalance::Auto);
+    let (image, _, _, info) = export::load_image_with_info_from_buffer(tiff(), options).unwrap();
+    let multipliers = info.white_balance;
+    assert_eq!(1.0, multipliers[1]);
+    assert!(multipliers.iter().all(|x| *x > 0.0));
+    // the estimated multipliers render the same image as custom ones
+    assert_eq!(image, render(WhiteBalance::Custom(multipliers)));
+
+    let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+        .with_white_balance(WhiteBalance::None);
+    let (_, _, _, info) = export::load_image_with_info_from_buffer(tiff(), options).unwrap();
+    assert_eq!([1.0; 3], info.white_balance);
+}
+
+#[test]
+fn test_render_parameters() {
+    let options = || {
+        export::Options::new(data::GAMMA_LINEAR, &data::XYZ2SRGB, false)
+            .with_white_balance(WhiteBalance::Auto)
+            .with_white_level(WhiteLevel::Custom(20000))
+    };
+    // the parameters are the same before and after the rendering
+    let parameters = export::render_parameters_from_buffer(tiff(), options()).unwrap();
+    let (_, _, _, info) = export::load_image_with_info_from_buffer(tiff(), options()).unwrap();
+    assert_eq!(parameters, info.parameters);
+    assert_eq!(info.white_balance, parameters.white_balance);
+    assert_eq!(20000, parameters.white_level);
+    // the identity camera matrix of the generic raws leaves the one of the color space
+    assert_eq!(data::XYZ2SRGB, parameters.color_matrix);
+    assert_eq!([0; 4], parameters.black_level);
+    assert_eq!(None, parameters.crop);
+    assert_eq!(quickraw::Orientation::Horizontal, parameters.orientation);
+}
+
+#[test]
+fn test_pick_white_balance() {
+    let options = || export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false);
+    // the channels of the gray ramp are interpolated alike inside of the image
+    let pick = |point, radius| {
+        export::pick_white_balance_from_buffer(tiff(), options(), point, radius)
+    };
+    let multipliers = pick((2, 1), 0).unwrap();
+    assert!(multipliers.iter().all(|x| (x - 1.0).abs() < 0.01));
+    let multipliers = pick((2, 1), 1).unwrap();
+    assert_eq!(1.0, multipliers[1]);
+    assert!(multipliers.iter().all(|x| *x > 0.0));
+
+    assert!(matches!(
+        pick((WIDTH, 0), 1),
+        Err(quickraw::RawFileReadingError::InvalidWhiteBalancePoint(_, 0))
+    ));
+    // the pixels of the patch are all clipped
+    let options = options().with_white_level(WhiteLevel::Custom(1000));
+    assert!(export::pick_white_balance_from_buffer(tiff(), options, (4, 3), 1).is_err());
+}
+
+#[test]
+fn test_white_balance_presets() {
+    // the generic raws have neither the matrix of a model nor a ColorMatrix
+    let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+        .with_white_balance(WhiteBalance::Daylight);
+    assert!(matches!(
+        export::load_image_from_buffer(tiff(), options),
+        Err(quickraw::RawFileReadingError::WhiteBalanceNeedsColorMatrix(
+            WhiteBalance::Daylight
+        ))
+    ));
+    assert_eq!(
+        None,
+        data::white_balance_preset(None, WhiteBalance::Daylight)
+    );
+
+    // the sensor is the most sensitive in green, red and blue are raised in daylight and blue
+    // even more in tungsten light
+    let color_matrix = COLOR_MATRIX;
+    let preset = |white_balance| {
+        data::white_balance_preset(Some(&color_matrix), white_balance).unwrap()
+    };
+    let (daylight, cloudy) = (preset(WhiteBalance::Daylight), preset(WhiteBalance::Cloudy));
+    let tungsten = preset(WhiteBalance::Tungsten);
+    assert!(daylight[0] > 1.0 && daylight[1] == 1.0 && daylight[2] > 1.0);
+    assert!(cloudy[0] > daylight[0] && cloudy[2] < daylight[2]);
+    assert!(tungsten[0] < daylight[0] && tungsten[2] > daylight[2]);
+    assert_eq!(
+        None,
+        data::white_balance_preset(Some(&color_matrix), WhiteBalance::AsShot)
+    );
+}
+
+#[test]
+fn test_temperature_white_balance() {
+    // the generic raws have no ColorMatrix to convert the temperature by
+    let decoded_image = quickraw::decode_buffer(tiff()).unwrap();
+    assert_eq!(None, decoded_image.as_shot_temperature());
+    let white_balance = WhiteBalance::Temperature {
+        kelvin: 2856.0,
+        tint: 0.0,
+    };
+    let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+        .with_white_balance(white_balance);
+    assert!(export::load_image_from_buffer(tiff(), options).is_err());
+
+    // the white of daylight is about 6504 K with the tint of D65 above the Planckian locus
+    let color_matrix = COLOR_MATRIX;
+    let daylight = data::white_balance_preset(Some(&color_matrix), WhiteBalance::Daylight).unwrap();
+    let (kelvin, tint) = data::white_balance_temperature(&color_matrix, daylight);
+    assert!((kelvin - 6504.0).abs() < 20.0 && (tint - 10.0).abs() < 1.0);
+    let temperature = data::temperature_white_balance(&color_matrix, kelvin, tint);
+    for (daylight, temperature) in daylight.into_iter().zip(temperature) {
+        assert!((daylight - temperature).abs() < 0.001);
+    }
+    // the lower temperatures need less red and more blue
+    let tungsten = data::temperature_white_balance(&color_matrix, 2856.0, 0.0);
+    assert!(tungsten[0] < daylight[0] && tungsten[1] == 1.0 && tungsten[2] > daylight[2]);
+
+    for (kelvin, tint) in [(3200.0, 0.0), (5500.0, 10.0), (7500.0, -15.0)] {
+        let multipliers = data::temperature_white_balance(&color_matrix, kelvin, tint);
+        let (estimated_kelvin, estimated_tint) =
+            data::white_balance_temperature(&color_matrix, multipliers);
+        assert!((estimated_kelvin - kelvin).abs() < kelvin * 0.005);
+        assert!((estimated_tint - tint).abs() < 0.5);
+    }
+}
+
+#[test]
+fn test_exposure_ev() {
+    let render = |exposure_ev| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::None)
+            .with_exposure_ev(exposure_ev);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+    let (normal, brighter, darker) = (render(0.0), render(1.0), render(-1.0));
+    for ((normal, brighter), darker) in normal.into_iter().zip(brighter).zip(darker) {
+        let expected = (normal as i32 * 2).min(u16::MAX as i32);
+        assert!((brighter as i32 - expected).abs() <= 1);
+        assert!((darker as i32 - normal as i32 / 2).abs() <= 1);
+    }
+}
+
+#[test]
+fn test_exposure_after_white_balance() {
+    let render = |exposure_ev| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::Custom([1.0, 2.0, 2.0]))
+            .with_exposure_ev(exposure_ev);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+    // red is halved before it is brightened, it doesn't clip with green at the white point
+    let (normal, brighter) = (render(0.0), render(2.0));
+    for (normal, brighter) in normal.chunks_exact(3).zip(brighter.chunks_exact(3)) {
+        for c in 0..3 {
+            let expected = (normal[c] as i32 * 4).min(u16::MAX as i32);
+            assert!((brighter[c] as i32 - expected).abs() <= 1);
+        }
+    }
+    assert!(brighter.chunks_exact(3).any(|pixel| pixel[0] > u16::MAX / 2));
+}
+
+#[test]
+fn test_auto_brightness() {
+    let options = |exposure_ev| {
+        export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::None)
+            .with_exposure_ev(exposure_ev)
+    };
+    let (image, _, _, info) =
+        export::load_image_with_info_from_buffer(tiff(), options(0.0).with_auto_brightness(99.0))
+            .unwrap();
+    assert!(info.auto_brightness_ev > 0.0 && info.auto_brightness_ev <= 3.0);
+    let (manual, _, _) =
+        export::load_image_from_buffer(tiff(), options(info.auto_brightness_ev)).unwrap();
+    assert_eq!(manual, image);
+
+    // the manual exposure compensation adds to the automatic brightness
+    let (image, _, _, darker) =
+        export::load_image_with_info_from_buffer(tiff(), options(-1.0).with_auto_brightness(99.0))
+            .unwrap();
+    assert_eq!(info.auto_brightness_ev, darker.auto_brightness_ev);
+    let (manual, _, _) =
+        export::load_image_from_buffer(tiff(), options(darker.auto_brightness_ev - 1.0)).unwrap();
+    assert_eq!(manual, image);
+}
+
+#[test]
+fn test_highlight_modes() {
+    let render = |highlight_mode, white_level| {
+        // red is halved by the white balance, its highlights are the lowest
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::Custom([1.0, 2.0, 2.0]))
+            .with_white_level(white_level)
+            .with_highlight_mode(highlight_mode);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+    // the sensor saturates at 10000, which is normalized to white
+    let clip = render(HighlightMode::Clip, WhiteLevel::Custom(10000));
+    assert!(clip.iter().all(|x| *x <= u16::MAX / 2));
+    let unclip = render(HighlightMode::Unclip, WhiteLevel::Custom(10000));
+    assert!(unclip.iter().any(|x| *x > u16::MAX / 2));
+    // nothing clips at the white level of the sensor
+    assert_eq!(
+        render(HighlightMode::Unclip, WhiteLevel::AsShot),
+        render(HighlightMode::Reconstruct, WhiteLevel::AsShot)
+    );
+}
+
+#[test]
+fn test_white_level() {
+    let render = |white_level| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::None)
+            .with_white_level(white_level);
+        export::load_image_with_info_from_buffer(tiff(), options).unwrap()
+    };
+    let (as_shot, _, _, info) = render(WhiteLevel::AsShot);
+    assert_eq!(u16::MAX, info.white_level);
+    // the samples of the scene are all different, nothing saturates
+    let (auto, _, _, info) = render(WhiteLevel::Auto);
+    assert_eq!(u16::MAX, info.white_level);
+    assert_eq!(as_shot, auto);
+
+    // the samples are normalized to the white level like an exposure of 1 EV
+    let (half, _, _, info) = render(WhiteLevel::Custom(u16::MAX / 2));
+    assert_eq!(u16::MAX / 2, info.white_level);
+    for (as_shot, half) in as_shot.into_iter().zip(half) {
+        let expected = (as_shot as i32 * 2).min(u16::MAX as i32);
+        assert!((half as i32 - expected).abs() <= 1);
+    }
+}
+
+#[test]
+fn test_transfer_function() {
+    let render = |transfer_function| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::None)
+            .with_transfer_function(transfer_function);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+    let linear = render(TransferFunction::Linear);
+    assert_eq!(linear, render(TransferFunction::PowerCurve([1.0, 0.0])));
+
+    let srgb = |l: f64| match l {
+        _ if l <= 0.0031308 => l * 12.92,
+        _ => 1.055 * l.powf(1.0 / 2.4) - 0.055,
+    };
+    for (linear, encoded) in linear.into_iter().zip(render(TransferFunction::Srgb)) {
+        let expected = srgb(linear as f64 / 65535.0) * 255.0;
+        assert!((encoded as f64 / 65535.0 * 255.0 - expected).abs() <= 1.0);
+    }
+}
+
+#[test]
+fn test_custom_transfer_function() {
+    let render = |transfer_function| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::None)
+            .with_transfer_function(transfer_function);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+    assert_eq!(
+        render(TransferFunction::Linear),
+        render(TransferFunction::Custom(|l| l))
+    );
+    // BT.2020 shares the curve of BT.709
+    assert_eq!(
+        render(TransferFunction::Rec709),
+        render(TransferFunction::Rec2020)
+    );
+}
+
+#[test]
+fn test_log_transfer_function() {
+    let render = |transfer_function| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::None)
+            .with_transfer_function(transfer_function);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+    let log = render(TransferFunction::FlatLog);
+    let preset = TransferFunction::Log {
+        black_offset: 0.08,
+        slope: 0.8,
+    };
+    assert_eq!(log, render(preset));
+
+    let encode = |l: f64| 1.0 + 0.8 * ((l + 0.08) / 1.08).log10();
+    for (linear, encoded) in render(TransferFunction::Linear).into_iter().zip(log) {
+        let expected = encode(linear as f64 / 65535.0) * 255.0;
+        assert!((encoded as f64 / 65535.0 * 255.0 - expected).abs() <= 1.0);
+    }
+}
+
+#[test]
+fn test_hdr_transfer_function() {
+    let render = |transfer_function| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::None)
+            .with_transfer_function(transfer_function);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+    let pq = |l: f64| {
+        let y = (l * 1000.0 / 10000.0).powf(0.1593017578125);
+        ((0.8359375 + 18.8515625 * y) / (1.0 + 18.6875 * y)).powf(78.84375)
+    };
+    let hlg = |l: f64| match l {
+        _ if l <= 1.0 / 12.0 => (3.0 * l).sqrt(),
+        _ => 0.17883277 * (12.0 * l - 0.28466892).ln() + 0.55991073,
+    };
+
+    let linear = render(TransferFunction::Linear);
+    let encoded = render(TransferFunction::Pq { peak_nits: 1000.0 });
+    for (linear, encoded) in linear.iter().zip(encoded) {
+        let expected = pq(*linear as f64 / 65535.0) * 1023.0;
+        assert!((encoded as f64 / 65535.0 * 1023.0 - expected).abs() <= 1.0);
+    }
+    for (linear, encoded) in linear.iter().zip(render(TransferFunction::Hlg)) {
+        let expected = hlg(*linear as f64 / 65535.0) * 1023.0;
+        assert!((encoded as f64 / 65535.0 * 1023.0 - expected).abs() <= 1.0);
+    }
+}
+
+#[test]
+fn test_channel_gammas() {
+    let options = || {
+        export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::None)
+    };
+    let gammas = [[0.5, 0.0], [0.45, 4.5], [0.4, 0.0]];
+    let options_of_gammas = options().with_channel_gammas(gammas);
+    let image = export::load_image_from_buffer(tiff(), options_of_gammas).unwrap().0;
+    // every channel is encoded like the whole image by its own gamma
+    for (c, gamma) in gammas.into_iter().enumerate() {
+        let options = options().with_transfer_function(TransferFunction::PowerCurve(gamma));
+        let expected = export::load_image_from_buffer(tiff(), options).unwrap().0;
+        for (pixel, expected) in image.chunks(3).zip(expected.chunks(3)) {
+            assert!((pixel[c] as i32 - expected[c] as i32).abs() <= 257);
+        }
+    }
+
+    for gammas in [[[0.5, 0.0], [0.0, 0.0], [0.4, 0.0]], [[-1.0, 0.0]; 3]] {
+        let options = options().with_channel_gammas(gammas);
+        assert!(matches!(
+            export::load_image_from_buffer(tiff(), options),
+            Err(quickraw::RawFileReadingError::InvalidGamma(_))
+        ));
+    }
+}
+
+#[test]
+fn test_tone_curve() {
+    let render = |tone_curve: Option<(Vec<u16>, bool)>| {
+        let mut options = export::Options::new(data::GAMMA_SRGB, &data::XYZ2RAW, false);
+        if let Some((tone_curve, replaces_gamma)) = tone_curve {
+            options = options.with_tone_curve(tone_curve, replaces_gamma);
+        }
+        export::load_image_from_buffer(tiff(), options).map(|x| x.0)
+    };
+    let image = render(None).unwrap();
+    // the identity curves of 16 and 8 bits leave the image as it is
+    let identity = (0..=u16::MAX).collect();
+    assert_eq!(image, render(Some((identity, false))).unwrap());
+    let identity = (0..256).map(|x| x * 257).collect();
+    assert_eq!(image, render(Some((identity, false))).unwrap());
+
+    let options = export::Options::new(data::GAMMA_SRGB, &data::XYZ2RAW, false)
+        .with_transfer_function(TransferFunction::Linear);
+    let linear = export::load_image_from_buffer(tiff(), options).unwrap().0;
+    assert_eq!(linear, render(Some((vec![0, u16::MAX], true))).unwrap());
+
+    assert!(matches!(
+        render(Some((vec![0], false))),
+        Err(quickraw::RawFileReadingError::InvalidToneCurve(1))
+    ));
+}
+
+#[test]
+fn test_adjustments() {
+    let render = |adjust: fn(export::Options) -> export::Options| {
+        let options = export::Options::new(data::GAMMA_SRGB, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::Custom([1.5, 1.0, 0.8]));
+        export::load_image_from_buffer(tiff(), adjust(options))
+            .unwrap()
+            .0
+    };
+    let image = render(|options| options);
+    // the neutral adjustments leave the image as it is
+    let neutral = render(|options| {
+        options
+            .with_brightness(1.0)
+            .with_contrast(0.0)
+            .with_saturation(1.0)
+    });
+    assert_eq!(image, neutral);
+
+    assert_ne!(image, render(|options| options.with_brightness(1.5)));
+    // the brightness is a gain of the linear values like the exposure compensation
+    assert_eq!(
+        render(|options| options.with_brightness(2.0)),
+        render(|options| options.with_exposure_ev(1.0))
+    );
+    assert_ne!(image, render(|options| options.with_contrast(0.5)));
+    for pixel in render(|options| options.with_saturation(0.0)).chunks_exact(3) {
+        assert!(pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    }
+}
+
+#[test]
+fn test_vibrance() {
+    let render = |adjust: fn(export::Options) -> export::Options| {
+        let options = export::Options::new(data::GAMMA_SRGB, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::Custom([1.5, 1.0, 0.8]));
+        export::load_image_from_buffer(tiff(), adjust(options))
+            .unwrap()
+            .0
+    };
+    let chroma = |pixel: &[u16]| {
+        let max = pixel.iter().max().unwrap();
+        let min = pixel.iter().min().unwrap();
+        (max - min) as i32
+    };
+    let image = render(|options| options);
+    assert_eq!(image, render(|options| options.with_vibrance(0.0)));
+    assert_eq!(
+        render(|options| options.with_saturation(1.5)),
+        render(|options| options.with_saturation(1.5).with_vibrance(0.0))
+    );
+
+    let vibrant = render(|options| options.with_vibrance(1.0));
+    let muted = render(|options| options.with_vibrance(-1.0));
+    assert_ne!(image, vibrant);
+    for ((pixel, vibrant), muted) in image
+        .chunks_exact(3)
+        .zip(vibrant.chunks_exact(3))
+        .zip(muted.chunks_exact(3))
+    {
+        assert!(chroma(vibrant) >= chroma(pixel) - 1);
+        assert!(chroma(muted) <= chroma(pixel) + 1);
+    }
+    // the extreme vibrance stops at the border of the range instead of clipping the hue
+    let extreme = render(|options| options.with_vibrance(100.0));
+    let order = |p: &[u16]| [p[0] <= p[1], p[1] <= p[2], p[0] <= p[2]];
+    for (pixel, extreme) in image.chunks_exact(3).zip(extreme.chunks_exact(3)) {
+        if chroma(pixel) > 2 {
+            assert!(chroma(extreme) >= chroma(pixel) - 1);
+            assert_eq!(order(pixel), order(extreme));
+        }
+    }
+}
+
+#[test]
+fn test_channel_mixer() {
+    // a color space of its own which the mixer goes along with
+    let color_space = [0.9, 0.1, 0.0, 0.0, 1.0, 0.0, 0.0, 0.2, 0.8];
+    let render = |channel_mixer| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &color_space, false)
+            .with_white_balance(WhiteBalance::Custom([1.5, 1.0, 0.8]))
+            .with_channel_mixer(channel_mixer);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+    let image = render(data::CHANNEL_MIXER_IDENTITY);
+    let swapped = render([0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0]);
+    for (pixel, swapped) in image.chunks_exact(3).zip(swapped.chunks_exact(3)) {
+        assert_eq!([pixel[2], pixel[1], pixel[0]], swapped);
+    }
+    let gray = render([1.0 / 3.0; 9]);
+    for pixel in gray.chunks_exact(3) {
+        assert!(pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    }
+
+    for channel_mixer in [[8.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0], [f32::NAN; 9]] {
+        let options = export::Options::new(data::GAMMA_LINEAR, &color_space, false)
+            .with_channel_mixer(channel_mixer);
+        assert!(matches!(
+            export::load_image_from_buffer(tiff(), options),
+            Err(quickraw::RawFileReadingError::InvalidChannelMixer(_))
+        ));
+    }
+}
+
+#[test]
+fn test_negative_inversion() {
+    let options = || export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false);
+    let render = |options| export::load_image_from_buffer(tiff(), options).map(|x| x.0);
+    let negative = render(options()).unwrap();
+    let auto = NegativeInversion::Auto { sample: None };
+    let positive = render(options().with_negative_inversion(auto)).unwrap();
+
+    // the denser parts of every channel turn brighter, the film base black
+    let channel = |image: &[u16], c: usize| -> Vec<u16> {
+        image.iter().skip(c).step_by(3).copied().collect()
+    };
+    for c in 0..3 {
+        let (negative, positive) = (channel(&negative, c), channel(&positive, c));
+        for i in 0..negative.len() {
+            for j in 0..negative.len() {
+                if negative[i] < negative[j] {
+                    assert!(positive[i] >= positive[j]);
+                }
+            }
+        }
+        let max = negative.iter().max().unwrap();
+        let brightest = negative.iter().position(|x| x == max).unwrap();
+        assert_eq!(0, positive[brightest]);
+    }
+
+    let sample = NegativeInversion::Auto {
+        sample: Some((4, 2)),
+    };
+    assert!(render(options().with_negative_inversion(sample)).is_ok());
+    for inversion in [
+        NegativeInversion::Auto {
+            sample: Some((WIDTH, 0)),
+        },
+        NegativeInversion::Manual { base_rgb: [0.5, 0.0, 0.5] },
+    ] {
+        assert!(matches!(
+            render(options().with_negative_inversion(inversion)),
+            Err(quickraw::RawFileReadingError::InvalidFilmBase)
+        ));
+    }
+}
+
+#[test]
+fn test_monochrome() {
+    let options = || {
+        export::Options::new(data::GAMMA_SRGB, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::Custom([1.5, 1.0, 0.8]))
+    };
+    let render = |options| export::load_image_from_buffer(tiff(), options).map(|x| x.0);
+    let monochrome = |weights| Monochrome { weights };
+    let image = render(options()).unwrap();
+    let red_filter = render(options().with_monochrome(monochrome([0.6, 0.3, 0.1]))).unwrap();
+    for pixel in red_filter.chunks_exact(3) {
+        assert!(pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    }
+    // the weights are normalized and the saturation is left out
+    let scaled = options()
+        .with_monochrome(monochrome([6.0, 3.0, 1.0]))
+        .with_saturation(2.0)
+        .with_vibrance(1.0);
+    assert_eq!(red_filter, render(scaled).unwrap());
+
+    let red = render(options().with_monochrome(monochrome([1.0, 0.0, 0.0]))).unwrap();
+    for (pixel, red) in image.chunks_exact(3).zip(red.chunks_exact(3)) {
+        assert_eq!([pixel[0]; 3], red);
+    }
+
+    assert!(matches!(
+        render(options().with_monochrome(monochrome([1.0, -1.0, 0.0]))),
+        Err(quickraw::RawFileReadingError::InvalidMonochromeWeights(_))
+    ));
+
+    // the weights mix the linear values before the transfer function
+    let linear = options().with_transfer_function(TransferFunction::Linear);
+    let linear = render(linear).unwrap();
+    let sqrt = options()
+        .with_transfer_function(TransferFunction::Custom(f32::sqrt))
+        .with_monochrome(monochrome([0.5, 0.0, 0.5]));
+    for (linear, sqrt) in linear.chunks_exact(3).zip(render(sqrt).unwrap().chunks_exact(3)) {
+        let mix = (linear[0] as f32 + linear[2] as f32) / 2.0;
+        if mix > 4000.0 {
+            let expected = (mix / 65535.0).sqrt() * 65535.0;
+            assert!((sqrt[0] as f32 - expected).abs() <= 4.0);
+        }
+    }
+}
+
+#[test]
+fn test_sharpen() {
+    let options = || {
+        export::Options::new(data::GAMMA_SRGB, &data::XYZ2RAW, false)
+            .with_white_balance(WhiteBalance::Custom([1.5, 1.0, 0.8]))
+    };
+    let render = |options| export::load_image_from_buffer(tiff(), options).map(|x| x.0);
+    let sharpen = |amount, threshold| Sharpen {
+        amount,
+        radius: 1.0,
+        threshold,
+    };
+    let image = render(options()).unwrap();
+    assert_eq!(image, render(options().with_sharpen(sharpen(0.0, 0.0))).unwrap());
+    // nothing reaches the threshold of the whole range
+    assert_eq!(image, render(options().with_sharpen(sharpen(1.0, 1.0))).unwrap());
+
+    let sharpened = render(options().with_sharpen(sharpen(1.0, 0.0))).unwrap();
+    assert_ne!(image, sharpened);
+    // only the luma is sharpened, the differences of the channels are kept unless they clip
+    for (pixel, sharpened) in image.chunks_exact(3).zip(sharpened.chunks_exact(3)) {
+        if sharpened.iter().all(|&x| 0 < x && x < u16::MAX) {
+            let difference = |p: &[u16], a: usize, b: usize| p[a] as i32 - p[b] as i32;
+            for c in [0, 2] {
+                assert!((difference(pixel, c, 1) - difference(sharpened, c, 1)).abs() <= 1);
+            }
+        }
+    }
+
+    assert!(matches!(
+        render(options().with_sharpen(Sharpen {
+            amount: 1.0,
+            radius: 0.0,
+            threshold: 0.0,
+        })),
+        Err(quickraw::RawFileReadingError::InvalidSharpen(_))
+    ));
+}
+
+#[test]
+fn test_tone_mapping() {
+    let render = |tone_mapping| {
+        let options = export::Options::new(data::GAMMA_SRGB, &data::XYZ2RAW, false)
+            .with_exposure_ev(2.0)
+            .with_tone_mapping(tone_mapping);
+        export::load_image_from_buffer(tiff(), options).unwrap().0
+    };
+    let image = render(ToneMapping::None);
+    let clipped = |image: &[u16]| image.iter().filter(|&&value| value == u16::MAX).count();
+
+    // the curves roll the highlights off where the plain exposure clips them
+    let filmic = ToneMapping::Filmic {
+        contrast: 0.2,
+        shoulder: 8.0,
+    };
+    for tone_mapping in [filmic, ToneMapping::Aces] {
+        let mapped = render(tone_mapping);
+        assert_ne!(image, mapped);
+        assert!(clipped(&mapped) < clipped(&image));
+    }
+}
+
+#[test]
+fn test_output_options() {
+    let output = Output::new(
+        DemosaicingMethod::Linear,
+        data::XYZ2SRGB,
+        [data::GAMMA_SRGB, 0.0],
+        OutputType::Raw16,
+        false,
+        false,
+    )
+    .with_white_balance(WhiteBalance::Custom([2.0, 1.0, 1.5]))
+    .with_exposure_ev(0.5)
+    .with_saturation(1.2);
+    let options = export::Options::new(data::GAMMA_SRGB, &data::XYZ2SRGB, false)
+        .with_white_balance(WhiteBalance::Custom([2.0, 1.0, 1.5]))
+        .with_exposure_ev(0.5)
+        .with_saturation(1.2);
+
+    // the output renders the image by the same options
+    assert_eq!(
+        export::load_image_from_buffer(tiff(), options).unwrap(),
+        export::load_image_from_buffer(tiff(), export::Options::from(&output)).unwrap()
+    );
+}
@@ -0,0 +1,554 @@
+//! Decodes synthetic ARW files, whose SR2SubIFD is encrypted like the one of the cameras.
+
+mod common;
+
+use quickraw::{data, export, WhiteBalance};
+
+const WIDTH: usize = 32;
+const HEIGHT: usize = 2;
+
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+
+// the knots of tag 0x7010, the tone curve is linear up to 2000
+const TONE_CURVE: [u16; 4] = [8000, 10400, 12900, 14100];
+const SR2_KEY: u32 = 0x1234_5678;
+
+/// The keystream of dcraw's sony_decrypt, which encrypts and decrypts alike
+fn sony_crypt(data: &mut [u8], key: u32) {
+    let mut pad = [0u32; 128];
+    let mut key = key;
+    for p in pad.iter_mut().take(4) {
+        key = key.wrapping_mul(48828125).wrapping_add(1);
+        *p = key;
+    }
+    pad[3] = pad[3] << 1 | (pad[0] ^ pad[2]) >> 31;
+    for p in 4..127 {
+        pad[p] = (pad[p - 4] ^ pad[p - 2]) << 1 | (pad[p - 3] ^ pad[p - 1]) >> 31;
+    }
+    for (i, word) in data.chunks_exact_mut(4).enumerate() {
+        let p = i + 127;
+        pad[p & 127] = pad[(p + 1) & 127] ^ pad[(p + 65) & 127];
+        for (byte, key) in word.iter_mut().zip(pad[p & 127].to_be_bytes()) {
+            *byte ^= key;
+        }
+    }
+}
+
+type Tag = (u16, u16, u32, u32);
+
+// the raw data follows the directories and their values
+const DATA: u32 = 640;
+const MINOLTA_WBG: u32 = 0x0057_4247;
+
+/// The tags of the raw IFD of an image of the size of the scene in a strip at `DATA`
+fn strip_tags(compression: u32, bps: u16, strip_len: usize) -> Vec<Tag> {
+    vec![
+        (0x0100, LONG, 1, WIDTH as u32),
+        (0x0101, LONG, 1, HEIGHT as u32),
+        (0x0102, SHORT, 1, bps as u32),
+        (0x0103, SHORT, 1, compression),
+        (0x0111, LONG, 1, DATA),
+        (0x0117, LONG, 1, strip_len as u32),
+    ]
+}
+
+/// An ARW of the `model` with the raw IFD in its SubIFDs and the `data` at `DATA`. The white
+/// balance and the white level are in its encrypted SR2SubIFD, or in the Minolta blocks of the
+/// DSLR-A100. The SR2SubIFD of the DSC-R1 holds the black level and the white balance in the
+/// older tags.
+fn arw(model: &[u8], raw_ifd: &[Tag], data: &[u8]) -> Vec<u8> {
+    const RAW_IFD: u32 = 100;
+    const EXIF: u32 = 300;
+    const COMPRESSED_BPP: u32 = 330;
+    const MAKE: u32 = 340;
+    const MODEL: u32 = 350;
+    const CURVE: u32 = 370;
+    const PRIVATE: u32 = 400;
+    const SR2_SUB: u32 = 460;
+    const SR2_LEN: u32 = 80;
+    const WB_LEVELS: u32 = 500;
+    const WHITE_LEVELS: u32 = 510;
+    const BLACK_LEVELS: u32 = 520;
+
+    let mut buffer = vec![0u8; DATA as usize + data.len()];
+    buffer[..8].copy_from_slice(&[b'I', b'I', 0x2a, 0, 8, 0, 0, 0]);
+    common::write_ifd(
+        &mut buffer,
+        8,
+        &[
+            (0x010f, ASCII, 5, MAKE),
+            (0x0110, ASCII, model.len() as u32 + 1, MODEL),
+            (0x0112, SHORT, 1, 1),
+            (0x014a, LONG, 1, RAW_IFD),
+            (0x8769, LONG, 1, EXIF),
+            (0xc634, BYTE, 4, PRIVATE),
+        ],
+        0,
+    );
+    let mut entries = raw_ifd.to_vec();
+    entries.extend([
+        (0x7010, SHORT, 4, CURVE),
+        (0x828e, BYTE, 4, u32::from_le_bytes([0, 1, 1, 2])),
+    ]);
+    entries.sort_by_key(|&(tag, ..)| tag);
+    common::write_ifd(&mut buffer, RAW_IFD as usize, &entries, 0);
+    common::write_ifd(
+        &mut buffer,
+        EXIF as usize,
+        &[(0x9102, RATIONAL, 1, COMPRESSED_BPP)],
+        0,
+    );
+
+    let put = |buffer: &mut [u8], at: u32, values: &[u16]| {
+        for (i, value) in values.iter().enumerate() {
+            let pos = at as usize + i * 2;
+            buffer[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+        }
+    };
+    if model == b"DSLR-A100" {
+        // the MRI block holds the WBG block, whose RGGB levels follow 4 bytes
+        let mut blocks = b"\0MRI".to_vec();
+        blocks.extend(20u32.to_le_bytes());
+        blocks.extend(MINOLTA_WBG.to_be_bytes());
+        blocks.extend(12u32.to_le_bytes());
+        blocks.extend([0; 4]);
+        blocks.extend([600u16, 300, 300, 450].iter().flat_map(|x| x.to_le_bytes()));
+        buffer[PRIVATE as usize..PRIVATE as usize + blocks.len()].copy_from_slice(&blocks);
+    } else {
+        // the key is read in big endian, whatever the byte order of the file
+        common::write_ifd(
+            &mut buffer,
+            PRIVATE as usize,
+            &[
+                (0x7200, LONG, 1, SR2_SUB),
+                (0x7201, LONG, 1, SR2_LEN),
+                (0x7221, LONG, 1, u32::from_le_bytes(SR2_KEY.to_be_bytes())),
+            ],
+            0,
+        );
+        if model == b"DSC-R1" {
+            common::write_ifd(
+                &mut buffer,
+                SR2_SUB as usize,
+                &[
+                    (0x7300, SHORT, 4, BLACK_LEVELS),
+                    (0x7303, SHORT, 4, WB_LEVELS),
+                ],
+                0,
+            );
+            // the black level and WB_GRBLevels
+            put(&mut buffer, BLACK_LEVELS, &[128; 4]);
+            put(&mut buffer, WB_LEVELS, &[1024, 2048, 1536, 1024]);
+        } else {
+            common::write_ifd(
+                &mut buffer,
+                SR2_SUB as usize,
+                &[
+                    (0x7312, SHORT, 4, WB_LEVELS),
+                    (0x787f, SHORT, 3, WHITE_LEVELS),
+                ],
+                0,
+            );
+            // WB_RGGBLevels and the white level of the 14 bit scale
+            put(&mut buffer, WB_LEVELS, &[2048, 1024, 1024, 1536]);
+            put(&mut buffer, WHITE_LEVELS, &[16383; 3]);
+        }
+        sony_crypt(
+            &mut buffer[SR2_SUB as usize..(SR2_SUB + SR2_LEN) as usize],
+            SR2_KEY,
+        );
+    }
+    put(&mut buffer, CURVE, &TONE_CURVE);
+    buffer[COMPRESSED_BPP as usize..COMPRESSED_BPP as usize + 8]
+        .copy_from_slice(&[8, 0, 0, 0, 1, 0, 0, 0]);
+    buffer[MAKE as usize..MAKE as usize + 5].copy_from_slice(b"SONY\0");
+    buffer[MODEL as usize..MODEL as usize + model.len()].copy_from_slice(model);
+    buffer[DATA as usize..].copy_from_slice(data);
+    buffer
+}
+
+/// Every pixel holds its own position in 14 bits
+fn scene(x: usize, y: usize) -> u16 {
+    common::position(x, y, WIDTH, 0) * 200
+}
+
+fn expected() -> Vec<u16> {
+    (0..WIDTH * HEIGHT)
+        .map(|i| scene(i % WIDTH, i / WIDTH))
+        .collect()
+}
+
+/// A block of 16 pixels of a color of an ARW2 row, the maximum and the minimum with their
+/// positions, and the 7 bit deltas of the other pixels to the minimum
+struct Block {
+    max: u32,
+    min: u32,
+    imax: usize,
+    imin: usize,
+    deltas: [u32; 14],
+}
+
+impl Block {
+    /// The bits of the block from the least significant one on
+    fn encode(&self) -> [u8; 16] {
+        let mut bits = 0u128;
+        let mut len = 0;
+        let mut push = |value: u32, width: u32| {
+            bits |= (value as u128) << len;
+            len += width;
+        };
+        push(self.max, 11);
+        push(self.min, 11);
+        push(self.imax as u32, 4);
+        push(self.imin as u32, 4);
+        for delta in self.deltas {
+            push(delta, 7);
+        }
+        bits.to_le_bytes()
+    }
+    /// The 11 bit values of the pixels, the deltas are shifted by the bits the range of the
+    /// block takes beyond 7, and the dropped bits are left at 0 like dcraw, LibRaw and rawspeed
+    fn values(&self) -> [u32; 16] {
+        let range_bits = 32 - (self.max - self.min).leading_zeros();
+        let shift = range_bits.saturating_sub(7);
+        let mut deltas = self.deltas.into_iter();
+        let mut values = [0; 16];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = match i {
+                _ if i == self.imax => self.max,
+                _ if i == self.imin => self.min,
+                _ => (self.min + (deltas.next().unwrap() << shift)).min(0x7ff),
+            };
+        }
+        values
+    }
+}
+
+/// Two rows of two blocks each, whose ranges take 10, 6, 8 and 9 bits
+fn arw2_blocks() -> [[Block; 2]; HEIGHT] {
+    let deltas = |step: u32| std::array::from_fn(|k| k as u32 * step);
+    [
+        [
+            Block {
+                max: 900,
+                min: 100,
+                imax: 0,
+                imin: 1,
+                deltas: deltas(8),
+            },
+            Block {
+                max: 60,
+                min: 20,
+                imax: 15,
+                imin: 14,
+                deltas: deltas(3),
+            },
+        ],
+        [
+            Block {
+                max: 650,
+                min: 450,
+                imax: 7,
+                imin: 8,
+                deltas: deltas(9),
+            },
+            Block {
+                max: 990,
+                min: 600,
+                imax: 3,
+                imin: 12,
+                deltas: deltas(7),
+            },
+        ],
+    ]
+}
+
+/// The reference values of the blocks, the 11 bit values index the tone curve by their double
+/// and are dithered between its neighbors like rawspeed, by the random state seeded from the
+/// first 16 bits of every row. The curve is linear where the values are.
+fn arw2_reference(blocks: &[[Block; 2]; HEIGHT]) -> Vec<u16> {
+    let mut image = vec![0u16; WIDTH * HEIGHT];
+    for (y, row) in blocks.iter().enumerate() {
+        let first = row[0].encode();
+        let mut random = u16::from_le_bytes([first[0], first[1]]) as u32;
+        for (j, block) in row.iter().enumerate() {
+            for (i, value) in block.values().into_iter().enumerate() {
+                let index = value << 1;
+                let pixel = match index {
+                    0 => 0,
+                    _ => index - 1 + ((2 * (random & 2047) + 1024) >> 12),
+                };
+                random = 15700 * (random & 65535) + (random >> 16);
+                image[y * WIDTH + j + i * 2] = pixel as u16;
+            }
+        }
+    }
+    image
+}
+
+#[test]
+fn test_arw2_blocks() {
+    let blocks = arw2_blocks();
+    let strip = blocks
+        .iter()
+        .flatten()
+        .flat_map(|block| block.encode())
+        .collect::<Vec<_>>();
+    let buffer = arw(b"ILCE-7M3", &strip_tags(0x7fff, 8, strip.len()), &strip);
+    let decoded_image = quickraw::decode_buffer(buffer).unwrap();
+
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    assert_eq!(arw2_reference(&blocks), decoded_image.image);
+    assert_eq!([2048, 1024, 1536], decoded_image.white_balance);
+    // the tone curve expands the values to 14 bits, whatever the BitsPerSample
+    assert_eq!(16383, decoded_image.white_level);
+}
+
+#[test]
+fn test_pad_to_14bit() {
+    // uncompressed 12 bit samples in 16 bit containers
+    let samples = (0..WIDTH * HEIGHT)
+        .map(|i| (i * 4095 / (WIDTH * HEIGHT - 1)) as u16)
+        .collect::<Vec<_>>();
+    let strip = samples
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<_>>();
+    let buffer = arw(b"ILCE-7M3", &strip_tags(1, 12, strip.len()), &strip);
+
+    let mut decoded_image = quickraw::decode_buffer(buffer.clone()).unwrap();
+    assert_eq!(samples, decoded_image.image);
+    assert_eq!(4095, decoded_image.white_level);
+    decoded_image.pad_to_14bit();
+    let padded = samples.iter().map(|x| x << 2).collect::<Vec<_>>();
+    assert_eq!(padded, decoded_image.image);
+    assert_eq!(16383, decoded_image.white_level);
+    // the samples of 14 bits are kept
+    decoded_image.pad_to_14bit();
+    assert_eq!(padded, decoded_image.image);
+
+    let options = |pads_14bit| {
+        export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false).with_pad_14bit(pads_14bit)
+    };
+    let (image, ..) = export::load_cfa_image_from_buffer(buffer.clone(), options(true)).unwrap();
+    assert_eq!(padded, image);
+    let (image, ..) = export::load_cfa_image_from_buffer(buffer, options(false)).unwrap();
+    assert_eq!(samples, image);
+}
+
+#[test]
+fn test_white_balance_presets() {
+    let strip = expected()
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<_>>();
+    let buffer = arw(b"ILCE-7M3", &strip_tags(1, 14, strip.len()), &strip);
+    let decoded_image = quickraw::decode_buffer(buffer.clone()).unwrap();
+    // the ARW files have no ColorMatrix, the presets come from the matrix of the model
+    assert_eq!(None, decoded_image.color_matrix);
+    let render = |white_balance| {
+        let options = export::Options::new(data::GAMMA_LINEAR, &data::XYZ2RAW, false)
+            .with_white_balance(white_balance);
+        export::load_image_from_buffer(buffer.clone(), options)
+            .unwrap()
+            .0
+    };
+    assert_eq!(render(WhiteBalance::AsShot), render(WhiteBalance::Daylight));
+    assert_ne!(render(WhiteBalance::AsShot), render(WhiteBalance::Tungsten));
+
+    // the white balance as shot stands for daylight, blue is raised in tungsten light and
+    // lowered under a cloudy sky
+    let as_shot = [2.0, 1.0, 1.5];
+    let preset = |white_balance| {
+        data::white_balance_preset_from_cam_matrix(
+            &decoded_image.cam_matrix,
+            as_shot,
+            white_balance,
+        )
+        .unwrap()
+    };
+    let daylight = preset(WhiteBalance::Daylight);
+    for (daylight, as_shot) in daylight.into_iter().zip(as_shot) {
+        assert!((daylight - as_shot).abs() < 1e-4);
+    }
+    let (cloudy, tungsten) = (preset(WhiteBalance::Cloudy), preset(WhiteBalance::Tungsten));
+    assert!(cloudy[2] < daylight[2] && cloudy[1] == 1.0);
+    assert!(tungsten[0] < daylight[0] && tungsten[2] > daylight[2] && tungsten[1] == 1.0);
+    assert_eq!(
+        None,
+        data::white_balance_preset_from_cam_matrix(
+            &decoded_image.cam_matrix,
+            as_shot,
+            WhiteBalance::AsShot
+        )
+    );
+}
+
+#[test]
+fn test_uncompressed_16bit() {
+    let samples = expected().iter().map(|x| x * 5).collect::<Vec<_>>();
+    let strip = samples
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<_>>();
+    let buffer = arw(b"ILCE-7M3", &strip_tags(1, 16, strip.len()), &strip);
+    let decoded_image = quickraw::decode_buffer(buffer).unwrap();
+
+    // none of the bits of the 16 bit containers are masked
+    assert_eq!(samples, decoded_image.image);
+    assert_eq!(65535, decoded_image.white_level);
+}
+
+#[test]
+fn test_lossless_tiles() {
+    const TILE_WIDTH: usize = 16;
+    // every 2x2 block of a tile is one sample of 4 components
+    let tile = |first_x: usize| {
+        let samples = (0..TILE_WIDTH / 2)
+            .flat_map(|col| {
+                let x = first_x + col * 2;
+                [scene(x, 0), scene(x + 1, 0), scene(x, 1), scene(x + 1, 1)]
+            })
+            .collect::<Vec<_>>();
+        common::ljpeg(&samples, TILE_WIDTH / 2, 1, 4, 14)
+    };
+    let tiles = [tile(0), tile(TILE_WIDTH)];
+
+    // SonyRawImageSize, then the tables of the tile offsets and sizes, then the tiles
+    let mut data = [WIDTH as u32, HEIGHT as u32].map(u32::to_le_bytes).concat();
+    let mut offset = DATA + 24;
+    for tile in &tiles {
+        data.extend(offset.to_le_bytes());
+        offset += tile.len() as u32;
+    }
+    for tile in &tiles {
+        data.extend((tile.len() as u32).to_le_bytes());
+    }
+    data.extend(tiles.concat());
+
+    // the ImageWidth and ImageLength of the lossless files are larger than the image
+    let raw_ifd = [
+        (0x0100, LONG, 1, WIDTH as u32 + 8),
+        (0x0101, LONG, 1, HEIGHT as u32 + 2),
+        (0x0102, SHORT, 1, 14),
+        (0x0103, SHORT, 1, 7),
+        (0x0142, LONG, 1, TILE_WIDTH as u32),
+        (0x0143, LONG, 1, HEIGHT as u32),
+        (0x0144, LONG, 2, DATA + 8),
+        (0x0145, LONG, 2, DATA + 16),
+        (0x7038, LONG, 2, DATA),
+    ];
+    let decoded_image = quickraw::decode_buffer(arw(b"ILCE-7M3", &raw_ifd, &data)).unwrap();
+
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    assert_eq!(expected(), decoded_image.image);
+    assert_eq!(16383, decoded_image.white_level);
+}
+
+#[test]
+fn test_arq() {
+    // the 4 samples of every pixel in the order of R, G, G, B
+    let samples = expected()
+        .iter()
+        .flat_map(|&x| [x, x + 1, x + 3, x + 4])
+        .collect::<Vec<_>>();
+    let strip = samples
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<_>>();
+    let mut raw_ifd = strip_tags(0x7fff, 14, strip.len());
+    raw_ifd.push((0x0115, SHORT, 1, 4));
+    let decoded_image = quickraw::decode_buffer(arw(b"ILCE-7RM4", &raw_ifd, &strip)).unwrap();
+
+    let rgb = expected()
+        .iter()
+        .flat_map(|&x| [x, x + 2, x + 4])
+        .collect::<Vec<_>>();
+    assert_eq!(quickraw::CFAPattern::None, decoded_image.cfa_pattern);
+    assert_eq!(rgb, decoded_image.image);
+    assert_eq!(16383, decoded_image.white_level);
+}
+
+#[test]
+fn test_a100() {
+    // 12 bit samples packed in big endian, the rows are 3872 pixels apart
+    const ROW_LEN: usize = 3872 * 3 / 2;
+    let mut strip = vec![0u8; ROW_LEN * HEIGHT];
+    for (row, samples) in strip
+        .chunks_exact_mut(ROW_LEN)
+        .zip(expected().chunks(WIDTH))
+    {
+        for (bytes, pair) in row.chunks_exact_mut(3).zip(samples.chunks_exact(2)) {
+            let (a, b) = (pair[0] >> 2, pair[1] >> 2);
+            bytes.copy_from_slice(&[(a >> 4) as u8, ((a & 0xf) << 4 | b >> 8) as u8, b as u8]);
+        }
+    }
+    let raw_ifd = [
+        (0x0100, LONG, 1, WIDTH as u32),
+        (0x0101, LONG, 1, HEIGHT as u32),
+        (0x0111, LONG, 1, DATA),
+        (0x0117, LONG, 1, strip.len() as u32),
+    ];
+    let decoded_image = quickraw::decode_buffer(arw(b"DSLR-A100", &raw_ifd, &strip)).unwrap();
+
+    let image = expected().iter().map(|x| x >> 2 << 4).collect::<Vec<_>>();
+    assert_eq!((WIDTH, HEIGHT), (decoded_image.width, decoded_image.height));
+    assert_eq!(image, decoded_image.image);
+    // the WBG levels of 600, 300 and 450 relative to the green of 512
+    assert_eq!([1024, 512, 768], decoded_image.white_balance);
+    assert_eq!([0; 4], decoded_image.black_level);
+}
+
+#[test]
+fn test_sr2() {
+    // the last columns of the ImageWidth are not part of the image of DSC-R1
+    const SR2_WIDTH: usize = 3936;
+    let samples = (0..SR2_WIDTH * HEIGHT)
+        .map(|i| i as u16 & 0x3fff)
+        .collect::<Vec<_>>();
+    // 14 bit samples in big endian, whatever the byte order of the file
+    let strip = samples
+        .iter()
+        .flat_map(|x| x.to_be_bytes())
+        .collect::<Vec<_>>();
+    let raw_ifd = [
+        (0x0100, LONG, 1, SR2_WIDTH as u32),
+        (0x0101, LONG, 1, HEIGHT as u32),
+        (0x0102, SHORT, 1, 14),
+        (0x0103, SHORT, 1, 1),
+        (0x0111, LONG, 1, DATA),
+        (0x0117, LONG, 1, strip.len() as u32),
+    ];
+    let buffer = arw(b"DSC-R1", &raw_ifd, &strip);
+    let decoded_image = quickraw::decode_buffer_keeping_black_level(buffer).unwrap();
+
+    let image = samples.iter().map(|x| x * 4).collect::<Vec<_>>();
+    assert_eq!(image, decoded_image.image);
+    assert_eq!(65532, decoded_image.white_level);
+    assert_eq!([512; 4], decoded_image.black_level);
+    assert_eq!([2048, 1024, 1536], decoded_image.white_balance);
+    assert_eq!(
+        Some(quickraw::Crop {
+            x: 0,
+            y: 0,
+            width: 3925,
+            height: HEIGHT as u32,
+        }),
+        decoded_image.crop
+    );
+}
+
+#[test]
+fn test_srf_is_not_supported() {
+    let strip = vec![0u8; WIDTH * HEIGHT * 2];
+    let buffer = arw(b"DSC-F828", &strip_tags(1, 14, strip.len()), &strip);
+
+    assert!(matches!(
+        quickraw::decode_buffer(buffer),
+        Err(quickraw::RawFileReadingError::ModelIsNotSupportedYet(model)) if model == "DSC-F828"
+    ));
+}